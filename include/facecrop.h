@@ -0,0 +1,70 @@
+/*
+ * C interface to facecrop, exported by the shared library built with
+ * `cargo build --release --features capi`.
+ */
+
+#ifndef FACECROP_H
+#define FACECROP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define FACECROP_MODEL_BLAZEFACE_640 0
+#define FACECROP_MODEL_BLAZEFACE_320 1
+#define FACECROP_MODEL_MTCNN 2
+
+/* Opaque handle to a loaded face detector. */
+typedef struct FacecropDetector FacecropDetector;
+
+typedef struct FacecropRect {
+    float x;
+    float y;
+    float width;
+    float height;
+} FacecropRect;
+
+typedef struct FacecropFace {
+    FacecropRect rect;
+    float confidence;
+} FacecropFace;
+
+typedef struct FacecropCropParams {
+    /* Non-zero to crop width x height pixels around the face, zero to crop
+     * relative to the face size with aspect_ratio and proportion_of_face. */
+    int32_t absolute;
+    uint32_t height;
+    uint32_t width;
+    float aspect_ratio;
+    float proportion_of_face;
+    float top_padding;
+} FacecropCropParams;
+
+/* Loads a face detector. Returns NULL if the model is unknown or can't be
+ * loaded. */
+FacecropDetector *facecrop_detector_new(uint32_t model);
+
+/* Frees a detector returned by facecrop_detector_new. NULL is ignored. */
+void facecrop_detector_free(FacecropDetector *detector);
+
+/* Detects the faces in an image of interleaved 8-bit RGB pixels, row by row
+ * without padding. Writes up to max_faces faces and returns the number
+ * detected, which may be more than max_faces, or -1 on error. */
+int64_t facecrop_detect(const FacecropDetector *detector, const uint8_t *rgb,
+                        uint32_t width, uint32_t height, FacecropFace *faces,
+                        size_t max_faces);
+
+/* Calculates the region of an image to crop for a face, clamped to the
+ * image. */
+FacecropRect facecrop_crop(FacecropRect face, uint32_t image_width,
+                           uint32_t image_height,
+                           const FacecropCropParams *params);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FACECROP_H */
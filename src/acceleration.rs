@@ -0,0 +1,295 @@
+use std::{path::Path, sync::Arc};
+
+use ort::{Environment, GraphOptimizationLevel, OrtResult, Session, SessionBuilder};
+use rust_faces::Provider;
+use tracing::{info, warn};
+
+use crate::cropping;
+
+/// True when CoreML can run the detector, i.e. on Apple platforms.
+const COREML_AVAILABLE: bool = cfg!(target_vendor = "apple");
+
+/// Which ONNX Runtime execution provider runs the detector.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Acceleration {
+    /// CoreML on Apple Silicon, or CPU everywhere else.
+    Auto,
+    Cpu,
+    CoreMl,
+    /// TensorRT on NVIDIA GPUs, with the tensorrt feature.
+    TensorRt,
+}
+
+/// How much ONNX Runtime rewrites the graph of a model when loading it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GraphOptimization {
+    Disable,
+    Basic,
+    Extended,
+    All,
+}
+
+/// Threading and graph optimization of the ONNX Runtime sessions, None leaving the runtime
+/// default.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SessionOptions {
+    pub intra_threads: Option<usize>,
+    pub inter_threads: Option<usize>,
+    pub graph_optimization: Option<GraphOptimization>,
+}
+
+/// Function to start building a session with the given options.
+///
+/// # Arguments
+///
+/// * `environment` - The environment of the session.
+/// * `options` - The threading and graph optimization of the session.
+///
+/// # Returns
+///
+/// * The session builder, ready for the model to be loaded.
+pub fn session_builder(
+    environment: &Arc<Environment>,
+    options: SessionOptions,
+) -> OrtResult<SessionBuilder> {
+    let mut builder = SessionBuilder::new(environment)?;
+    if let Some(intra_threads) = options.intra_threads {
+        builder = builder.with_intra_threads(intra_threads.min(i16::MAX as usize) as i16)?;
+    }
+    if let Some(inter_threads) = options.inter_threads {
+        // inter-op threads only run independent nodes side by side in parallel execution
+        builder = builder
+            .with_parallel_execution(inter_threads > 1)?
+            .with_inter_threads(inter_threads.min(i16::MAX as usize) as i16)?;
+    }
+    if let Some(graph_optimization) = options.graph_optimization {
+        builder = builder.with_optimization_level(match graph_optimization {
+            GraphOptimization::Disable => GraphOptimizationLevel::Disable,
+            GraphOptimization::Basic => GraphOptimizationLevel::Level1,
+            GraphOptimization::Extended => GraphOptimizationLevel::Level2,
+            GraphOptimization::All => GraphOptimizationLevel::Level3,
+        })?;
+    }
+    Ok(builder)
+}
+
+/// Function to load an ONNX model into a session of its own.
+///
+/// # Arguments
+///
+/// * `model_path` - Path to the ONNX model.
+/// * `options` - The threading and graph optimization of the session.
+///
+/// # Returns
+///
+/// * The session.
+pub fn load_session(model_path: &Path, options: SessionOptions) -> OrtResult<Session> {
+    let environment = Environment::builder()
+        .with_name("facecrop")
+        .build()?
+        .into_arc();
+    session_builder(&environment, options)?.with_model_from_file(model_path)
+}
+
+/// Function to get the execution provider to build the detector with.
+///
+/// CoreML isn't available outside Apple platforms, where requesting it falls back to the CPU
+/// with a warning. NNAPI isn't supported by the detector runtime so Android devices use the CPU.
+///
+/// # Arguments
+///
+/// * `acceleration` - The requested acceleration.
+///
+/// # Returns
+///
+/// * The execution provider.
+pub fn provider(acceleration: Acceleration) -> Provider {
+    match acceleration {
+        Acceleration::Auto if COREML_AVAILABLE && cfg!(target_arch = "aarch64") => {
+            Provider::OrtCoreMl
+        }
+        Acceleration::Auto | Acceleration::Cpu => Provider::OrtCpu,
+        Acceleration::CoreMl if COREML_AVAILABLE => Provider::OrtCoreMl,
+        Acceleration::CoreMl => {
+            warn!("CoreML is only available on Apple platforms. Using the CPU instead");
+            Provider::OrtCpu
+        }
+        // rust-faces can't build TensorRT sessions, see tensorrt_detector
+        Acceleration::TensorRt => Provider::OrtCpu,
+    }
+}
+
+/// Function to build a detector that runs on TensorRT. rust-faces only builds sessions for the
+/// providers it knows, so the detector is built from its model files with an environment that
+/// has the TensorRT provider, falling back to the CPU for anything TensorRT can't run.
+///
+/// # Arguments
+///
+/// * `model` - The detector model.
+/// * `precision` - Precision of the weights to load, if quantized weights are available.
+/// * `engine_cache` - Directory to keep the built TensorRT engines in, so later runs load them
+///   instead of building them again. None builds the engines on every run.
+///
+/// # Returns
+///
+/// * The detector.
+#[cfg(feature = "tensorrt")]
+pub fn tensorrt_detector(
+    model: cropping::FaceDetectorModel,
+    precision: cropping::ModelPrecision,
+    engine_cache: Option<&Path>,
+) -> Box<dyn rust_faces::FaceDetector> {
+    use ort::{execution_providers::TensorRTExecutionProviderOptions, ExecutionProvider};
+    use rust_faces::{BlazeFace, BlazeFaceParams, MtCnn, MtCnnParams};
+
+    if let Some(engine_cache) = engine_cache {
+        std::fs::create_dir_all(engine_cache)
+            .unwrap_or_else(|_| panic!("Failed to create TensorRT engine cache directory"));
+    }
+    let provider = ExecutionProvider::TensorRT(TensorRTExecutionProviderOptions {
+        engine_cache_enable: engine_cache.is_some(),
+        engine_cache_path: engine_cache
+            .map(|engine_cache| engine_cache.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        ..Default::default()
+    });
+    if !provider.is_available() {
+        warn!("TensorRT is not available in this ONNX Runtime. Using the CPU instead");
+    }
+    let environment = Environment::builder()
+        .with_name("facecrop")
+        .with_execution_providers([provider, ExecutionProvider::CPU(Default::default())])
+        .build()
+        .unwrap_or_else(|_| panic!("Failed to build TensorRT environment"))
+        .into_arc();
+
+    let model_paths = match cropping::quantized_model_path(model, precision) {
+        Some(model_path) => vec![model_path],
+        None => {
+            let model_paths = cropping::model_paths(model);
+            if model_paths.iter().any(|path| !path.exists()) {
+                // building the detector the usual way downloads its model files
+                cropping::get_face_detector(
+                    model,
+                    cropping::ModelPrecision::Fp32,
+                    rust_faces::InferParams::default(),
+                );
+            }
+            model_paths
+        }
+    };
+    let model_paths: Vec<_> = model_paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    match model {
+        cropping::FaceDetectorModel::BlazeFace640 | cropping::FaceDetectorModel::BlazeFace320 => {
+            Box::new(BlazeFace::from_file(
+                environment,
+                &model_paths[0],
+                BlazeFaceParams::default(),
+            ))
+        }
+        cropping::FaceDetectorModel::MtCnn => Box::new(
+            MtCnn::from_file(
+                environment,
+                &model_paths[0],
+                &model_paths[1],
+                &model_paths[2],
+                MtCnnParams::default(),
+            )
+            .unwrap_or_else(|_| panic!("Failed to build face detector")),
+        ),
+    }
+}
+
+#[cfg(not(feature = "tensorrt"))]
+pub fn tensorrt_detector(
+    _model: cropping::FaceDetectorModel,
+    _precision: cropping::ModelPrecision,
+    _engine_cache: Option<&Path>,
+) -> Box<dyn rust_faces::FaceDetector> {
+    panic!("facecrop was built without the tensorrt feature");
+}
+
+fn provider_name(provider: &Provider) -> String {
+    match provider {
+        Provider::OrtCpu => "CPU".to_string(),
+        Provider::OrtCuda(device) => format!("CUDA (device {})", device),
+        Provider::OrtVino(device) => format!("OpenVINO (device {})", device),
+        Provider::OrtCoreMl => "CoreML".to_string(),
+    }
+}
+
+/// SIMD instruction sets the CPU supports that image decoding and resizing can use. NEON is part
+/// of the aarch64 baseline so the resize loops are always vectorized with it there.
+fn simd_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        features.push("neon");
+    }
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if std::arch::is_x86_feature_detected!("sse4.1") {
+            features.push("sse4.1");
+        }
+        if std::arch::is_x86_feature_detected!("avx2") {
+            features.push("avx2");
+        }
+    }
+    features
+}
+
+/// Function to log the platform facecrop runs on and the acceleration it would use, without
+/// building the detector.
+///
+/// # Arguments
+///
+/// * `acceleration` - The requested acceleration.
+pub fn log_info(acceleration: Acceleration) {
+    let simd = simd_features();
+    let cargo_features: Vec<_> = [
+        ("video", cfg!(feature = "video")),
+        ("heif", cfg!(feature = "heif")),
+        ("avif", cfg!(feature = "avif")),
+        ("upscale", cfg!(feature = "upscale")),
+        ("matting", cfg!(feature = "matting")),
+        ("tensorrt", cfg!(feature = "tensorrt")),
+        ("s3", cfg!(feature = "s3")),
+        ("gcs", cfg!(feature = "gcs")),
+        ("azure", cfg!(feature = "azure")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name)
+    .collect();
+
+    info!("facecrop {}", env!("CARGO_PKG_VERSION"));
+    info!(
+        "Platform: {}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    );
+    info!(
+        "Execution provider: {}",
+        match acceleration {
+            Acceleration::TensorRt => "TensorRT".to_string(),
+            _ => provider_name(&provider(acceleration)),
+        }
+    );
+    info!(
+        "SIMD: {}",
+        match simd.is_empty() {
+            true => "none".to_string(),
+            false => simd.join(", "),
+        }
+    );
+    info!(
+        "Cargo features: {}",
+        match cargo_features.is_empty() {
+            true => "none".to_string(),
+            false => cargo_features.join(", "),
+        }
+    );
+}
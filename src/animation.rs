@@ -0,0 +1,132 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, ImageResult};
+use tracing::{debug, warn};
+
+use crate::{contact_sheet::ContactSheet, CropBudget, Pipeline};
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameSamplingParams {
+    /// Sample every nth frame, starting with the first.
+    pub every_nth: usize,
+    /// Stop decoding once this many frames have been sampled.
+    pub max_frames: Option<usize>,
+}
+
+/// Function to decode an animated GIF and pass a sampled subset of its frames to a callback.
+///
+/// # Arguments
+///
+/// * `gif_path` - Path to the GIF file.
+/// * `params` - Which frames to sample.
+/// * `on_frame` - Called with the index and image of each sampled frame in order. Returning
+///   false stops decoding.
+///
+/// # Returns
+///
+/// * The number of frames sampled, or an error if the GIF can't be decoded.
+pub fn for_each_frame<F>(
+    gif_path: &Path,
+    params: &FrameSamplingParams,
+    mut on_frame: F,
+) -> ImageResult<usize>
+where
+    F: FnMut(usize, DynamicImage) -> bool,
+{
+    let decoder = GifDecoder::new(BufReader::new(File::open(gif_path)?))?;
+
+    let mut sampled = 0;
+    for (index, frame) in decoder.into_frames().enumerate() {
+        if params
+            .max_frames
+            .is_some_and(|max_frames| sampled >= max_frames)
+        {
+            break;
+        }
+        // frames are decoded regardless as each one is composited onto the previous ones
+        let frame = frame?;
+        if index % params.every_nth != 0 {
+            continue;
+        }
+        sampled += 1;
+        if !on_frame(index, to_image(frame.into_buffer())) {
+            break;
+        }
+    }
+    Ok(sampled)
+}
+
+/// Frames are always decoded with an alpha channel, which is dropped if the frame is opaque so
+/// the crops of opaque GIFs are written like any other image.
+fn to_image(frame: image::RgbaImage) -> DynamicImage {
+    let image = DynamicImage::ImageRgba8(frame);
+    match image
+        .as_rgba8()
+        .unwrap()
+        .pixels()
+        .all(|pixel| pixel[3] == u8::MAX)
+    {
+        true => DynamicImage::ImageRgb8(image.into_rgb8()),
+        false => image,
+    }
+}
+
+/// Name of a frame of an animation, e.g. `clip-frame0012`.
+pub fn frame_name(animation_name: &str, index: usize) -> String {
+    format!("{}-frame{:04}", animation_name, index)
+}
+
+/// Function to detect and crop the faces in sampled frames of an animated GIF. Outputs are named
+/// after the index of the frame they were taken from.
+///
+/// # Arguments
+///
+/// * `pipeline` - The pipeline to run each frame through.
+/// * `gif_path` - Path to the GIF file.
+/// * `gif_name` - Name the frame names are derived from.
+/// * `gif_contact_sheet` - Contact sheet of the GIF, if any.
+/// * `budget` - Crops to skip and write at most. Decoding stops once it runs out.
+///
+/// # Returns
+///
+/// * The number of crops written.
+pub fn process<'a>(
+    pipeline: &mut Pipeline<'a>,
+    gif_path: &Path,
+    gif_name: &str,
+    mut gif_contact_sheet: Option<&mut ContactSheet<'a>>,
+    budget: &mut CropBudget,
+) -> usize {
+    if pipeline.args.pipeline.virtual_crop {
+        warn!(
+            "Virtual crops are not supported for animation {}. Skipping",
+            gif_path.display()
+        );
+        return 0;
+    }
+
+    let mut crops_written = 0;
+    // copied out as the pipeline is borrowed mutably by the callback
+    let frame_sampling = pipeline.animation_sampling;
+    let frames = for_each_frame(gif_path, &frame_sampling, |index, frame| {
+        let frame_name = frame_name(gif_name, index);
+        crops_written += pipeline.process_image(
+            frame,
+            &frame_name,
+            gif_path,
+            gif_contact_sheet.as_deref_mut(),
+            budget,
+        );
+        budget.remaining != Some(0)
+    });
+    match frames {
+        Ok(frames) => debug!("Sampled {} frames from {}", frames, gif_path.display()),
+        Err(error) => warn!(
+            "Failed to decode animation {}: {}. Skipping the rest of it",
+            gif_path.display(),
+            error
+        ),
+    }
+
+    crops_written
+}
@@ -0,0 +1,342 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use tracing::{debug, warn};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    contact_sheet::ContactSheet, decoding, provenance, skip_own_output, supported_image_extensions,
+    CropBudget, Pipeline,
+};
+
+const ARCHIVE_SUFFIXES: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+/// Extensions of files that are already compressed, which are stored in zips as they are.
+const COMPRESSED_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "avif", "jxl"];
+
+/// A file read from an archive.
+pub struct ArchiveEntry {
+    /// Path of the entry within the archive.
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_lowercase();
+    ARCHIVE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// Name of an archive without its (possibly compound) extension, e.g. `photos.tar.gz` -> `photos`.
+pub fn archive_name(path: &Path) -> String {
+    let name = path.file_name().unwrap().to_string_lossy();
+    ARCHIVE_SUFFIXES
+        .iter()
+        .find(|suffix| name.to_lowercase().ends_with(*suffix))
+        .map(|suffix| name[..name.len() - suffix.len()].to_string())
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Function to read the files of a zip or (gzipped) tar archive with the given extensions into
+/// memory and pass them to a callback, without extracting the archive.
+///
+/// # Arguments
+///
+/// * `archive_path` - Path to the archive.
+/// * `extensions` - Extensions of the entries to read, e.g. `["jpg", "png"]`.
+/// * `on_entry` - Called with each matching entry in archive order. Returning false stops
+///   reading.
+///
+/// # Returns
+///
+/// * The number of entries passed to the callback, or an error if the archive can't be read.
+pub fn for_each_entry<F>(
+    archive_path: &Path,
+    extensions: &[&str],
+    mut on_entry: F,
+) -> Result<usize, String>
+where
+    F: FnMut(ArchiveEntry) -> bool,
+{
+    let file = File::open(archive_path).map_err(|error| error.to_string())?;
+    let name = archive_path.to_string_lossy().to_lowercase();
+    let matches = |path: &Path| {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extensions.contains(&extension.to_lowercase().as_str()))
+    };
+
+    let mut read = 0;
+    if name.ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(file).map_err(|error| error.to_string())?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i).map_err(|error| error.to_string())?;
+            let path = match entry.enclosed_name() {
+                Some(path) if entry.is_file() && matches(path) => path.to_path_buf(),
+                _ => continue,
+            };
+            let mut bytes = Vec::with_capacity(entry.size() as usize);
+            entry
+                .read_to_end(&mut bytes)
+                .map_err(|error| error.to_string())?;
+            read += 1;
+            if !on_entry(ArchiveEntry { path, bytes }) {
+                break;
+            }
+        }
+    } else {
+        let reader: Box<dyn Read> = match name.ends_with(".tar") {
+            true => Box::new(file),
+            false => Box::new(GzDecoder::new(file)),
+        };
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().map_err(|error| error.to_string())? {
+            let mut entry = entry.map_err(|error| error.to_string())?;
+            let path = entry
+                .path()
+                .map_err(|error| error.to_string())?
+                .to_path_buf();
+            if !entry.header().entry_type().is_file() || !matches(&path) {
+                continue;
+            }
+            let mut bytes = Vec::new();
+            entry
+                .read_to_end(&mut bytes)
+                .map_err(|error| error.to_string())?;
+            read += 1;
+            if !on_entry(ArchiveEntry { path, bytes }) {
+                break;
+            }
+        }
+    }
+
+    Ok(read)
+}
+
+/// A zip archive the files of a directory are added to one at a time, e.g. the outputs of a run
+/// as they are written, so they are never held twice on disk. An archive dropped before it is
+/// finished is still completed with the files added so far.
+pub struct ZipOutput {
+    archive: ZipWriter<File>,
+    dir: PathBuf,
+    /// Names of the files in the archive.
+    names: HashSet<String>,
+}
+
+impl ZipOutput {
+    /// Function to start the archive.
+    ///
+    /// # Arguments
+    ///
+    /// * `zip_path` - Path to write the archive to.
+    /// * `dir` - The directory, whose nested directories are kept within the archive.
+    ///
+    /// # Returns
+    ///
+    /// * The empty archive, or an error if it can't be created.
+    pub fn create(zip_path: &Path, dir: &Path) -> Result<Self, String> {
+        Ok(Self {
+            archive: ZipWriter::new(File::create(zip_path).map_err(|error| error.to_string())?),
+            dir: dir.to_path_buf(),
+            names: HashSet::new(),
+        })
+    }
+
+    /// Function to check whether a file of the directory was added to the archive.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.names.contains(&self.name(path))
+    }
+
+    /// Function to add a file of the directory to the archive.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file below the directory.
+    ///
+    /// # Returns
+    ///
+    /// * Whether the file was added, false if a file of the same name already was, or an error if
+    ///   it can't be written.
+    pub fn add(&mut self, path: &Path) -> Result<bool, String> {
+        let name = self.name(path);
+        if self.names.contains(&name) {
+            return Ok(false);
+        }
+        self.archive
+            .start_file(name.as_str(), entry_options(&name))
+            .map_err(|error| error.to_string())?;
+        let mut file = File::open(path).map_err(|error| error.to_string())?;
+        std::io::copy(&mut file, &mut self.archive).map_err(|error| error.to_string())?;
+        self.names.insert(name);
+        Ok(true)
+    }
+
+    /// Function to add the files of the directory that weren't added yet and complete the
+    /// archive.
+    ///
+    /// # Returns
+    ///
+    /// * The number of files in the archive, or an error if it can't be written.
+    pub fn finish(mut self) -> Result<usize, String> {
+        let mut paths = Vec::new();
+        list_files(&self.dir, &mut paths).map_err(|error| error.to_string())?;
+        paths.sort();
+        for path in &paths {
+            self.add(path)?;
+        }
+        self.archive
+            .finish()
+            .and_then(|mut file| Ok(file.flush()?))
+            .map_err(|error| error.to_string())?;
+        Ok(self.names.len())
+    }
+
+    /// Name of a file of the directory within the archive.
+    fn name(&self, path: &Path) -> String {
+        path.strip_prefix(&self.dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+/// Function to write files held in memory into a zip archive.
+///
+/// # Arguments
+///
+/// * `entries` - Path of each file within the archive and its contents.
+///
+/// # Returns
+///
+/// * The archive.
+pub fn zip_entries(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, bytes) in entries {
+        write_entry(&mut archive, name, bytes)
+            .unwrap_or_else(|_| panic!("Failed to write zip archive"));
+    }
+    archive
+        .finish()
+        .unwrap_or_else(|_| panic!("Failed to write zip archive"))
+        .into_inner()
+}
+
+fn write_entry<W: Write + Seek>(
+    archive: &mut ZipWriter<W>,
+    name: &str,
+    bytes: &[u8],
+) -> zip::result::ZipResult<()> {
+    archive.start_file(name, entry_options(name))?;
+    archive.write_all(bytes)?;
+    Ok(())
+}
+
+/// Options of a zip entry, deflating it unless it is already compressed.
+fn entry_options(name: &str) -> FileOptions {
+    let compressed = Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            COMPRESSED_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        });
+    FileOptions::default().compression_method(match compressed {
+        true => CompressionMethod::Stored,
+        false => CompressionMethod::Deflated,
+    })
+}
+
+/// Function to collect the paths of the files below a directory.
+fn list_files(dir: &Path, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.is_dir() {
+            true => list_files(&path, paths)?,
+            false => paths.push(path),
+        }
+    }
+    Ok(())
+}
+
+/// Function to detect and crop the faces in the images of a zip or tar archive, decoding them in
+/// memory. Outputs are named after the archive and the path of the image within it.
+///
+/// # Arguments
+///
+/// * `pipeline` - The pipeline to run each image through.
+/// * `archive_path` - Path to the archive.
+/// * `archive_name` - Name the image names are prefixed with.
+/// * `archive_contact_sheet` - Contact sheet of the archive, if any.
+/// * `budget` - Crops to skip and write at most. Reading stops once it runs out.
+///
+/// # Returns
+///
+/// * The number of crops written.
+pub fn process<'a>(
+    pipeline: &mut Pipeline<'a>,
+    archive_path: &Path,
+    archive_name: &str,
+    mut archive_contact_sheet: Option<&mut ContactSheet<'a>>,
+    budget: &mut CropBudget,
+) -> usize {
+    if pipeline.args.pipeline.virtual_crop {
+        warn!(
+            "Virtual crops are not supported for archive {}. Skipping",
+            archive_path.display()
+        );
+        return 0;
+    }
+
+    let own_outputs = pipeline.args.pipeline.own_outputs;
+    let mut crops_written = 0;
+    let extensions = supported_image_extensions();
+    let entries = for_each_entry(archive_path, &extensions, |entry| {
+        let entry_path = archive_path.join(&entry.path);
+        if skip_own_output(&entry_path, own_outputs, || {
+            provenance::has_marker(&entry.bytes)
+        }) {
+            return true;
+        }
+        let input_image = match decoding::decode(&entry.bytes) {
+            Ok(input_image) => input_image,
+            Err(error) => {
+                warn!(
+                    "Failed to decode {}: {}. Skipping",
+                    entry_path.display(),
+                    error
+                );
+                return true;
+            }
+        };
+
+        // entries in different folders can share a name so the folders are kept in the name
+        let entry_name = entry
+            .path
+            .with_extension("")
+            .components()
+            .filter(|component| matches!(component, std::path::Component::Normal(_)))
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("_");
+        crops_written += pipeline.process_image(
+            input_image,
+            &format!("{}-{}", archive_name, entry_name),
+            &entry_path,
+            archive_contact_sheet.as_deref_mut(),
+            budget,
+        );
+        budget.remaining != Some(0)
+    });
+    match entries {
+        Ok(entries) => debug!("Read {} images from {}", entries, archive_path.display()),
+        Err(error) => warn!(
+            "Failed to read archive {}: {}. Skipping the rest of it",
+            archive_path.display(),
+            error
+        ),
+    }
+
+    crops_written
+}
@@ -0,0 +1,325 @@
+use ndarray::{Array4, CowArray};
+use ort::{tensor::OrtOwnedTensor, OrtResult, Session, Value};
+use rust_faces::{Face, Rect};
+use serde::{Deserialize, Serialize};
+
+/// Side of the square face region the gender and age model takes as input.
+const GENDER_AGE_INPUT_SIZE: u32 = 96;
+/// Factor the side of a face box is enlarged by to get the region the gender and age model looks
+/// at, as in its training data.
+const GENDER_AGE_REGION_SCALE: f32 = 1.5;
+/// Side of the square face region the emotion model takes as input.
+const EMOTION_INPUT_SIZE: u32 = 64;
+/// Factor the side of a face box is enlarged by to get the region the emotion model looks at. Its
+/// training data is cropped tightly to the face.
+const EMOTION_REGION_SCALE: f32 = 1.1;
+/// Side of the square face region the occlusion model takes as input.
+const OCCLUSION_INPUT_SIZE: u32 = 112;
+/// Factor the side of a face box is enlarged by to get the region the occlusion model looks at,
+/// so hands and hair around the face are in it.
+const OCCLUSION_REGION_SCALE: f32 = 1.2;
+/// The expressions the emotion model tells apart, in the order of its scores.
+const EMOTIONS: [Emotion; 8] = [
+    Emotion::Neutral,
+    Emotion::Happy,
+    Emotion::Surprised,
+    Emotion::Sad,
+    Emotion::Angry,
+    Emotion::Disgusted,
+    Emotion::Fearful,
+    Emotion::Contemptuous,
+];
+
+/// Attributes of a face estimated by the attribute models. Attributes that weren't estimated are
+/// None.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FaceAttributes {
+    /// Apparent age in years.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gender: Option<Gender>,
+    /// Facial expression.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emotion: Option<Emotion>,
+    /// Probability that the face is covered by a mask, hands, hair or other objects, from 0.0 to
+    /// 1.0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occluded: Option<f32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Gender {
+    Female,
+    Male,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Emotion {
+    Neutral,
+    Happy,
+    Surprised,
+    Sad,
+    Angry,
+    Disgusted,
+    Fearful,
+    Contemptuous,
+}
+
+impl Emotion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Emotion::Neutral => "neutral",
+            Emotion::Happy => "happy",
+            Emotion::Surprised => "surprised",
+            Emotion::Sad => "sad",
+            Emotion::Angry => "angry",
+            Emotion::Disgusted => "disgusted",
+            Emotion::Fearful => "fearful",
+            Emotion::Contemptuous => "contemptuous",
+        }
+    }
+}
+
+/// A model estimating some of the attributes of a face, run on each detected face.
+pub trait AttributeModel {
+    /// Function to estimate the attributes of a face the model knows about.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image the face was detected in.
+    /// * `face` - The face.
+    /// * `attributes` - The attributes of the face, to set the estimated attributes in.
+    fn annotate(
+        &self,
+        image: &image::RgbImage,
+        face: &Face,
+        attributes: &mut FaceAttributes,
+    ) -> OrtResult<()>;
+}
+
+/// The attribute models run after detection, each adding the attributes it estimates.
+pub struct AttributePipeline {
+    models: Vec<Box<dyn AttributeModel>>,
+}
+
+impl AttributePipeline {
+    pub fn new(models: Vec<Box<dyn AttributeModel>>) -> Self {
+        Self { models }
+    }
+
+    /// Function to estimate the attributes of a face with every model.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image the face was detected in.
+    /// * `face` - The face.
+    ///
+    /// # Returns
+    ///
+    /// * The attributes of the face.
+    pub fn annotate(&self, image: &image::RgbImage, face: &Face) -> OrtResult<FaceAttributes> {
+        let mut attributes = FaceAttributes::default();
+        for model in &self.models {
+            model.annotate(image, face, &mut attributes)?;
+        }
+        Ok(attributes)
+    }
+}
+
+/// InsightFace-style gender and age model, mapping a face to its female and male scores and its
+/// age divided by 100.
+pub struct GenderAgeModel {
+    session: Session,
+    /// True to set the age of the faces.
+    age: bool,
+    /// True to set the gender of the faces.
+    gender: bool,
+}
+
+impl GenderAgeModel {
+    /// Wraps a gender and age model.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Session of the ONNX model, taking a 1x3x96x96 RGB input of 0.0 - 255.0.
+    /// * `age` - True to set the age of the faces.
+    /// * `gender` - True to set the gender of the faces.
+    pub fn new(session: Session, age: bool, gender: bool) -> Self {
+        Self {
+            session,
+            age,
+            gender,
+        }
+    }
+}
+
+impl AttributeModel for GenderAgeModel {
+    fn annotate(
+        &self,
+        image: &image::RgbImage,
+        face: &Face,
+        attributes: &mut FaceAttributes,
+    ) -> OrtResult<()> {
+        let region = face_region(
+            image,
+            &face.rect,
+            GENDER_AGE_REGION_SCALE,
+            GENDER_AGE_INPUT_SIZE,
+        );
+        let size = GENDER_AGE_INPUT_SIZE as usize;
+        let input = Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| {
+            region.get_pixel(x as u32, y as u32)[c] as f32
+        });
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let scores: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let scores: Vec<f32> = scores.view().iter().copied().collect();
+        if let [female, male, age, ..] = scores[..] {
+            if self.gender {
+                attributes.gender = Some(match male > female {
+                    true => Gender::Male,
+                    false => Gender::Female,
+                });
+            }
+            if self.age {
+                attributes.age = Some(age * 100.0);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// FER+-style emotion model, mapping a grayscale face to a score for each of `EMOTIONS`.
+pub struct EmotionModel {
+    session: Session,
+}
+
+impl EmotionModel {
+    /// Wraps an emotion model.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Session of the ONNX model, taking a 1x1x64x64 grayscale input of 0.0 -
+    ///   255.0.
+    pub fn new(session: Session) -> Self {
+        Self { session }
+    }
+}
+
+impl AttributeModel for EmotionModel {
+    fn annotate(
+        &self,
+        image: &image::RgbImage,
+        face: &Face,
+        attributes: &mut FaceAttributes,
+    ) -> OrtResult<()> {
+        let region = image::imageops::grayscale(&face_region(
+            image,
+            &face.rect,
+            EMOTION_REGION_SCALE,
+            EMOTION_INPUT_SIZE,
+        ));
+        let size = EMOTION_INPUT_SIZE as usize;
+        let input = Array4::from_shape_fn((1, 1, size, size), |(_, _, y, x)| {
+            region.get_pixel(x as u32, y as u32)[0] as f32
+        });
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let scores: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        attributes.emotion = scores
+            .view()
+            .iter()
+            .zip(EMOTIONS)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, emotion)| emotion);
+        Ok(())
+    }
+}
+
+/// Binary occlusion classifier, mapping a face to the scores of it being clear and occluded.
+pub struct OcclusionModel {
+    session: Session,
+}
+
+impl OcclusionModel {
+    /// Wraps an occlusion model.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Session of the ONNX model, taking a 1x3x112x112 RGB input scaled to 0.0 -
+    ///   1.0.
+    pub fn new(session: Session) -> Self {
+        Self { session }
+    }
+}
+
+impl AttributeModel for OcclusionModel {
+    fn annotate(
+        &self,
+        image: &image::RgbImage,
+        face: &Face,
+        attributes: &mut FaceAttributes,
+    ) -> OrtResult<()> {
+        let region = face_region(
+            image,
+            &face.rect,
+            OCCLUSION_REGION_SCALE,
+            OCCLUSION_INPUT_SIZE,
+        );
+        let size = OCCLUSION_INPUT_SIZE as usize;
+        let input = Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| {
+            region.get_pixel(x as u32, y as u32)[c] as f32 / 255.0
+        });
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let scores: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let scores: Vec<f32> = scores.view().iter().copied().collect();
+        if let [clear, occluded, ..] = scores[..] {
+            // softmax of the two scores
+            attributes.occluded = Some(1.0 / (1.0 + (clear - occluded).exp()));
+        }
+        Ok(())
+    }
+}
+
+/// Function to sample the square region around a face an attribute model takes as input.
+///
+/// # Arguments
+///
+/// * `image` - The image the face was detected in.
+/// * `face` - The bounding box of the face.
+/// * `region_scale` - Factor the long side of the box is enlarged by to get the side of the
+///   region.
+/// * `size` - Side of the model input.
+///
+/// # Returns
+///
+/// * The region, `size` pixels square and black outside the image.
+fn face_region(
+    image: &image::RgbImage,
+    face: &Rect,
+    region_scale: f32,
+    size: u32,
+) -> image::RgbImage {
+    let side = face.width.max(face.height) * region_scale;
+    let x = face.x + (face.width - side) / 2.0;
+    let y = face.y + (face.height - side) / 2.0;
+    let scale = side / size as f32;
+    image::RgbImage::from_fn(size, size, |column, row| {
+        image::imageops::interpolate_bilinear(
+            image,
+            x + (column as f32 + 0.5) * scale,
+            y + (row as f32 + 0.5) * scale,
+        )
+        .unwrap_or(image::Rgb([0, 0, 0]))
+    })
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use rust_faces::{Face, Rect};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{cropping, manifest::FaceRecord};
+
+/// Confidence of imported faces without a score, which are taken to be labeled by hand.
+const LABELED_CONFIDENCE: f32 = 1.0;
+/// Number of landmarks detectors place, which imported keypoints are read as when they match.
+const LANDMARK_COUNT: usize = 5;
+
+/// Faces supplied for an image instead of detected, e.g. by another detector or annotation tool.
+#[derive(Deserialize)]
+struct ImageBoxes {
+    path: PathBuf,
+    faces: Vec<FaceRecord>,
+}
+
+#[derive(Deserialize)]
+struct CocoDataset {
+    images: Vec<CocoImage>,
+    #[serde(default)]
+    annotations: Vec<CocoAnnotation>,
+}
+
+#[derive(Deserialize)]
+struct CocoImage {
+    id: u64,
+    file_name: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct CocoAnnotation {
+    image_id: u64,
+    /// `[x, y, width, height]` of the box.
+    bbox: [f32; 4],
+    #[serde(default)]
+    score: Option<f32>,
+    #[serde(default)]
+    keypoints: Option<Vec<f32>>,
+}
+
+/// Function to load precomputed face boxes. The output of `facecrop detect --format json`, a
+/// JSON object per line, is accepted as well as a JSON array of the same objects.
+///
+/// # Arguments
+///
+/// * `boxes_path` - Path to the boxes file.
+///
+/// # Returns
+///
+/// * The faces of every image in the file, keyed by canonical path so the inputs of a run can
+///   be looked up however their paths were written. The faces of an image are ordered left to
+///   right and top to bottom like detected faces.
+pub fn load(boxes_path: &Path) -> HashMap<PathBuf, Vec<Face>> {
+    let contents =
+        std::fs::read_to_string(boxes_path).unwrap_or_else(|_| panic!("Failed to read boxes file"));
+    let images: Vec<ImageBoxes> = match contents.trim_start().starts_with('[') {
+        true => serde_json::from_str(&contents)
+            .unwrap_or_else(|error| panic!("Failed to parse boxes file: {}", error)),
+        false => contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).unwrap_or_else(|error| {
+                    panic!("Failed to parse line {} of boxes file: {}", i + 1, error)
+                })
+            })
+            .collect(),
+    };
+
+    let mut boxes: HashMap<PathBuf, Vec<Face>> = HashMap::new();
+    for image in images {
+        let path = std::fs::canonicalize(&image.path).unwrap_or_else(|_| {
+            warn!(
+                "Image {} in boxes file does not exist",
+                image.path.display()
+            );
+            image.path.clone()
+        });
+        boxes
+            .entry(path)
+            .or_default()
+            .extend(image.faces.into_iter().map(to_face));
+    }
+    for faces in boxes.values_mut() {
+        cropping::sort_faces(faces);
+    }
+    boxes
+}
+
+/// Function to load the faces of a COCO dataset, e.g. one exported with `--export-annotations`
+/// and corrected in a labeling tool. Every category is taken to be a face.
+///
+/// # Arguments
+///
+/// * `annotations_path` - Path to the COCO JSON file.
+/// * `images_dir` - Directory the file names of the images are relative to.
+///
+/// # Returns
+///
+/// * The faces of every image in the file, keyed by canonical path like the boxes of `load`.
+///   Images without annotations have no faces.
+pub fn load_coco(annotations_path: &Path, images_dir: &Path) -> HashMap<PathBuf, Vec<Face>> {
+    let contents = std::fs::read_to_string(annotations_path)
+        .unwrap_or_else(|_| panic!("Failed to read annotations file"));
+    let dataset: CocoDataset = serde_json::from_str(&contents)
+        .unwrap_or_else(|error| panic!("Failed to parse annotations file: {}", error));
+
+    let mut image_paths: HashMap<u64, PathBuf> = HashMap::new();
+    let mut boxes: HashMap<PathBuf, Vec<Face>> = HashMap::new();
+    for image in dataset.images {
+        let image_path = images_dir.join(&image.file_name);
+        let path = std::fs::canonicalize(&image_path).unwrap_or_else(|_| {
+            warn!(
+                "Image {} in annotations file does not exist",
+                image_path.display()
+            );
+            image_path.clone()
+        });
+        boxes.entry(path.clone()).or_default();
+        image_paths.insert(image.id, path);
+    }
+    for annotation in dataset.annotations {
+        let Some(path) = image_paths.get(&annotation.image_id) else {
+            warn!(
+                "Annotation of unknown image {} in annotations file. Skipping",
+                annotation.image_id
+            );
+            continue;
+        };
+        let [x, y, width, height] = annotation.bbox;
+        // keypoints become landmarks when there are as many as detectors place and all are labeled
+        let landmarks = annotation
+            .keypoints
+            .filter(|keypoints| {
+                keypoints.len() == LANDMARK_COUNT * 3
+                    && keypoints.chunks(3).all(|keypoint| keypoint[2] > 0.0)
+            })
+            .map(|keypoints| {
+                keypoints
+                    .chunks(3)
+                    .map(|keypoint| (keypoint[0], keypoint[1]))
+                    .collect()
+            });
+        boxes.get_mut(path).unwrap().push(Face {
+            rect: Rect::at(x, y).with_size(width, height),
+            confidence: annotation.score.unwrap_or(LABELED_CONFIDENCE),
+            landmarks,
+        });
+    }
+    for faces in boxes.values_mut() {
+        cropping::sort_faces(faces);
+    }
+    boxes
+}
+
+/// Function to merge detected faces with imported ones, preferring the imported faces where they
+/// overlap as they are typically labeled by hand.
+///
+/// # Arguments
+///
+/// * `imported` - The imported faces of an image.
+/// * `detected` - The detected faces of the image.
+/// * `max_overlap` - Largest overlap, as intersection over the smaller area, a detected face
+///   may have with an imported one to be kept.
+///
+/// # Returns
+///
+/// * The imported faces and the detected faces none of them overlap, ordered left to right and
+///   top to bottom like detected faces.
+pub fn merge_faces(imported: &[Face], detected: Vec<Face>, max_overlap: f32) -> Vec<Face> {
+    let mut faces = imported.to_vec();
+    faces.extend(detected.into_iter().filter(|face| {
+        imported
+            .iter()
+            .all(|imported| imported.rect.iou_min(&face.rect) <= max_overlap)
+    }));
+    cropping::sort_faces(&mut faces);
+    faces
+}
+
+/// Function to look up the supplied faces of an image.
+///
+/// # Arguments
+///
+/// * `boxes` - The loaded boxes.
+/// * `image_path` - Path of the image.
+///
+/// # Returns
+///
+/// * The faces of the image, or None if the boxes file doesn't list it.
+pub fn faces_of<'a>(
+    boxes: &'a HashMap<PathBuf, Vec<Face>>,
+    image_path: &Path,
+) -> Option<&'a [Face]> {
+    let path = std::fs::canonicalize(image_path).unwrap_or_else(|_| image_path.to_path_buf());
+    boxes.get(&path).map(Vec::as_slice)
+}
+
+fn to_face(face: FaceRecord) -> Face {
+    Face {
+        rect: Rect::at(face.rect.x, face.rect.y).with_size(face.rect.width, face.rect.height),
+        confidence: face.confidence,
+        landmarks: face.landmarks,
+    }
+}
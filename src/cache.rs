@@ -0,0 +1,104 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::output;
+
+const CACHE_FILE_NAME: &str = ".facecrop-cache.json";
+
+/// What an input was last processed from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Content hash of the input.
+    pub source_hash: String,
+    /// Hash of the parameters that affect the outputs.
+    pub params_hash: String,
+}
+
+/// The inputs processed into an output directory, so later runs can skip the ones that are
+/// unchanged and were processed with the same parameters.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    /// Entries keyed by the path of the input relative to the input root, like the run state.
+    entries: BTreeMap<PathBuf, CacheEntry>,
+    /// Hash of the parameters of this run.
+    #[serde(skip)]
+    params_hash: String,
+}
+
+impl Cache {
+    /// Whether an input was last processed from the same content with the same parameters.
+    pub fn is_current(&self, input: &Path, source_hash: &str) -> bool {
+        self.entries.get(input).is_some_and(|entry| {
+            entry.source_hash == source_hash && entry.params_hash == self.params_hash
+        })
+    }
+
+    /// Whether an input was processed before.
+    pub fn contains(&self, input: &Path) -> bool {
+        self.entries.contains_key(input)
+    }
+
+    /// Records that an input was processed with the parameters of this run.
+    pub fn insert(&mut self, input: PathBuf, source_hash: String) {
+        let params_hash = self.params_hash.clone();
+        self.entries.insert(
+            input,
+            CacheEntry {
+                source_hash,
+                params_hash,
+            },
+        );
+    }
+}
+
+/// Function to hash the parameters of a run.
+///
+/// # Arguments
+///
+/// * `params` - Description of the parameters that affect the outputs.
+///
+/// # Returns
+///
+/// * The hash as `sha256:<hex>`.
+pub fn params_hash(params: &str) -> String {
+    let digest = Sha256::digest(params.as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256:{}", hex)
+}
+
+pub fn cache_path(output_dir: &Path) -> PathBuf {
+    output_dir.join(CACHE_FILE_NAME)
+}
+
+/// Function to load the cache of an output directory.
+///
+/// # Arguments
+///
+/// * `output_dir` - The output directory.
+/// * `params_hash` - Hash of the parameters of this run.
+///
+/// # Returns
+///
+/// * The cache, empty if the output directory doesn't have one yet.
+pub fn load(output_dir: &Path, params_hash: String) -> Cache {
+    let mut cache = match std::fs::read_to_string(cache_path(output_dir)) {
+        Ok(contents) => {
+            serde_json::from_str(&contents).unwrap_or_else(|_| panic!("Failed to parse cache file"))
+        }
+        Err(_) => Cache::default(),
+    };
+    cache.params_hash = params_hash;
+    cache
+}
+
+pub fn save(output_dir: &Path, cache: &Cache) {
+    let contents =
+        serde_json::to_string_pretty(cache).unwrap_or_else(|_| panic!("Failed to serialize cache"));
+    output::write_atomically(&cache_path(output_dir), contents)
+        .unwrap_or_else(|_| panic!("Failed to write cache file"));
+}
@@ -0,0 +1,202 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    slice,
+};
+
+use rust_faces::{Face, FaceDetector, InferParams, Rect};
+
+use crate::cropping::{self, AbsoluteCrop, CropParams, CropParamsKind, RelativeCrop};
+
+/// Opaque handle to a loaded face detector.
+pub struct FacecropDetector {
+    detector: Box<dyn FaceDetector>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FacecropRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FacecropFace {
+    pub rect: FacecropRect,
+    pub confidence: f32,
+}
+
+#[repr(C)]
+pub struct FacecropCropParams {
+    /// Non-zero to crop `width` x `height` pixels around the face, zero to crop relative to the
+    /// face size with `aspect_ratio` and `proportion_of_face`.
+    pub absolute: i32,
+    pub height: u32,
+    pub width: u32,
+    pub aspect_ratio: f32,
+    pub proportion_of_face: f32,
+    pub top_padding: f32,
+}
+
+impl From<Rect> for FacecropRect {
+    fn from(rect: Rect) -> Self {
+        Self {
+            x: rect.x,
+            y: rect.y,
+            width: rect.width,
+            height: rect.height,
+        }
+    }
+}
+
+impl From<FacecropRect> for Rect {
+    fn from(rect: FacecropRect) -> Self {
+        Rect::at(rect.x, rect.y).with_size(rect.width, rect.height)
+    }
+}
+
+/// Function to load a face detector.
+///
+/// # Arguments
+///
+/// * `model` - 0 for BlazeFace 640, 1 for BlazeFace 320 and 2 for MTCNN.
+///
+/// # Returns
+///
+/// * The detector, or null if the model is unknown or can't be loaded. Free it with
+///   `facecrop_detector_free`.
+#[no_mangle]
+pub extern "C" fn facecrop_detector_new(model: u32) -> *mut FacecropDetector {
+    let model = match model {
+        0 => cropping::FaceDetectorModel::BlazeFace640,
+        1 => cropping::FaceDetectorModel::BlazeFace320,
+        2 => cropping::FaceDetectorModel::MtCnn,
+        _ => return std::ptr::null_mut(),
+    };
+    match catch_unwind(|| cropping::build_face_detector(model, InferParams::default())) {
+        Ok(Ok(detector)) => Box::into_raw(Box::new(FacecropDetector { detector })),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Function to free a detector returned by `facecrop_detector_new`.
+///
+/// # Safety
+///
+/// `detector` must be null or a detector that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn facecrop_detector_free(detector: *mut FacecropDetector) {
+    if !detector.is_null() {
+        drop(Box::from_raw(detector));
+    }
+}
+
+/// Function to detect the faces in an image.
+///
+/// # Arguments
+///
+/// * `detector` - The detector to use.
+/// * `rgb` - The pixels of the image as interleaved 8-bit RGB, row by row without padding.
+/// * `width` - The width of the image.
+/// * `height` - The height of the image.
+/// * `faces` - Filled with up to `max_faces` of the detected faces.
+/// * `max_faces` - The capacity of `faces`.
+///
+/// # Returns
+///
+/// * The number of faces detected, which may be more than `max_faces`, or -1 on error.
+///
+/// # Safety
+///
+/// `rgb` must point to `width * height * 3` bytes and `faces` to `max_faces` faces.
+#[no_mangle]
+pub unsafe extern "C" fn facecrop_detect(
+    detector: *const FacecropDetector,
+    rgb: *const u8,
+    width: u32,
+    height: u32,
+    faces: *mut FacecropFace,
+    max_faces: usize,
+) -> i64 {
+    if detector.is_null() || rgb.is_null() || (faces.is_null() && max_faces > 0) {
+        return -1;
+    }
+    let pixels = slice::from_raw_parts(rgb, width as usize * height as usize * 3).to_vec();
+    let Some(image) = image::RgbImage::from_raw(width, height, pixels) else {
+        return -1;
+    };
+    let detector = &*(*detector).detector;
+    let detected = match catch_unwind(AssertUnwindSafe(|| {
+        cropping::detect_faces_in_image(&image, detector)
+    })) {
+        Ok(detected) => detected,
+        Err(_) => return -1,
+    };
+    for (i, face) in detected.iter().take(max_faces).enumerate() {
+        *faces.add(i) = FacecropFace {
+            rect: face.rect.into(),
+            confidence: face.confidence,
+        };
+    }
+    detected.len() as i64
+}
+
+/// Function to calculate the region of an image to crop for a face, as facecrop would.
+///
+/// # Arguments
+///
+/// * `face` - The bounding box of the face.
+/// * `image_width` - The width of the image.
+/// * `image_height` - The height of the image.
+/// * `params` - How to crop the face.
+///
+/// # Returns
+///
+/// * The crop, clamped to the image. Empty if `params` is null.
+///
+/// # Safety
+///
+/// `params` must be null or point to valid crop parameters.
+#[no_mangle]
+pub unsafe extern "C" fn facecrop_crop(
+    face: FacecropRect,
+    image_width: u32,
+    image_height: u32,
+    params: *const FacecropCropParams,
+) -> FacecropRect {
+    let Some(params) = params.as_ref() else {
+        return Rect::at(0.0, 0.0).with_size(0.0, 0.0).into();
+    };
+    let crop_params = CropParams {
+        top_padding: params.top_padding,
+        bottom_padding: 0.0,
+        left_padding: 0.0,
+        right_padding: 0.0,
+        face_anchor: None,
+        compose: cropping::Compose::Centered,
+        dims_multiple_of: None,
+        kind: match params.absolute {
+            0 => CropParamsKind::Relative(RelativeCrop {
+                aspect_ratio: params.aspect_ratio,
+                proportion_of_face: params.proportion_of_face,
+            }),
+            _ => CropParamsKind::Absolute(AbsoluteCrop {
+                height: params.height,
+                width: params.width,
+            }),
+        },
+    };
+    let face = Face {
+        rect: face.into(),
+        confidence: 1.0,
+        landmarks: None,
+    };
+    cropping::calculate_face_crop(
+        &face,
+        &Rect::at(0.0, 0.0).with_size(image_width as f32, image_height as f32),
+        &crop_params,
+    )
+    .into()
+}
@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+/// Placeholders a caption template can use.
+pub const PLACEHOLDERS: [&str; 7] = [
+    "token",
+    "source",
+    "face",
+    "size_bucket",
+    "age",
+    "gender",
+    "emotion",
+];
+
+/// What the placeholders of a caption template are filled in with. Attributes that weren't
+/// estimated are None and leave their placeholder empty.
+pub struct CaptionValues<'a> {
+    pub token: Option<&'a str>,
+    /// File stem of the input the crop was taken from.
+    pub source: &'a str,
+    pub face: usize,
+    pub size_bucket: &'a str,
+    pub age: Option<f32>,
+    pub gender: Option<&'a str>,
+    pub emotion: Option<&'a str>,
+}
+
+/// Function to check that a caption template only uses known placeholders.
+///
+/// # Arguments
+///
+/// * `template` - The caption template, e.g. `a photo of {token} person`.
+///
+/// # Returns
+///
+/// * The first unknown placeholder, if there is one.
+pub fn unknown_placeholder(template: &str) -> Option<String> {
+    placeholders(template)
+        .find(|placeholder| !PLACEHOLDERS.contains(placeholder))
+        .map(str::to_string)
+}
+
+/// Function to fill in the placeholders of a caption template.
+///
+/// # Arguments
+///
+/// * `template` - The caption template.
+/// * `values` - What the placeholders are filled in with.
+///
+/// # Returns
+///
+/// * The caption, with the whitespace left by empty placeholders collapsed.
+pub fn render(template: &str, values: &CaptionValues) -> String {
+    let mut caption = template.to_string();
+    for placeholder in PLACEHOLDERS {
+        let value = match placeholder {
+            "token" => values.token.unwrap_or_default().to_string(),
+            "source" => values.source.to_string(),
+            "face" => values.face.to_string(),
+            "size_bucket" => values.size_bucket.to_string(),
+            "age" => values
+                .age
+                .map(|age| format!("{:.0}", age))
+                .unwrap_or_default(),
+            "gender" => values.gender.unwrap_or_default().to_string(),
+            "emotion" => values.emotion.unwrap_or_default().to_string(),
+            _ => unreachable!(),
+        };
+        caption = caption.replace(&format!("{{{}}}", placeholder), &value);
+    }
+    caption.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Function to get the path of the caption of a crop, e.g. `0-0.998.txt` for `0-0.998.jpg`.
+pub fn caption_path(crop_path: &Path) -> PathBuf {
+    crop_path.with_extension("txt")
+}
+
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}').map(|(placeholder, _)| placeholder))
+}
@@ -0,0 +1,203 @@
+use std::path::Path;
+
+use image::imageops::FilterType;
+use ndarray::{Array4, CowArray};
+use ort::{tensor::OrtOwnedTensor, Environment, OrtResult, Session, Value};
+use rust_faces::{Face, Rect};
+
+use crate::{
+    acceleration::{self, SessionOptions},
+    sweep,
+};
+
+/// Side of the square image the class model takes as input.
+const CLASS_INPUT_SIZE: u32 = 640;
+/// Lowest score of the boxes that are kept, as models exported with NMS usually keep a long tail
+/// of low scoring ones.
+const CLASS_SCORE_THRESHOLD: f32 = 0.5;
+/// Values in each box the class model returns: the corners, the score and the class.
+const BOX_VALUES: usize = 6;
+
+/// A box found by a class model, with the index of its class.
+#[derive(Clone, Debug)]
+pub struct ClassFace {
+    pub face: Face,
+    pub class: usize,
+}
+
+/// Custom detector of several classes of boxes in one run, e.g. faces, heads and people, such as
+/// a YOLO model exported with NMS.
+pub struct ClassModel {
+    session: Session,
+}
+
+impl ClassModel {
+    /// Loads a class model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_path` - Path to the ONNX model, taking a 1x3x640x640 RGB input in 0..1 and
+    ///   returning the boxes it found as rows of x1, y1, x2, y2, score and class index, in input
+    ///   pixels.
+    /// * `session_options` - Threading and graph optimization of the session.
+    pub fn from_file(model_path: &Path, session_options: SessionOptions) -> OrtResult<Self> {
+        let environment = Environment::builder()
+            .with_name("facecrop")
+            .build()?
+            .into_arc();
+        let session = acceleration::session_builder(&environment, session_options)?
+            .with_model_from_file(model_path)?;
+        Ok(Self { session })
+    }
+
+    /// Function to detect the boxes of every class in an image.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image.
+    ///
+    /// # Returns
+    ///
+    /// * The boxes scoring at least `CLASS_SCORE_THRESHOLD`, in image coordinates.
+    pub fn detect(&self, image: &image::RgbImage) -> OrtResult<Vec<ClassFace>> {
+        let resized = image::imageops::resize(
+            image,
+            CLASS_INPUT_SIZE,
+            CLASS_INPUT_SIZE,
+            FilterType::Triangle,
+        );
+        let input = Array4::from_shape_fn(
+            (1, 3, CLASS_INPUT_SIZE as usize, CLASS_INPUT_SIZE as usize),
+            |(_, c, y, x)| resized.get_pixel(x as u32, y as u32)[c] as f32 / 255.0,
+        );
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let output: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let values: Vec<f32> = output.view().iter().copied().collect();
+        Ok(to_class_faces(&values, image.width(), image.height()))
+    }
+}
+
+/// Function to map the rows the class model returns to boxes in the image.
+///
+/// # Arguments
+///
+/// * `values` - The rows of x1, y1, x2, y2, score and class index in input pixels, one after the
+///   other.
+/// * `width` - The width of the image.
+/// * `height` - The height of the image.
+///
+/// # Returns
+///
+/// * The boxes scoring at least `CLASS_SCORE_THRESHOLD`, clamped to the image.
+fn to_class_faces(values: &[f32], width: u32, height: u32) -> Vec<ClassFace> {
+    let scale_x = width as f32 / CLASS_INPUT_SIZE as f32;
+    let scale_y = height as f32 / CLASS_INPUT_SIZE as f32;
+    values
+        .chunks_exact(BOX_VALUES)
+        .filter(|row| row[4] >= CLASS_SCORE_THRESHOLD)
+        .filter_map(|row| {
+            let x1 = (row[0] * scale_x).clamp(0.0, width as f32);
+            let y1 = (row[1] * scale_y).clamp(0.0, height as f32);
+            let x2 = (row[2] * scale_x).clamp(0.0, width as f32);
+            let y2 = (row[3] * scale_y).clamp(0.0, height as f32);
+            // boxes outside the image are left empty by clamping
+            (x2 > x1 && y2 > y1).then(|| ClassFace {
+                face: Face {
+                    rect: Rect::at(x1, y1).with_size(x2 - x1, y2 - y1),
+                    confidence: row[4],
+                    landmarks: None,
+                },
+                class: row[5].round() as usize,
+            })
+        })
+        .collect()
+}
+
+/// Crop arguments overridden for the boxes of a class, parsed from
+/// `--class-params CLASS:name=value,name=value`.
+#[derive(Clone, Debug)]
+pub struct ClassParams {
+    pub class: String,
+    pub params: Vec<(String, f32)>,
+}
+
+pub fn parse_class_params(value: &str) -> Result<ClassParams, String> {
+    let (class, params) = value.split_once(':').ok_or_else(|| {
+        format!(
+            "Invalid class parameters {}. Expected class:name=value,name=value",
+            value
+        )
+    })?;
+    let params = params
+        .split(',')
+        .map(|param| {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid class parameter {}. Expected name=value", param))?;
+            let name = sweep::param_name(name)?;
+            let value = value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid value for class parameter {}", name))?;
+            Ok((name, value))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(ClassParams {
+        class: class.trim().to_string(),
+        params,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes_are_mapped_to_the_image() {
+        let values = [
+            // a face in the top left quarter
+            0.0, 0.0, 320.0, 320.0, 0.9, 0.0, //
+            // a low scoring head
+            100.0, 100.0, 200.0, 200.0, 0.2, 1.0, //
+            // a person reaching past the right edge
+            480.0, 160.0, 700.0, 640.0, 0.7, 2.0,
+        ];
+        let faces = to_class_faces(&values, 1280, 320);
+        let boxes: Vec<_> = faces
+            .iter()
+            .map(|face| {
+                let rect = face.face.rect;
+                (face.class, (rect.x, rect.y, rect.width, rect.height))
+            })
+            .collect();
+        assert_eq!(
+            boxes,
+            [
+                (0, (0.0, 0.0, 640.0, 160.0)),
+                (2, (960.0, 80.0, 320.0, 240.0))
+            ]
+        );
+        assert_eq!(faces[1].face.confidence, 0.7);
+    }
+
+    #[test]
+    fn class_params_are_parsed() {
+        let class_params = parse_class_params("head:top-padding=0.5, width=256").unwrap();
+        assert_eq!(class_params.class, "head");
+        assert_eq!(
+            class_params.params,
+            [
+                ("top_padding".to_string(), 0.5),
+                ("width".to_string(), 256.0)
+            ]
+        );
+        assert!(parse_class_params("top_padding=0.5").is_err());
+        assert!(parse_class_params("head:top_padding").is_err());
+        assert!(parse_class_params("head:zoom=2").is_err());
+        assert!(parse_class_params("head:width=wide").is_err());
+    }
+}
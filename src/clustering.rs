@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use crate::{embedding, manifest::CropRecord};
+
+/// Prefix of the names of person clusters, followed by their number.
+const CLUSTER_PREFIX: &str = "person-";
+
+/// A group of faces taken to be the same person.
+struct Cluster {
+    name: String,
+    /// Sum of the embeddings of the faces in the cluster, which points the same way as their
+    /// mean.
+    embedding_sum: Vec<f32>,
+}
+
+/// Faces grouped by identity as they are cropped. Each face joins the cluster it is most similar
+/// to, or starts a new one if it isn't similar enough to any, so a face is never moved once its
+/// crop is written.
+pub struct PersonClusters {
+    clusters: Vec<Cluster>,
+    min_similarity: f32,
+}
+
+impl PersonClusters {
+    /// Function to start clustering, continuing the clusters of earlier runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `crops` - The crops of earlier runs. Crops with an embedding in a cluster directory
+    ///   seed that cluster.
+    /// * `min_similarity` - Lowest cosine similarity between a face and a cluster for the face to
+    ///   join it.
+    ///
+    /// # Returns
+    ///
+    /// * The clusters.
+    pub fn new(crops: &[CropRecord], min_similarity: f32) -> Self {
+        let mut clusters = Self {
+            clusters: Vec::new(),
+            min_similarity,
+        };
+        for crop in crops {
+            if let (Some(name), Some(embedding)) = (cluster_name(&crop.path), &crop.embedding) {
+                clusters.add(name, embedding);
+            }
+        }
+        clusters
+    }
+
+    /// Function to assign a face to a cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `embedding` - The identity embedding of the face.
+    ///
+    /// # Returns
+    ///
+    /// * The name of the cluster, e.g. "person-0001".
+    pub fn assign(&mut self, embedding: &[f32]) -> String {
+        let closest = self
+            .clusters
+            .iter()
+            .map(|cluster| {
+                (
+                    cluster,
+                    embedding::cosine_similarity(&cluster.embedding_sum, embedding),
+                )
+            })
+            .filter(|(_, similarity)| *similarity >= self.min_similarity)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(cluster, _)| cluster.name.clone());
+        let name = closest.unwrap_or_else(|| {
+            let number = self
+                .clusters
+                .iter()
+                .filter_map(|cluster| cluster.name[CLUSTER_PREFIX.len()..].parse::<usize>().ok())
+                .max()
+                .unwrap_or(0);
+            format!("{}{:04}", CLUSTER_PREFIX, number + 1)
+        });
+        self.add(&name, embedding);
+        name
+    }
+
+    fn add(&mut self, name: &str, embedding: &[f32]) {
+        match self
+            .clusters
+            .iter_mut()
+            .find(|cluster| cluster.name == name)
+        {
+            Some(cluster) => {
+                for (sum, value) in cluster.embedding_sum.iter_mut().zip(embedding) {
+                    *sum += value;
+                }
+            }
+            None => self.clusters.push(Cluster {
+                name: name.to_string(),
+                embedding_sum: embedding.to_vec(),
+            }),
+        }
+    }
+}
+
+/// Function to get the cluster a crop was put in from its path.
+fn cluster_name(crop_path: &Path) -> Option<&str> {
+    crop_path
+        .components()
+        .filter_map(|component| component.as_os_str().to_str())
+        .find(|name| {
+            name.strip_prefix(CLUSTER_PREFIX).is_some_and(|number| {
+                !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+            })
+        })
+}
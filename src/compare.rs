@@ -0,0 +1,123 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use tracing::info;
+
+use crate::manifest::{CropRecord, Manifest, RectRecord};
+
+/// Overlap of the face boxes above which two crops are taken to be of the same face.
+const MATCH_IOU: f32 = 0.5;
+/// Overlap of the face or crop boxes below which a matched crop is reported as changed.
+const UNCHANGED_IOU: f32 = 0.95;
+
+/// Counts of the differences between two runs.
+#[derive(Debug, Default)]
+pub struct Comparison {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+/// Function to match the crops of two runs by source and face box overlap and log the crops
+/// that were added, removed or changed in the second run.
+///
+/// # Arguments
+///
+/// * `before` - Manifest of the first run.
+/// * `after` - Manifest of the second run.
+///
+/// # Returns
+///
+/// * The number of crops in each category.
+pub fn compare(before: &Manifest, after: &Manifest) -> Comparison {
+    let before_by_source = by_source(&before.crops);
+    let after_by_source = by_source(&after.crops);
+    let sources: Vec<&Path> = before_by_source
+        .keys()
+        .chain(after_by_source.keys())
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let mut comparison = Comparison::default();
+    for source in sources {
+        let before_crops = before_by_source.get(source).cloned().unwrap_or_default();
+        let mut after_crops = after_by_source.get(source).cloned().unwrap_or_default();
+        for before_crop in before_crops {
+            // greedily match the most overlapping remaining face of the second run
+            let best_match = after_crops
+                .iter()
+                .enumerate()
+                .map(|(i, after_crop)| (i, iou(&before_crop.face_rect, &after_crop.face_rect)))
+                .filter(|(_, overlap)| *overlap >= MATCH_IOU)
+                .max_by(|(_, a), (_, b)| a.total_cmp(b));
+            match best_match {
+                Some((i, face_overlap)) => {
+                    let after_crop = after_crops.remove(i);
+                    let crop_overlap = iou(&before_crop.crop_rect, &after_crop.crop_rect);
+                    if face_overlap < UNCHANGED_IOU || crop_overlap < UNCHANGED_IOU {
+                        info!(
+                            "CHANGED {} -> {} (face IoU {:.2}, crop IoU {:.2}, confidence {:.3} -> {:.3})",
+                            before_crop.path.display(),
+                            after_crop.path.display(),
+                            face_overlap,
+                            crop_overlap,
+                            before_crop.confidence,
+                            after_crop.confidence
+                        );
+                        comparison.changed += 1;
+                    } else {
+                        comparison.unchanged += 1;
+                    }
+                }
+                None => {
+                    info!(
+                        "REMOVED {} (face {} of {})",
+                        before_crop.path.display(),
+                        before_crop.face,
+                        source.display()
+                    );
+                    comparison.removed += 1;
+                }
+            }
+        }
+        for after_crop in after_crops {
+            info!(
+                "ADDED {} (face {} of {})",
+                after_crop.path.display(),
+                after_crop.face,
+                source.display()
+            );
+            comparison.added += 1;
+        }
+    }
+
+    comparison
+}
+
+fn by_source(crops: &[CropRecord]) -> BTreeMap<&Path, Vec<&CropRecord>> {
+    let mut by_source: BTreeMap<&Path, Vec<&CropRecord>> = BTreeMap::new();
+    for crop in crops {
+        by_source
+            .entry(crop.source.as_path())
+            .or_default()
+            .push(crop);
+    }
+    by_source
+}
+
+/// Intersection over union of two rects.
+fn iou(a: &RectRecord, b: &RectRecord) -> f32 {
+    let width = ((a.x + a.width).min(b.x + b.width) - a.x.max(b.x)).max(0.0);
+    let height = ((a.y + a.height).min(b.y + b.height) - a.y.max(b.y)).max(0.0);
+    let intersection = width * height;
+    let union = a.width * a.height + b.width * b.height - intersection;
+    match union > 0.0 {
+        true => intersection / union,
+        false => 0.0,
+    }
+}
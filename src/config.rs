@@ -0,0 +1,58 @@
+use clap::{ArgAction, ArgMatches, Command};
+
+/// Function to render the effective value of every option of a run as TOML, including the
+/// defaults of options that weren't given.
+///
+/// # Arguments
+///
+/// * `command` - The built command the matches were parsed with.
+/// * `matches` - The parsed command line.
+///
+/// # Returns
+///
+/// * The options as a TOML document keyed by option name. Options without a value are omitted.
+pub fn to_toml(command: &Command, matches: &ArgMatches) -> String {
+    let mut config = toml::Table::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let value = match arg.get_action() {
+            ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version => {
+                continue
+            }
+            ArgAction::SetTrue | ArgAction::SetFalse => toml::Value::Boolean(matches.get_flag(id)),
+            ArgAction::Count => toml::Value::Integer(matches.get_count(id) as i64),
+            _ => {
+                let Some(values) = matches.get_raw(id) else {
+                    continue;
+                };
+                let mut values: Vec<_> = values
+                    .map(|value| to_value(&value.to_string_lossy()))
+                    .collect();
+                if values.is_empty() {
+                    continue;
+                }
+                match arg
+                    .get_num_args()
+                    .is_some_and(|range| range.max_values() > 1)
+                    || matches!(arg.get_action(), ArgAction::Append)
+                {
+                    true => toml::Value::Array(values),
+                    false => values.remove(0),
+                }
+            }
+        };
+        config.insert(id.to_string(), value);
+    }
+    toml::to_string(&config).unwrap_or_else(|_| panic!("Failed to serialize config"))
+}
+
+/// Numbers are written as TOML numbers and everything else, e.g. durations and paths, as strings.
+fn to_value(value: &str) -> toml::Value {
+    if let Ok(integer) = value.parse() {
+        return toml::Value::Integer(integer);
+    }
+    match value.parse() {
+        Ok(float) => toml::Value::Float(float),
+        Err(_) => toml::Value::String(value.to_string()),
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static UNICODE: AtomicBool = AtomicBool::new(true);
+
+/// What the terminal the logs are written to can render.
+#[derive(Debug)]
+pub struct ConsoleCapabilities {
+    /// True to colour log levels with ANSI escape codes.
+    pub color: bool,
+    /// True to decorate status messages with emoji.
+    pub unicode: bool,
+}
+
+/// Function to detect what the terminal can render. Colour is disabled when the output isn't a
+/// terminal, `NO_COLOR` is set or `TERM=dumb`. Emoji are disabled unless the locale is UTF-8, or
+/// on Windows unless running in Windows Terminal.
+///
+/// # Arguments
+///
+/// * `no_color` - True to disable colour regardless of the terminal.
+/// * `ascii` - True to disable emoji regardless of the terminal.
+/// * `logs_to_stderr` - True if logs are written to stderr instead of stdout.
+///
+/// # Returns
+///
+/// * The capabilities to configure logging with.
+pub fn detect(no_color: bool, ascii: bool, logs_to_stderr: bool) -> ConsoleCapabilities {
+    let is_terminal = match logs_to_stderr {
+        true => std::io::stderr().is_terminal(),
+        false => std::io::stdout().is_terminal(),
+    };
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let dumb = std::env::var("TERM").is_ok_and(|term| term == "dumb");
+
+    ConsoleCapabilities {
+        color: !no_color && !no_color_env && !dumb && is_terminal,
+        unicode: !ascii && !dumb && is_terminal && unicode_locale(),
+    }
+}
+
+fn unicode_locale() -> bool {
+    if cfg!(windows) {
+        return std::env::var_os("WT_SESSION").is_some();
+    }
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|value| {
+            let value = value.to_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+}
+
+pub fn init(capabilities: &ConsoleCapabilities) {
+    UNICODE.store(capabilities.unicode, Ordering::Relaxed);
+}
+
+/// Returns the emoji prefixed with a space for appending to a status message, or nothing if the
+/// terminal can't render it.
+pub fn symbol(emoji: &str) -> String {
+    match UNICODE.load(Ordering::Relaxed) {
+        true => format!(" {}", emoji),
+        false => String::new(),
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::{Path, PathBuf};
+
+use image::{Pixel, Rgb, RgbImage};
+
+use crate::{cropping::Image, drawing, output};
+
+const BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+const TILE_MARGIN: u32 = 4;
+const LABEL_SCALE: u32 = 2;
+const LABEL_LINES: u32 = 2;
+
+/// Parameters controlling the layout of a contact sheet.
+#[derive(Debug)]
+pub struct ContactSheetParams {
+    /// Size of the square each thumbnail is fitted into.
+    pub tile_size: u32,
+    pub columns: u32,
+    /// Maximum number of rows per sheet. Once a sheet is full it is written out and a new page
+    /// is started, so memory stays bounded on large runs.
+    pub rows: u32,
+}
+
+struct Tile {
+    thumbnail: RgbImage,
+    labels: [String; LABEL_LINES as usize],
+}
+
+/// Grid montage of labelled crop thumbnails.
+pub struct ContactSheet<'a> {
+    params: &'a ContactSheetParams,
+    output_path: PathBuf,
+    tiles: Vec<Tile>,
+    pages_written: usize,
+}
+
+impl<'a> ContactSheet<'a> {
+    pub fn new(params: &'a ContactSheetParams, output_path: PathBuf) -> Self {
+        Self {
+            params,
+            output_path,
+            tiles: Vec::new(),
+            pages_written: 0,
+        }
+    }
+
+    /// Adds a crop to the sheet, writing out the current page if it is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `crop` - The crop to add.
+    /// * `source` - Name of the source image, used as the first label line.
+    /// * `caption` - Second label line, e.g. the face index and confidence.
+    pub fn add<P: Pixel<Subpixel = u8> + 'static>(
+        &mut self,
+        crop: &Image<P>,
+        source: &str,
+        caption: String,
+    ) {
+        let scale = self.params.tile_size as f32 / crop.width().max(crop.height()).max(1) as f32;
+        let thumbnail = image::imageops::thumbnail(
+            crop,
+            ((crop.width() as f32 * scale) as u32).max(1),
+            ((crop.height() as f32 * scale) as u32).max(1),
+        );
+        let thumbnail = RgbImage::from_fn(thumbnail.width(), thumbnail.height(), |x, y| {
+            thumbnail.get_pixel(x, y).to_rgb()
+        });
+
+        self.tiles.push(Tile {
+            thumbnail,
+            labels: [source.to_string(), caption],
+        });
+        if self.tiles.len() as u32 == self.params.columns * self.params.rows {
+            self.write_page(true);
+        }
+    }
+
+    /// Writes out any remaining tiles.
+    ///
+    /// # Returns
+    ///
+    /// * The number of sheets written over the lifetime of the contact sheet.
+    pub fn finish(mut self) -> usize {
+        if !self.tiles.is_empty() {
+            // only split into numbered pages if a previous page was already written
+            let numbered = self.pages_written > 0;
+            self.write_page(numbered);
+        }
+        self.pages_written
+    }
+
+    fn write_page(&mut self, numbered: bool) {
+        let page = self.render();
+        let output_path = match numbered {
+            true => page_path(&self.output_path, self.pages_written + 1),
+            false => self.output_path.clone(),
+        };
+        let format =
+            image::ImageFormat::from_path(&output_path).unwrap_or(image::ImageFormat::Jpeg);
+        output::save_image(&page, &output_path, format);
+        self.pages_written += 1;
+        self.tiles.clear();
+    }
+
+    fn render(&self) -> RgbImage {
+        let tile_size = self.params.tile_size;
+        let label_height = drawing::label_height(LABEL_SCALE);
+        let cell_width = tile_size + TILE_MARGIN * 2;
+        let cell_height = tile_size + label_height * LABEL_LINES + TILE_MARGIN * 2;
+        let max_characters = drawing::label_capacity(tile_size, LABEL_SCALE);
+
+        let columns = self.params.columns.min(self.tiles.len() as u32);
+        let rows = (self.tiles.len() as u32).div_ceil(self.params.columns);
+        let mut sheet = RgbImage::from_pixel(columns * cell_width, rows * cell_height, BACKGROUND);
+
+        for (i, tile) in self.tiles.iter().enumerate() {
+            let cell_x = (i as u32 % self.params.columns) * cell_width + TILE_MARGIN;
+            let cell_y = (i as u32 / self.params.columns) * cell_height + TILE_MARGIN;
+
+            // center the thumbnail within its square
+            let x = cell_x + (tile_size - tile.thumbnail.width()) / 2;
+            let y = cell_y + (tile_size - tile.thumbnail.height()) / 2;
+            image::imageops::replace(&mut sheet, &tile.thumbnail, x as i64, y as i64);
+
+            for (line, label) in tile.labels.iter().enumerate() {
+                let label: String = label.chars().take(max_characters).collect();
+                let label_y = cell_y + tile_size + line as u32 * label_height;
+                drawing::draw_label(
+                    &mut sheet,
+                    &label,
+                    cell_x as i32,
+                    label_y as i32,
+                    LABEL_SCALE,
+                );
+            }
+        }
+
+        sheet
+    }
+}
+
+/// Path of a numbered page, e.g. `sheet.jpg` -> `sheet-2.jpg`.
+fn page_path(output_path: &Path, page: usize) -> PathBuf {
+    let stem = output_path.file_stem().unwrap().to_str().unwrap();
+    match output_path.extension() {
+        Some(extension) => {
+            output_path.with_file_name(format!("{}-{}.{}", stem, page, extension.to_str().unwrap()))
+        }
+        None => output_path.with_file_name(format!("{}-{}", stem, page)),
+    }
+}
@@ -1,26 +1,78 @@
+use image::{ImageBuffer, Pixel};
+use ndarray::{Array4, CowArray};
+use ort::{tensor::OrtOwnedTensor, OrtResult, Session, Value};
 use rust_faces::{
-    BlazeFaceParams, Face, FaceDetection, FaceDetector, FaceDetectorBuilder, InferParams, Rect,
-    ToArray3,
+    BlazeFaceParams, Face, FaceDetection, FaceDetector, FaceDetectorBuilder, InferParams,
+    MtCnnParams, Rect, RustFacesResult, ToArray3,
 };
+use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-#[derive(Debug)]
-pub struct CropInputs<'a> {
-    pub input_image: &'a image::RgbImage,
+pub type Image<P> = ImageBuffer<P, Vec<<P as Pixel>::Subpixel>>;
+
+/// Ratio of the long to the short side of an image above which it is detected in strips.
+const MAX_DETECTION_ASPECT_RATIO: f32 = 2.0;
+/// Fraction of each strip that overlaps the next, so faces on a strip boundary are whole in one
+/// of the strips.
+const STRIP_OVERLAP: f32 = 0.25;
+/// Overlap, relative to the smaller box, above which detections from overlapping strips are
+/// taken to be the same face.
+const STRIP_MERGE_OVERLAP: f32 = 0.5;
+/// Side of the square face region the landmark model takes as input.
+const LANDMARK_INPUT_SIZE: u32 = 112;
+/// Factor the side of a face box is enlarged by to get the region the landmark model looks at,
+/// so the jawline, which detector boxes cut off, is inside it.
+const LANDMARK_REGION_SCALE: f32 = 1.4;
+/// Number of landmarks along the jawline, which come first in the 68-point layout.
+const JAWLINE_LANDMARKS: usize = 17;
+/// Height of the eyes within a face box, as a proportion of it from the top, for placing the eye
+/// line of faces without landmarks.
+const EYE_LINE: f32 = 0.4;
+
+pub struct CropInputs<'a, P: Pixel> {
+    pub input_image: &'a Image<P>,
     pub faces: &'a Vec<Face>,
 }
 
-#[derive(Debug)]
-pub struct CropOutputs {
-    pub image: image::RgbImage,
+pub struct CropOutputs<P: Pixel> {
+    pub image: Image<P>,
     pub confidence: f32,
+    /// Bounding box of the face in the input image.
+    pub face: Rect,
+    /// Region of the input image the crop was taken from.
+    pub crop: Rect,
 }
 
 #[derive(Debug)]
 pub struct CropParams {
     pub top_padding: f32,
+    /// Proportion of the crop height added below it, e.g. to include the shoulders.
+    pub bottom_padding: f32,
+    /// Proportion of the crop width added to its left.
+    pub left_padding: f32,
+    /// Proportion of the crop width added to its right.
+    pub right_padding: f32,
+    /// Where the centre of the face lands within the crop, as proportions of its width and
+    /// height from the top left, if set, e.g. (0.5, 0.38) for portrait framing. This positions
+    /// the crop instead of the top, left and right padding, which then only make it larger.
+    pub face_anchor: Option<(f32, f32)>,
+    pub compose: Compose,
+    /// Multiple the width and height of the crop are rounded to, if any, e.g. for video encoders
+    /// that need even dimensions.
+    pub dims_multiple_of: Option<u32>,
     pub kind: CropParamsKind,
 }
 
+/// How the face is framed within the crop.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compose {
+    /// Centred horizontally, with the top padding or face anchor deciding the rest.
+    Centered,
+    /// With the eye line on the upper third of the crop and the face on the left or right third,
+    /// whichever leaves the crop more of the image to take in.
+    Thirds,
+}
+
 #[derive(Debug)]
 pub enum CropParamsKind {
     Absolute(AbsoluteCrop),
@@ -39,20 +91,457 @@ pub struct RelativeCrop {
     pub proportion_of_face: f32,
 }
 
-pub fn get_face_detector() -> Box<dyn FaceDetector> {
-    let face_detector =
-        FaceDetectorBuilder::new(FaceDetection::BlazeFace640(BlazeFaceParams::default()))
-            .download()
-            .infer_params(InferParams::default())
-            .build()
-            .unwrap_or_else(|_| panic!("Failed to build face detector"));
-    face_detector
+/// Coarse scale of a face, so crops can be sampled by face size without recomputing it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeBucket {
+    Tiny,
+    Small,
+    Medium,
+    Large,
+}
+
+impl SizeBucket {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SizeBucket::Tiny => "tiny",
+            SizeBucket::Small => "small",
+            SizeBucket::Medium => "medium",
+            SizeBucket::Large => "large",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SizeBucketParams {
+    /// Smallest face size in pixels of the small, medium and large buckets. Faces below the
+    /// first threshold are tiny.
+    pub thresholds: [u32; 3],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaceDetectorModel {
+    BlazeFace640,
+    BlazeFace320,
+    MtCnn,
 }
 
+impl FaceDetectorModel {
+    /// Name of the model as given on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            FaceDetectorModel::BlazeFace640 => "blazeface-640",
+            FaceDetectorModel::BlazeFace320 => "blazeface-320",
+            FaceDetectorModel::MtCnn => "mtcnn",
+        }
+    }
+}
+
+pub const FACE_DETECTOR_MODELS: [FaceDetectorModel; 3] = [
+    FaceDetectorModel::BlazeFace640,
+    FaceDetectorModel::BlazeFace320,
+    FaceDetectorModel::MtCnn,
+];
+
+/// Numeric precision of the detector weights. Quantized weights trade a little accuracy for
+/// faster inference on the CPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModelPrecision {
+    Fp32,
+    Fp16,
+    Int8,
+}
+
+/// Function to build a detector, loading quantized weights if they are available.
+///
+/// # Arguments
+///
+/// * `model` - The detector model.
+/// * `precision` - Precision of the weights.
+/// * `infer_params` - How inference is run.
+///
+/// # Returns
+///
+/// * The detector.
+pub fn get_face_detector(
+    model: FaceDetectorModel,
+    precision: ModelPrecision,
+    infer_params: InferParams,
+) -> Box<dyn FaceDetector> {
+    let face_detector = match quantized_model_path(model, precision) {
+        Some(model_path) => FaceDetectorBuilder::new(detection(model))
+            .from_file(model_path.to_string_lossy().into_owned())
+            .infer_params(infer_params)
+            .build(),
+        None => build_face_detector(model, infer_params),
+    };
+    face_detector.unwrap_or_else(|_| panic!("Failed to build face detector"))
+}
+
+pub fn build_face_detector(
+    model: FaceDetectorModel,
+    infer_params: InferParams,
+) -> RustFacesResult<Box<dyn FaceDetector>> {
+    FaceDetectorBuilder::new(detection(model))
+        .download()
+        .infer_params(infer_params)
+        .build()
+}
+
+fn detection(model: FaceDetectorModel) -> FaceDetection {
+    match model {
+        FaceDetectorModel::BlazeFace640 => FaceDetection::BlazeFace640(BlazeFaceParams::default()),
+        FaceDetectorModel::BlazeFace320 => FaceDetection::BlazeFace320(BlazeFaceParams::default()),
+        FaceDetectorModel::MtCnn => FaceDetection::MtCnn(MtCnnParams::default()),
+    }
+}
+
+/// Function to get where rust-faces keeps the model files of a detector once downloaded.
+///
+/// # Arguments
+///
+/// * `model` - The detector model.
+///
+/// # Returns
+///
+/// * The paths of the model files, in the order the detector loads them.
+pub fn model_paths(model: FaceDetectorModel) -> Vec<std::path::PathBuf> {
+    let file_names: &[&str] = match model {
+        FaceDetectorModel::BlazeFace640 => &["blazeface-640.onnx"],
+        FaceDetectorModel::BlazeFace320 => &["blazeface-320.onnx"],
+        FaceDetectorModel::MtCnn => &["mtcnn-pnet.onnx", "mtcnn-rnet.onnx", "mtcnn-onet.onnx"],
+    };
+    let cache_dir = std::env::var_os("HOME")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_default()
+        .join(".rust_faces");
+    file_names
+        .iter()
+        .map(|file_name| cache_dir.join(file_name))
+        .collect()
+}
+
+/// Function to find the quantized weights of a detector. They aren't published with the full
+/// precision weights, so they are looked up next to them with the precision appended to the
+/// file name, e.g. `blazeface-640-int8.onnx`.
+///
+/// # Arguments
+///
+/// * `model` - The detector model.
+/// * `precision` - Precision of the weights.
+///
+/// # Returns
+///
+/// * The path of the quantized weights, or None to use the full precision weights.
+pub fn quantized_model_path(
+    model: FaceDetectorModel,
+    precision: ModelPrecision,
+) -> Option<std::path::PathBuf> {
+    let suffix = match precision {
+        ModelPrecision::Fp32 => return None,
+        ModelPrecision::Fp16 => "fp16",
+        ModelPrecision::Int8 => "int8",
+    };
+    if model == FaceDetectorModel::MtCnn {
+        warn!("Quantized weights aren't supported for MTCNN. Using fp32");
+        return None;
+    }
+    let model_path = &model_paths(model)[0];
+    let quantized_path = model_path.with_file_name(format!(
+        "{}-{}.onnx",
+        model_path.file_stem().unwrap().to_string_lossy(),
+        suffix
+    ));
+    if !quantized_path.exists() {
+        warn!(
+            "No {} weights at {}. Using fp32",
+            suffix,
+            quantized_path.display()
+        );
+        return None;
+    }
+    Some(quantized_path)
+}
+
+/// Dense landmark model, locating 68 points along the jawline, eyebrows, eyes, nose and mouth of a
+/// face in the iBUG 300-W layout, e.g. PFLD.
+pub struct LandmarkModel {
+    session: Session,
+}
+
+impl LandmarkModel {
+    /// Wraps a landmark model.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Session of the ONNX model, taking a 1x3x112x112 RGB input scaled to 0.0 -
+    ///   1.0 and returning the x and y of each point normalized to the input.
+    pub fn new(session: Session) -> Self {
+        Self { session }
+    }
+
+    /// Function to locate the landmarks of a face.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image the face was detected in.
+    /// * `face` - The bounding box of the face.
+    ///
+    /// # Returns
+    ///
+    /// * The landmarks in image coordinates.
+    pub fn landmarks(&self, image: &image::RgbImage, face: &Rect) -> OrtResult<Vec<(f32, f32)>> {
+        let side = face.width.max(face.height) * LANDMARK_REGION_SCALE;
+        let x = face.x + (face.width - side) / 2.0;
+        let y = face.y + (face.height - side) / 2.0;
+        let scale = side / LANDMARK_INPUT_SIZE as f32;
+        let input = Array4::from_shape_fn(
+            (
+                1,
+                3,
+                LANDMARK_INPUT_SIZE as usize,
+                LANDMARK_INPUT_SIZE as usize,
+            ),
+            |(_, c, row, column)| {
+                // pixels outside the image are black
+                image::imageops::interpolate_bilinear(
+                    image,
+                    x + (column as f32 + 0.5) * scale,
+                    y + (row as f32 + 0.5) * scale,
+                )
+                .map_or(0.0, |pixel| pixel[c] as f32 / 255.0)
+            },
+        );
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let points: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let points: Vec<f32> = points.view().iter().copied().collect();
+        Ok(points
+            .chunks_exact(2)
+            .map(|point| (x + point[0] * side, y + point[1] * side))
+            .collect())
+    }
+}
+
+/// Function to widen a face box to the jawline, which detectors cut off at the sides and chin, so
+/// crops computed from it contain the whole face.
+///
+/// # Arguments
+///
+/// * `face` - The bounding box of the face.
+/// * `landmarks` - The 68 landmarks of the face.
+///
+/// # Returns
+///
+/// * The smallest box containing the face box and the jawline.
+pub fn jawline_rect(face: &Rect, landmarks: &[(f32, f32)]) -> Rect {
+    let (mut left, mut top) = (face.x, face.y);
+    let (mut right, mut bottom) = (face.x + face.width, face.y + face.height);
+    for (x, y) in landmarks.iter().take(JAWLINE_LANDMARKS) {
+        (left, top) = (left.min(*x), top.min(*y));
+        (right, bottom) = (right.max(*x), bottom.max(*y));
+    }
+    Rect::at(left, top).with_size(right - left, bottom - top)
+}
+
+/// Function to build a detector that keeps low scoring face candidates, for visualizing what the
+/// detector almost found.
+///
+/// # Arguments
+///
+/// * `model` - The detector model. Only BlazeFace exposes its score threshold.
+/// * `infer_params` - How inference is run.
+/// * `score_threshold` - Lowest score of the candidates to keep.
+///
+/// # Returns
+///
+/// * The detector, or None if the model doesn't support a custom score threshold.
+pub fn get_candidate_detector(
+    model: FaceDetectorModel,
+    infer_params: InferParams,
+    score_threshold: f32,
+) -> Option<Box<dyn FaceDetector>> {
+    let params = BlazeFaceParams {
+        score_threshold,
+        ..Default::default()
+    };
+    let detection = match model {
+        FaceDetectorModel::BlazeFace640 => FaceDetection::BlazeFace640(params),
+        FaceDetectorModel::BlazeFace320 => FaceDetection::BlazeFace320(params),
+        FaceDetectorModel::MtCnn => return None,
+    };
+    let face_detector = FaceDetectorBuilder::new(detection)
+        .download()
+        .infer_params(infer_params)
+        .build()
+        .unwrap_or_else(|_| panic!("Failed to build face detector"));
+    Some(face_detector)
+}
+
+/// Function to detect the faces in an image.
+///
+/// The detector resizes its input to a fixed size, which squashes the faces in panoramas and
+/// other images with extreme aspect ratios beyond recognition. Such images are detected in
+/// overlapping square strips along their long side instead and the detections merged.
+///
+/// # Arguments
+///
+/// * `input_image` - The image to detect faces in.
+/// * `face_detector` - The detector to use.
+///
+/// # Returns
+///
+/// * The detected faces, in image coordinates, ordered left to right and top to bottom so face
+///   indices don't depend on the order the detector returns them in.
 pub fn detect_faces_in_image(
     input_image: &image::RgbImage,
     face_detector: &dyn FaceDetector,
 ) -> Vec<Face> {
+    let (width, height) = input_image.dimensions();
+    let (long_side, short_side) = (width.max(height), width.min(height));
+    let mut faces = match short_side == 0
+        || long_side as f32 / short_side as f32 <= MAX_DETECTION_ASPECT_RATIO
+    {
+        true => detect_faces(input_image, face_detector),
+        false => detect_faces_in_strips(input_image, face_detector),
+    };
+    sort_faces(&mut faces);
+    faces
+}
+
+/// Function to order faces left to right and top to bottom, the order face indices follow.
+///
+/// # Arguments
+///
+/// * `faces` - The faces to order.
+pub fn sort_faces(faces: &mut [Face]) {
+    faces.sort_by(face_order);
+}
+
+/// Function to compare faces in the order `sort_faces` puts them in, for sorting values that
+/// hold faces.
+///
+/// # Arguments
+///
+/// * `a` - The first face.
+/// * `b` - The second face.
+///
+/// # Returns
+///
+/// * The order of the faces, left to right and top to bottom.
+pub fn face_order(a: &Face, b: &Face) -> std::cmp::Ordering {
+    a.rect
+        .x
+        .total_cmp(&b.rect.x)
+        .then(a.rect.y.total_cmp(&b.rect.y))
+}
+
+fn detect_faces_in_strips(
+    input_image: &image::RgbImage,
+    face_detector: &dyn FaceDetector,
+) -> Vec<Face> {
+    let (width, height) = input_image.dimensions();
+    let (long_side, short_side) = (width.max(height), width.min(height));
+    // the strips are detected concurrently so a detector pool can spread them across workers
+    let faces: Vec<Face> = std::thread::scope(|scope| {
+        let strips: Vec<_> = strip_offsets(long_side, short_side)
+            .into_iter()
+            .map(|offset| {
+                let (x, y) = match width > height {
+                    true => (offset, 0),
+                    false => (0, offset),
+                };
+                let strip =
+                    image::imageops::crop_imm(input_image, x, y, short_side, short_side).to_image();
+                scope.spawn(move || {
+                    detect_faces(&strip, face_detector)
+                        .into_iter()
+                        .map(|face| translate_face(face, x as f32, y as f32))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        strips
+            .into_iter()
+            .flat_map(|strip| strip.join().unwrap())
+            .collect()
+    });
+
+    // detections of a face in overlapping strips include partial detections of faces cut by a
+    // strip boundary
+    merge_overlapping(faces, STRIP_MERGE_OVERLAP)
+}
+
+/// Function to merge the detections of the same face, e.g. in overlapping regions of an image,
+/// keeping the most confident of them.
+///
+/// # Arguments
+///
+/// * `faces` - The detections.
+/// * `max_overlap` - Overlap, relative to the smaller box, above which two detections are taken
+///   to be the same face.
+///
+/// # Returns
+///
+/// * The merged faces, most confident first.
+pub fn merge_overlapping(mut faces: Vec<Face>, max_overlap: f32) -> Vec<Face> {
+    faces.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    let mut merged: Vec<Face> = Vec::new();
+    for face in faces {
+        if merged
+            .iter()
+            .all(|kept| kept.rect.iou_min(&face.rect) <= max_overlap)
+        {
+            merged.push(face);
+        }
+    }
+    merged
+}
+
+/// Function to calculate where the square strips of an image start along its long side.
+///
+/// # Arguments
+///
+/// * `long_side` - The length of the long side of the image.
+/// * `short_side` - The length of the short side of the image, and the side of the strips.
+///
+/// # Returns
+///
+/// * The offsets of the strips, overlapping by `STRIP_OVERLAP` with the last one ending at the
+///   end of the long side.
+fn strip_offsets(long_side: u32, short_side: u32) -> Vec<u32> {
+    let step = ((short_side as f32 * (1.0 - STRIP_OVERLAP)) as u32).max(1);
+    let mut offsets: Vec<u32> = (0..long_side - short_side).step_by(step as usize).collect();
+    offsets.push(long_side - short_side);
+    offsets
+}
+
+fn translate_face(face: Face, x: f32, y: f32) -> Face {
+    Face {
+        rect: Rect::at(face.rect.x + x, face.rect.y + y)
+            .with_size(face.rect.width, face.rect.height),
+        confidence: face.confidence,
+        landmarks: face.landmarks.map(|landmarks| {
+            landmarks
+                .into_iter()
+                .map(|(landmark_x, landmark_y)| (landmark_x + x, landmark_y + y))
+                .collect()
+        }),
+    }
+}
+
+/// Function to convert an image in the height x width x channel layout detectors take back to an
+/// image buffer.
+pub fn array_to_image(image: &ndarray::ArrayViewD<u8>) -> image::RgbImage {
+    let (height, width) = (image.shape()[0] as u32, image.shape()[1] as u32);
+    image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb(std::array::from_fn(|c| image[[y as usize, x as usize, c]]))
+    })
+}
+
+fn detect_faces(input_image: &image::RgbImage, face_detector: &dyn FaceDetector) -> Vec<Face> {
     let preprocessed_image = input_image.clone().into_array3();
 
     let faces = face_detector
@@ -62,20 +551,66 @@ pub fn detect_faces_in_image(
     faces
 }
 
-pub fn crop_faces(faces_to_crop: CropInputs, crop_params: &CropParams) -> Option<Vec<CropOutputs>> {
-    if faces_to_crop.faces.len() == 0 {
+/// Function to flatten an image with an alpha channel onto a solid background.
+///
+/// The detector only looks at RGB values, which are undefined wherever alpha is zero, so
+/// transparent regions are replaced with the background color before detection.
+///
+/// # Arguments
+///
+/// * `input_image` - The image with an alpha channel.
+/// * `background` - The color to composite the image onto.
+///
+/// # Returns
+///
+/// * The composited RGB image.
+pub fn flatten_alpha(
+    input_image: &image::RgbaImage,
+    background: image::Rgb<u8>,
+) -> image::RgbImage {
+    image::RgbImage::from_fn(input_image.width(), input_image.height(), |x, y| {
+        let pixel = input_image.get_pixel(x, y);
+        let alpha = pixel[3] as f32 / 255.0;
+        image::Rgb(std::array::from_fn(|c| {
+            (pixel[c] as f32 * alpha + background[c] as f32 * (1.0 - alpha)).round() as u8
+        }))
+    })
+}
+
+pub fn crop_faces<P: Pixel + 'static>(
+    faces_to_crop: CropInputs<P>,
+    crop_params: &CropParams,
+) -> Option<Vec<CropOutputs<P>>> {
+    crop_faces_by(faces_to_crop, |_| crop_params)
+}
+
+/// Function to crop faces whose crop settings differ, e.g. by the class of their box.
+///
+/// # Arguments
+///
+/// * `faces_to_crop` - The image and the faces in it.
+/// * `crop_params` - The crop settings of a face, given its index.
+///
+/// # Returns
+///
+/// * The crops, indexed like the faces, or None if there are no faces.
+pub fn crop_faces_by<'p, P: Pixel + 'static>(
+    faces_to_crop: CropInputs<P>,
+    crop_params: impl Fn(usize) -> &'p CropParams,
+) -> Option<Vec<CropOutputs<P>>> {
+    if faces_to_crop.faces.is_empty() {
         return None;
     }
 
     let mut outputs = Vec::new();
-    for face in faces_to_crop.faces.iter() {
+    for (i, face) in faces_to_crop.faces.iter().enumerate() {
         let crop = calculate_face_crop(
-            &face.rect,
+            face,
             &Rect::at(0.0, 0.0).with_size(
                 faces_to_crop.input_image.width() as f32,
                 faces_to_crop.input_image.height() as f32,
             ),
-            &crop_params,
+            crop_params(i),
         );
         let cropped_image = image::imageops::crop_imm(
             faces_to_crop.input_image,
@@ -89,30 +624,54 @@ pub fn crop_faces(faces_to_crop: CropInputs, crop_params: &CropParams) -> Option
         outputs.push(CropOutputs {
             image: cropped_image,
             confidence: face.confidence,
+            face: face.rect,
+            crop,
         });
     }
 
     Some(outputs)
 }
 
-fn calculate_face_crop(face: &Rect, image: &Rect, params: &CropParams) -> Rect {
+pub fn calculate_face_crop(face: &Face, image: &Rect, params: &CropParams) -> Rect {
     let (crop_height, crop_width) = match &params.kind {
         CropParamsKind::Absolute(absolute_params) => {
             (absolute_params.height as f32, absolute_params.width as f32)
         }
         CropParamsKind::Relative(relative_params) => calculate_crop_dimensions_by_ratios(
-            face,
+            &face.rect,
             relative_params.aspect_ratio,
             relative_params.proportion_of_face,
         ),
     };
 
-    let (crop_x, crop_y) =
-        calculate_crop_position(face, crop_height, crop_width, params.top_padding);
+    let (crop_x, crop_y) = calculate_crop_position(face, image, crop_height, crop_width, params);
+    let crop_width = crop_width * (1.0 + params.left_padding + params.right_padding);
+    let crop_height = crop_height * (1.0 + params.bottom_padding);
+    let Some(multiple) = params.dims_multiple_of.filter(|multiple| *multiple > 1) else {
+        return Rect::at(crop_x, crop_y)
+            .with_size(crop_width, crop_height)
+            .intersection(image); // can never crop outside the image
+    };
 
-    Rect::at(crop_x, crop_y)
-        .with_size(crop_width, crop_height)
-        .intersection(image) // can never crop outside the image
+    // rounded about the centre of the crop so the face stays where the padding put it
+    let multiple = multiple as f32;
+    let (rounded_width, rounded_height) = (
+        (crop_width / multiple).round().max(1.0) * multiple,
+        (crop_height / multiple).round().max(1.0) * multiple,
+    );
+    let crop = Rect::at(
+        crop_x + (crop_width - rounded_width) / 2.0,
+        crop_y + (crop_height - rounded_height) / 2.0,
+    )
+    .with_size(rounded_width, rounded_height)
+    .intersection(image);
+    // clamping to the image can break the multiple, so the crop is trimmed down to one unless
+    // the image is smaller than that
+    let trim = |size: f32| match (size / multiple).floor() * multiple {
+        trimmed if trimmed > 0.0 => trimmed,
+        _ => size,
+    };
+    Rect::at(crop.x.floor(), crop.y.floor()).with_size(trim(crop.width), trim(crop.height))
 }
 
 /// Function to calculate the crop dimensions (height and width) for a detected face in an image.
@@ -136,25 +695,307 @@ fn calculate_crop_dimensions_by_ratios(
     (crop_height, crop_width)
 }
 
-/// Function to calculate the crop position (x and y) for a detected face in an image.
+/// Function to calculate the crop position (x and y) for a detected face in an image. With the
+/// rule of thirds, the eye line of the face is placed on the upper third of the crop and its centre
+/// on the third towards the side of the face with less of the image. Otherwise, the crop is placed
+/// so the centre of the face is at the face anchor, if there is one, or it is centred on the face
+/// horizontally before the left padding is added to it.
 ///
 /// # Arguments
 ///
-/// * `face` - The dimensions of the face.
-/// * `crop_height` - The calculated height of the crop.
-/// * `crop_width` - The calculated width of the crop.
-/// * `top_padding` - The proportion of the image that should be above the face.
+/// * `face` - The detected face, whose landmarks locate its eyes.
+/// * `image` - The dimensions of the image.
+/// * `crop_height` - The calculated height of the crop, before the bottom padding.
+/// * `crop_width` - The calculated width of the crop, before the left and right padding.
+/// * `params` - The crop settings, with the composition, the face anchor or the proportion of
+///   the image that should be above the face and the padding to add to the left of the crop.
 ///
 /// # Returns
 ///
 /// * A tuple (crop_x, crop_y) representing the crop position.
 fn calculate_crop_position(
-    face: &Rect,
+    face: &Face,
+    image: &Rect,
     crop_height: f32,
     crop_width: f32,
-    top_padding: f32,
+    params: &CropParams,
 ) -> (f32, f32) {
-    let crop_x = face.x + (face.width / 2.0) - (crop_width / 2.0);
-    let crop_y = face.y - (crop_height * top_padding);
+    let padded_width = crop_width * (1.0 + params.left_padding + params.right_padding);
+    let padded_height = crop_height * (1.0 + params.bottom_padding);
+    let face_center_x = face.rect.x + (face.rect.width / 2.0);
+    if params.compose == Compose::Thirds {
+        // the face goes on the third nearer its side of the image so the crop takes in the rest
+        let image_center_x = image.x + (image.width / 2.0);
+        let third_x = match face_center_x < image_center_x {
+            true => 1.0 / 3.0,
+            false => 2.0 / 3.0,
+        };
+        return (
+            face_center_x - (padded_width * third_x),
+            eye_line(face) - (padded_height / 3.0),
+        );
+    }
+    let face = &face.rect;
+    if let Some((anchor_x, anchor_y)) = params.face_anchor {
+        return (
+            face.x + (face.width / 2.0) - (padded_width * anchor_x),
+            face.y + (face.height / 2.0) - (padded_height * anchor_y),
+        );
+    }
+    let crop_x =
+        face.x + (face.width / 2.0) - (crop_width / 2.0) - (crop_width * params.left_padding);
+    let crop_y = face.y - (crop_height * params.top_padding);
     (crop_x, crop_y)
 }
+
+/// Function to locate the eye line of a face, from its eye landmarks if it has the five that
+/// detectors place, or from where eyes usually are in a face box otherwise.
+///
+/// # Arguments
+///
+/// * `face` - The detected face.
+///
+/// # Returns
+///
+/// * The height of the eyes in the image.
+fn eye_line(face: &Face) -> f32 {
+    match face.landmarks.as_deref() {
+        // the eyes come first
+        Some([right_eye, left_eye, _, _, _]) => (right_eye.1 + left_eye.1) / 2.0,
+        _ => face.rect.y + (face.rect.height * EYE_LINE),
+    }
+}
+
+/// Function to classify a face into a size bucket by the longest side of its bounding box.
+///
+/// # Arguments
+///
+/// * `face` - The face bounding box.
+/// * `params` - The bucket thresholds.
+///
+/// # Returns
+///
+/// * The size bucket of the face.
+pub fn size_bucket(face: &Rect, params: &SizeBucketParams) -> SizeBucket {
+    let size = face.width.max(face.height);
+    let [small, medium, large] = params.thresholds.map(|threshold| threshold as f32);
+    match size {
+        size if size >= large => SizeBucket::Large,
+        size if size >= medium => SizeBucket::Medium,
+        size if size >= small => SizeBucket::Small,
+        _ => SizeBucket::Tiny,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Rect {
+        Rect::at(0.0, 0.0).with_size(1000.0, 1000.0)
+    }
+
+    /// Function to create a detected face without landmarks.
+    fn face_at(x: f32, y: f32, width: f32, height: f32) -> Face {
+        Face {
+            rect: Rect::at(x, y).with_size(width, height),
+            confidence: 1.0,
+            landmarks: None,
+        }
+    }
+
+    /// Function to create the parameters of a 200x200 crop with a quarter of it above the face.
+    fn crop_params() -> CropParams {
+        CropParams {
+            top_padding: 0.25,
+            bottom_padding: 0.0,
+            left_padding: 0.0,
+            right_padding: 0.0,
+            face_anchor: None,
+            compose: Compose::Centered,
+            dims_multiple_of: None,
+            kind: CropParamsKind::Absolute(AbsoluteCrop {
+                height: 200,
+                width: 200,
+            }),
+        }
+    }
+
+    fn rect_of(rect: Rect) -> (f32, f32, f32, f32) {
+        (rect.x, rect.y, rect.width, rect.height)
+    }
+
+    #[test]
+    fn strip_offsets_overlap_and_reach_the_end() {
+        assert_eq!(strip_offsets(4000, 1000), [0, 750, 1500, 2250, 3000]);
+        // the last strip is moved back to end at the end of the long side
+        assert_eq!(strip_offsets(3200, 1000), [0, 750, 1500, 2200]);
+        assert_eq!(strip_offsets(1000, 1000), [0]);
+        assert_eq!(strip_offsets(5, 1), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn crops_are_centred_on_the_face_below_the_top_padding() {
+        let face = face_at(400.0, 400.0, 100.0, 100.0);
+        let crop = calculate_face_crop(&face, &image(), &crop_params());
+        assert_eq!(rect_of(crop), (350.0, 350.0, 200.0, 200.0));
+
+        let params = CropParams {
+            kind: CropParamsKind::Relative(RelativeCrop {
+                aspect_ratio: 0.5,
+                proportion_of_face: 0.25,
+            }),
+            ..crop_params()
+        };
+        let crop = calculate_face_crop(&face, &image(), &params);
+        assert_eq!(rect_of(crop), (350.0, 300.0, 200.0, 400.0));
+    }
+
+    #[test]
+    fn padding_extends_the_crop_around_the_face() {
+        let face = face_at(400.0, 400.0, 100.0, 100.0);
+        let params = CropParams {
+            bottom_padding: 0.5,
+            left_padding: 0.25,
+            right_padding: 0.25,
+            ..crop_params()
+        };
+        let crop = calculate_face_crop(&face, &image(), &params);
+        // the face stays centred as the left and right padding are equal
+        assert_eq!(rect_of(crop), (300.0, 350.0, 300.0, 300.0));
+
+        let params = CropParams {
+            left_padding: 0.5,
+            ..crop_params()
+        };
+        let crop = calculate_face_crop(&face, &image(), &params);
+        assert_eq!(rect_of(crop), (250.0, 350.0, 300.0, 200.0));
+    }
+
+    #[test]
+    fn crops_are_clamped_to_the_image() {
+        let face = face_at(0.0, 0.0, 100.0, 100.0);
+        let crop = calculate_face_crop(&face, &image(), &crop_params());
+        assert_eq!(rect_of(crop), (0.0, 0.0, 150.0, 150.0));
+
+        let face = face_at(950.0, 900.0, 50.0, 100.0);
+        let crop = calculate_face_crop(&face, &image(), &crop_params());
+        assert_eq!(rect_of(crop), (875.0, 850.0, 125.0, 150.0));
+    }
+
+    #[test]
+    fn crop_dimensions_are_rounded_to_the_multiple() {
+        let face = face_at(400.0, 400.0, 100.0, 100.0);
+        let params = CropParams {
+            bottom_padding: 0.05,
+            dims_multiple_of: Some(64),
+            ..crop_params()
+        };
+        // 200x210 is rounded to 192x192 about its centre
+        let crop = calculate_face_crop(&face, &image(), &params);
+        assert_eq!(rect_of(crop), (354.0, 359.0, 192.0, 192.0));
+
+        for dims_multiple_of in [None, Some(1)] {
+            let params = CropParams {
+                dims_multiple_of,
+                ..crop_params()
+            };
+            let crop = calculate_face_crop(&face, &image(), &params);
+            assert_eq!(rect_of(crop), (350.0, 350.0, 200.0, 200.0));
+        }
+    }
+
+    #[test]
+    fn clamped_crops_are_trimmed_to_the_multiple() {
+        let params = CropParams {
+            dims_multiple_of: Some(64),
+            ..crop_params()
+        };
+        let face = face_at(0.0, 0.0, 100.0, 100.0);
+        let crop = calculate_face_crop(&face, &image(), &params);
+        assert_eq!(rect_of(crop), (0.0, 0.0, 128.0, 128.0));
+
+        // an image smaller than the multiple is kept whole
+        let image = Rect::at(0.0, 0.0).with_size(50.0, 40.0);
+        let face = face_at(10.0, 10.0, 20.0, 20.0);
+        let crop = calculate_face_crop(&face, &image, &params);
+        assert_eq!(rect_of(crop), (0.0, 0.0, 50.0, 40.0));
+    }
+
+    #[test]
+    fn face_anchor_places_the_face_centre_in_the_crop() {
+        let face = face_at(400.0, 400.0, 100.0, 100.0);
+        let params = CropParams {
+            face_anchor: Some((0.5, 0.38)),
+            ..crop_params()
+        };
+        let crop = calculate_face_crop(&face, &image(), &params);
+        // the top padding is ignored, the face centre (450, 450) is 38% down the crop
+        assert_eq!(rect_of(crop), (350.0, 374.0, 200.0, 200.0));
+
+        // padding makes the crop larger around the same anchor
+        let params = CropParams {
+            bottom_padding: 0.5,
+            left_padding: 0.5,
+            ..params
+        };
+        let crop = calculate_face_crop(&face, &image(), &params);
+        assert_eq!(rect_of(crop), (300.0, 336.0, 300.0, 300.0));
+    }
+
+    #[test]
+    fn thirds_put_the_eyes_on_the_upper_third() {
+        let params = CropParams {
+            compose: Compose::Thirds,
+            kind: CropParamsKind::Absolute(AbsoluteCrop {
+                height: 300,
+                width: 300,
+            }),
+            ..crop_params()
+        };
+        // without landmarks the eyes are 40% down the face, at 440
+        let face = face_at(600.0, 400.0, 100.0, 100.0);
+        let crop = calculate_face_crop(&face, &image(), &params);
+        // on the right of the image, the face goes on the right third
+        assert_eq!(rect_of(crop), (450.0, 340.0, 300.0, 300.0));
+
+        let face = face_at(200.0, 400.0, 100.0, 100.0);
+        let crop = calculate_face_crop(&face, &image(), &params);
+        assert_eq!(rect_of(crop), (150.0, 340.0, 300.0, 300.0));
+
+        let face = Face {
+            landmarks: Some(vec![
+                (230.0, 410.0),
+                (270.0, 430.0),
+                (250.0, 450.0),
+                (235.0, 470.0),
+                (265.0, 470.0),
+            ]),
+            ..face
+        };
+        let crop = calculate_face_crop(&face, &image(), &params);
+        assert_eq!(rect_of(crop), (150.0, 320.0, 300.0, 300.0));
+    }
+
+    #[test]
+    fn overlapping_detections_keep_the_most_confident() {
+        let faces = vec![
+            Face {
+                confidence: 0.6,
+                ..face_at(100.0, 100.0, 100.0, 100.0)
+            },
+            // the part of the same face left in a strip
+            Face {
+                confidence: 0.9,
+                ..face_at(100.0, 150.0, 100.0, 50.0)
+            },
+            Face {
+                confidence: 0.8,
+                ..face_at(400.0, 100.0, 100.0, 100.0)
+            },
+        ];
+        let merged = merge_overlapping(faces, 0.5);
+        let confidences: Vec<f32> = merged.iter().map(|face| face.confidence).collect();
+        assert_eq!(confidences, [0.9, 0.8]);
+    }
+}
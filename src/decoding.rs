@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use image::{DynamicImage, ImageResult};
+
+/// Function to decode an image, guessing its format from its contents.
+///
+/// HEIC/HEIF images are decoded with libheif when built with the heif feature and AVIF images
+/// are decoded by `image` when built with the avif feature.
+///
+/// # Arguments
+///
+/// * `encoded` - The encoded image.
+///
+/// # Returns
+///
+/// * The decoded image.
+pub fn decode(encoded: &[u8]) -> ImageResult<DynamicImage> {
+    #[cfg(feature = "heif")]
+    if crate::heif::is_heif(encoded) {
+        return crate::heif::decode(encoded);
+    }
+    image::load_from_memory(encoded)
+}
+
+/// Function to read and decode an image file, guessing its format from its contents so misnamed
+/// files are still decoded.
+///
+/// # Arguments
+///
+/// * `image_path` - Path to the image file.
+/// * `streaming` - True to decode the file as it is read rather than read it into memory first,
+///   so the encoded and decoded image aren't held at once.
+///
+/// # Returns
+///
+/// * The decoded image.
+pub fn open(image_path: &Path, streaming: bool) -> ImageResult<DynamicImage> {
+    if !streaming {
+        return decode(&std::fs::read(image_path)?);
+    }
+    let reader = image::io::Reader::open(image_path)?.with_guessed_format()?;
+    // libheif only decodes from memory
+    #[cfg(feature = "heif")]
+    if reader.format().is_none() {
+        return decode(&std::fs::read(image_path)?);
+    }
+    reader.decode()
+}
@@ -0,0 +1,103 @@
+use std::f32::consts::PI;
+
+use image::{imageops::FilterType, GenericImageView, Pixel};
+
+/// Side of the downscaled grayscale image the hash is computed from.
+const SAMPLE_SIZE: usize = 32;
+/// Side of the block of lowest frequencies kept, giving a 64 bit hash.
+const HASH_SIZE: usize = 8;
+
+/// Perceptual hashes of the crops written so far, to skip near-duplicates such as the crops of a
+/// burst of shots.
+pub struct Deduplicator {
+    hashes: Vec<u64>,
+    /// Largest number of differing hash bits for two crops to count as duplicates.
+    max_distance: u32,
+}
+
+impl Deduplicator {
+    pub fn new(max_distance: u32) -> Self {
+        Self {
+            hashes: Vec::new(),
+            max_distance,
+        }
+    }
+
+    /// Function to check a crop against the crops seen so far, remembering it if it is new.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The crop.
+    ///
+    /// # Returns
+    ///
+    /// * True if the crop is a near-duplicate of one seen before.
+    pub fn check<I>(&mut self, image: &I) -> bool
+    where
+        I: GenericImageView,
+        I::Pixel: Pixel<Subpixel = u8> + 'static,
+    {
+        let hash = phash(image);
+        if self
+            .hashes
+            .iter()
+            .any(|seen| (seen ^ hash).count_ones() <= self.max_distance)
+        {
+            return true;
+        }
+        self.hashes.push(hash);
+        false
+    }
+}
+
+/// Function to compute the DCT-based perceptual hash of an image, which changes little under
+/// resizing, recompression and small shifts.
+///
+/// # Arguments
+///
+/// * `image` - The image to hash.
+///
+/// # Returns
+///
+/// * The hash, with a bit set for every low frequency above the median.
+pub fn phash<I>(image: &I) -> u64
+where
+    I: GenericImageView,
+    I::Pixel: Pixel<Subpixel = u8> + 'static,
+{
+    let gray = image::imageops::grayscale(image);
+    let sample = image::imageops::resize(
+        &gray,
+        SAMPLE_SIZE as u32,
+        SAMPLE_SIZE as u32,
+        FilterType::Triangle,
+    );
+    let cosines: Vec<Vec<f32>> = (0..HASH_SIZE)
+        .map(|frequency| {
+            (0..SAMPLE_SIZE)
+                .map(|x| {
+                    ((2 * x + 1) as f32 * frequency as f32 * PI / (2 * SAMPLE_SIZE) as f32).cos()
+                })
+                .collect()
+        })
+        .collect();
+    let mut coefficients = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            let mut sum = 0.0;
+            for (x, y, pixel) in sample.enumerate_pixels() {
+                sum += pixel[0] as f32 * cosines[u][x as usize] * cosines[v][y as usize];
+            }
+            coefficients.push(sum);
+        }
+    }
+    // the DC term only reflects the average brightness so it is left out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|(_, coefficient)| **coefficient > median)
+        .fold(0, |hash, (i, _)| hash | 1 << i)
+}
@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use tracing::info;
+
+use crate::{
+    console, cropping, get_detector_pool, input_kind, list_input_dir, manifest, read_image,
+    skip_unsupported, Args, DetectFormat, InputKind,
+};
+
+/// Function to detect the faces in an image or directory and report them, without writing any
+/// crops.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run, for the detector and alpha background.
+/// * `path` - The image file or directory.
+/// * `format` - How to report the faces.
+pub fn run(args: &Args, path: &Path, format: DetectFormat) {
+    if !path.exists() {
+        panic!("Input path does not exist");
+    }
+    let image_paths = match path.is_file() {
+        true => vec![path.to_path_buf()],
+        false => list_input_dir(path, false, args.follow_symlinks)
+            .into_iter()
+            .filter(|path| input_kind(path) == InputKind::Image)
+            .filter(|path| !skip_unsupported(path))
+            .collect(),
+    };
+    info!("Instantiating face detector{}", console::symbol("🤖"));
+    let face_detector = get_detector_pool(args, false);
+    for image_path in &image_paths {
+        let input_image = read_image(image_path, args.low_memory);
+        let detection_image = match input_image.color().has_alpha() {
+            true => cropping::flatten_alpha(&input_image.to_rgba8(), args.alpha_background),
+            false => input_image.to_rgb8(),
+        };
+        let faces = cropping::detect_faces_in_image(&detection_image, &face_detector);
+        if format == DetectFormat::Json {
+            let faces: Vec<_> = faces.iter().map(manifest::FaceRecord::from).collect();
+            let detections = serde_json::json!({ "path": image_path, "faces": faces });
+            println!("{}", detections);
+            continue;
+        }
+        info!("Found {} faces in {}", faces.len(), image_path.display());
+        for (i, face) in faces.iter().enumerate() {
+            info!(
+                "Face {} at ({:.0}, {:.0}) with size {:.0}x{:.0} and confidence {:.3}",
+                i, face.rect.x, face.rect.y, face.rect.width, face.rect.height, face.confidence
+            );
+        }
+    }
+}
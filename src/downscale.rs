@@ -0,0 +1,148 @@
+use rust_faces::{Face, FaceDetector, Rect, RustFacesResult, ToArray3};
+
+use crate::cropping;
+
+/// Face detector that downscales images whose long side is above a limit before passing them to
+/// the wrapped detector, and maps the detections back to the coordinates of the full image.
+/// Detection rarely needs full resolution, so this makes high resolution inputs much cheaper to
+/// detect in while the crops are still cut from the full image.
+pub struct DownscalingDetector {
+    detector: Box<dyn FaceDetector>,
+    max_dim: u32,
+}
+
+impl DownscalingDetector {
+    /// Wraps a detector.
+    ///
+    /// # Arguments
+    ///
+    /// * `detector` - The detector to pass the downscaled images to.
+    /// * `max_dim` - Longest side, in pixels, an image is detected at.
+    pub fn new(detector: Box<dyn FaceDetector>, max_dim: u32) -> Self {
+        Self {
+            detector,
+            max_dim: max_dim.max(1),
+        }
+    }
+}
+
+impl FaceDetector for DownscalingDetector {
+    fn detect(&self, image: ndarray::ArrayViewD<u8>) -> RustFacesResult<Vec<Face>> {
+        let (height, width) = (image.shape()[0] as u32, image.shape()[1] as u32);
+        let long_side = width.max(height);
+        if long_side <= self.max_dim {
+            return self.detector.detect(image);
+        }
+        let scale = self.max_dim as f32 / long_side as f32;
+        let full_image = cropping::array_to_image(&image);
+        let downscaled = image::imageops::resize(
+            &full_image,
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+            image::imageops::FilterType::Triangle,
+        );
+        // the sides are rounded separately so each is mapped back by its own factor
+        let scale_x = downscaled.width() as f32 / width as f32;
+        let scale_y = downscaled.height() as f32 / height as f32;
+        let faces = self
+            .detector
+            .detect(downscaled.into_array3().view().into_dyn())?;
+        Ok(faces
+            .into_iter()
+            .map(|face| Face {
+                rect: Rect {
+                    x: face.rect.x / scale_x,
+                    y: face.rect.y / scale_y,
+                    width: face.rect.width / scale_x,
+                    height: face.rect.height / scale_y,
+                },
+                confidence: face.confidence,
+                landmarks: face.landmarks.map(|landmarks| {
+                    landmarks
+                        .into_iter()
+                        .map(|(x, y)| (x / scale_x, y / scale_y))
+                        .collect()
+                }),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Detector finding a face covering the centre of whatever image it is given, which records
+    /// the sizes of the images it was given.
+    struct CentredFaceDetector {
+        sizes: Arc<Mutex<Vec<(u32, u32)>>>,
+    }
+
+    impl FaceDetector for CentredFaceDetector {
+        fn detect(&self, image: ndarray::ArrayViewD<u8>) -> RustFacesResult<Vec<Face>> {
+            let (height, width) = (image.shape()[0] as f32, image.shape()[1] as f32);
+            self.sizes
+                .lock()
+                .unwrap()
+                .push((width as u32, height as u32));
+            Ok(vec![Face {
+                rect: Rect {
+                    x: width / 4.0,
+                    y: height / 4.0,
+                    width: width / 2.0,
+                    height: height / 2.0,
+                },
+                confidence: 0.9,
+                landmarks: Some(vec![(width / 2.0, height / 2.0)]),
+            }])
+        }
+    }
+
+    fn detect(width: u32, height: u32, max_dim: u32) -> (Vec<(u32, u32)>, Face) {
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let detector = DownscalingDetector::new(
+            Box::new(CentredFaceDetector {
+                sizes: sizes.clone(),
+            }),
+            max_dim,
+        );
+        let image = ndarray::Array3::<u8>::zeros((height as usize, width as usize, 3));
+        let mut faces = detector.detect(image.view().into_dyn()).unwrap();
+        let sizes = sizes.lock().unwrap().clone();
+        (sizes, faces.remove(0))
+    }
+
+    #[test]
+    fn small_images_are_detected_at_full_size() {
+        let (sizes, face) = detect(400, 300, 1000);
+        assert_eq!(sizes, [(400, 300)]);
+        assert_eq!(
+            (face.rect.x, face.rect.y, face.rect.width, face.rect.height),
+            (100.0, 75.0, 200.0, 150.0)
+        );
+    }
+
+    #[test]
+    fn detections_are_mapped_back_to_the_full_image() {
+        let (sizes, face) = detect(1200, 600, 300);
+        assert_eq!(sizes, [(300, 150)]);
+        assert_eq!(
+            (face.rect.x, face.rect.y, face.rect.width, face.rect.height),
+            (300.0, 150.0, 600.0, 300.0)
+        );
+        assert_eq!(face.landmarks, Some(vec![(600.0, 300.0)]));
+        assert_eq!(face.confidence, 0.9);
+    }
+
+    #[test]
+    fn rounded_sides_are_mapped_back_by_their_own_factor() {
+        // 333 rows are downscaled to 33 rather than 33.3, so mapping rows back by the 0.1 of the
+        // long side would leave the bottom of the image out
+        let (sizes, face) = detect(1000, 333, 100);
+        assert_eq!(sizes, [(100, 33)]);
+        assert!((face.rect.y + face.rect.height * 1.5 - 333.0).abs() < 1e-3);
+        assert!((face.rect.x + face.rect.width * 1.5 - 1000.0).abs() < 1e-3);
+    }
+}
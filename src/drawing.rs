@@ -0,0 +1,170 @@
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_filled_rect_mut, draw_hollow_rect_mut};
+use rust_faces::{Face, Rect};
+
+const DETECTION_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+const LANDMARK_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+const CROP_COLOR: Rgb<u8> = Rgb([0, 128, 255]);
+const LABEL_BACKGROUND: Rgb<u8> = Rgb([0, 0, 0]);
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// 3x5 bitmap glyphs for the characters used in labels. Each row is 3 bits, most significant
+/// bit on the left.
+fn glyph(character: char) -> Option<[u8; 5]> {
+    match character.to_ascii_uppercase() {
+        '0' => Some([0b111, 0b101, 0b101, 0b101, 0b111]),
+        '1' => Some([0b010, 0b110, 0b010, 0b010, 0b111]),
+        '2' => Some([0b111, 0b001, 0b111, 0b100, 0b111]),
+        '3' => Some([0b111, 0b001, 0b111, 0b001, 0b111]),
+        '4' => Some([0b101, 0b101, 0b111, 0b001, 0b001]),
+        '5' => Some([0b111, 0b100, 0b111, 0b001, 0b111]),
+        '6' => Some([0b111, 0b100, 0b111, 0b101, 0b111]),
+        '7' => Some([0b111, 0b001, 0b001, 0b001, 0b001]),
+        '8' => Some([0b111, 0b101, 0b111, 0b101, 0b111]),
+        '9' => Some([0b111, 0b101, 0b111, 0b001, 0b111]),
+        'A' => Some([0b010, 0b101, 0b111, 0b101, 0b101]),
+        'B' => Some([0b110, 0b101, 0b110, 0b101, 0b110]),
+        'C' => Some([0b011, 0b100, 0b100, 0b100, 0b011]),
+        'D' => Some([0b110, 0b101, 0b101, 0b101, 0b110]),
+        'E' => Some([0b111, 0b100, 0b110, 0b100, 0b111]),
+        'F' => Some([0b111, 0b100, 0b110, 0b100, 0b100]),
+        'G' => Some([0b011, 0b100, 0b101, 0b101, 0b011]),
+        'H' => Some([0b101, 0b101, 0b111, 0b101, 0b101]),
+        'I' => Some([0b111, 0b010, 0b010, 0b010, 0b111]),
+        'J' => Some([0b001, 0b001, 0b001, 0b101, 0b010]),
+        'K' => Some([0b101, 0b101, 0b110, 0b101, 0b101]),
+        'L' => Some([0b100, 0b100, 0b100, 0b100, 0b111]),
+        'M' => Some([0b101, 0b111, 0b111, 0b101, 0b101]),
+        'N' => Some([0b110, 0b101, 0b101, 0b101, 0b101]),
+        'O' => Some([0b010, 0b101, 0b101, 0b101, 0b010]),
+        'P' => Some([0b110, 0b101, 0b110, 0b100, 0b100]),
+        'Q' => Some([0b010, 0b101, 0b101, 0b110, 0b011]),
+        'R' => Some([0b110, 0b101, 0b110, 0b101, 0b101]),
+        'S' => Some([0b011, 0b100, 0b010, 0b001, 0b110]),
+        'T' => Some([0b111, 0b010, 0b010, 0b010, 0b010]),
+        'U' => Some([0b101, 0b101, 0b101, 0b101, 0b111]),
+        'V' => Some([0b101, 0b101, 0b101, 0b101, 0b010]),
+        'W' => Some([0b101, 0b101, 0b111, 0b111, 0b101]),
+        'X' => Some([0b101, 0b101, 0b010, 0b101, 0b101]),
+        'Y' => Some([0b101, 0b101, 0b010, 0b010, 0b010]),
+        'Z' => Some([0b111, 0b001, 0b010, 0b100, 0b111]),
+        '.' => Some([0b000, 0b000, 0b000, 0b000, 0b010]),
+        '-' => Some([0b000, 0b000, 0b111, 0b000, 0b000]),
+        '_' => Some([0b000, 0b000, 0b000, 0b000, 0b111]),
+        '#' => Some([0b101, 0b111, 0b101, 0b111, 0b101]),
+        ' ' => Some([0b000, 0b000, 0b000, 0b000, 0b000]),
+        _ => None,
+    }
+}
+
+/// Function to annotate an image with detections and the crops computed from them.
+///
+/// # Arguments
+///
+/// * `image` - The image to draw on.
+/// * `faces` - The detected faces. Boxes, landmarks and a `#index confidence` label are drawn.
+/// * `crops` - The crop rectangles computed for each face, in the same order as `faces`.
+pub fn draw_overlay(image: &mut RgbImage, faces: &[Face], crops: &[Rect]) {
+    // keep lines and labels legible on large photos
+    let scale = (image.width().max(image.height()) / 600).max(1);
+
+    for crop in crops {
+        draw_thick_rect(image, crop, scale, CROP_COLOR);
+    }
+
+    for (i, face) in faces.iter().enumerate() {
+        draw_thick_rect(image, &face.rect, scale, DETECTION_COLOR);
+        for landmark in face.landmarks.iter().flatten() {
+            draw_filled_circle_mut(
+                image,
+                (landmark.0 as i32, landmark.1 as i32),
+                2 * scale as i32,
+                LANDMARK_COLOR,
+            );
+        }
+
+        let label = format!("#{} {:.3}", i, face.confidence);
+        let label_y = face.rect.y as i32 - label_height(scale * 2) as i32;
+        draw_label(image, &label, face.rect.x as i32, label_y.max(0), scale * 2);
+    }
+}
+
+fn draw_thick_rect(image: &mut RgbImage, rect: &Rect, thickness: u32, color: Rgb<u8>) {
+    for offset in 0..thickness {
+        let width = rect.width as i32 + 2 * offset as i32;
+        let height = rect.height as i32 + 2 * offset as i32;
+        if width <= 0 || height <= 0 {
+            continue;
+        }
+        draw_hollow_rect_mut(
+            image,
+            imageproc::rect::Rect::at(rect.x as i32 - offset as i32, rect.y as i32 - offset as i32)
+                .of_size(width as u32, height as u32),
+            color,
+        );
+    }
+}
+
+/// Width in pixels of a label with the given number of characters.
+pub fn label_width(characters: usize, scale: u32) -> u32 {
+    (GLYPH_WIDTH + 1) * scale * characters as u32 + scale
+}
+
+/// Number of characters of a label that fit within the given width.
+pub fn label_capacity(width: u32, scale: u32) -> usize {
+    (width.saturating_sub(scale) / ((GLYPH_WIDTH + 1) * scale)) as usize
+}
+
+/// Height in pixels of a label.
+pub fn label_height(scale: u32) -> u32 {
+    (GLYPH_HEIGHT + 2) * scale
+}
+
+/// Function to draw a label onto a black background. Letters are drawn in upper case and
+/// characters without a glyph are left blank.
+///
+/// # Arguments
+///
+/// * `image` - The image to draw on.
+/// * `label` - The text to draw.
+/// * `x` - X coordinate of the top-left corner of the label.
+/// * `y` - Y coordinate of the top-left corner of the label.
+/// * `scale` - Size in pixels of a single glyph dot.
+pub fn draw_label(image: &mut RgbImage, label: &str, x: i32, y: i32, scale: u32) {
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    draw_filled_rect_mut(
+        image,
+        imageproc::rect::Rect::at(x, y).of_size(
+            label_width(label.chars().count(), scale),
+            label_height(scale),
+        ),
+        LABEL_BACKGROUND,
+    );
+
+    for (i, character) in label.chars().enumerate() {
+        let rows = match glyph(character) {
+            Some(rows) => rows,
+            None => continue,
+        };
+        let glyph_x = x + (scale + i as u32 * advance) as i32;
+        let glyph_y = y + scale as i32;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                draw_filled_rect_mut(
+                    image,
+                    imageproc::rect::Rect::at(
+                        glyph_x + (column * scale) as i32,
+                        glyph_y + (row as u32 * scale) as i32,
+                    )
+                    .of_size(scale, scale),
+                    DETECTION_COLOR,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::{embedding, manifest::CropRecord};
+
+/// A crop in a group of crops of the same person from different inputs.
+#[derive(Debug, Serialize)]
+pub struct DuplicateCrop {
+    /// Path of the crop, relative to the output directory.
+    pub path: PathBuf,
+    /// Path of the input the crop was taken from.
+    pub source: PathBuf,
+    /// Index of the face within the input.
+    pub face: usize,
+}
+
+/// Crops whose embeddings are nearly identical although they come from different inputs, e.g. the
+/// same frame exported twice or a duplicated download.
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    /// Lowest similarity between two crops of the group that were matched.
+    pub min_similarity: f32,
+    pub crops: Vec<DuplicateCrop>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+/// Function to group the crops of a run whose embeddings are nearly identical across different
+/// inputs. Every pair of crops is compared, so this is quadratic in the number of crops.
+///
+/// # Arguments
+///
+/// * `crops` - The crops of the run. Crops without an embedding are left out.
+/// * `min_similarity` - Lowest cosine similarity of two crops for them to be duplicates.
+///
+/// # Returns
+///
+/// * The groups of two or more crops, in the order of their first crop in the run.
+pub fn duplicate_groups(crops: &[CropRecord], min_similarity: f32) -> Vec<DuplicateGroup> {
+    let crops: Vec<_> = crops
+        .iter()
+        .filter_map(|crop| crop.embedding.as_ref().map(|embedding| (crop, embedding)))
+        .collect();
+    // each crop points at another crop of its group, and the root of the group at itself
+    let mut parents: Vec<usize> = (0..crops.len()).collect();
+    let mut similarities: Vec<f32> = vec![1.0; crops.len()];
+    for (i, (crop, embedding)) in crops.iter().enumerate() {
+        for (j, (other, other_embedding)) in crops.iter().enumerate().skip(i + 1) {
+            if crop.source == other.source {
+                continue;
+            }
+            let similarity = embedding::cosine_similarity(embedding, other_embedding);
+            if similarity < min_similarity {
+                continue;
+            }
+            let (root_i, root_j) = (root(&mut parents, i), root(&mut parents, j));
+            let (first, second) = (root_i.min(root_j), root_i.max(root_j));
+            parents[second] = first;
+            similarities[first] = similarities[first]
+                .min(similarities[second])
+                .min(similarity);
+        }
+    }
+
+    let mut groups: Vec<(usize, DuplicateGroup)> = Vec::new();
+    for (i, (crop, _)) in crops.iter().enumerate() {
+        let group_root = root(&mut parents, i);
+        let duplicate = DuplicateCrop {
+            path: crop.path.clone(),
+            source: crop.source.clone(),
+            face: crop.face,
+        };
+        match groups.iter_mut().find(|(root, _)| *root == group_root) {
+            Some((_, group)) => group.crops.push(duplicate),
+            None => groups.push((
+                group_root,
+                DuplicateGroup {
+                    min_similarity: similarities[group_root],
+                    crops: vec![duplicate],
+                },
+            )),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(_, group)| group)
+        .filter(|group| group.crops.len() > 1)
+        .collect()
+}
+
+fn root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+pub fn save(report_path: &Path, report: &DuplicateReport) {
+    let contents = serde_json::to_string_pretty(report)
+        .unwrap_or_else(|_| panic!("Failed to serialize duplicate report"));
+    std::fs::write(report_path, contents)
+        .unwrap_or_else(|_| panic!("Failed to write duplicate report"));
+}
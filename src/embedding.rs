@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use ndarray::{Array4, CowArray};
+use ort::{tensor::OrtOwnedTensor, Environment, OrtResult, Session, Value};
+use rust_faces::Face;
+
+use crate::acceleration::{self, SessionOptions};
+
+/// Side of the aligned face the recognition model takes as input.
+const ALIGNED_SIZE: u32 = 112;
+/// Where the eyes, nose tip and mouth corners of a face are placed in the aligned image, as in
+/// the ArcFace training data.
+const ALIGNED_LANDMARKS: [(f32, f32); 5] = [
+    (38.2946, 51.6963),
+    (73.5318, 51.4800),
+    (56.0252, 71.7366),
+    (41.5493, 92.3655),
+    (70.7299, 92.2041),
+];
+
+/// ArcFace-style face recognition model, mapping an aligned face to an identity embedding.
+pub struct EmbeddingModel {
+    session: Session,
+}
+
+impl EmbeddingModel {
+    /// Loads a recognition model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_path` - Path to the ONNX model, taking a 1x3x112x112 RGB input.
+    /// * `session_options` - Threading and graph optimization of the session.
+    pub fn from_file(model_path: &Path, session_options: SessionOptions) -> OrtResult<Self> {
+        let environment = Environment::builder()
+            .with_name("facecrop")
+            .build()?
+            .into_arc();
+        let session = acceleration::session_builder(&environment, session_options)?
+            .with_model_from_file(model_path)?;
+        Ok(Self { session })
+    }
+
+    /// Function to compute the embedding of a face.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image the face was detected in.
+    /// * `face` - The face.
+    ///
+    /// # Returns
+    ///
+    /// * The L2-normalized embedding, e.g. 512 values for ArcFace.
+    pub fn embed(&self, image: &image::RgbImage, face: &Face) -> OrtResult<Vec<f32>> {
+        let aligned = align(image, face);
+        let input = Array4::from_shape_fn(
+            (1, 3, ALIGNED_SIZE as usize, ALIGNED_SIZE as usize),
+            |(_, c, y, x)| (aligned.get_pixel(x as u32, y as u32)[c] as f32 - 127.5) / 127.5,
+        );
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let embedding: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let mut embedding: Vec<f32> = embedding.view().iter().copied().collect();
+        let norm = embedding
+            .iter()
+            .map(|value| value * value)
+            .sum::<f32>()
+            .sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|value| *value /= norm);
+        }
+        Ok(embedding)
+    }
+}
+
+/// Function to warp a face into the pose the recognition model expects.
+///
+/// Faces with five landmarks are aligned with the similarity transform that best maps their
+/// landmarks onto `ALIGNED_LANDMARKS`. Other faces are cropped to their bounding box instead.
+///
+/// # Arguments
+///
+/// * `image` - The image the face was detected in.
+/// * `face` - The face.
+///
+/// # Returns
+///
+/// * The aligned face, `ALIGNED_SIZE` pixels square.
+fn align(image: &image::RgbImage, face: &Face) -> image::RgbImage {
+    let size = ALIGNED_SIZE as f32;
+    // maps points of the aligned image to the input image as (scale * rotation, translation)
+    let ((a, b), (tx, ty)) = match &face.landmarks {
+        Some(landmarks) if landmarks.len() == ALIGNED_LANDMARKS.len() => {
+            similarity_transform(&ALIGNED_LANDMARKS, landmarks)
+        }
+        _ => {
+            let scale = face.rect.width.max(face.rect.height) / size;
+            let x = face.rect.x + (face.rect.width - size * scale) / 2.0;
+            let y = face.rect.y + (face.rect.height - size * scale) / 2.0;
+            ((scale, 0.0), (x, y))
+        }
+    };
+    image::RgbImage::from_fn(ALIGNED_SIZE, ALIGNED_SIZE, |x, y| {
+        let (x, y) = (x as f32, y as f32);
+        image::imageops::interpolate_bilinear(image, a * x - b * y + tx, b * x + a * y + ty)
+            .unwrap_or(image::Rgb([0, 0, 0]))
+    })
+}
+
+/// Function to fit the least-squares similarity transform between two sets of points.
+///
+/// # Arguments
+///
+/// * `from` - The points to map.
+/// * `to` - The points they should map to.
+///
+/// # Returns
+///
+/// * `((a, b), (tx, ty))` such that `to = [a -b; b a] * from + t`.
+fn similarity_transform(from: &[(f32, f32)], to: &[(f32, f32)]) -> ((f32, f32), (f32, f32)) {
+    let count = from.len() as f32;
+    let mean = |points: &[(f32, f32)]| {
+        let (x, y) = points
+            .iter()
+            .fold((0.0, 0.0), |(x, y), point| (x + point.0, y + point.1));
+        (x / count, y / count)
+    };
+    let (from_x, from_y) = mean(from);
+    let (to_x, to_y) = mean(to);
+    let (mut dot, mut cross, mut norm) = (0.0, 0.0, 0.0);
+    for (p, q) in from.iter().zip(to) {
+        let (px, py) = (p.0 - from_x, p.1 - from_y);
+        let (qx, qy) = (q.0 - to_x, q.1 - to_y);
+        dot += px * qx + py * qy;
+        cross += px * qy - py * qx;
+        norm += px * px + py * py;
+    }
+    let (a, b) = (dot / norm, cross / norm);
+    let tx = to_x - (a * from_x - b * from_y);
+    let ty = to_y - (b * from_x + a * from_y);
+    ((a, b), (tx, ty))
+}
+
+/// Function to compare two embeddings.
+///
+/// # Arguments
+///
+/// * `a` - The first embedding.
+/// * `b` - The second embedding.
+///
+/// # Returns
+///
+/// * The cosine similarity, up to 1.0 for identical embeddings.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |embedding: &[f32]| {
+        embedding
+            .iter()
+            .map(|value| value * value)
+            .sum::<f32>()
+            .sqrt()
+    };
+    dot / (norm(a) * norm(b)).max(f32::EPSILON)
+}
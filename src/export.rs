@@ -0,0 +1,256 @@
+use std::path::{Path, PathBuf};
+
+use rust_faces::Face;
+use serde::Serialize;
+
+/// Names of the five landmarks detectors place, in the order they return them. Left and right
+/// are the subject's, so the right eye is on the left of the image.
+const KEYPOINT_NAMES: [&str; 5] = ["right_eye", "left_eye", "nose", "mouth_right", "mouth_left"];
+/// Visibility flag of a labeled and visible COCO or YOLO keypoint.
+const KEYPOINT_VISIBLE: f32 = 2.0;
+/// Name of the file listing the classes of a YOLO dataset, one per line by class index.
+const YOLO_CLASSES_FILE: &str = "classes.txt";
+
+/// The faces of a processed image, for exporting them as annotations.
+#[derive(Debug)]
+pub struct AnnotatedImage {
+    /// Path of the image relative to the input directory.
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub faces: Vec<Face>,
+}
+
+#[derive(Serialize)]
+struct CocoDataset {
+    images: Vec<CocoImage>,
+    annotations: Vec<CocoAnnotation>,
+    categories: Vec<CocoCategory>,
+}
+
+#[derive(Serialize)]
+struct CocoImage {
+    id: usize,
+    file_name: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct CocoAnnotation {
+    id: usize,
+    image_id: usize,
+    category_id: usize,
+    /// `[x, y, width, height]` of the face box.
+    bbox: [f32; 4],
+    area: f32,
+    iscrowd: u8,
+    /// Confidence of the detector, as in COCO results files.
+    score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keypoints: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_keypoints: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct CocoCategory {
+    id: usize,
+    name: &'static str,
+    supercategory: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keypoints: Option<[&'static str; 5]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skeleton: Option<Vec<[usize; 2]>>,
+}
+
+/// Function to write the faces of the processed images as a COCO dataset with a single `face`
+/// category.
+///
+/// # Arguments
+///
+/// * `annotations_path` - Path of the JSON file to write.
+/// * `images` - The processed images and their faces.
+/// * `keypoints` - True to also write the landmarks of the faces as COCO keypoints.
+pub fn save_coco(annotations_path: &Path, images: &[AnnotatedImage], keypoints: bool) {
+    let mut annotations = Vec::new();
+    for (image_index, image) in images.iter().enumerate() {
+        for face in &image.faces {
+            // faces without five landmarks, e.g. from supplied boxes, have no keypoints
+            let face_keypoints = face
+                .landmarks
+                .as_ref()
+                .filter(|landmarks| keypoints && landmarks.len() == KEYPOINT_NAMES.len())
+                .map(|landmarks| {
+                    landmarks
+                        .iter()
+                        .flat_map(|(x, y)| [*x, *y, KEYPOINT_VISIBLE])
+                        .collect::<Vec<_>>()
+                });
+            annotations.push(CocoAnnotation {
+                id: annotations.len() + 1,
+                image_id: image_index + 1,
+                category_id: 1,
+                bbox: [face.rect.x, face.rect.y, face.rect.width, face.rect.height],
+                area: face.rect.width * face.rect.height,
+                iscrowd: 0,
+                score: face.confidence,
+                num_keypoints: keypoints.then(|| match face_keypoints {
+                    Some(_) => KEYPOINT_NAMES.len(),
+                    None => 0,
+                }),
+                keypoints: keypoints
+                    .then(|| face_keypoints.unwrap_or_else(|| vec![0.0; KEYPOINT_NAMES.len() * 3])),
+            });
+        }
+    }
+    let dataset = CocoDataset {
+        images: images
+            .iter()
+            .enumerate()
+            .map(|(image_index, image)| CocoImage {
+                id: image_index + 1,
+                file_name: image.path.to_string_lossy().replace('\\', "/"),
+                width: image.width,
+                height: image.height,
+            })
+            .collect(),
+        annotations,
+        categories: vec![CocoCategory {
+            id: 1,
+            name: "face",
+            supercategory: "person",
+            keypoints: keypoints.then_some(KEYPOINT_NAMES),
+            skeleton: keypoints.then(Vec::new),
+        }],
+    };
+    let contents = serde_json::to_string(&dataset)
+        .unwrap_or_else(|_| panic!("Failed to serialize annotations"));
+    std::fs::write(annotations_path, contents)
+        .unwrap_or_else(|_| panic!("Failed to write annotations"));
+}
+
+/// Function to write the faces of the processed images as YOLO labels, with a `.txt` file per
+/// image listing its faces as class 0 and their boxes normalized to the image, next to a
+/// `classes.txt` naming the class.
+///
+/// # Arguments
+///
+/// * `labels_dir` - Directory to write the labels to. The labels mirror the paths of the images
+///   in the input directory.
+/// * `images` - The processed images and their faces.
+/// * `keypoints` - True to also write the landmarks of the faces as YOLO pose keypoints.
+pub fn save_yolo(labels_dir: &Path, images: &[AnnotatedImage], keypoints: bool) {
+    std::fs::create_dir_all(labels_dir)
+        .unwrap_or_else(|_| panic!("Failed to create annotations directory"));
+    std::fs::write(labels_dir.join(YOLO_CLASSES_FILE), "face\n")
+        .unwrap_or_else(|_| panic!("Failed to write annotation classes"));
+    for image in images {
+        let (width, height) = (image.width as f32, image.height as f32);
+        let mut labels = String::new();
+        for face in &image.faces {
+            // boxes reaching past the image are clipped as YOLO expects values from 0.0 to 1.0
+            let left = (face.rect.x / width).clamp(0.0, 1.0);
+            let top = (face.rect.y / height).clamp(0.0, 1.0);
+            let right = ((face.rect.x + face.rect.width) / width).clamp(0.0, 1.0);
+            let bottom = ((face.rect.y + face.rect.height) / height).clamp(0.0, 1.0);
+            labels.push_str(&format!(
+                "0 {:.6} {:.6} {:.6} {:.6}",
+                (left + right) / 2.0,
+                (top + bottom) / 2.0,
+                right - left,
+                bottom - top,
+            ));
+            if keypoints {
+                match &face.landmarks {
+                    Some(landmarks) if landmarks.len() == KEYPOINT_NAMES.len() => {
+                        for (x, y) in landmarks {
+                            labels.push_str(&format!(
+                                " {:.6} {:.6} {}",
+                                (x / width).clamp(0.0, 1.0),
+                                (y / height).clamp(0.0, 1.0),
+                                KEYPOINT_VISIBLE,
+                            ));
+                        }
+                    }
+                    _ => labels.push_str(&" 0 0 0".repeat(KEYPOINT_NAMES.len())),
+                }
+            }
+            labels.push('\n');
+        }
+        let labels_path = labels_dir.join(&image.path).with_extension("txt");
+        if let Some(parent) = labels_path.parent() {
+            std::fs::create_dir_all(parent)
+                .unwrap_or_else(|_| panic!("Failed to create annotations directory"));
+        }
+        std::fs::write(labels_path, labels)
+            .unwrap_or_else(|_| panic!("Failed to write annotations"));
+    }
+}
+
+/// Function to write the faces of the processed images as Pascal VOC annotations, with an `.xml`
+/// file per image listing its faces as `face` objects.
+///
+/// # Arguments
+///
+/// * `annotations_dir` - Directory to write the annotations to. The annotations mirror the paths
+///   of the images in the input directory.
+/// * `images` - The processed images and their faces.
+pub fn save_voc(annotations_dir: &Path, images: &[AnnotatedImage]) {
+    for image in images {
+        let file_name = image
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let mut objects = String::new();
+        for face in &image.faces {
+            // boxes reaching past the image are clipped and marked as truncated
+            let (width, height) = (image.width as f32, image.height as f32);
+            let left = face.rect.x.clamp(0.0, width);
+            let top = face.rect.y.clamp(0.0, height);
+            let right = (face.rect.x + face.rect.width).clamp(0.0, width);
+            let bottom = (face.rect.y + face.rect.height).clamp(0.0, height);
+            let truncated = left != face.rect.x
+                || top != face.rect.y
+                || right != face.rect.x + face.rect.width
+                || bottom != face.rect.y + face.rect.height;
+            objects.push_str(&format!(
+                "  <object>\n    <name>face</name>\n    <pose>Unspecified</pose>\n    \
+                <truncated>{}</truncated>\n    <difficult>0</difficult>\n    <bndbox>\n      \
+                <xmin>{}</xmin>\n      <ymin>{}</ymin>\n      <xmax>{}</xmax>\n      \
+                <ymax>{}</ymax>\n    </bndbox>\n  </object>\n",
+                truncated as u8,
+                left.round() as u32,
+                top.round() as u32,
+                right.round() as u32,
+                bottom.round() as u32
+            ));
+        }
+        let annotation = format!(
+            "<annotation>\n  <filename>{}</filename>\n  <path>{}</path>\n  <source>\n    \
+            <database>facecrop</database>\n  </source>\n  <size>\n    <width>{}</width>\n    \
+            <height>{}</height>\n    <depth>3</depth>\n  </size>\n  <segmented>0</segmented>\n\
+            {}</annotation>\n",
+            escape_text(&file_name),
+            escape_text(&image.path.to_string_lossy().replace('\\', "/")),
+            image.width,
+            image.height,
+            objects
+        );
+        let annotation_path = annotations_dir.join(&image.path).with_extension("xml");
+        if let Some(parent) = annotation_path.parent() {
+            std::fs::create_dir_all(parent)
+                .unwrap_or_else(|_| panic!("Failed to create annotations directory"));
+        }
+        std::fs::write(annotation_path, annotation)
+            .unwrap_or_else(|_| panic!("Failed to write annotations"));
+    }
+}
+
+fn escape_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
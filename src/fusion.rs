@@ -0,0 +1,183 @@
+use rust_faces::{Face, Rect};
+
+use crate::cropping;
+
+/// Overlap above which boxes from different detectors are taken to be the same face.
+const FUSION_IOU: f32 = 0.55;
+
+/// A face fused from the detections of several detectors.
+pub struct FusedFace {
+    pub face: Face,
+    /// Confidence of each detector in the face, in the order the detections were given, or None
+    /// if the detector missed it.
+    pub confidences: Vec<Option<f32>>,
+}
+
+/// Boxes found to be the same face, with the detector each came from.
+struct Cluster {
+    members: Vec<(usize, Face)>,
+    fused: Rect,
+}
+
+impl Cluster {
+    fn new(source: usize, face: Face) -> Self {
+        Self {
+            fused: face.rect,
+            members: vec![(source, face)],
+        }
+    }
+
+    fn add(&mut self, source: usize, face: Face) {
+        self.members.push((source, face));
+        // the fused box is the confidence weighted average of the boxes
+        let total: f32 = self.members.iter().map(|(_, face)| face.confidence).sum();
+        let weighted = |value: fn(&Rect) -> f32| {
+            self.members
+                .iter()
+                .map(|(_, face)| value(&face.rect) * face.confidence)
+                .sum::<f32>()
+                / total
+        };
+        self.fused = Rect {
+            x: weighted(|rect| rect.x),
+            y: weighted(|rect| rect.y),
+            width: weighted(|rect| rect.width),
+            height: weighted(|rect| rect.height),
+        };
+    }
+}
+
+/// Function to fuse the detections of several detectors with weighted box fusion. Unlike
+/// non-maximum suppression, which keeps the most confident box of a face, every box of a face
+/// contributes to its fused box, weighted by its confidence.
+///
+/// # Arguments
+///
+/// * `detections` - The faces found by each detector.
+///
+/// # Returns
+///
+/// * The fused faces, whose confidence is the mean confidence of their boxes scaled down by the
+///   share of detectors that missed them, ordered left to right and top to bottom.
+pub fn weighted_box_fusion(detections: Vec<Vec<Face>>) -> Vec<FusedFace> {
+    let sources = detections.len();
+    let mut faces: Vec<(usize, Face)> = detections
+        .into_iter()
+        .enumerate()
+        .flat_map(|(source, faces)| faces.into_iter().map(move |face| (source, face)))
+        .collect();
+    faces.sort_by(|(_, a), (_, b)| b.confidence.total_cmp(&a.confidence));
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (source, face) in faces {
+        let best = clusters
+            .iter_mut()
+            .map(|cluster| (cluster.fused.iou(&face.rect), cluster))
+            .filter(|(iou, _)| *iou > FUSION_IOU)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b));
+        match best {
+            Some((_, cluster)) => cluster.add(source, face),
+            None => clusters.push(Cluster::new(source, face)),
+        }
+    }
+
+    let mut fused: Vec<FusedFace> = clusters
+        .into_iter()
+        .map(|cluster| {
+            let mut confidences = vec![None; sources];
+            for (source, face) in &cluster.members {
+                let confidence = confidences[*source].get_or_insert(face.confidence);
+                *confidence = confidence.max(face.confidence);
+            }
+            let found_by = confidences.iter().flatten().count();
+            let mean = cluster
+                .members
+                .iter()
+                .map(|(_, face)| face.confidence)
+                .sum::<f32>()
+                / cluster.members.len() as f32;
+            // the landmarks of the most confident box, as detectors place different landmarks
+            let landmarks = cluster.members[0].1.landmarks.clone();
+            FusedFace {
+                face: Face {
+                    rect: cluster.fused,
+                    confidence: mean * found_by as f32 / sources as f32,
+                    landmarks,
+                },
+                confidences,
+            }
+        })
+        .collect();
+    fused.sort_by(|a, b| cropping::face_order(&a.face, &b.face));
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(x: f32, y: f32, size: f32, confidence: f32) -> Face {
+        Face {
+            rect: Rect::at(x, y).with_size(size, size),
+            confidence,
+            landmarks: Some(vec![(x, y)]),
+        }
+    }
+
+    fn assert_rect(face: &FusedFace, (x, y, width, height): (f32, f32, f32, f32)) {
+        let rect = face.face.rect;
+        for (actual, expected) in [
+            (rect.x, x),
+            (rect.y, y),
+            (rect.width, width),
+            (rect.height, height),
+        ] {
+            assert!(
+                (actual - expected).abs() < 1e-3,
+                "{:?} != {:?}",
+                rect,
+                (x, y, width, height)
+            );
+        }
+    }
+
+    #[test]
+    fn overlapping_boxes_are_averaged_by_confidence() {
+        let fused = weighted_box_fusion(vec![
+            vec![face(0.0, 0.0, 100.0, 0.9)],
+            vec![face(10.0, 10.0, 100.0, 0.6)],
+        ]);
+        assert_eq!(fused.len(), 1);
+        assert_rect(&fused[0], (4.0, 4.0, 100.0, 100.0));
+        assert!((fused[0].face.confidence - 0.75).abs() < 1e-6);
+        assert_eq!(fused[0].confidences, [Some(0.9), Some(0.6)]);
+        // landmarks come from the most confident box
+        assert_eq!(fused[0].face.landmarks, Some(vec![(0.0, 0.0)]));
+    }
+
+    #[test]
+    fn faces_missed_by_a_detector_lose_confidence() {
+        let fused = weighted_box_fusion(vec![
+            vec![face(0.0, 0.0, 100.0, 0.9)],
+            vec![face(500.0, 0.0, 50.0, 0.8), face(0.0, 0.0, 100.0, 0.7)],
+        ]);
+        assert_eq!(fused.len(), 2);
+        // ordered left to right whatever the order of the detections
+        assert_rect(&fused[0], (0.0, 0.0, 100.0, 100.0));
+        assert_eq!(fused[0].confidences, [Some(0.9), Some(0.7)]);
+        assert_rect(&fused[1], (500.0, 0.0, 50.0, 50.0));
+        assert!((fused[1].face.confidence - 0.4).abs() < 1e-6);
+        assert_eq!(fused[1].confidences, [None, Some(0.8)]);
+    }
+
+    #[test]
+    fn boxes_overlapping_too_little_stay_separate() {
+        let fused = weighted_box_fusion(vec![
+            vec![face(0.0, 0.0, 100.0, 0.9)],
+            vec![face(50.0, 0.0, 100.0, 0.9)],
+        ]);
+        assert_eq!(fused.len(), 2);
+        assert_eq!(fused[0].confidences, [Some(0.9), None]);
+        assert_eq!(fused[1].confidences, [None, Some(0.9)]);
+    }
+}
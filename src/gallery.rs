@@ -0,0 +1,152 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tracing::warn;
+
+use crate::manifest::CropRecord;
+
+/// Long side of the thumbnails embedded in the report.
+const THUMBNAIL_SIZE: u32 = 160;
+/// JPEG quality of the thumbnails, which only need to be good enough to judge a crop.
+const THUMBNAIL_QUALITY: u8 = 80;
+/// Confidence from which a crop gets a high confidence badge.
+const HIGH_CONFIDENCE: f32 = 0.9;
+/// Confidence from which a crop gets a medium confidence badge.
+const MEDIUM_CONFIDENCE: f32 = 0.7;
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;background:#fafafa;color:#222}\
+h2{font-size:1em;margin:1.5em 0 .5em;word-break:break-all}\
+.crops{display:flex;flex-wrap:wrap;gap:12px}\
+figure{margin:0;padding:6px;background:#fff;border:1px solid #ddd;border-radius:4px}\
+figure img{display:block;max-width:160px;max-height:160px}\
+figcaption{margin-top:4px;display:flex;gap:4px;flex-wrap:wrap}\
+.badge{font-size:.75em;padding:1px 6px;border-radius:8px;background:#e5e5e5}\
+.high{background:#c8ecc9}.medium{background:#fbe7b0}.low{background:#f6c4c4}";
+
+/// Function to write a self-contained HTML page with a thumbnail of every crop of a run, grouped
+/// by the input it was taken from, with badges for the confidence and size of the face and links
+/// to the crops and inputs.
+///
+/// # Arguments
+///
+/// * `report_path` - Path of the HTML file to write.
+/// * `output_dir` - The output directory the crop paths are relative to.
+/// * `crops` - The crops of the run.
+pub fn save(report_path: &Path, output_dir: &Path, crops: &[CropRecord]) {
+    let mut sources: Vec<(&Path, Vec<&CropRecord>)> = Vec::new();
+    for crop in crops {
+        match sources
+            .iter_mut()
+            .find(|(source, _)| *source == crop.source)
+        {
+            Some((_, source_crops)) => source_crops.push(crop),
+            None => sources.push((&crop.source, vec![crop])),
+        }
+    }
+
+    let mut body = format!(
+        "<h1>facecrop report</h1>\n<p>{} crop(s) from {} input(s)</p>\n",
+        crops.len(),
+        sources.len()
+    );
+    for (source, source_crops) in &sources {
+        body.push_str(&format!(
+            "<section>\n<h2><a href=\"{}\">{}</a></h2>\n<div class=\"crops\">\n",
+            file_url(source),
+            escape(&source.to_string_lossy())
+        ));
+        for crop in source_crops {
+            let crop_path = output_dir.join(&crop.path);
+            let thumbnail = match thumbnail(&crop_path) {
+                Some(thumbnail) => format!(
+                    "<img src=\"data:image/jpeg;base64,{}\" alt=\"{}\">",
+                    thumbnail,
+                    escape(&crop.path.to_string_lossy())
+                ),
+                None => {
+                    warn!("Failed to read crop {} for the report", crop_path.display());
+                    "<span>missing</span>".to_string()
+                }
+            };
+            let confidence_class = match crop.confidence {
+                confidence if confidence >= HIGH_CONFIDENCE => "high",
+                confidence if confidence >= MEDIUM_CONFIDENCE => "medium",
+                _ => "low",
+            };
+            body.push_str(&format!(
+                "<figure><a href=\"{}\">{}</a><figcaption>\
+                <span class=\"badge {}\">{:.2}</span>\
+                <span class=\"badge\">{}</span>\
+                <span class=\"badge\">{:.0}×{:.0}</span>\
+                </figcaption></figure>\n",
+                file_url(&crop_path),
+                thumbnail,
+                confidence_class,
+                crop.confidence,
+                crop.size_bucket.name(),
+                crop.face_rect.width,
+                crop.face_rect.height
+            ));
+        }
+        body.push_str("</div>\n</section>\n");
+    }
+
+    let html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+        <title>facecrop report</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        STYLE, body
+    );
+    std::fs::write(report_path, html).unwrap_or_else(|_| panic!("Failed to write report"));
+}
+
+/// Function to encode a small JPEG of a crop for embedding in the report.
+///
+/// # Arguments
+///
+/// * `crop_path` - Path to the crop.
+///
+/// # Returns
+///
+/// * The base64 encoded thumbnail, or None if the crop can't be read.
+fn thumbnail(crop_path: &Path) -> Option<String> {
+    let crop = image::open(crop_path).ok()?;
+    let thumbnail =
+        image::DynamicImage::ImageRgb8(crop.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).into_rgb8());
+    let mut encoded = Vec::new();
+    thumbnail
+        .write_to(
+            &mut Cursor::new(&mut encoded),
+            image::ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY),
+        )
+        .ok()?;
+    Some(STANDARD.encode(encoded))
+}
+
+/// Function to link a file from the report, which can be written anywhere.
+fn file_url(path: &Path) -> String {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut url = String::from("file://");
+    if !path.starts_with('/') {
+        url.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
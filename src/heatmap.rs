@@ -0,0 +1,75 @@
+use image::{Rgb, RgbImage};
+use rust_faces::Face;
+
+use crate::drawing;
+
+/// Detector score below which candidates are left out of the heatmap. Far below the detection
+/// threshold so near misses show up.
+pub const CANDIDATE_SCORE_THRESHOLD: f32 = 0.05;
+const LABEL_SCALE: u32 = 2;
+
+/// Function to render the detector scores of face candidates over a dimmed grayscale copy of an
+/// image. Each candidate contributes a blob over its box that fades from its score at the center,
+/// and overlapping candidates keep the highest score.
+///
+/// # Arguments
+///
+/// * `input_image` - The image the candidates were detected in.
+/// * `candidates` - Face candidates from a detector with a low score threshold.
+///
+/// # Returns
+///
+/// * The heatmap, with each candidate labelled with its score.
+pub fn render(input_image: &RgbImage, candidates: &[Face]) -> RgbImage {
+    let (width, height) = input_image.dimensions();
+    let mut scores = vec![0.0f32; (width * height) as usize];
+    for candidate in candidates {
+        let rect = candidate.rect;
+        let (center_x, center_y) = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+        let x_range = (rect.x.max(0.0) as u32)..((rect.x + rect.width).min(width as f32) as u32);
+        let y_range = (rect.y.max(0.0) as u32)..((rect.y + rect.height).min(height as f32) as u32);
+        for y in y_range {
+            for x in x_range.clone() {
+                let dx = (x as f32 - center_x) / (rect.width / 2.0);
+                let dy = (y as f32 - center_y) / (rect.height / 2.0);
+                let score = candidate.confidence * (1.0 - (dx * dx + dy * dy)).max(0.0);
+                let cell = &mut scores[(y * width + x) as usize];
+                *cell = cell.max(score);
+            }
+        }
+    }
+
+    let mut heatmap = RgbImage::from_fn(width, height, |x, y| {
+        let pixel = input_image.get_pixel(x, y);
+        let luma =
+            (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) * 0.5;
+        let score = scores[(y * width + x) as usize];
+        let color = score_color(score);
+        Rgb(std::array::from_fn(|c| {
+            (luma * (1.0 - score) + color[c] as f32 * score).round() as u8
+        }))
+    });
+
+    for candidate in candidates {
+        drawing::draw_label(
+            &mut heatmap,
+            &format!("{:.2}", candidate.confidence),
+            candidate.rect.x as i32,
+            candidate.rect.y as i32 - drawing::label_height(LABEL_SCALE) as i32,
+            LABEL_SCALE,
+        );
+    }
+
+    heatmap
+}
+
+/// Maps a score in [0, 1] from blue through green and yellow to red.
+fn score_color(score: f32) -> Rgb<u8> {
+    let score = score.clamp(0.0, 1.0);
+    let (r, g, b) = match score {
+        s if s < 1.0 / 3.0 => (0.0, s * 3.0, 1.0 - s * 3.0),
+        s if s < 2.0 / 3.0 => ((s - 1.0 / 3.0) * 3.0, 1.0, 0.0),
+        s => (1.0, 1.0 - (s - 2.0 / 3.0) * 3.0, 0.0),
+    };
+    Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+}
@@ -0,0 +1,64 @@
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    DynamicImage, ImageError, ImageResult, RgbImage, RgbaImage,
+};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+/// `ftyp` brands of HEIF images, excluding AVIF which is decoded by `image`.
+const HEIF_BRANDS: [&[u8; 4]; 6] = [b"heic", b"heix", b"hevc", b"hevx", b"mif1", b"msf1"];
+
+/// Returns true if the encoded image starts with a HEIF `ftyp` box.
+pub fn is_heif(encoded: &[u8]) -> bool {
+    encoded.len() >= 12
+        && &encoded[4..8] == b"ftyp"
+        && HEIF_BRANDS.iter().any(|brand| &encoded[8..12] == *brand)
+}
+
+/// Function to decode the primary image of a HEIC/HEIF file.
+///
+/// # Arguments
+///
+/// * `encoded` - The encoded image.
+///
+/// # Returns
+///
+/// * The decoded image as RGB, or RGBA if the image has an alpha channel.
+pub fn decode(encoded: &[u8]) -> ImageResult<DynamicImage> {
+    let context = HeifContext::read_from_bytes(encoded).map_err(decoding_error)?;
+    let handle = context.primary_image_handle().map_err(decoding_error)?;
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = match has_alpha {
+        true => RgbChroma::Rgba,
+        false => RgbChroma::Rgb,
+    };
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(chroma), None)
+        .map_err(decoding_error)?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or_else(|| decoding_error("HEIF image has no interleaved plane"))?;
+
+    // rows may be padded so copy them without the stride padding
+    let channels = if has_alpha { 4 } else { 3 };
+    let row_len = plane.width as usize * channels;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    let image = match has_alpha {
+        true => {
+            RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8)
+        }
+        false => RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8),
+    };
+    image.ok_or_else(|| decoding_error("HEIF image has an unexpected size"))
+}
+
+fn decoding_error(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("HEIF".to_string()),
+        error,
+    ))
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use ignore::gitignore::Gitignore;
+use tracing::{debug, warn};
+
+/// Name of the files listing, in gitignore syntax, what to skip when scanning an input directory.
+pub const IGNORE_FILE_NAME: &str = ".facecropignore";
+
+/// The rules of the `.facecropignore` files that apply in a directory, i.e. those in it and the
+/// directories above it up to the input directory.
+#[derive(Clone, Default)]
+pub struct IgnoreRules {
+    /// Matchers of the ignore files, the deepest last.
+    matchers: Vec<Gitignore>,
+}
+
+impl IgnoreRules {
+    /// Function to load the rules that apply in a directory below the input directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_dir` - The input directory, whose ignore file applies everywhere.
+    /// * `dir` - The directory, e.g. a priority directory that is scanned on its own.
+    ///
+    /// # Returns
+    ///
+    /// * The rules of the ignore files from the input directory down to the directory.
+    pub fn load(input_dir: &Path, dir: &Path) -> Self {
+        let mut rules = Self::default().descend(input_dir);
+        if let Ok(relative_dir) = dir.strip_prefix(input_dir) {
+            let mut current_dir = input_dir.to_path_buf();
+            for component in relative_dir.components() {
+                current_dir.push(component);
+                rules = rules.descend(&current_dir);
+            }
+        }
+        rules
+    }
+
+    /// Function to add the rules of the ignore file of a subdirectory, if it has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The subdirectory being entered.
+    ///
+    /// # Returns
+    ///
+    /// * The rules that apply in the subdirectory.
+    pub fn descend(&self, dir: &Path) -> Self {
+        let ignore_path = dir.join(IGNORE_FILE_NAME);
+        if !ignore_path.is_file() {
+            return self.clone();
+        }
+        let (matcher, error) = Gitignore::new(&ignore_path);
+        if let Some(error) = error {
+            warn!(
+                "Failed to read part of {}: {}",
+                ignore_path.display(),
+                error
+            );
+        }
+        debug!(
+            "Loaded {} rule(s) from {}",
+            matcher.num_ignores() + matcher.num_whitelists(),
+            ignore_path.display()
+        );
+        let mut rules = self.clone();
+        rules.matchers.push(matcher);
+        rules
+    }
+
+    /// Whether a file or directory is ignored. Like in git, the deepest ignore file with a
+    /// matching rule decides, and `!pattern` rules include paths again.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matchers
+            .iter()
+            .rev()
+            .filter(|matcher| path.starts_with(matcher.path()))
+            .map(|matcher| matcher.matched_path_or_any_parents(path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+}
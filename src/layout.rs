@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+
+/// Folder for faces that don't belong to a person cluster.
+const UNCLUSTERED: &str = "unclustered";
+/// Folder for inputs without a capture date.
+const UNDATED: &str = "undated";
+
+/// How crops are organized within the output directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Every crop directly in the output directory.
+    Flat,
+    /// `<person_cluster>/<YYYY>/<MM>/` by identity cluster and capture date.
+    PersonDate,
+    /// `<repeats>_<subject> <class>/`, the folder Kohya-style fine-tuning scripts read the
+    /// training images of a concept and how often to repeat them per epoch from.
+    Kohya {
+        repeats: u32,
+        subject: String,
+        class: String,
+    },
+}
+
+/// Year and month an input was captured, from its EXIF data.
+#[derive(Copy, Clone, Debug)]
+pub struct CaptureDate {
+    pub year: u16,
+    pub month: u8,
+}
+
+/// Function to read the capture date of an image from its EXIF `DateTimeOriginal` tag, falling
+/// back to the `DateTime` tag.
+///
+/// # Arguments
+///
+/// * `image_path` - Path to the image file.
+///
+/// # Returns
+///
+/// * The capture date, or None if the image has no (valid) EXIF date.
+pub fn capture_date(image_path: &Path) -> Option<CaptureDate> {
+    capture_date_time(image_path).map(|date_time| CaptureDate {
+        year: date_time.year,
+        month: date_time.month,
+    })
+}
+
+/// Function to read when an image was captured from its EXIF `DateTimeOriginal` tag, falling
+/// back to the `DateTime` tag, along with the UTC offset of its `OffsetTimeOriginal` or
+/// `OffsetTime` tag if it has one.
+///
+/// # Arguments
+///
+/// * `image_path` - Path to the image file.
+///
+/// # Returns
+///
+/// * The capture date and time, or None if the image has no (valid) EXIF date.
+pub fn capture_date_time(image_path: &Path) -> Option<exif::DateTime> {
+    let file = std::fs::File::open(image_path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .ok()?;
+    let ascii = |tag| match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(values) => values.first().cloned(),
+        _ => None,
+    };
+    [
+        (exif::Tag::DateTimeOriginal, exif::Tag::OffsetTimeOriginal),
+        (exif::Tag::DateTime, exif::Tag::OffsetTime),
+    ]
+    .iter()
+    .find_map(|&(tag, offset_tag)| {
+        let mut date_time = exif::DateTime::from_ascii(&ascii(tag)?).ok()?;
+        if let Some(offset) = ascii(offset_tag) {
+            let _ = date_time.parse_offset(&offset);
+        }
+        Some(date_time)
+    })
+    .filter(|date_time| (1..=12).contains(&date_time.month))
+}
+
+/// Function to get the directory a crop is written to, relative to the output directory.
+///
+/// # Arguments
+///
+/// * `layout` - The output layout.
+/// * `person_cluster` - Identity cluster of the face, if known.
+/// * `capture_date` - Capture date of the input, if known.
+///
+/// # Returns
+///
+/// * The relative directory, empty for the flat layout.
+pub fn crop_dir(
+    layout: &Layout,
+    person_cluster: Option<&str>,
+    capture_date: Option<&CaptureDate>,
+) -> PathBuf {
+    match layout {
+        Layout::Flat => PathBuf::new(),
+        Layout::PersonDate => {
+            let person_dir = PathBuf::from(person_cluster.unwrap_or(UNCLUSTERED));
+            match capture_date {
+                Some(date) => person_dir
+                    .join(format!("{:04}", date.year))
+                    .join(format!("{:02}", date.month)),
+                None => person_dir.join(UNDATED),
+            }
+        }
+        Layout::Kohya {
+            repeats,
+            subject,
+            class,
+        } => PathBuf::from(format!("{}_{} {}", repeats, subject, class)),
+    }
+}
@@ -0,0 +1,9 @@
+//! C interface to the face detection and cropping of facecrop, built as a shared library with the
+//! `capi` feature. See `include/facecrop.h`.
+
+#[cfg(feature = "capi")]
+mod capi;
+// the C interface only uses part of the cropping module
+#[cfg(feature = "capi")]
+#[allow(dead_code)]
+mod cropping;
@@ -1,85 +1,1049 @@
 use std::{
+    collections::{HashMap, HashSet},
     fmt,
+    io::{Read, Write},
+    panic::{catch_unwind, AssertUnwindSafe},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use clap::{Parser, ValueEnum};
-use image;
-use tracing::{debug, info, warn};
-use tracing_subscriber;
+use clap::{
+    error::ErrorKind, parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser,
+    Subcommand, ValueEnum,
+};
+use contact_sheet::ContactSheet;
+use pool::DetectorPool;
+use tracing::{debug, error, info, trace, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use watchdog::WatchdogDetector;
 
+mod acceleration;
+mod animation;
+mod archive;
+mod attributes;
+mod boxes;
+mod cache;
+mod caption;
+mod classes;
+mod clustering;
+mod compare;
+mod config;
+mod console;
+mod contact_sheet;
 mod cropping;
+mod decoding;
+mod dedupe;
+mod detect;
+mod downscale;
+mod drawing;
+mod duplicates;
+mod embedding;
+mod export;
+mod fusion;
+mod gallery;
+mod heatmap;
+#[cfg(feature = "heif")]
+mod heif;
+mod ignore_file;
+mod layout;
+mod manifest;
+#[cfg(feature = "matting")]
+mod matting;
+mod metrics;
+mod multiscale;
+mod output;
+mod pdf;
+mod pool;
+mod pose;
 mod post_processing;
+mod prefetch;
+mod provenance;
+mod redact;
+mod report;
+mod review;
+mod rotation;
+mod selection;
+mod selftest;
+mod server;
+mod split;
+mod state;
+mod storage;
+mod summary;
+mod sweep;
+#[cfg(feature = "upscale")]
+mod upscale;
+mod verify;
+#[cfg(feature = "video")]
+mod video;
+mod watch;
+mod watchdog;
+mod writer;
+mod xmp;
+
+/// Input or output path that stands for stdin or stdout.
+const STDIO_PATH: &str = "-";
+/// Exit code of a run that completed and found faces.
+const EXIT_SUCCESS: i32 = 0;
+/// Exit code of a run that stopped on an error, e.g. invalid arguments or an unwritable output
+/// directory.
+const EXIT_FATAL: i32 = 1;
+/// Exit code of a run that completed but failed to process some of its inputs.
+const EXIT_INPUTS_FAILED: i32 = 2;
+/// Exit code of a run that completed but found no faces in any input.
+const EXIT_NO_FACES: i32 = 3;
+/// Number of inputs processed between saves of the manifest, cache and run state, as rewriting
+/// and re-signing the whole manifest after every input would grow quadratically with the run.
+const RUN_FILES_SAVE_INTERVAL: usize = 100;
+/// Number of threads decoding inputs ahead and encoding crops, unless given or --low-memory.
+const DEFAULT_STAGE_THREADS: usize = 2;
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "tif", "tiff"];
+/// Video inputs are only processed when built with the video feature.
+const VIDEO_EXTENSIONS: [&str; 2] = ["mp4", "mkv"];
+/// Animated inputs, each frame of which is processed.
+const ANIMATION_EXTENSIONS: [&str; 1] = ["gif"];
+/// HEIC/HEIF inputs are only processed when built with the heif feature.
+const HEIF_EXTENSIONS: [&str; 2] = ["heic", "heif"];
+/// AVIF inputs are only processed when built with the avif feature.
+const AVIF_EXTENSIONS: [&str; 1] = ["avif"];
+/// Inference timeout when watching or serving, as there is no operator to restart a hung detector.
+const LONG_RUNNING_INFERENCE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest overlap a detected face may have with an imported one to be kept alongside it.
+const IMPORT_MERGE_OVERLAP: f32 = 0.5;
 
-/// facecrop extracts crops of all faces within a given image (.png|.jpeg|.jpg)
+/// facecrop extracts crops of all faces within a given image (.png|.jpeg|.jpg|.webp|.gif|.heic|.avif)
 /// or directory of images.
 ///
 /// Crops are calculated based on the face bounding box and can be either absolute (pixels)
 /// or relative to the face size (propostion of the face height to crop). Each crop is then
 /// optionally resized to the given size and/or filtered out.
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
 #[command(author, version)]
 #[command(
     about = "\
-        facecrop extracts crops of all faces within a given image (.png|.jpeg|.jpg) \
+        facecrop extracts crops of all faces within a given image (.png|.jpeg|.jpg|.webp|.gif|.heic|.avif) \
         or directory of images.\n\n\
         Crops are calculated based on the face bounding box and can be either absolute (pixels) \
         or relative to the face size (propostion of the face height to crop). \
         Each crop is then optionally resized to the given size and/or filtered out.\
     ",
     long_about = None,
+    subcommand_negates_reqs = true,
 )]
 struct Args {
-    /// Path to the image file or directory to process
-    #[arg()]
-    image_path_or_dir: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Path to write output files to
-    #[arg()]
-    output_dir: String,
+    /// Path to the image file or directory to process, or "-" to read a single image from stdin
+    #[arg(required = true)]
+    image_path_or_dir: Option<String>,
+
+    /// Path to write output files to, or "-" to write the crop of a single face to stdout. Left
+    /// out with --output-zip
+    #[arg(required_unless_present = "output_zip")]
+    output_dir: Option<String>,
 
     /// Strategy to use to crop faces. This can either be "absolute" or "relative"
-    #[arg(short, long, value_enum, default_value = "relative")]
+    #[arg(short, long, value_enum, default_value = "relative", global = true)]
     strategy: CropStrategy,
 
     /// Aspect ratio (width:height) to crop the image by. 1.0 indicates a square crop
     /// while 1.5 indicates a crop that is 1.5 times as wide as it is tall.
-    #[arg(short = 'a', long = "aspect_ratio", default_value = "1.0")]
+    #[arg(
+        short = 'a',
+        long = "aspect_ratio",
+        default_value = "1.0",
+        global = true
+    )]
     aspect_ratio: f32,
 
     /// Top padding. Portion of the image that should be padded on top of the face
     /// This is a float between 0.0 and 1.0
-    #[arg(short, long, default_value = "0.1")]
+    #[arg(short, long, default_value = "0.1", global = true)]
     top_padding: f32,
 
+    /// Bottom padding. Portion of the crop height to add below it, e.g. to include the shoulders
+    /// This is a float between 0.0 and 1.0
+    #[arg(long, default_value = "0.0", global = true)]
+    padding_bottom: f32,
+
+    /// Left padding. Portion of the crop width to add to its left
+    /// This is a float between 0.0 and 1.0
+    #[arg(long, default_value = "0.0", global = true)]
+    padding_left: f32,
+
+    /// Right padding. Portion of the crop width to add to its right
+    /// This is a float between 0.0 and 1.0
+    #[arg(long, default_value = "0.0", global = true)]
+    padding_right: f32,
+
+    /// Where the centre of the face should land within the crop, as "x,y" proportions of its
+    /// width and height from the top left, e.g. "0.5,0.38" for portrait framing. Replaces the top
+    /// padding and the centring of the face, while the bottom, left and right padding still make
+    /// the crop larger
+    #[arg(long, value_parser = parse_face_anchor, global = true)]
+    face_anchor: Option<FaceAnchor>,
+
+    /// How faces are framed within their crops. This can either be "centered" (centring the face
+    /// horizontally, with the top padding or face anchor placing it vertically) or "thirds"
+    /// (placing the eye line on the upper third and the face on the left or right third,
+    /// whichever leaves more of the image in the crop)
+    #[arg(
+        long,
+        value_enum,
+        default_value = "centered",
+        conflicts_with = "face_anchor",
+        global = true
+    )]
+    compose: Compose,
+
+    /// Multiple to round the width and height of crops to, e.g. 2, 8 or 64 for video encoders
+    /// and models that need it. Crops clamped by the edge of the image are trimmed down to one
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global = true)]
+    dims_multiple_of: Option<u32>,
+
     /// Portion of the image that the face should take up vertically (from the top)
     /// This is a float between 0.0 and 1.0
-    #[arg(short, long, default_value = "0.3")]
+    #[arg(short, long, default_value = "0.3", global = true)]
     proportion_of_face: f32,
 
     /// Height of the crop. Used to determine the crop dimensions if strategy="absolute".
     /// If strategy="relative" and resize=true, the cropped image will be resized to this height.
-    #[arg(long, default_value = "1024")]
+    #[arg(long, default_value = "1024", global = true)]
     height: u32,
 
     /// Width of the crop. Used to determine the crop dimensions if strategy="absolute".
     /// If strategy="relative" and resize=true, the cropped image will be resized to this width.
-    #[arg(long, default_value = "1024")]
+    #[arg(long, default_value = "1024", global = true)]
     width: u32,
 
     /// True to resize the cropped image to the specified height and width. False to leave the
     /// cropped image at the original size
-    #[arg(short, long, default_value = "false")]
+    #[arg(short, long, default_value = "false", global = true)]
     resize: bool,
 
+    /// How resized crops are fitted to the height and width when their aspect ratio differs,
+    /// e.g. after being clamped to the edge of the image. This can either be "stretch"
+    /// (distorting the crop), "pad" (letterboxing it with black, or transparency for crops with
+    /// alpha) or "cover" (center cropping it)
+    #[arg(long, value_enum, default_value = "stretch", global = true)]
+    fit: Fit,
+
+    /// Filter crops are resized with. This can either be "nearest", "bilinear", "catmullrom" or
+    /// "lanczos3"
+    #[arg(long, value_enum, default_value = "lanczos3", global = true)]
+    resize_filter: ResizeFilter,
+
+    /// True to keep crops smaller than the height and width, or than their training resolution,
+    /// at their original size instead of upscaling them, only resizing larger crops down. False
+    /// to resize every crop
+    #[arg(long, default_value = "false", global = true)]
+    no_upscale: bool,
+
     /// True to filter out crops that are smaller than the specified height and width. False to
     /// output all crops
-    #[arg(short, long, default_value = "false")]
+    #[arg(short, long, default_value = "false", global = true)]
     filter_by_size: bool,
 
+    /// Strength of the unsharp mask applied to crops after they are resized, from 0.0 (off) to
+    /// 1.0, e.g. 0.3 to bring back the detail softened by downscaling large crops
+    #[arg(long, value_parser = parse_sharpen, default_value = "0.0", global = true)]
+    sharpen: f32,
+
+    /// True to stretch the brightness of each crop to the full range after it is resized, so
+    /// crops taken in mixed lighting are more uniform
+    #[arg(long, default_value = "false", global = true)]
+    auto_levels: bool,
+
+    /// True to equalize the local contrast of each crop with CLAHE (contrast limited adaptive
+    /// histogram equalization) after it is resized, e.g. for backlit faces. Applied after
+    /// --auto-levels
+    #[arg(long, default_value = "false", global = true)]
+    clahe: bool,
+
+    /// True to lightly denoise crops with a 3x3 median filter after they are resized, e.g. for
+    /// phone photos. Applied before sharpening
+    #[arg(long, default_value = "false", global = true)]
+    denoise: bool,
+
+    /// Super-resolution model to upscale crops smaller than the specified height and width with
+    /// before they are resized, instead of only resizing them with Lanczos. This can only be
+    /// "realesrgan". Requires the upscale feature and --resize
+    #[arg(long, value_enum, requires = "upscale_model", global = true)]
+    upscale: Option<Upscale>,
+
+    /// Path to the ONNX super-resolution model, e.g. Real-ESRGAN x4
+    #[arg(long, global = true)]
+    upscale_model: Option<PathBuf>,
+
+    /// True to follow symlinks to files and directories when scanning the input directory, e.g.
+    /// for libraries with symlinked albums. Symlinks that lead back to a directory already being
+    /// scanned are skipped. By default symlinks are skipped
+    #[arg(long, default_value = "false", global = true)]
+    follow_symlinks: bool,
+
+    /// Format crops are written in. This can either be "auto" (PNG for images with transparency
+    /// or more than 8 bits per channel, JPEG otherwise), "jpeg", "png" or "webp"
+    #[arg(long, value_enum, default_value = "auto", global = true)]
+    output_format: OutputFormat,
+
+    /// Quality (1-100) of JPEG and WebP outputs
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100), default_value = "75", global = true)]
+    quality: u8,
+
+    /// Chroma subsampling of JPEG crops. This can either be "444" (full colour resolution, e.g.
+    /// for datasets), "422" or "420" (smallest files)
+    #[arg(long, value_enum, default_value = "422", global = true)]
+    jpeg_subsampling: JpegSubsampling,
+
+    /// True to write JPEG crops as progressive JPEGs
+    #[arg(long, default_value = "false", global = true)]
+    progressive: bool,
+
+    /// Size in pixels of each thumbnail in a contact sheet
+    #[arg(long, default_value = "192", global = true)]
+    contact_sheet_tile_size: u32,
+
+    /// Number of thumbnails per row of a contact sheet
+    #[arg(long, default_value = "8", global = true)]
+    contact_sheet_columns: u32,
+
+    /// Maximum number of rows in a contact sheet before a new sheet is started
+    #[arg(long, default_value = "10", global = true)]
+    contact_sheet_rows: u32,
+
+    /// Colour the crops are written in. This can either be "rgb" or "grayscale" (8-bit, with
+    /// alpha kept for transparent crops), e.g. for recognition pipelines that take grayscale
+    /// input or for smaller files
+    #[arg(long, value_enum, default_value = "rgb", global = true)]
+    color_mode: ColorMode,
+
+    /// Background color (R,G,B) that transparent images are composited onto before detection.
+    /// Crops are still taken from the original image and written as PNGs with alpha preserved
+    #[arg(long, value_parser = parse_color, default_value = "255,255,255", global = true)]
+    alpha_background: image::Rgb<u8>,
+
+    /// Reduce memory use for small devices such as a Raspberry Pi. Faces are detected with
+    /// blazeface-320 instead of blazeface-640, inference runs on one thread unless
+    /// --intra-threads or --inter-threads are given, inputs are decoded and crops written on the
+    /// main thread unless --decode-threads or --encode-threads are given, image files are decoded
+    /// as they are read rather than read into memory first, and the decoded image is converted
+    /// for detection in place rather than copied
+    #[arg(long, default_value = "false", global = true)]
+    low_memory: bool,
+
+    /// Precision of the detector weights. "fp16" and "int8" load quantized weights from the
+    /// rust-faces model cache, e.g. ~/.rust_faces/blazeface-640-int8.onnx, falling back to
+    /// "fp32" if there are none
+    #[arg(long, value_enum, default_value = "fp32", global = true)]
+    model_precision: ModelPrecision,
+
+    /// Also detect faces in copies of each image rotated by 90, 180 and 270 degrees, to find
+    /// faces lying sideways or upside down as in scanned prints. Crops are still taken from the
+    /// image as it is. Detection takes four times as long
+    #[arg(long, default_value = "false", global = true)]
+    try_rotations: bool,
+
+    /// Also detect faces in 2x upsampled copies of the corners and centre of each image, to find
+    /// the very small faces of wide group photos. Detection takes six times as long
+    #[arg(long, default_value = "false", global = true)]
+    multiscale: bool,
+
+    /// Downscale images whose long side is above this many pixels before detecting faces in
+    /// them, e.g. 1600. The boxes are mapped back and the crops cut from the full image
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global = true)]
+    detect_max_dim: Option<u32>,
+
+    /// Time after which an inference is considered hung and the detector is rebuilt, e.g. "30s".
+    /// Defaults to 60s when watching
+    #[arg(long, value_parser = parse_duration, global = true)]
+    inference_timeout: Option<Duration>,
+
+    /// Number of face detectors kept loaded to detect in parallel. The strips of a panorama are
+    /// spread across them
+    #[arg(long, default_value = "1", global = true)]
+    workers: usize,
+
+    /// Execution provider to run the detector with. This can either be "auto" (CoreML on Apple
+    /// Silicon, otherwise the CPU), "cpu", "core-ml" or "tensorrt" (with the tensorrt feature)
+    #[arg(long, value_enum, default_value = "auto", global = true)]
+    acceleration: Acceleration,
+
+    /// Directory to cache the TensorRT engines built for the detector in, so later runs load them
+    /// instead of building them again
+    #[arg(long, global = true)]
+    tensorrt_engine_cache: Option<PathBuf>,
+
+    /// Number of threads ONNX Runtime runs each operator on. Defaults to the number of cores, or
+    /// 1 with --low-memory
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    intra_threads: Option<u16>,
+
+    /// Number of threads ONNX Runtime runs independent operators on side by side. Defaults to
+    /// the runtime's choice, or 1 with --low-memory
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    inter_threads: Option<u16>,
+
+    /// How much ONNX Runtime optimizes the graph of each model when loading it. This can either
+    /// be "disable", "basic", "extended" or "all" (the runtime default)
+    #[arg(long, value_enum, global = true)]
+    graph_optimization: Option<GraphOptimization>,
+
     /// Verbosity
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
     verbose: u8,
+
+    /// True to disable coloured output. Also disabled when NO_COLOR is set or the output isn't a
+    /// terminal
+    #[arg(long, default_value = "false", global = true)]
+    no_color: bool,
+
+    /// True to only write ASCII, e.g. for consoles and CI logs that can't render emoji
+    #[arg(long, default_value = "false", global = true)]
+    ascii: bool,
+
+    // the pipeline options of `facecrop <IMAGE_PATH_OR_DIR> <OUTPUT_DIR>`, replaced by those of
+    // the subcommand running the pipeline, if any
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+}
+
+/// Options of the pipeline that crops, redacts or watches inputs, taken after the subcommand that
+/// runs it or without one.
+#[derive(clap::Args, Clone, Debug)]
+struct PipelineArgs {
+    /// Index of the face to write when writing to stdout. Only needed if the image has more than
+    /// one face
+    #[arg(long)]
+    face_index: Option<usize>,
+
+    /// Smallest face size in pixels (longest side of the face box) of the small, medium and
+    /// large size buckets, e.g. "32,96,256". Faces below the first are tiny. The bucket of each
+    /// crop is recorded in the manifest
+    #[arg(long, value_parser = parse_size_buckets, default_value = "32,96,256")]
+    size_buckets: SizeBuckets,
+
+    /// Write a caption next to every crop in a .txt file of the same name, for training diffusion
+    /// models, e.g. "a photo of {token} person". Placeholders are {token}, {source} (the input
+    /// file name without its extension), {face}, {size_bucket} and the estimated {age}, {gender}
+    /// ("woman" or "man") and {emotion}, which are empty unless given in --attributes
+    #[arg(long, value_parser = parse_caption_template)]
+    caption_template: Option<String>,
+
+    /// Rare token identifying the subject, filled in for {token} in --caption-template
+    #[arg(long, requires = "caption_template")]
+    caption_token: Option<String>,
+
+    /// Divide the crops into splits of a dataset, each written to a folder of the output directory
+    /// named after it, e.g. "train:0.9,val:0.1". Each input is routed to a split as a whole so
+    /// faces from one image are never in two splits. Ratios are relative to each other
+    #[arg(long, value_parser = parse_splits)]
+    split: Option<Splits>,
+
+    /// Seed of the routing of inputs to splits. Inputs are routed by their path relative to the
+    /// input directory, so the same seed gives the same splits on every run
+    #[arg(long, default_value = "0", requires = "split")]
+    split_seed: u64,
+
+    /// True to also add the size bucket of each crop to its filename
+    #[arg(long, default_value = "false")]
+    size_bucket_in_filename: bool,
+
+    /// True to name crops after a hash of their face box, e.g. "photo-1a2b3c4d.jpg", instead of
+    /// the face index and confidence, so reprocessing an image reproduces the same file names
+    #[arg(long, default_value = "false")]
+    stable_names: bool,
+
+    /// How crops are organized in the output directory. This can either be "flat",
+    /// "person-date" (<person_cluster>/<YYYY>/<MM>/ by identity cluster and EXIF capture date,
+    /// clustering the faces with the recognition model given by --embedding-model) or "kohya"
+    /// (<repeats>_<subject> <class>/ for Kohya-style fine-tuning, with each crop resized to the
+    /// closest of --training-resolutions)
+    #[arg(long, value_enum, default_value = "flat")]
+    layout: OutputLayout,
+
+    /// Minimum cosine similarity between the embedding of a face and a person cluster for
+    /// --layout person-date to put the face in it rather than in a new cluster
+    #[arg(long, default_value = "0.5")]
+    cluster_threshold: f32,
+
+    /// Number of times fine-tuning repeats each crop per epoch, for --layout kohya
+    #[arg(long, default_value = "10")]
+    kohya_repeats: u32,
+
+    /// Token identifying the subject in the folder name of --layout kohya. Defaults to
+    /// --caption-token, or "ohwx"
+    #[arg(long)]
+    kohya_subject: Option<String>,
+
+    /// Class of the subject in the folder name of --layout kohya
+    #[arg(long, default_value = "person")]
+    kohya_class: String,
+
+    /// Training resolutions of --layout kohya, as widthxheight separated by commas. Each crop is
+    /// resized and center cropped to the resolution closest to its aspect ratio instead of the
+    /// output size
+    #[arg(
+        long,
+        value_parser = parse_resolutions,
+        default_value = "512x512,576x448,448x576,640x384,384x640"
+    )]
+    training_resolutions: Resolutions,
+
+    /// True to also process the subdirectories of the input directory and mirror them in the
+    /// output directory, so `input/a/b.jpg` has its outputs written under `output/a/`
+    #[arg(long, default_value = "false")]
+    preserve_structure: bool,
+
+    /// Subdirectory of the input directory, e.g. "urgent", whose inputs (including those in its
+    /// subdirectories) are processed before all others. Can be given more than once, in order of
+    /// priority
+    #[arg(long = "priority-dir")]
+    priority_dirs: Vec<PathBuf>,
+
+    /// Only process inputs modified after a time, given in UTC as e.g. "2024-05-01",
+    /// "2024-05-01T18:00:00Z" or Unix seconds, or "last-run" for the start of the last complete
+    /// run into the output directory that was given --newer-than, for incremental runs over a
+    /// growing library
+    #[arg(long, value_parser = parse_newer_than)]
+    newer_than: Option<NewerThan>,
+
+    /// File listing the inputs to process, one path per line, or "-" to read the list from stdin.
+    /// The listed inputs are processed instead of the ones found by scanning the input directory,
+    /// which must contain them. They are processed in the same order as scanned inputs, sorted
+    /// by --priority-dirs and path, and inputs listed twice are processed once
+    #[arg(long)]
+    input_list: Option<PathBuf>,
+
+    /// True to separate the paths of --input-list with NUL characters instead of newlines, e.g.
+    /// for the output of `find -print0`
+    #[arg(long, default_value = "false", requires = "input_list")]
+    null: bool,
+
+    /// True to write the crops of each input into a directory named after it, e.g.
+    /// `output/wedding/0-0.998.jpg` for `wedding.jpg`, instead of prefixing them with its name
+    #[arg(long, default_value = "false")]
+    per_image_dirs: bool,
+
+    /// True to write a copy of each input image with all faces obscured instead of writing crops
+    #[arg(long, default_value = "false")]
+    redact: bool,
+
+    /// Style used to obscure faces when redact=true. This can either be "blur", "pixelate" or
+    /// "solid"
+    #[arg(long, value_enum, default_value = "blur")]
+    redact_style: RedactStyle,
+
+    /// Size in pixels of the blocks faces are divided into when redact_style="pixelate"
+    #[arg(long, default_value = "16")]
+    redact_block_size: u32,
+
+    /// Color (R,G,B) faces are filled with when redact_style="solid"
+    #[arg(long, value_parser = parse_color, default_value = "0,0,0")]
+    redact_color: image::Rgb<u8>,
+
+    /// True to write the crop of the most confident face into the XMP crop tags of each input
+    /// image instead of writing crops. Non-destructive editors then show the face crop while the
+    /// original pixels are left untouched. Only supported for JPEG and PNG inputs
+    #[arg(long, default_value = "false")]
+    virtual_crop: bool,
+
+    /// True to embed the source file name, detection confidence, crop rectangle and facecrop
+    /// version into each crop as XMP, so crops stay traceable when separated from the manifest.
+    /// Only supported for JPEG and PNG crops
+    #[arg(long, default_value = "false")]
+    embed_provenance: bool,
+
+    /// Path to write a contact sheet to: a labelled grid of every crop written in the run. Large
+    /// runs are split into numbered sheets
+    #[arg(long)]
+    contact_sheet: Option<PathBuf>,
+
+    /// True to also write a contact sheet of the crops of each input image to the output
+    /// directory
+    #[arg(long, default_value = "false")]
+    contact_sheet_per_image: bool,
+
+    /// True to also write a copy of each input image annotated with the detected faces, their
+    /// confidences and landmarks, and the computed crop rectangles
+    #[arg(long, default_value = "false")]
+    debug_overlay: bool,
+
+    /// True to also write a heatmap of each input image showing the detector scores of face
+    /// candidates, including those below the detection threshold, to help understand missed
+    /// faces. Only supported by the BlazeFace detectors
+    #[arg(long, default_value = "false")]
+    confidence_heatmap: bool,
+
+    /// True to remove the background of the crops with the matting model given by
+    /// --matting-model, writing them as transparent PNGs (or WebPs), e.g. for profile pictures.
+    /// Requires the matting feature
+    #[arg(long, default_value = "false", requires = "matting_model")]
+    remove_background: bool,
+
+    /// Path to a MODNet-style ONNX portrait matting model
+    #[arg(long)]
+    matting_model: Option<PathBuf>,
+
+    /// Color (R,G,B) the removed background is replaced with instead of being made transparent
+    #[arg(long, value_parser = parse_color, requires = "remove_background")]
+    background_color: Option<image::Rgb<u8>>,
+
+    /// What to do with input images that were written by facecrop itself, e.g. when the output
+    /// directory of a previous run is passed as the input. This can either be "skip", "warn"
+    /// (process them but log a warning) or "process"
+    #[arg(long, value_enum, default_value = "skip")]
+    own_outputs: OwnOutputsPolicy,
+
+    /// Stop cleanly once this many crops have been written, e.g. to stay within a manual review
+    /// budget. The position is recorded in the output directory so the run can be continued
+    /// with --resume
+    #[arg(long)]
+    stop_after_faces: Option<usize>,
+
+    /// True to continue a previous run into the same output directory from where it stopped
+    #[arg(long, default_value = "false")]
+    resume: bool,
+
+    /// True to skip inputs that already have crops recorded in the manifest of the output
+    /// directory, or that the manifest records had none
+    #[arg(long, default_value = "false")]
+    skip_existing: bool,
+
+    /// How skip_existing decides that the crops of an input are current. This can either be
+    /// "exists" (all of its crops still exist) or "hash" (its crops exist and the input hasn't
+    /// changed since they were made). Stale crops are removed before the input is reprocessed
+    #[arg(long, value_enum, default_value = "exists")]
+    skip_existing_check: SkipExistingCheck,
+
+    /// True to record the content of each input and the parameters it was processed with in
+    /// `.facecrop-cache.json` in the output directory, and skip inputs that are unchanged since
+    /// they were processed with the same parameters, even if their modification times changed.
+    /// Inputs whose content or parameters changed have their crops replaced
+    #[arg(long, default_value = "false")]
+    cache: bool,
+
+    /// What to do when a crop or redacted copy would overwrite an existing file. This can either
+    /// be "overwrite", "skip" (keep the existing file) or "rename" (append a numeric suffix)
+    #[arg(long, value_enum, default_value = "overwrite")]
+    on_conflict: OnConflict,
+
+    /// True to set the modification time of each crop to when its input was taken, from its
+    /// EXIF capture time or else its modification time, so sorting the output directory by date
+    /// still follows the photos
+    #[arg(long, default_value = "false")]
+    preserve_times: bool,
+
+    /// Path to a key file to sign the manifest with. The SHA-256 of every crop is recorded in the
+    /// manifest and the crop records are signed with HMAC-SHA256, so the crops can be checked
+    /// with `facecrop verify-manifest` before they are ingested
+    #[arg(long)]
+    sign_key: Option<PathBuf>,
+
+    /// True to compute an identity embedding of each cropped face with the recognition model
+    /// given by --embedding-model and record it in the manifest
+    #[arg(long, default_value = "false", requires = "embedding_model")]
+    embeddings: bool,
+
+    /// Path to an ArcFace-style ONNX recognition model taking 112x112 aligned faces
+    #[arg(long, required_if_eq("layout", "person-date"))]
+    embedding_model: Option<PathBuf>,
+
+    /// Path to write a JSON report to after the run, grouping the crops whose embeddings are
+    /// nearly identical although they come from different inputs, e.g. the same frame exported
+    /// twice or duplicated downloads
+    #[arg(long, requires = "embeddings")]
+    duplicate_report: Option<PathBuf>,
+
+    /// Lowest cosine similarity between the embeddings of two crops for the duplicate report to
+    /// group them
+    #[arg(long, default_value = "0.95")]
+    duplicate_similarity: f32,
+
+    /// Path to write the statistics of the run to as JSON after it finishes, as logged: the
+    /// images and faces processed, the crops written and left out by each filter and the time
+    /// spent in each stage
+    #[arg(long)]
+    summary: Option<PathBuf>,
+
+    /// Path to write a self-contained HTML page to after the run, with a thumbnail of every crop
+    /// grouped by input, badges for its confidence and size and links to the crops and inputs
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Path to write a paginated PDF to after the run, laying out every crop in a grid with
+    /// captions for its input, confidence and face size, to review offline or share
+    #[arg(long)]
+    export_pdf: Option<PathBuf>,
+
+    /// Path of a zip file to write the crops, manifest and other outputs into instead of loose
+    /// files in an output directory, which is then left out. Crops and the other outputs of an
+    /// input are added as it is done, so a run that stops early leaves a zip of what it wrote;
+    /// with --report, --export-pdf or --max-total-crops they are staged in a temporary directory
+    /// until the run finishes
+    #[arg(long)]
+    output_zip: Option<PathBuf>,
+
+    /// Path to write the faces of all processed images to after the run, so the detections can
+    /// seed a labeled dataset. A JSON file for COCO, or a directory for YOLO and Pascal VOC
+    #[arg(long)]
+    export_annotations: Option<PathBuf>,
+
+    /// Format of the exported annotations. "coco" writes a COCO dataset with a single "face"
+    /// category, "yolo" a `.txt` file of normalized boxes per image and a `classes.txt`, and
+    /// "voc" a Pascal VOC `.xml` file per image
+    #[arg(
+        long,
+        default_value_t = AnnotationFormat::Coco,
+        value_enum,
+        requires = "export_annotations"
+    )]
+    annotation_format: AnnotationFormat,
+
+    /// Path to a COCO dataset with faces of the inputs, e.g. labeled by hand, whose file names
+    /// are relative to the input directory. How they are used is set by --annotation-policy
+    #[arg(long)]
+    import_annotations: Option<PathBuf>,
+
+    /// How imported annotations are used. "replace" crops the imported faces instead of running
+    /// the detector and skips images the annotations don't list, "merge" also runs the detector
+    /// and keeps the detected faces that don't overlap an imported one
+    #[arg(
+        long,
+        default_value_t = AnnotationPolicy::Replace,
+        value_enum,
+        requires = "import_annotations"
+    )]
+    annotation_policy: AnnotationPolicy,
+
+    /// Also export the landmarks of the faces as keypoints. Faces the detector placed no
+    /// landmarks on get keypoints that aren't labeled
+    #[arg(long, requires = "export_annotations")]
+    export_keypoints: bool,
+
+    /// Attributes to estimate for each face and record in the manifest, separated by commas.
+    /// These can be "age" or "gender", estimated with the model given by --age-gender-model,
+    /// "emotion", estimated with the model given by --emotion-model, or "occlusion", estimated
+    /// with the model given by --occlusion-model
+    #[arg(long, value_enum, value_delimiter = ',')]
+    attributes: Vec<Attribute>,
+
+    /// Path to an InsightFace-style ONNX gender and age model taking 96x96 faces
+    #[arg(
+        long,
+        required_if_eq_any([("attributes", "age"), ("attributes", "gender")])
+    )]
+    age_gender_model: Option<PathBuf>,
+
+    /// Only crop faces estimated to be at least this many years old. Ages are estimated with the
+    /// model given by --age-gender-model and recorded in the manifest
+    #[arg(long, requires = "age_gender_model")]
+    min_age: Option<f32>,
+
+    /// Path to a FER+-style ONNX emotion model taking 64x64 grayscale faces
+    #[arg(long, required_if_eq("attributes", "emotion"))]
+    emotion_model: Option<PathBuf>,
+
+    /// Only crop faces whose expression is one of these, separated by commas, e.g.
+    /// "happy,surprised". Expressions are estimated with the model given by --emotion-model and
+    /// recorded in the manifest
+    #[arg(long, value_enum, value_delimiter = ',', requires = "emotion_model")]
+    only_emotions: Vec<Emotion>,
+
+    /// Path to an ONNX occlusion classifier taking 112x112 faces and returning the scores of a
+    /// clear and an occluded face
+    #[arg(long, required_if_eq("attributes", "occlusion"))]
+    occlusion_model: Option<PathBuf>,
+
+    /// True to skip faces covered by masks, hands or hair, as estimated by the model given by
+    /// --occlusion-model. The occlusion of each face is recorded in the manifest
+    #[arg(long, default_value = "false", requires = "occlusion_model")]
+    skip_masked: bool,
+
+    /// Probability of a face being occluded above which --skip-masked skips it
+    #[arg(long, default_value = "0.5")]
+    max_occlusion: f32,
+
+    /// True to also add the expression of each face to its crop's filename, estimated with the
+    /// model given by --emotion-model
+    #[arg(long, default_value = "false", requires = "emotion_model")]
+    emotion_in_filename: bool,
+
+    /// Also locate dense landmarks on each face with the model given by --landmark-model and
+    /// write them to a `.landmarks.json` sidecar next to each crop. This can only be "68"
+    #[arg(long, value_enum, requires = "landmark_model")]
+    landmarks: Option<Landmarks>,
+
+    /// Path to an ONNX 68-point landmark model taking 112x112 faces, e.g. PFLD
+    #[arg(long)]
+    landmark_model: Option<PathBuf>,
+
+    /// True to estimate the yaw, pitch and roll of each face from its landmarks and record them
+    /// in the manifest and the landmarks sidecars
+    #[arg(long, default_value = "false")]
+    head_pose: bool,
+
+    /// True to widen the crops to the jawline located by --landmarks, which the detector boxes
+    /// cut off
+    #[arg(long, default_value = "false", requires = "landmarks")]
+    jawline_crops: bool,
+
+    /// Skip crops that are near-duplicates of crops already written in the run, e.g. from burst
+    /// shots. This can only be "phash" (perceptual hash)
+    #[arg(long, value_enum)]
+    dedupe: Option<Dedupe>,
+
+    /// Largest number of differing bits between the 64 bit perceptual hashes of two crops for
+    /// them to count as duplicates
+    #[arg(long, default_value = "6")]
+    dedupe_distance: u32,
+
+    /// True to also skip crops that are near-duplicates of images already in the output
+    /// directory
+    #[arg(long, default_value = "false", requires = "dedupe")]
+    dedupe_existing: bool,
+
+    /// Keep only the N highest ranked crops of the run, removing lower ranked crops as better
+    /// ones are found
+    #[arg(long)]
+    max_total_crops: Option<usize>,
+
+    /// What crops are ranked by when --max-total-crops is given. This can either be
+    /// "confidence" or "sharpness" (variance of the Laplacian)
+    #[arg(long, value_enum, default_value = "confidence")]
+    rank_by: RankBy,
+
+    /// Path to an image of a reference face. Only faces matching it are cropped, using the
+    /// recognition model given by --embedding-model
+    #[arg(long, requires = "embedding_model")]
+    match_face: Option<PathBuf>,
+
+    /// Minimum cosine similarity between the embeddings of a face and the reference face for it
+    /// to match
+    #[arg(long, default_value = "0.4")]
+    match_threshold: f32,
+
+    /// Detector tried when the primary one finds no faces in an image, usually slower but more
+    /// sensitive, e.g. "mtcnn" to rescue profile views and low-light shots. This can either be
+    /// "blazeface-640", "blazeface-320" or "mtcnn"
+    #[arg(long, value_enum)]
+    fallback_model: Option<DetectorModel>,
+
+    /// Detector run alongside the primary one on every image, for a higher recall at the cost of
+    /// a second inference. The boxes of both are fused with weighted box fusion and the
+    /// confidence of each detector is recorded in the manifest
+    #[arg(long, value_enum)]
+    ensemble: Option<DetectorModel>,
+
+    /// Path to a custom ONNX detector of several classes, e.g. a YOLO model exported with NMS
+    /// that finds faces, heads and people, used instead of the built-in face detector. It takes a
+    /// 1x3x640x640 RGB input in 0..1 and returns rows of x1, y1, x2, y2, score and class index,
+    /// in input pixels. The crops of each class are written to a directory named after it
+    #[arg(
+        long,
+        requires = "class_names",
+        conflicts_with_all = ["fallback_model", "ensemble", "import_annotations"]
+    )]
+    class_model: Option<PathBuf>,
+
+    /// Names of the classes of --class-model, in the order of their indices, e.g.
+    /// "face,head,person"
+    #[arg(long, value_delimiter = ',', requires = "class_model")]
+    class_names: Vec<String>,
+
+    /// Classes of --class-model that are cropped, e.g. "face,head". Defaults to all of them
+    #[arg(long, value_delimiter = ',', requires = "class_model")]
+    classes: Vec<String>,
+
+    /// Crop arguments overridden for the boxes of a class, e.g. "person:aspect_ratio=0.5", so
+    /// one run of --class-model feeds crops of different framings. Takes the arguments that can
+    /// be swept. Can be given once per class
+    #[arg(
+        long,
+        value_parser = classes::parse_class_params,
+        requires = "class_model"
+    )]
+    class_params: Vec<classes::ClassParams>,
+
+    /// Number of threads decoding the next image inputs while faces are detected in the current
+    /// one. 0 decodes each input when it is reached. Defaults to 2, or 0 with --low-memory
+    #[arg(long)]
+    decode_threads: Option<usize>,
+
+    /// Number of threads encoding and writing crops while the next faces are cropped. 0 writes
+    /// each crop when it is cut. Defaults to 2, or 0 with --low-memory
+    #[arg(long)]
+    encode_threads: Option<usize>,
+
+    /// Time between the frames of a video input that are sampled for detection, e.g. "1.0s" or
+    /// "500ms"
+    #[arg(long, value_parser = parse_duration, default_value = "1.0s")]
+    frame_interval: Duration,
+
+    /// Sample every nth frame of a video input instead of sampling by time. Every frame of a GIF
+    /// input is sampled unless this is given
+    #[arg(long, conflicts_with = "frame_interval")]
+    every_nth_frame: Option<usize>,
+
+    /// Maximum number of frames sampled from each video or GIF input, to bound the runtime on long clips
+    #[arg(long)]
+    max_frames: Option<usize>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Crop the faces in an image or the images in a directory. `facecrop <IMAGE_PATH_OR_DIR>
+    /// <OUTPUT_DIR>` is short for this
+    Crop {
+        /// Path to the image file or directory to process
+        image_path_or_dir: String,
+        /// Path to write output files to. Left out with --output-zip
+        #[arg(required_unless_present = "output_zip")]
+        output_dir: Option<String>,
+        #[command(flatten)]
+        pipeline: PipelineArgs,
+    },
+    /// Crop the faces in an image or the images in a directory using boxes from a file instead of
+    /// detecting them, e.g. boxes from another detector or manual annotation
+    CropFrom {
+        /// Path to the image file or directory to process
+        image_path_or_dir: String,
+        /// Path to write output files to. Left out with --output-zip
+        #[arg(required_unless_present = "output_zip")]
+        output_dir: Option<String>,
+        /// Path to the boxes of each image, in the format written by `detect --format json`.
+        /// Images without boxes are skipped
+        #[arg(long)]
+        boxes: PathBuf,
+        #[command(flatten)]
+        pipeline: PipelineArgs,
+    },
+    /// Detect the faces in an image or the images in a directory and report them without writing
+    /// anything
+    Detect {
+        /// Path to the image file or directory to detect faces in
+        path: PathBuf,
+        /// Format to report the faces in. This can either be "text" to log them or "json" to
+        /// print a JSON object per image to stdout, with logs moving to stderr
+        #[arg(long, value_enum, default_value = "text")]
+        format: DetectFormat,
+    },
+    /// Write copies of an image or the images in a directory with every face redacted instead of
+    /// cropping them
+    Redact {
+        /// Path to the image file or directory to process
+        image_path_or_dir: String,
+        /// Path to write the redacted copies to. Left out with --output-zip
+        #[arg(required_unless_present = "output_zip")]
+        output_dir: Option<String>,
+        #[command(flatten)]
+        pipeline: PipelineArgs,
+    },
+    /// Crop the faces in sampled frames of a video. Requires the video feature
+    Video {
+        /// Path to the video file to process
+        video_path: PathBuf,
+        /// Path to write output files to. Left out with --output-zip
+        #[arg(required_unless_present = "output_zip")]
+        output_dir: Option<PathBuf>,
+        #[command(flatten)]
+        pipeline: PipelineArgs,
+    },
+    /// Serve detection and cropping over HTTP, keeping the detectors loaded between requests.
+    /// `POST /detect` with an image body returns its faces as JSON, `POST /crop?face=<index>`
+    /// returns the crop of a face and `POST /crop?face=all` returns a zip of the crops of every
+    /// face
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+        /// Number of requests handled at once. Defaults to --workers, as further requests would
+        /// only wait for a free detector
+        #[arg(long)]
+        max_concurrent_requests: Option<usize>,
+        /// Number of requests waiting for a free handler before further ones are answered with a
+        /// 429. Waiting uploads aren't read until they're handled
+        #[arg(long, default_value = "16")]
+        queue_depth: usize,
+    },
+    /// Run the bundled sample images through every face detector and check the detections and
+    /// crop geometry against stored expectations, to validate an install
+    Selftest,
+    /// Decode every image in a file or directory without running detection and report the ones
+    /// that are corrupt or truncated, so bad data is found before a long run
+    Verify {
+        /// Path to the image file or directory to verify
+        path: PathBuf,
+    },
+    /// Match the crops of two runs by source and face box overlap using their manifests and
+    /// report the crops that were added, removed or changed, e.g. after a detector upgrade
+    Compare {
+        /// Path to the output directory of the first run
+        before: PathBuf,
+        /// Path to the output directory of the second run
+        after: PathBuf,
+    },
+    /// Check the signature of the manifest in an output directory and that its crops haven't been
+    /// modified since they were written
+    VerifyManifest {
+        /// Path to the output directory of the signed run
+        output_dir: PathBuf,
+        /// Path to the key file the manifest was signed with
+        #[arg(long)]
+        key: PathBuf,
+    },
+    /// Review the crops of an output directory one at a time in the terminal, keeping,
+    /// rejecting or moving each, and record the decisions in its manifest
+    Review {
+        /// Path to the output directory of the run to review
+        output_dir: PathBuf,
+        /// Directory to move crops to, relative to the output directory
+        #[arg(long, default_value = "moved")]
+        move_to: PathBuf,
+    },
+    /// Watch a directory and process new inputs as they appear, keeping the detector loaded
+    /// between them. Runs until interrupted. Inputs already in the directory are not processed
+    Watch {
+        /// Path to the directory to watch
+        input_dir: PathBuf,
+        /// Path to write output files to
+        output_dir: PathBuf,
+        /// Address to serve Prometheus metrics on at `/metrics`, e.g. 127.0.0.1:9090
+        #[arg(long)]
+        metrics_address: Option<String>,
+        #[command(flatten)]
+        pipeline: PipelineArgs,
+    },
+    /// Report the platform, the acceleration the detector runs with and the enabled cargo
+    /// features
+    Info,
+    /// Crop a sample of the inputs with every combination of the given parameter values and
+    /// write a contact sheet per input with one row per face and one column per combination, to
+    /// pick settings before a full run
+    Sweep {
+        /// Path to the image file or directory to sample
+        path: PathBuf,
+        /// Path to write the contact sheets to
+        output_dir: PathBuf,
+        /// Parameter to sweep and its values, e.g. "proportion_of_face=0.2,0.3,0.4". Can be
+        /// given more than once. One of proportion_of_face, top_padding, padding_bottom,
+        /// padding_left, padding_right, aspect_ratio, height or width
+        #[arg(long = "param", value_parser = sweep::parse_param, required = true)]
+        params: Vec<sweep::SweepParam>,
+        /// Number of inputs to sample
+        #[arg(long, default_value = "20")]
+        sample: usize,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to print the completion script for
+        shell: clap_complete::Shell,
+    },
+    /// Print the effective value of every option, including defaults, as TOML
+    PrintConfig {
+        #[command(flatten)]
+        pipeline: PipelineArgs,
+    },
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -94,201 +1058,4607 @@ impl fmt::Display for CropStrategy {
     }
 }
 
-#[derive(Debug)]
-struct Paths {
-    input_image_paths: Vec<PathBuf>,
-    output_dir: PathBuf,
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Compose {
+    Centered,
+    Thirds,
 }
 
-fn main() {
-    let args = Args::parse();
-
-    let level = match args.verbose {
-        0 => tracing::Level::INFO,
-        1 => tracing::Level::DEBUG,
-        _ => tracing::Level::TRACE,
-    };
-    tracing_subscriber::fmt().with_max_level(level).init();
+impl fmt::Display for Compose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
 
-    info!(
-        "Running program with args \
-        image_path_or_dir={} \
-        output_dir={} \
-        strategy={} \
-        aspect_ratio={} \
-        top_padding={} \
-        proportion_of_face={} \
-        height={} \
-        width={} \
-        resize={} \
-        filter_by_size={} \
-        verbose={}
-        ",
-        args.image_path_or_dir,
-        args.output_dir,
-        args.strategy,
-        args.aspect_ratio,
-        args.top_padding,
-        args.proportion_of_face,
-        args.height,
-        args.width,
-        args.resize,
-        args.filter_by_size,
-        args.verbose,
-    );
-    info!("Checking args");
-    let paths = get_paths(&args);
-    let crop_params = get_crop_params(&args);
-    let post_process_params = get_post_process_params(&args);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Fit {
+    Stretch,
+    Pad,
+    Cover,
+}
 
-    info!("Instantiating face detector 🤖");
-    let face_detector = cropping::get_face_detector();
-    info!("Starting inference and cropping 🚀");
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
 
-    for image_path in &paths.input_image_paths {
-        let input_image = read_image(image_path);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ResizeFilter {
+    Nearest,
+    Bilinear,
+    Catmullrom,
+    Lanczos3,
+}
 
-        let faces = cropping::detect_faces_in_image(&input_image, &*face_detector);
-        debug!("Detected {} faces in {}", faces.len(), image_path.display());
+impl fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
 
-        let image_name = image_path.file_stem().unwrap().to_str().unwrap();
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ColorMode {
+    Rgb,
+    Grayscale,
+}
 
-        process_faces(
-            cropping::CropInputs {
-                input_image: &input_image,
-                faces: &faces,
-            },
-            &crop_params,
-            &post_process_params,
-            &paths.output_dir,
-            image_name,
-        );
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
-    info!("Finished processing images 🎉");
 }
 
-fn get_paths(args: &Args) -> Paths {
-    let input_image_path = std::path::PathBuf::from(&args.image_path_or_dir);
-    if !input_image_path.exists() {
-        panic!("Input path does not exist");
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum RedactStyle {
+    Blur,
+    Pixelate,
+    Solid,
+}
+
+impl fmt::Display for RedactStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
-    let input_image_paths = match input_image_path.is_file() {
-        true => {
-            info!("Received file {}", input_image_path.display());
-            vec![input_image_path.clone()]
-        }
-        false => {
-            info!("Received directory {}", input_image_path.display());
+}
 
-            let mut input_image_paths = vec![];
-            for entry in std::fs::read_dir(&input_image_path)
-                .unwrap_or_else(|_| panic!("Failed to read input directory"))
-            {
-                let entry = entry.unwrap();
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(extension) = path.extension() {
-                        if extension == "jpg" || extension == "jpeg" || extension == "png" {
-                            debug!("Found image {}", path.display());
-                            input_image_paths.push(path);
-                        }
-                    }
-                }
-            }
-            input_image_paths
-        }
-    };
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum JpegSubsampling {
+    #[value(name = "444")]
+    Yuv444,
+    #[value(name = "422")]
+    Yuv422,
+    #[value(name = "420")]
+    Yuv420,
+}
 
-    let output_dir = std::path::PathBuf::from(&args.output_dir);
-    if output_dir.exists() && !output_dir.is_dir() {
-        panic!("Output directory is not a directory");
-    }
-    std::fs::create_dir_all(&args.output_dir)
-        .unwrap_or_else(|_| panic!("Failed to create output directory"));
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Attribute {
+    Age,
+    Gender,
+    Emotion,
+    Occlusion,
+}
 
-    Paths {
-        input_image_paths: input_image_paths,
-        output_dir: output_dir,
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
-fn get_crop_params(args: &Args) -> cropping::CropParams {
-    if args.top_padding < 0.0 || args.top_padding > 1.0 {
-        panic!("Top padding must be between 0.0 and 1.0");
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Emotion {
+    Neutral,
+    Happy,
+    Surprised,
+    Sad,
+    Angry,
+    Disgusted,
+    Fearful,
+    Contemptuous,
+}
+
+impl fmt::Display for Emotion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
-    let crop_params_kind = match args.strategy {
-        CropStrategy::Absolute => cropping::CropParamsKind::Absolute(cropping::AbsoluteCrop {
-            height: args.height,
-            width: args.width,
-        }),
-        CropStrategy::Relative => {
-            if args.aspect_ratio <= 0.0 {
-                panic!("Aspect ratio must be greater than 0");
-            }
-            if args.proportion_of_face < 0.0 || args.proportion_of_face > 1.0 {
-                panic!("Proportion of face must be between 0.0 and 1.0");
-            }
+}
 
-            cropping::CropParamsKind::Relative(cropping::RelativeCrop {
-                aspect_ratio: args.aspect_ratio,
-                proportion_of_face: args.proportion_of_face,
-            })
-        }
-    };
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Landmarks {
+    #[value(name = "68")]
+    SixtyEight,
+}
 
-    cropping::CropParams {
-        top_padding: args.top_padding,
-        kind: crop_params_kind,
+impl fmt::Display for Landmarks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
-fn get_post_process_params(args: &Args) -> post_processing::PostProcessParams {
-    post_processing::PostProcessParams {
-        resize: args.resize,
-        filter_by_size: args.filter_by_size,
-        height: args.height,
-        width: args.width,
+impl fmt::Display for JpegSubsampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
-fn read_image(input_image_path: &std::path::Path) -> image::RgbImage {
-    let input_image = image::open(&input_image_path.to_str().unwrap())
-        .unwrap_or_else(|_| panic!("Failed to open image file"))
-        .into_rgb8();
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum OutputFormat {
+    Auto,
+    Jpeg,
+    Png,
+    Webp,
+}
 
-    input_image
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
-fn process_faces(
-    faces_to_crop: cropping::CropInputs,
-    crop_params: &cropping::CropParams,
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum OutputLayout {
+    Flat,
+    PersonDate,
+    Kohya,
+}
+
+impl fmt::Display for OutputLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum AnnotationFormat {
+    Coco,
+    Yolo,
+    Voc,
+}
+
+impl fmt::Display for AnnotationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum AnnotationPolicy {
+    Replace,
+    Merge,
+}
+
+impl fmt::Display for AnnotationPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Acceleration {
+    Auto,
+    Cpu,
+    CoreMl,
+    #[value(name = "tensorrt")]
+    TensorRt,
+}
+
+impl fmt::Display for Acceleration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum DetectorModel {
+    #[value(name = "blazeface-640")]
+    BlazeFace640,
+    #[value(name = "blazeface-320")]
+    BlazeFace320,
+    #[value(name = "mtcnn")]
+    MtCnn,
+}
+
+impl fmt::Display for DetectorModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ModelPrecision {
+    Fp32,
+    Fp16,
+    Int8,
+}
+
+impl fmt::Display for ModelPrecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum GraphOptimization {
+    Disable,
+    Basic,
+    Extended,
+    All,
+}
+
+impl fmt::Display for GraphOptimization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum OnConflict {
+    Overwrite,
+    Skip,
+    Rename,
+}
+
+impl fmt::Display for OnConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum DetectFormat {
+    Text,
+    Json,
+}
+
+impl fmt::Display for DetectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum SkipExistingCheck {
+    Exists,
+    Hash,
+}
+
+impl fmt::Display for SkipExistingCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Dedupe {
+    Phash,
+}
+
+impl fmt::Display for Dedupe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Upscale {
+    Realesrgan,
+}
+
+impl fmt::Display for Upscale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum RankBy {
+    Confidence,
+    Sharpness,
+}
+
+impl fmt::Display for RankBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum OwnOutputsPolicy {
+    Skip,
+    Warn,
+    Process,
+}
+
+impl fmt::Display for OwnOutputsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Thresholds parsed from `--size-buckets`.
+#[derive(Copy, Clone, Debug)]
+struct SizeBuckets([u32; 3]);
+
+/// Proportions parsed from `--face-anchor`, as (x, y).
+#[derive(Copy, Clone, Debug)]
+struct FaceAnchor(f32, f32);
+
+/// Time parsed from `--newer-than`.
+#[derive(Copy, Clone, Debug)]
+enum NewerThan {
+    Time(SystemTime),
+    /// When the last complete run into the output directory started.
+    LastRun,
+}
+
+/// Resolutions parsed from `--training-resolutions`, as (width, height).
+#[derive(Clone, Debug)]
+struct Resolutions(Vec<(u32, u32)>);
+
+/// Splits parsed from `--split`.
+#[derive(Clone, Debug)]
+struct Splits(Vec<split::Split>);
+
+/// Where and how the outputs of a single input image are written.
+#[derive(Debug)]
+struct OutputTarget<'a> {
+    output_dir: &'a Path,
+    /// Where the output directory is stored, which decides whether outputs already exist.
+    output_storage: &'a dyn storage::Storage,
+    /// Directory within the output directory that mirrors the directory of the input.
+    output_subdir: &'a Path,
+    image_name: &'a str,
+    /// Path of the input the outputs are taken from.
+    source: &'a Path,
+    format: image::ImageFormat,
+    /// Quality of lossy formats.
+    quality: u8,
+    jpeg_options: output::JpegOptions,
+    /// True to embed where each crop was taken from into it.
+    embed_provenance: bool,
+    size_bucket_in_name: bool,
+    /// Split of the dataset the crops are written to, if the crops are split.
+    split: Option<&'a str>,
+    /// Expressions of the faces to add to the names of their crops, if they are.
+    emotions_in_name: Option<Vec<Option<attributes::Emotion>>>,
+    /// True to name crops after their face box instead of the face index and confidence.
+    stable_names: bool,
+    /// True to write crops into a directory named after the image.
+    per_image_dir: bool,
+    on_conflict: output::ConflictPolicy,
+    layout: &'a layout::Layout,
+    capture_date: Option<layout::CaptureDate>,
+    /// Identity clusters of the faces, if crops are organized by person.
+    person_clusters: Option<Vec<String>>,
+    /// Classes of the faces, if they are boxes of a class model, whose crops go to a directory
+    /// per class.
+    classes: Option<Vec<String>>,
+    /// Threads the crops are encoded and written on.
+    writer: &'a writer::Writer,
+}
+
+impl OutputTarget<'_> {
+    fn path(&self, suffix: &str) -> PathBuf {
+        self.output_dir
+            .join(self.output_subdir)
+            .join(self.file_name(suffix))
+    }
+
+    /// Path of the crop of a face relative to the output directory, following the layout.
+    fn crop_path(&self, face: usize, suffix: &str) -> PathBuf {
+        let crop_dir = Path::new(self.split.unwrap_or_default())
+            .join(
+                self.classes
+                    .as_ref()
+                    .map_or("", |classes| classes[face].as_str()),
+            )
+            .join(self.output_subdir);
+        let crop_dir = crop_dir.join(layout::crop_dir(
+            self.layout,
+            self.person_clusters
+                .as_ref()
+                .map(|person_clusters| person_clusters[face].as_str()),
+            self.capture_date.as_ref(),
+        ));
+        match self.per_image_dir {
+            true => crop_dir.join(self.image_name).join(format!(
+                "{}.{}",
+                suffix,
+                self.format.extensions_str()[0]
+            )),
+            false => crop_dir.join(self.file_name(suffix)),
+        }
+    }
+
+    fn file_name(&self, suffix: &str) -> String {
+        format!(
+            "{}-{}.{}",
+            self.image_name,
+            suffix,
+            self.format.extensions_str()[0]
+        )
+    }
+}
+
+/// Run-wide state deciding which crops are written.
+struct CropSelection {
+    /// Hashes of the crops written so far, if near-duplicates are skipped.
+    deduplicator: Option<dedupe::Deduplicator>,
+    /// The highest ranked crops so far, if the run keeps a fixed number of crops.
+    top_crops: Option<selection::TopCrops>,
+    rank_by: RankBy,
+    /// Crops of earlier inputs that were replaced by higher ranked ones and need removing from
+    /// the manifest.
+    replaced: Vec<PathBuf>,
+}
+
+/// Number of crops to skip (already written by a previous run) and to write at most.
+#[derive(Debug)]
+struct CropBudget {
+    skip: usize,
+    remaining: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum InputKind {
+    Image,
+    Video,
+    /// An animated GIF.
+    Animation,
+    /// A zip or tar archive of images.
+    Archive,
+}
+
+struct Paths {
+    input_image_paths: Vec<PathBuf>,
+    /// The input directory, or the directory of a single input file.
+    input_root: PathBuf,
+    output_dir: PathBuf,
+    /// Where the inputs are fetched from to the input root as they are read, e.g. an object
+    /// store prefix.
+    input_storage: Arc<dyn storage::Storage>,
+    /// Where the outputs are stored as they are written.
+    output_storage: Box<dyn storage::Storage>,
+}
+
+/// Where the faces of the images of a run come from.
+enum FaceSource<'a> {
+    Detector(&'a dyn rust_faces::FaceDetector),
+    /// Boxes supplied for each image, so detection is skipped.
+    Boxes(HashMap<PathBuf, Vec<rust_faces::Face>>),
+    /// Boxes of the classes of a custom model, cropped with the settings of their class.
+    Classes(&'a classes::ClassModel),
+}
+
+/// Which classes of a class model are cropped, and how.
+struct ClassCrops {
+    /// Names of the classes, by index.
+    names: Vec<String>,
+    /// Crop settings of the classes that are cropped, by index.
+    crop_params: HashMap<usize, cropping::CropParams>,
+}
+
+/// Second detector run alongside the primary one, with the names of both models to record
+/// their confidences under.
+struct Ensemble<'a> {
+    detector: &'a dyn rust_faces::FaceDetector,
+    model_names: [&'static str; 2],
+}
+
+/// What the models run after detection found out about the faces of an image, indexed like the
+/// faces.
+struct FaceAnnotations {
+    /// Dense landmarks, if they are located.
+    landmarks: Option<Vec<Vec<(f32, f32)>>>,
+    /// Estimated attributes, if any are.
+    attributes: Option<Vec<attributes::FaceAttributes>>,
+    /// Identity embeddings, if the faces are clustered by identity before they are cropped.
+    embeddings: Option<Vec<Vec<f32>>>,
+}
+
+/// Detection and cropping state shared by every input of a run.
+struct Pipeline<'a> {
+    args: &'a Args,
+    face_source: FaceSource<'a>,
+    /// Detector keeping low scoring candidates, if confidence heatmaps are written.
+    candidate_detector: Option<&'a dyn rust_faces::FaceDetector>,
+    /// Detector tried on images the primary detector finds no faces in, if any.
+    fallback_detector: Option<&'a dyn rust_faces::FaceDetector>,
+    /// Imported faces the detected faces are merged with, if any.
+    imported_faces: Option<HashMap<PathBuf, Vec<rust_faces::Face>>>,
+    ensemble: Option<Ensemble<'a>>,
+    /// The fused faces of the current image and the confidence of each detector in them, if
+    /// detected by an ensemble.
+    fused_faces: Vec<fusion::FusedFace>,
+    class_crops: Option<&'a ClassCrops>,
+    /// The boxes of the current image of the classes that are cropped, if detected by a class
+    /// model.
+    class_faces: Vec<classes::ClassFace>,
+    crop_params: &'a cropping::CropParams,
+    post_process_params: &'a post_processing::PostProcessParams,
+    size_bucket_params: &'a cropping::SizeBucketParams,
+    redact_params: Option<&'a post_processing::RedactParams>,
+    output_dir: &'a Path,
+    /// Where the output directory is stored, e.g. an object store prefix.
+    output_storage: &'a dyn storage::Storage,
+    /// Where the inputs are read from, which the manifest lists them by.
+    input_storage: &'a Arc<dyn storage::Storage>,
+    /// The input directory, or the directory of a single input file.
+    input_root: &'a Path,
+    /// Directory the outputs of the current input are written to, relative to the output
+    /// directory. Empty unless the input directory structure is preserved.
+    output_subdir: PathBuf,
+    /// Outputs of the current input other than its crops, e.g. captions and debug overlays,
+    /// stored along with the crops.
+    outputs: Vec<PathBuf>,
+    layout: layout::Layout,
+    contact_sheet_params: &'a contact_sheet::ContactSheetParams,
+    run_contact_sheet: Option<ContactSheet<'a>>,
+    manifest: manifest::Manifest,
+    /// Key to sign the manifest with, if any.
+    signing_key: Option<Vec<u8>>,
+    /// Inputs processed into the output directory before, if unchanged ones are skipped.
+    cache: Option<cache::Cache>,
+    /// Recognition model, if embeddings are recorded, faces matched against a reference or
+    /// clustered by identity.
+    embedding_model: Option<&'a embedding::EmbeddingModel>,
+    /// Identity clusters of the faces cropped so far, if crops are organized by person.
+    person_clusters: Option<clustering::PersonClusters>,
+    /// Embedding of the reference face, if only faces matching it are cropped.
+    reference_embedding: Option<Vec<f32>>,
+    /// Dense landmark model, if landmarks are located.
+    landmark_model: Option<&'a cropping::LandmarkModel>,
+    /// Attribute models, if face attributes are estimated.
+    attribute_pipeline: Option<&'a attributes::AttributePipeline>,
+    /// The faces of every processed image, if they are exported as annotations.
+    annotated_images: Option<Vec<export::AnnotatedImage>>,
+    summary: summary::RunSummary,
+    /// Metrics served to Prometheus, if watching.
+    metrics: Option<&'a metrics::Metrics>,
+    selection: CropSelection,
+    /// Image inputs decoded ahead of the one being processed, if any.
+    prefetcher: Option<prefetch::Prefetcher>,
+    writer: writer::Writer,
+    /// Matting model, if the background of the crops is removed.
+    #[cfg(feature = "matting")]
+    matting_model: Option<&'a matting::MattingModel>,
+    #[cfg(feature = "video")]
+    frame_sampling: video::FrameSamplingParams,
+    animation_sampling: animation::FrameSamplingParams,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Filters the faces of an image down to those matching the reference face.
+    ///
+    /// # Arguments
+    ///
+    /// * `faces` - The faces of the image.
+    /// * `detection_image` - The image the faces were detected in.
+    /// * `reference_embedding` - The embedding of the reference face.
+    /// * `image_name` - Name of the image, for logging.
+    ///
+    /// # Returns
+    ///
+    /// * The matching faces.
+    fn matching_faces(
+        &self,
+        faces: Vec<rust_faces::Face>,
+        detection_image: &image::RgbImage,
+        reference_embedding: &[f32],
+        image_name: &str,
+    ) -> Vec<rust_faces::Face> {
+        let embedding_model = self.embedding_model.unwrap();
+        let face_count = faces.len();
+        let matching: Vec<_> = faces
+            .into_iter()
+            .filter(|face| {
+                let embedding = embedding_model
+                    .embed(detection_image, face)
+                    .unwrap_or_else(|_| panic!("Failed to compute face embedding"));
+                let similarity = embedding::cosine_similarity(&embedding, reference_embedding);
+                trace!("Face similarity to the reference face is {:.3}", similarity);
+                similarity >= self.args.pipeline.match_threshold
+            })
+            .collect();
+        debug!(
+            "{} of {} faces in {} match the reference face",
+            matching.len(),
+            face_count,
+            image_name
+        );
+        matching
+    }
+
+    /// Estimates the attributes of the faces of an image and filters them down to those old
+    /// enough, with the expressions asked for and, if masked faces are skipped, not occluded.
+    ///
+    /// # Arguments
+    ///
+    /// * `faces` - The faces of the image.
+    /// * `detection_image` - The image the faces were detected in.
+    /// * `attribute_pipeline` - The attribute models.
+    /// * `image_name` - Name of the image, for logging.
+    ///
+    /// # Returns
+    ///
+    /// * The faces kept and their attributes.
+    fn annotated_faces(
+        &mut self,
+        faces: Vec<rust_faces::Face>,
+        detection_image: &image::RgbImage,
+        attribute_pipeline: &attributes::AttributePipeline,
+        image_name: &str,
+    ) -> (Vec<rust_faces::Face>, Vec<attributes::FaceAttributes>) {
+        let face_count = faces.len();
+        let emotions: Vec<_> = self
+            .args
+            .pipeline
+            .only_emotions
+            .iter()
+            .copied()
+            .map(get_emotion)
+            .collect();
+        let mut annotated_faces = Vec::new();
+        let mut face_attributes = Vec::new();
+        for face in faces {
+            let attributes = attribute_pipeline
+                .annotate(detection_image, &face)
+                .unwrap_or_else(|_| panic!("Failed to estimate face attributes"));
+            // a face left out by several filters is counted under the first
+            let filter = match (self.args.pipeline.min_age, attributes.age) {
+                (Some(min_age), Some(age)) => {
+                    trace!("Face age is estimated at {:.1}", age);
+                    (age < min_age).then_some("too young")
+                }
+                _ => None,
+            }
+            .or_else(|| match (emotions.is_empty(), attributes.emotion) {
+                (false, Some(emotion)) => {
+                    trace!("Face expression is estimated as {}", emotion.name());
+                    (!emotions.contains(&emotion)).then_some("other expression")
+                }
+                _ => None,
+            })
+            .or_else(
+                || match (self.args.pipeline.skip_masked, attributes.occluded) {
+                    (true, Some(occluded)) => {
+                        trace!("Face occlusion is estimated at {:.3}", occluded);
+                        (occluded > self.args.pipeline.max_occlusion).then_some("masked")
+                    }
+                    _ => None,
+                },
+            );
+            match filter {
+                Some(filter) => self.summary.add_filtered(filter, 1),
+                None => {
+                    annotated_faces.push(face);
+                    face_attributes.push(attributes);
+                }
+            }
+        }
+        let (faces, attributes) = (annotated_faces, face_attributes);
+        if self.args.pipeline.min_age.is_some()
+            || !emotions.is_empty()
+            || self.args.pipeline.skip_masked
+        {
+            debug!(
+                "{} of {} faces in {} have the attributes asked for",
+                faces.len(),
+                face_count,
+                image_name
+            );
+        }
+        (faces, attributes)
+    }
+
+    /// Function to find the class of a face of the current image.
+    ///
+    /// # Arguments
+    ///
+    /// * `face` - The face.
+    ///
+    /// # Returns
+    ///
+    /// * The index of the class, or None if the face isn't a box of the class model.
+    fn face_class(&self, face: &rust_faces::Face) -> Option<usize> {
+        let rect = face.rect;
+        self.class_faces
+            .iter()
+            .find(|class_face| {
+                let class_rect = class_face.face.rect;
+                (
+                    class_rect.x,
+                    class_rect.y,
+                    class_rect.width,
+                    class_rect.height,
+                ) == (rect.x, rect.y, rect.width, rect.height)
+            })
+            .map(|class_face| class_face.class)
+    }
+
+    /// Adds the crops of an image to the manifest, with the embeddings of their faces if they
+    /// are recorded and their head poses and attributes if they are estimated, and writes the
+    /// captions and landmarks sidecars of the crops.
+    ///
+    /// # Arguments
+    ///
+    /// * `crops` - The crops written for the image.
+    /// * `detection_image` - The image the faces were detected in.
+    /// * `faces` - The faces of the image, indexed by the crops.
+    /// * `annotations` - What the models run after detection found out about the faces.
+    ///
+    /// # Returns
+    ///
+    /// * The number of crops.
+    fn record_crops(
+        &mut self,
+        mut crops: Vec<manifest::CropRecord>,
+        detection_image: &image::RgbImage,
+        faces: &[rust_faces::Face],
+        annotations: &FaceAnnotations,
+    ) -> usize {
+        let landmarks = annotations.landmarks.as_deref();
+        if let Some(attributes) = &annotations.attributes {
+            for crop in &mut crops {
+                crop.attributes = Some(attributes[crop.face].clone());
+            }
+        }
+        if let Some(template) = &self.args.pipeline.caption_template {
+            for crop in &crops {
+                let attributes = crop.attributes.as_ref();
+                let caption = caption::render(
+                    template,
+                    &caption::CaptionValues {
+                        token: self.args.pipeline.caption_token.as_deref(),
+                        source: &crop
+                            .source
+                            .file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy(),
+                        face: crop.face,
+                        size_bucket: crop.size_bucket.name(),
+                        age: attributes.and_then(|attributes| attributes.age),
+                        gender: attributes
+                            .and_then(|attributes| attributes.gender)
+                            .map(|gender| match gender {
+                                attributes::Gender::Female => "woman",
+                                attributes::Gender::Male => "man",
+                            }),
+                        emotion: attributes
+                            .and_then(|attributes| attributes.emotion)
+                            .map(|emotion| emotion.name()),
+                    },
+                );
+                let caption_path = caption::caption_path(&self.output_dir.join(&crop.path));
+                std::fs::write(&caption_path, caption)
+                    .unwrap_or_else(|_| panic!("Failed to write caption"));
+                self.outputs.push(caption_path);
+            }
+        }
+        if self.args.pipeline.head_pose {
+            for crop in &mut crops {
+                // dense landmarks are reduced to the five the detector finds
+                let points = match landmarks {
+                    Some(landmarks) => pose::five_point_landmarks(&landmarks[crop.face]),
+                    None => faces[crop.face].landmarks.clone(),
+                };
+                crop.head_pose = points.as_deref().and_then(pose::estimate);
+            }
+        }
+        if let Some(landmarks) = landmarks {
+            for crop in &crops {
+                let rect = &crop.crop_rect;
+                let points = landmarks[crop.face]
+                    .iter()
+                    .map(|(x, y)| [(x - rect.x) / rect.width, (y - rect.y) / rect.height])
+                    .collect();
+                let crop_path = self.output_dir.join(&crop.path);
+                manifest::save_landmarks(
+                    &crop_path,
+                    &manifest::LandmarksSidecar {
+                        source: crop.source.clone(),
+                        face: crop.face,
+                        points,
+                        head_pose: crop.head_pose,
+                    },
+                );
+                self.outputs
+                    .push(manifest::landmarks_sidecar_path(&crop_path));
+            }
+        }
+        // the embeddings of clustered faces are recorded so later runs add to the same clusters
+        if let Some(embeddings) = &annotations.embeddings {
+            for crop in &mut crops {
+                crop.embedding = Some(embeddings[crop.face].clone());
+            }
+        } else if let (true, Some(embedding_model)) =
+            (self.args.pipeline.embeddings, self.embedding_model)
+        {
+            for crop in &mut crops {
+                crop.embedding = Some(
+                    embedding_model
+                        .embed(detection_image, &faces[crop.face])
+                        .unwrap_or_else(|_| panic!("Failed to compute face embedding")),
+                );
+            }
+        }
+        if let Some(ensemble) = &self.ensemble {
+            for crop in &mut crops {
+                // looked up by box as matching against a reference face drops faces
+                let rect = faces[crop.face].rect;
+                crop.detector_confidences = self
+                    .fused_faces
+                    .iter()
+                    .find(|fused| {
+                        let fused = fused.face.rect;
+                        (fused.x, fused.y, fused.width, fused.height)
+                            == (rect.x, rect.y, rect.width, rect.height)
+                    })
+                    .map(|fused| {
+                        ensemble
+                            .model_names
+                            .iter()
+                            .zip(&fused.confidences)
+                            .filter_map(|(name, confidence)| {
+                                confidence.map(|confidence| (name.to_string(), confidence))
+                            })
+                            .collect()
+                    });
+            }
+        }
+        let crops_written = crops.len();
+        self.manifest.crops.extend(crops);
+        let replaced = std::mem::take(&mut self.selection.replaced);
+        if !replaced.is_empty() {
+            self.manifest
+                .crops
+                .retain(|crop| !replaced.contains(&crop.path));
+        }
+        crops_written
+    }
+
+    /// Removes the background of an image with the matting model, if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `detection_image` - The image the faces were detected in.
+    /// * `alpha_image` - The original image if its alpha channel is written out.
+    /// * `keep_alpha` - True if the crops are written with an alpha channel.
+    ///
+    /// # Returns
+    ///
+    /// * The image to take transparent crops from and, if the crops are opaque, the image with
+    ///   the background replaced by a flat color to take them from instead.
+    #[cfg(feature = "matting")]
+    fn remove_background(
+        &self,
+        detection_image: &image::RgbImage,
+        alpha_image: Option<image::RgbaImage>,
+        keep_alpha: bool,
+    ) -> (Option<image::RgbaImage>, Option<image::RgbImage>) {
+        let matting_model = match self.matting_model {
+            Some(matting_model) => matting_model,
+            None => return (alpha_image, None),
+        };
+        let matted_image = matting_model
+            .remove_background(detection_image, alpha_image.as_ref())
+            .unwrap_or_else(|_| panic!("Failed to remove background"));
+        match keep_alpha {
+            true => (Some(matted_image), None),
+            false => {
+                let background = self
+                    .args
+                    .pipeline
+                    .background_color
+                    .unwrap_or(self.args.alpha_background);
+                (
+                    None,
+                    Some(cropping::flatten_alpha(&matted_image, background)),
+                )
+            }
+        }
+    }
+
+    #[cfg(not(feature = "matting"))]
+    fn remove_background(
+        &self,
+        _detection_image: &image::RgbImage,
+        alpha_image: Option<image::RgbaImage>,
+        _keep_alpha: bool,
+    ) -> (Option<image::RgbaImage>, Option<image::RgbImage>) {
+        (alpha_image, None)
+    }
+
+    /// Detects the faces in an image and writes its crops, redacted copy or virtual crop.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_image` - The image to process, either a decoded file or a video frame.
+    /// * `image_name` - Name the outputs are prefixed with.
+    /// * `image_path` - Path of the input file the image was read from.
+    /// * `image_contact_sheet` - Contact sheet of the input the image belongs to, if any.
+    /// * `budget` - Crops to skip and write at most. Updated with the crops of this image.
+    ///
+    /// # Returns
+    ///
+    /// * The number of crops written.
+    fn process_image(
+        &mut self,
+        input_image: image::DynamicImage,
+        image_name: &str,
+        image_path: &Path,
+        image_contact_sheet: Option<&mut ContactSheet<'a>>,
+        budget: &mut CropBudget,
+    ) -> usize {
+        let has_alpha = input_image.color().has_alpha();
+        let transparent_output = has_alpha
+            || (self.args.pipeline.remove_background
+                && self.args.pipeline.background_color.is_none());
+        let high_bit_depth = is_high_bit_depth(input_image.color());
+        let (format, keep_alpha) = match (self.args.output_format, high_bit_depth) {
+            (OutputFormat::Auto, true) => (image::ImageFormat::Png, transparent_output),
+            _ => get_output_format(self.args, transparent_output),
+        };
+        // 16-bit inputs keep their bit depth through crop and resize when written as PNGs
+        let deep_image = (high_bit_depth
+            && format == image::ImageFormat::Png
+            && !self.args.pipeline.remove_background
+            && post_processing::keeps_bit_depth(self.post_process_params))
+        .then(|| match keep_alpha {
+            true => image::DynamicImage::ImageRgba16(input_image.to_rgba16()),
+            false => image::DynamicImage::ImageRgb16(input_image.to_rgb16()),
+        });
+        // with --low-memory the decoded buffer is converted in place where possible and only
+        // kept alongside the detection image when its alpha channel is written out
+        let (detection_image, alpha_image) = match (has_alpha, self.args.low_memory) {
+            (true, true) => {
+                let rgba_image = input_image.into_rgba8();
+                let detection_image =
+                    cropping::flatten_alpha(&rgba_image, self.args.alpha_background);
+                (detection_image, keep_alpha.then_some(rgba_image))
+            }
+            (true, false) => (
+                cropping::flatten_alpha(&input_image.to_rgba8(), self.args.alpha_background),
+                keep_alpha.then(|| input_image.into_rgba8()),
+            ),
+            (false, true) => (input_image.into_rgb8(), None),
+            (false, false) => (input_image.to_rgb8(), None),
+        };
+
+        let started = Instant::now();
+        let faces = match &self.face_source {
+            FaceSource::Detector(face_detector) => {
+                let mut faces = match &self.ensemble {
+                    Some(ensemble) => {
+                        let (faces, ensemble_faces) = std::thread::scope(|scope| {
+                            let ensemble_faces = scope.spawn(|| {
+                                cropping::detect_faces_in_image(&detection_image, ensemble.detector)
+                            });
+                            (
+                                cropping::detect_faces_in_image(&detection_image, *face_detector),
+                                ensemble_faces.join().unwrap(),
+                            )
+                        });
+                        self.fused_faces = fusion::weighted_box_fusion(vec![faces, ensemble_faces]);
+                        self.fused_faces
+                            .iter()
+                            .map(|fused| fused.face.clone())
+                            .collect()
+                    }
+                    None => cropping::detect_faces_in_image(&detection_image, *face_detector),
+                };
+                debug!("Detected {} faces in {}", faces.len(), image_name);
+                if let (true, Some(fallback_detector)) = (faces.is_empty(), self.fallback_detector)
+                {
+                    faces = cropping::detect_faces_in_image(&detection_image, fallback_detector);
+                    debug!(
+                        "Detected {} faces in {} with the fallback model",
+                        faces.len(),
+                        image_name
+                    );
+                }
+                match self
+                    .imported_faces
+                    .as_ref()
+                    .and_then(|imported_faces| boxes::faces_of(imported_faces, image_path))
+                {
+                    Some(imported) => {
+                        let faces = boxes::merge_faces(imported, faces, IMPORT_MERGE_OVERLAP);
+                        debug!(
+                            "Merged {} imported faces into {} faces in {}",
+                            imported.len(),
+                            faces.len(),
+                            image_name
+                        );
+                        faces
+                    }
+                    None => faces,
+                }
+            }
+            FaceSource::Boxes(boxes) => match boxes::faces_of(boxes, image_path) {
+                Some(faces) => faces.to_vec(),
+                None => {
+                    warn!("No boxes for image {}. Skipping", image_path.display());
+                    return 0;
+                }
+            },
+            FaceSource::Classes(class_model) => {
+                let class_crops = self.class_crops.unwrap();
+                self.class_faces = class_model
+                    .detect(&detection_image)
+                    .unwrap_or_else(|_| panic!("Failed to detect with the class model"))
+                    .into_iter()
+                    .filter(|class_face| class_crops.crop_params.contains_key(&class_face.class))
+                    .collect();
+                debug!(
+                    "Detected {} boxes of the cropped classes in {}",
+                    self.class_faces.len(),
+                    image_name
+                );
+                self.class_faces
+                    .iter()
+                    .map(|class_face| class_face.face.clone())
+                    .collect()
+            }
+        };
+        self.summary.add_image(&faces);
+        if let Some(metrics) = self.metrics {
+            metrics.add_image(faces.len());
+        }
+        self.record_stage(metrics::Stage::Detect, started);
+        let started = Instant::now();
+        let faces = match &self.reference_embedding {
+            Some(reference_embedding) => {
+                let face_count = faces.len();
+                let faces =
+                    self.matching_faces(faces, &detection_image, reference_embedding, image_name);
+                self.summary
+                    .add_filtered("not matching", face_count - faces.len());
+                faces
+            }
+            None => faces,
+        };
+        let (faces, attributes) = match self.attribute_pipeline {
+            Some(attribute_pipeline) => {
+                let (faces, attributes) =
+                    self.annotated_faces(faces, &detection_image, attribute_pipeline, image_name);
+                (faces, Some(attributes))
+            }
+            None => (faces, None),
+        };
+        let landmarks = self.landmark_model.map(|landmark_model| {
+            faces
+                .iter()
+                .map(|face| {
+                    landmark_model
+                        .landmarks(&detection_image, &face.rect)
+                        .unwrap_or_else(|_| panic!("Failed to locate face landmarks"))
+                })
+                .collect::<Vec<_>>()
+        });
+        // faces are clustered before they are cropped as their cluster decides where the crop
+        // goes
+        let embeddings = self.person_clusters.is_some().then(|| {
+            let embedding_model = self.embedding_model.unwrap();
+            faces
+                .iter()
+                .map(|face| {
+                    embedding_model
+                        .embed(&detection_image, face)
+                        .unwrap_or_else(|_| panic!("Failed to compute face embedding"))
+                })
+                .collect::<Vec<_>>()
+        });
+        let person_clusters = self.person_clusters.as_mut().zip(embeddings.as_ref()).map(
+            |(person_clusters, embeddings)| {
+                embeddings
+                    .iter()
+                    .map(|embedding| person_clusters.assign(embedding))
+                    .collect()
+            },
+        );
+        self.record_stage(metrics::Stage::Annotate, started);
+        let started = Instant::now();
+        // the crops are widened to the jawline while the records keep the detected boxes
+        let annotations = FaceAnnotations {
+            landmarks,
+            attributes,
+            embeddings,
+        };
+        if let Some(annotated_images) = &mut self.annotated_images {
+            annotated_images.push(export::AnnotatedImage {
+                path: image_path
+                    .strip_prefix(self.input_root)
+                    .unwrap_or(image_path)
+                    .to_path_buf(),
+                width: detection_image.width(),
+                height: detection_image.height(),
+                faces: faces.clone(),
+            });
+        }
+        // boxes of a class model are cropped with the settings of their class
+        let face_classes: Option<Vec<usize>> = self.class_crops.map(|_| {
+            faces
+                .iter()
+                .filter_map(|face| self.face_class(face))
+                .collect()
+        });
+        let face_crop_params: Vec<&cropping::CropParams> = match (self.class_crops, &face_classes) {
+            (Some(class_crops), Some(face_classes)) => face_classes
+                .iter()
+                .map(|class| &class_crops.crop_params[class])
+                .collect(),
+            _ => vec![self.crop_params; faces.len()],
+        };
+        let faces_to_crop = match (&annotations.landmarks, self.args.pipeline.jawline_crops) {
+            (Some(landmarks), true) => faces
+                .iter()
+                .zip(landmarks)
+                .map(|(face, landmarks)| rust_faces::Face {
+                    rect: cropping::jawline_rect(&face.rect, landmarks),
+                    ..face.clone()
+                })
+                .collect(),
+            _ => faces.clone(),
+        };
+
+        let target = OutputTarget {
+            output_dir: self.output_dir,
+            output_storage: self.output_storage,
+            output_subdir: &self.output_subdir,
+            image_name,
+            source: image_path,
+            format,
+            quality: self.args.quality,
+            jpeg_options: get_jpeg_options(self.args),
+            embed_provenance: self.args.pipeline.embed_provenance,
+            size_bucket_in_name: self.args.pipeline.size_bucket_in_filename,
+            split: self.args.pipeline.split.as_ref().map(|splits| {
+                let source = image_path
+                    .strip_prefix(self.input_root)
+                    .unwrap_or(image_path);
+                split::assign(&splits.0, self.args.pipeline.split_seed, source)
+            }),
+            emotions_in_name: annotations
+                .attributes
+                .as_ref()
+                .filter(|_| self.args.pipeline.emotion_in_filename)
+                .map(|attributes| {
+                    attributes
+                        .iter()
+                        .map(|attributes| attributes.emotion)
+                        .collect()
+                }),
+            stable_names: self.args.pipeline.stable_names,
+            per_image_dir: self.args.pipeline.per_image_dirs,
+            on_conflict: get_conflict_policy(self.args),
+            layout: &self.layout,
+            capture_date: match self.layout {
+                layout::Layout::PersonDate => layout::capture_date(image_path),
+                _ => None,
+            },
+            person_clusters,
+            classes: self.class_crops.zip(face_classes.as_ref()).map(
+                |(class_crops, face_classes)| {
+                    face_classes
+                        .iter()
+                        .map(|class| class_crops.names[*class].clone())
+                        .collect()
+                },
+            ),
+            writer: &self.writer,
+        };
+
+        if self.args.pipeline.debug_overlay {
+            self.outputs.push(write_debug_overlay(
+                &detection_image,
+                &faces_to_crop,
+                &face_crop_params,
+                &self.output_dir.join(&self.output_subdir),
+                image_name,
+            ));
+        }
+
+        if let Some(candidate_detector) = self.candidate_detector {
+            self.outputs.push(write_confidence_heatmap(
+                &detection_image,
+                candidate_detector,
+                &self.output_dir.join(&self.output_subdir),
+                image_name,
+            ));
+        }
+
+        if self.args.pipeline.virtual_crop {
+            write_virtual_crop(
+                image_path,
+                &detection_image,
+                &faces_to_crop,
+                &face_crop_params,
+            );
+            return 0;
+        }
+
+        let (alpha_image, background_image) =
+            self.remove_background(&detection_image, alpha_image, keep_alpha);
+        let crop_image = background_image.as_ref().unwrap_or(&detection_image);
+        let mut contact_sheets: Vec<_> = self
+            .run_contact_sheet
+            .iter_mut()
+            .chain(image_contact_sheet)
+            .collect();
+        let crops_written = match (&alpha_image, self.redact_params) {
+            (Some(alpha_image), Some(redact_params)) => redact::redact_faces(
+                cropping::CropInputs {
+                    input_image: alpha_image,
+                    faces: &faces_to_crop,
+                },
+                redact_params,
+                &target,
+                &mut self.outputs,
+            ),
+            (None, Some(redact_params)) => redact::redact_faces(
+                cropping::CropInputs {
+                    input_image: &detection_image,
+                    faces: &faces_to_crop,
+                },
+                redact_params,
+                &target,
+                &mut self.outputs,
+            ),
+            (Some(alpha_image), None) => {
+                let crops = process_faces(
+                    cropping::CropInputs {
+                        input_image: alpha_image,
+                        faces: &faces_to_crop,
+                    },
+                    &face_crop_params,
+                    self.post_process_params,
+                    self.size_bucket_params,
+                    &target,
+                    deep_image.as_ref(),
+                    &mut contact_sheets,
+                    &mut self.selection,
+                    budget,
+                    &mut self.summary,
+                );
+                self.record_crops(crops, &detection_image, &faces, &annotations)
+            }
+            (None, None) => {
+                let crops = process_faces(
+                    cropping::CropInputs {
+                        input_image: crop_image,
+                        faces: &faces_to_crop,
+                    },
+                    &face_crop_params,
+                    self.post_process_params,
+                    self.size_bucket_params,
+                    &target,
+                    deep_image.as_ref(),
+                    &mut contact_sheets,
+                    &mut self.selection,
+                    budget,
+                    &mut self.summary,
+                );
+                self.record_crops(crops, &detection_image, &faces, &annotations)
+            }
+        };
+
+        budget.remaining = budget
+            .remaining
+            .map(|remaining| remaining.saturating_sub(crops_written));
+        self.summary.crops_written += crops_written;
+        self.record_stage(metrics::Stage::Crop, started);
+        crops_written
+    }
+
+    /// Processes a single input of any kind and records its crops in the manifest, which is
+    /// saved with the other run files every few inputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_path` - Path of the input, or "-" for stdin.
+    /// * `budget` - Crops to skip and write at most. Updated with the crops of this input.
+    ///
+    /// # Returns
+    ///
+    /// * The number of crops written, or None if the input was skipped as its crops already
+    ///   exist.
+    fn process_input(&mut self, input_path: &Path, budget: &mut CropBudget) -> Option<usize> {
+        let args = self.args;
+        self.input_storage.fetch(input_path);
+        // listed objects are only checked for facecrop's own outputs once they are fetched
+        if self.input_storage.url(input_path).is_some()
+            && input_kind(input_path) == InputKind::Image
+            && skip_own_output(input_path, args.pipeline.own_outputs, || {
+                provenance::is_facecrop_output(input_path)
+            })
+        {
+            return None;
+        }
+        // the manifest lists the inputs where they were read from rather than where they were
+        // staged
+        let source_path = self
+            .input_storage
+            .url(input_path)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| input_path.to_path_buf());
+        // hashing reads the whole input, so it is only done when the cache or the check of
+        // existing crops compares it
+        let source_hash = match input_path.as_os_str() != STDIO_PATH
+            && (args.pipeline.cache
+                || (args.pipeline.skip_existing
+                    && args.pipeline.skip_existing_check == SkipExistingCheck::Hash))
+        {
+            true => manifest::file_hash(input_path),
+            false => None,
+        };
+        let cache_key = input_path
+            .strip_prefix(self.input_root)
+            .unwrap_or(input_path)
+            .to_path_buf();
+        if let (Some(cache), Some(source_hash)) = (self.cache.as_ref(), source_hash.as_deref()) {
+            if cache.is_current(&cache_key, source_hash)
+                && self
+                    .manifest
+                    .crops
+                    .iter()
+                    .filter(|crop| crop.source.starts_with(&source_path))
+                    .all(|crop| {
+                        self.output_storage
+                            .exists(&self.output_dir.join(&crop.path))
+                    })
+            {
+                info!(
+                    "Skipping {} as it is unchanged since it was processed with the same \
+                    parameters",
+                    input_path.display()
+                );
+                return None;
+            }
+            if cache.contains(&cache_key) {
+                info!(
+                    "{} or the parameters changed since it was processed. Reprocessing",
+                    input_path.display()
+                );
+                remove_crops(
+                    &mut self.manifest,
+                    &source_path,
+                    self.output_dir,
+                    self.output_storage,
+                );
+            }
+        }
+        if args.pipeline.skip_existing
+            && self.manifest.inputs_without_crops.iter().any(|input| {
+                input.source == source_path
+                    && (args.pipeline.skip_existing_check == SkipExistingCheck::Exists
+                        || (source_hash.is_some() && input.source_hash == source_hash))
+            })
+        {
+            info!(
+                "Skipping {} as it had no crops when it was processed before",
+                input_path.display()
+            );
+            return None;
+        }
+        if args.pipeline.skip_existing
+            && has_current_crops(
+                &mut self.manifest,
+                &source_path,
+                source_hash.as_deref(),
+                args.pipeline.skip_existing_check,
+                self.output_dir,
+                self.output_storage,
+            )
+        {
+            info!(
+                "Skipping {} as its crops already exist",
+                input_path.display()
+            );
+            return None;
+        }
+        let first_new_crop = self.manifest.crops.len();
+        self.outputs.clear();
+
+        if args.pipeline.preserve_structure {
+            self.output_subdir = input_path
+                .parent()
+                .and_then(|parent| parent.strip_prefix(self.input_root).ok())
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            std::fs::create_dir_all(self.output_dir.join(&self.output_subdir))
+                .unwrap_or_else(|_| panic!("Failed to create output directory"));
+        }
+
+        let input_kind = input_kind(input_path);
+        let input_name = match input_kind {
+            _ if input_path.as_os_str() == STDIO_PATH => "stdin".to_string(),
+            InputKind::Archive => archive::archive_name(input_path),
+            _ => input_path
+                .file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        };
+        let mut image_contact_sheet = match args.pipeline.contact_sheet_per_image {
+            true => Some(ContactSheet::new(
+                self.contact_sheet_params,
+                self.output_dir
+                    .join(&self.output_subdir)
+                    .join(format!("{}-sheet.jpg", input_name)),
+            )),
+            false => None,
+        };
+
+        let crops_written = match input_kind {
+            InputKind::Image => {
+                let started = Instant::now();
+                let input_image = self.read_input_image(input_path);
+                self.record_stage(metrics::Stage::Decode, started);
+                self.process_image(
+                    input_image,
+                    &input_name,
+                    input_path,
+                    image_contact_sheet.as_mut(),
+                    budget,
+                )
+            }
+            #[cfg(feature = "video")]
+            InputKind::Video => video::process(
+                self,
+                input_path,
+                &input_name,
+                image_contact_sheet.as_mut(),
+                budget,
+            ),
+            #[cfg(not(feature = "video"))]
+            InputKind::Video => {
+                unreachable!("Video inputs are only collected with the video feature")
+            }
+            InputKind::Animation => animation::process(
+                self,
+                input_path,
+                &input_name,
+                image_contact_sheet.as_mut(),
+                budget,
+            ),
+            InputKind::Archive => archive::process(
+                self,
+                input_path,
+                &input_name,
+                image_contact_sheet.as_mut(),
+                budget,
+            ),
+        };
+
+        if let Some(contact_sheet) = image_contact_sheet {
+            contact_sheet.finish();
+        }
+        for crop in &mut self.manifest.crops[first_new_crop..] {
+            crop.source_hash = source_hash.clone();
+            if let Some(url) = self.input_storage.url(&crop.source) {
+                crop.source = PathBuf::from(url);
+            }
+        }
+        // the manifest only lists crops once they are on disk
+        let started = Instant::now();
+        let failed = self.writer.flush();
+        self.record_stage(metrics::Stage::Write, started);
+        if !failed.is_empty() {
+            let output_dir = self.output_dir;
+            let crops_before = self.manifest.crops.len();
+            self.manifest
+                .crops
+                .retain(|crop| !failed.contains(&output_dir.join(&crop.path)));
+            self.summary.crops_written = self
+                .summary
+                .crops_written
+                .saturating_sub(crops_before - self.manifest.crops.len());
+        }
+        if let Some(modified) = args
+            .pipeline
+            .preserve_times
+            .then(|| source_time(input_path))
+            .flatten()
+        {
+            for crop in &self.manifest.crops[first_new_crop..] {
+                let crop_path = self.output_dir.join(&crop.path);
+                let preserved = std::fs::File::options()
+                    .write(true)
+                    .open(&crop_path)
+                    .and_then(|file| file.set_modified(modified));
+                if let Err(error) = preserved {
+                    warn!(
+                        "Failed to set the modification time of {}: {}",
+                        crop_path.display(),
+                        error
+                    );
+                }
+            }
+        }
+        self.manifest
+            .inputs_without_crops
+            .retain(|input| input.source != source_path);
+        // an input whose crops failed to be written is processed again rather than skipped
+        if failed.is_empty()
+            && input_path.as_os_str() != STDIO_PATH
+            && !self
+                .manifest
+                .crops
+                .iter()
+                .any(|crop| crop.source.starts_with(&source_path))
+        {
+            self.manifest
+                .inputs_without_crops
+                .push(manifest::InputRecord {
+                    source: source_path.clone(),
+                    source_hash: source_hash.clone(),
+                });
+        }
+        // a zip holds the only copy of the crops once they are stored, so they are hashed for the
+        // signature first
+        if self.signing_key.is_some() {
+            manifest::hash_crops(&mut self.manifest.crops[first_new_crop..], self.output_dir);
+        }
+        self.output_storage.store(
+            &self.manifest.crops[first_new_crop..]
+                .iter()
+                .map(|crop| self.output_dir.join(&crop.path))
+                .chain(self.outputs.drain(..))
+                .collect::<Vec<_>>(),
+        );
+        // the input is counted as failed once the crops that were written are in the manifest,
+        // and is left out of the cache so it is processed again
+        if !failed.is_empty() {
+            for crop_path in &failed {
+                error!("Failed to save output image {}", crop_path.display());
+            }
+            panic!("Failed to save {} output image(s)", failed.len());
+        }
+        if let (Some(cache), Some(source_hash)) = (self.cache.as_mut(), source_hash) {
+            cache.insert(cache_key, source_hash);
+        }
+
+        Some(crops_written)
+    }
+
+    /// Saves the manifest, signing it if a key is given, and the cache to the output directory.
+    fn save_run_files(&mut self) {
+        if !self.manifest.crops.is_empty() || !self.manifest.inputs_without_crops.is_empty() {
+            if let Some(signing_key) = &self.signing_key {
+                manifest::sign(&mut self.manifest, self.output_dir, signing_key);
+            }
+            manifest::save(self.output_dir, &self.manifest);
+        }
+        if let Some(cache) = &self.cache {
+            cache::save(self.output_dir, cache);
+        }
+    }
+
+    /// Records the time spent in a stage since it started in the run summary and the metrics.
+    fn record_stage(&mut self, stage: metrics::Stage, started: Instant) {
+        let elapsed = started.elapsed();
+        self.summary.stage_seconds.add(stage, elapsed.as_secs_f64());
+        if let Some(metrics) = self.metrics {
+            metrics.observe(stage, elapsed);
+        }
+    }
+
+    /// Reads an image input, taking it from the prefetched inputs if it was decoded ahead.
+    fn read_input_image(&mut self, input_path: &Path) -> image::DynamicImage {
+        match self
+            .prefetcher
+            .as_mut()
+            .and_then(|prefetcher| prefetcher.take(input_path))
+        {
+            Some(input_image) => {
+                input_image.unwrap_or_else(|_| panic!("Failed to open image file"))
+            }
+            None => read_image(input_path, self.args.low_memory),
+        }
+    }
+
+    /// Starts decoding the image inputs among the given inputs ahead of processing them.
+    fn prefetch(&mut self, input_paths: &[PathBuf]) {
+        let decode_threads = get_stage_threads(self.args, self.args.pipeline.decode_threads);
+        self.prefetcher = (decode_threads > 0).then(|| {
+            let image_paths = input_paths
+                .iter()
+                .filter(|path| {
+                    path.as_os_str() != STDIO_PATH && input_kind(path) == InputKind::Image
+                })
+                .cloned()
+                .collect();
+            prefetch::Prefetcher::new(
+                image_paths,
+                decode_threads,
+                self.input_storage.clone(),
+                self.args.low_memory,
+            )
+        });
+    }
+}
+
+fn main() {
+    // panics are fatal errors, which exit with 1 rather than the 101 of an uncaught panic
+    let exit_code = catch_unwind(run).unwrap_or(EXIT_FATAL);
+    // removed here so they don't outlive a run that stopped on a panic
+    storage::remove_staging_dirs();
+    std::process::exit(exit_code);
+}
+
+/// Function to run the program.
+///
+/// # Returns
+///
+/// * The exit code.
+fn run() -> i32 {
+    let mut command = Args::command();
+    let matches = match command.try_get_matches_from_mut(std::env::args_os()) {
+        Ok(matches) => matches,
+        Err(error) => return report_usage(error),
+    };
+    if let Some(error) = misplaced_pipeline_arg(&mut command, &matches) {
+        return report_usage(error);
+    }
+    let args = match Args::from_arg_matches(&matches) {
+        Ok(args) => args,
+        Err(error) => return report_usage(error),
+    };
+    let args = resolve_pipeline_args(args);
+
+    let level = match args.verbose {
+        0 => tracing::Level::INFO,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    // logs move to stderr when stdout carries the output image or detections
+    let logs_to_stderr = args.output_dir.as_deref() == Some(STDIO_PATH)
+        || matches!(
+            args.command,
+            Some(Command::Detect {
+                format: DetectFormat::Json,
+                ..
+            })
+        );
+    let capabilities = console::detect(args.no_color, args.ascii, logs_to_stderr);
+    console::init(&capabilities);
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(capabilities.color)
+        .with_writer(match logs_to_stderr {
+            true => BoxMakeWriter::new(std::io::stderr),
+            false => BoxMakeWriter::new(std::io::stdout),
+        })
+        .init();
+
+    match &args.command {
+        Some(Command::Selftest) => {
+            info!("Running self test{}", console::symbol("🩺"));
+            if !selftest::run() {
+                return EXIT_FATAL;
+            }
+            info!("Self test passed{}", console::symbol("🎉"));
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut command, "facecrop", &mut std::io::stdout());
+            return EXIT_SUCCESS;
+        }
+        Some(Command::PrintConfig { .. }) => {
+            // the pipeline options are given after the subcommand, which also has the global ones
+            let (name, matches) = matches.subcommand().unwrap();
+            let command = command.find_subcommand(name).unwrap();
+            print!("{}", config::to_toml(command, matches));
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Info) => {
+            acceleration::log_info(get_acceleration(&args));
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Sweep {
+            path,
+            output_dir,
+            params,
+            sample,
+        }) => {
+            sweep::run(&args, path, output_dir, params, *sample);
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Compare { before, after }) => {
+            let load = |output_dir: &PathBuf| {
+                manifest::load(output_dir).unwrap_or_else(|| {
+                    panic!("Failed to read manifest of {}", output_dir.display())
+                })
+            };
+            let comparison = compare::compare(&load(before), &load(after));
+            info!(
+                "{} added, {} removed, {} changed, {} unchanged",
+                comparison.added, comparison.removed, comparison.changed, comparison.unchanged
+            );
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Review {
+            output_dir,
+            move_to,
+        }) => {
+            let counts = review::review(output_dir, move_to);
+            info!(
+                "{} kept, {} rejected, {} moved, {} undecided",
+                counts.kept, counts.rejected, counts.moved, counts.undecided
+            );
+            return EXIT_SUCCESS;
+        }
+        Some(Command::VerifyManifest { output_dir, key }) => {
+            let manifest = manifest::load(output_dir)
+                .unwrap_or_else(|| panic!("Failed to read manifest of {}", output_dir.display()));
+            let problems = manifest::verify(&manifest, output_dir, &read_signing_key(key));
+            for problem in &problems {
+                error!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return EXIT_FATAL;
+            }
+            info!(
+                "Manifest and {} crops verified{}",
+                manifest.crops.len(),
+                console::symbol("🔏")
+            );
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Verify { path }) => {
+            if !path.exists() {
+                panic!("Input path does not exist");
+            }
+            let image_paths = match path.is_file() {
+                true => vec![path.clone()],
+                false => list_input_dir(path, false, args.follow_symlinks)
+                    .into_iter()
+                    .filter(|path| input_kind(path) == InputKind::Image)
+                    .filter(|path| !skip_unsupported(path))
+                    .collect(),
+            };
+            info!(
+                "Verifying {} images{}",
+                image_paths.len(),
+                console::symbol("🔍")
+            );
+            if !verify::run(&image_paths) {
+                return EXIT_FATAL;
+            }
+            info!("All images decoded successfully{}", console::symbol("🎉"));
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Detect { path, format }) => {
+            detect::run(&args, path, *format);
+            return EXIT_SUCCESS;
+        }
+        Some(Command::Serve {
+            address,
+            max_concurrent_requests,
+            queue_depth,
+        }) => {
+            server::run(
+                &args,
+                address,
+                max_concurrent_requests.unwrap_or(args.workers),
+                *queue_depth,
+            );
+            return EXIT_SUCCESS;
+        }
+        Some(
+            Command::Crop { .. }
+            | Command::CropFrom { .. }
+            | Command::Redact { .. }
+            | Command::Video { .. }
+            | Command::Watch { .. },
+        )
+        | None => (),
+    }
+
+    let watching = matches!(args.command, Some(Command::Watch { .. }));
+
+    info!("Running program with args {:?}", args);
+    info!("Checking args");
+    if logs_to_stderr {
+        return write_face_to_stdout(&args);
+    }
+    let run_started = SystemTime::now();
+    let mut paths = get_paths(&args);
+    let last_run = state::load(&paths.output_dir).and_then(|state| state.last_run);
+    if let (Some(newer_than), false) = (args.pipeline.newer_than, watching) {
+        let since = match newer_than {
+            NewerThan::Time(time) => Some(time),
+            NewerThan::LastRun => {
+                last_run.map(|last_run| UNIX_EPOCH + Duration::from_secs(last_run))
+            }
+        };
+        match since {
+            Some(since) => {
+                let input_count = paths.input_image_paths.len();
+                paths.input_image_paths.retain(|path| {
+                    path.as_os_str() == STDIO_PATH
+                        || paths
+                            .input_storage
+                            .modified(path)
+                            .is_none_or(|modified| modified >= since)
+                });
+                info!(
+                    "Skipping {} input(s) not modified since {}",
+                    input_count - paths.input_image_paths.len(),
+                    match newer_than {
+                        NewerThan::Time(_) => "the given time",
+                        NewerThan::LastRun => "the last run",
+                    }
+                );
+            }
+            None => {
+                info!("No earlier run recorded in the output directory. Processing every input")
+            }
+        }
+    }
+    let crop_params = get_crop_params(&args);
+    let post_process_params = get_post_process_params(&args);
+    let redact_params = get_redact_params(&args);
+    let contact_sheet_params = get_contact_sheet_params(&args);
+    let size_bucket_params = get_size_bucket_params(&args);
+
+    let candidate_detector = match args.pipeline.confidence_heatmap {
+        true => {
+            let (model, infer_params) = get_detector_params(&args);
+            let candidate_detector = cropping::get_candidate_detector(
+                model,
+                infer_params,
+                heatmap::CANDIDATE_SCORE_THRESHOLD,
+            );
+            if candidate_detector.is_none() {
+                warn!(
+                    "Confidence heatmaps are not supported by the {:?} detector",
+                    model
+                );
+            }
+            candidate_detector
+                .map(|candidate_detector| with_detect_max_dim(&args, candidate_detector))
+        }
+        false => None,
+    };
+    // the classes are checked before the class model is loaded
+    let class_crops = args
+        .pipeline
+        .class_model
+        .as_ref()
+        .map(|_| get_class_crops(&args));
+    let face_detector;
+    let class_model;
+    let face_source = match (&args.command, &args.pipeline.import_annotations) {
+        (Some(Command::CropFrom { .. }), Some(_)) => {
+            panic!("--import-annotations can't be used with crop-from, which crops the --boxes")
+        }
+        (Some(Command::CropFrom { boxes, .. }), None) => FaceSource::Boxes(boxes::load(boxes)),
+        (_, Some(annotations_path))
+            if args.pipeline.annotation_policy == AnnotationPolicy::Replace =>
+        {
+            FaceSource::Boxes(boxes::load_coco(annotations_path, &paths.input_root))
+        }
+        (_, None) if class_crops.is_some() => {
+            info!("Loading class model{}", console::symbol("🤖"));
+            class_model = classes::ClassModel::from_file(
+                args.pipeline.class_model.as_ref().unwrap(),
+                get_session_options(&args),
+            )
+            .unwrap_or_else(|_| panic!("Failed to load class model"));
+            FaceSource::Classes(&class_model)
+        }
+        _ => {
+            info!("Instantiating face detector{}", console::symbol("🤖"));
+            face_detector = get_detector_pool(&args, watching);
+            FaceSource::Detector(&face_detector)
+        }
+    };
+    // merged annotations are looked up after detection, replacing ones are the face source
+    let imported_faces = match (&face_source, &args.pipeline.import_annotations) {
+        (FaceSource::Detector(_), Some(annotations_path)) => {
+            Some(boxes::load_coco(annotations_path, &paths.input_root))
+        }
+        _ => None,
+    };
+    // --low-memory only replaces the primary detector, the models given for the others are kept
+    if args.low_memory && matches!(face_source, FaceSource::Detector(_)) {
+        for (flag, model) in [
+            ("--fallback-model", args.pipeline.fallback_model),
+            ("--ensemble", args.pipeline.ensemble),
+        ] {
+            if let Some(model) = model.filter(|model| *model != DetectorModel::BlazeFace320) {
+                warn!(
+                    "{} {} is loaded as given, though --low-memory detects with blazeface-320",
+                    flag,
+                    get_detector_model(model).name()
+                );
+            }
+        }
+    }
+    let (fallback_detector, ensemble_detector) = match face_source {
+        FaceSource::Detector(_) => (
+            args.pipeline
+                .fallback_model
+                .map(|model| get_model_detector(&args, get_detector_model(model))),
+            args.pipeline
+                .ensemble
+                .map(|model| get_model_detector(&args, get_detector_model(model))),
+        ),
+        FaceSource::Boxes(_) | FaceSource::Classes(_) => (None, None),
+    };
+    // crop-from crops the given boxes rather than those of the class model
+    let class_crops = class_crops.filter(|_| matches!(face_source, FaceSource::Classes(_)));
+    if args.pipeline.remove_background && !cfg!(feature = "matting") {
+        panic!("facecrop was built without the matting feature");
+    }
+    #[cfg(feature = "matting")]
+    let matting_model = args
+        .pipeline
+        .matting_model
+        .as_ref()
+        .filter(|_| args.pipeline.remove_background)
+        .map(|model_path| {
+            matting::MattingModel::from_file(model_path, get_session_options(&args))
+                .unwrap_or_else(|_| panic!("Failed to load matting model"))
+        });
+    let embedding_model = match args.pipeline.embeddings
+        || args.pipeline.match_face.is_some()
+        || args.pipeline.layout == OutputLayout::PersonDate
+    {
+        true => {
+            let model_path = args.pipeline.embedding_model.as_ref().unwrap();
+            Some(
+                embedding::EmbeddingModel::from_file(model_path, get_session_options(&args))
+                    .unwrap_or_else(|_| panic!("Failed to load embedding model")),
+            )
+        }
+        false => None,
+    };
+    let attribute_pipeline = get_attribute_pipeline(&args);
+    let landmark_model = args.pipeline.landmarks.map(|_| {
+        let model_path = args.pipeline.landmark_model.as_ref().unwrap();
+        cropping::LandmarkModel::new(
+            acceleration::load_session(model_path, get_session_options(&args))
+                .unwrap_or_else(|_| panic!("Failed to load landmark model")),
+        )
+    });
+    let reference_embedding = args.pipeline.match_face.as_ref().map(|reference_path| {
+        let embedding_model = embedding_model.as_ref().unwrap();
+        match &face_source {
+            FaceSource::Detector(face_detector) => {
+                get_reference_embedding(&args, reference_path, embedding_model, *face_detector)
+            }
+            // the reference face still needs detecting when the inputs' boxes are supplied or
+            // aren't all faces
+            FaceSource::Boxes(_) | FaceSource::Classes(_) => {
+                let face_detector = get_face_detector(&args);
+                get_reference_embedding(&args, reference_path, embedding_model, &*face_detector)
+            }
+        }
+    });
+    info!("Starting inference and cropping{}", console::symbol("🚀"));
+
+    let resume_state = match args.pipeline.resume {
+        true => state::load(&paths.output_dir).filter(|state| state.next_input.is_some()),
+        false => None,
+    };
+    // the state is relative to the input directory, which may have moved since it was written
+    let resume_input = resume_state
+        .as_ref()
+        .and_then(|resume_state| resume_state.next_input_path(&paths.input_root));
+    if let (Some(resume_state), Some(resume_input)) = (&resume_state, &resume_input) {
+        info!(
+            "Resuming from {} after {} crops",
+            resume_input.display(),
+            resume_state.crops_written
+        );
+    }
+
+    // metrics are served from another thread for as long as the watcher runs
+    let metrics = watching.then(|| Arc::new(metrics::Metrics::default()));
+    if let (
+        Some(metrics),
+        Some(Command::Watch {
+            metrics_address: Some(address),
+            ..
+        }),
+    ) = (&metrics, &args.command)
+    {
+        let (metrics, address) = (metrics.clone(), address.clone());
+        std::thread::spawn(move || {
+            server::serve(&address, 1, 0, &metrics, |_| {
+                server::Response::error(404, "Not found")
+            })
+        });
+    }
+
+    let mut pipeline = Pipeline {
+        args: &args,
+        face_source,
+        candidate_detector: candidate_detector.as_deref(),
+        fallback_detector: fallback_detector.as_deref(),
+        imported_faces,
+        ensemble: ensemble_detector.as_deref().map(|detector| Ensemble {
+            detector,
+            model_names: [
+                get_detector_params(&args).0.name(),
+                get_detector_model(args.pipeline.ensemble.unwrap()).name(),
+            ],
+        }),
+        fused_faces: Vec::new(),
+        class_crops: class_crops.as_ref(),
+        class_faces: Vec::new(),
+        crop_params: &crop_params,
+        post_process_params: &post_process_params,
+        size_bucket_params: &size_bucket_params,
+        redact_params: redact_params.as_ref(),
+        output_dir: &paths.output_dir,
+        output_storage: &*paths.output_storage,
+        input_storage: &paths.input_storage,
+        input_root: &paths.input_root,
+        output_subdir: PathBuf::new(),
+        outputs: Vec::new(),
+        layout: get_layout(&args),
+        #[cfg(feature = "video")]
+        frame_sampling: get_frame_sampling_params(&args),
+        animation_sampling: get_animation_sampling_params(&args),
+        run_contact_sheet: args
+            .pipeline
+            .contact_sheet
+            .as_ref()
+            .map(|path| ContactSheet::new(&contact_sheet_params, path.clone())),
+        contact_sheet_params: &contact_sheet_params,
+        // a resumed run adds to the manifest of the run it continues, and skipping existing or
+        // cached inputs needs to know what previous runs wrote
+        manifest: match resume_state.is_some() || args.pipeline.skip_existing || args.pipeline.cache
+        {
+            true => manifest::load(&paths.output_dir).unwrap_or_default(),
+            false => manifest::Manifest::default(),
+        },
+        signing_key: args.pipeline.sign_key.as_deref().map(read_signing_key),
+        cache: args
+            .pipeline
+            .cache
+            .then(|| cache::load(&paths.output_dir, cache_params_hash(&args))),
+        embedding_model: embedding_model.as_ref(),
+        // faces join the clusters of the crops already in the output directory
+        person_clusters: (args.pipeline.layout == OutputLayout::PersonDate).then(|| {
+            clustering::PersonClusters::new(
+                &manifest::load(&paths.output_dir).unwrap_or_default().crops,
+                args.pipeline.cluster_threshold,
+            )
+        }),
+        reference_embedding,
+        landmark_model: landmark_model.as_ref(),
+        attribute_pipeline: attribute_pipeline.as_ref(),
+        annotated_images: args
+            .pipeline
+            .export_annotations
+            .as_ref()
+            .map(|_| Vec::new()),
+        summary: summary::RunSummary::default(),
+        metrics: metrics.as_deref(),
+        selection: CropSelection {
+            deduplicator: args
+                .pipeline
+                .dedupe
+                .map(|_| get_deduplicator(&args, &paths.output_dir)),
+            top_crops: args.pipeline.max_total_crops.map(selection::TopCrops::new),
+            rank_by: args.pipeline.rank_by,
+            replaced: Vec::new(),
+        },
+        prefetcher: None,
+        writer: writer::Writer::new(get_stage_threads(&args, args.pipeline.encode_threads)),
+        #[cfg(feature = "matting")]
+        matting_model: matting_model.as_ref(),
+    };
+    if watching {
+        watch::run(&mut pipeline, &paths);
+        return EXIT_SUCCESS;
+    }
+    let mut remaining_crops = args.pipeline.stop_after_faces;
+
+    let mut unsaved_inputs = 0;
+    pipeline.prefetch(&paths.input_image_paths);
+    for input_path in &paths.input_image_paths {
+        let mut budget = CropBudget {
+            skip: 0,
+            remaining: remaining_crops,
+        };
+        if let (Some(resume_state), Some(resume_input)) = (&resume_state, &resume_input) {
+            let queue_key = |path| queue_key(path, &paths.input_root, &args.pipeline.priority_dirs);
+            match queue_key(input_path).cmp(&queue_key(resume_input)) {
+                std::cmp::Ordering::Less => continue,
+                std::cmp::Ordering::Equal => budget.skip = resume_state.crops_written,
+                std::cmp::Ordering::Greater => (),
+            }
+        }
+        let skipped_crops = budget.skip;
+
+        // a failed input is logged and counted rather than stopping the run
+        let processed = catch_unwind(AssertUnwindSafe(|| {
+            pipeline.process_input(input_path, &mut budget)
+        }));
+        paths.input_storage.release(input_path);
+        unsaved_inputs += 1;
+        let crops_written = match processed {
+            Ok(Some(crops_written)) => crops_written,
+            Ok(None) => continue,
+            Err(_) => {
+                error!("Failed to process {}", input_path.display());
+                pipeline.summary.inputs_failed += 1;
+                continue;
+            }
+        };
+
+        remaining_crops = budget.remaining;
+        if remaining_crops == Some(0) {
+            pipeline.save_run_files();
+            // the input may still have crops left so it is where the next run picks up
+            state::save(
+                &paths.output_dir,
+                &state::RunState::new(
+                    input_path,
+                    &paths.input_root,
+                    skipped_crops + crops_written,
+                    last_run,
+                ),
+            );
+            paths
+                .output_storage
+                .checkpoint(&run_files(&paths.output_dir));
+            info!(
+                "Reached the limit of {} crops. Stopping. Continue with --resume",
+                args.pipeline.stop_after_faces.unwrap()
+            );
+            break;
+        }
+        // the run state only moves on once the manifest records what was done up to it
+        if unsaved_inputs >= RUN_FILES_SAVE_INTERVAL {
+            pipeline.save_run_files();
+            state::save(
+                &paths.output_dir,
+                &state::RunState::new(
+                    &next_input_path(&paths.input_image_paths, input_path),
+                    &paths.input_root,
+                    0,
+                    last_run,
+                ),
+            );
+            paths
+                .output_storage
+                .checkpoint(&run_files(&paths.output_dir));
+            unsaved_inputs = 0;
+        }
+    }
+    if remaining_crops != Some(0) {
+        pipeline.save_run_files();
+        paths
+            .output_storage
+            .remove(&state::state_path(&paths.output_dir))
+            .unwrap_or_else(|_| panic!("Failed to remove run state file"));
+        if args.pipeline.newer_than.is_some() {
+            state::save(&paths.output_dir, &state::RunState::finished(run_started));
+        }
+    }
+    if let Some(report_path) = &args.pipeline.duplicate_report {
+        let groups = duplicates::duplicate_groups(
+            &pipeline.manifest.crops,
+            args.pipeline.duplicate_similarity,
+        );
+        info!(
+            "Found {} group(s) of near-identical crops from different inputs. Saved report to {}",
+            groups.len(),
+            report_path.display()
+        );
+        duplicates::save(report_path, &duplicates::DuplicateReport { groups });
+    }
+    pipeline.summary.log();
+    if let Some(summary_path) = &args.pipeline.summary {
+        summary::save(summary_path, &pipeline.summary);
+        info!("Saved the run summary to {}", summary_path.display());
+    }
+    if let Some(report_path) = &args.pipeline.report {
+        gallery::save(report_path, &paths.output_dir, &pipeline.manifest.crops);
+        info!(
+            "Saved a report of {} crop(s) to {}",
+            pipeline.manifest.crops.len(),
+            report_path.display()
+        );
+    }
+    if let Some(pdf_path) = &args.pipeline.export_pdf {
+        pdf::save(pdf_path, &paths.output_dir, &pipeline.manifest.crops);
+        info!(
+            "Saved a PDF of {} crop(s) to {}",
+            pipeline.manifest.crops.len(),
+            pdf_path.display()
+        );
+    }
+    if let (Some(annotations_path), Some(annotated_images)) = (
+        &args.pipeline.export_annotations,
+        &pipeline.annotated_images,
+    ) {
+        match args.pipeline.annotation_format {
+            AnnotationFormat::Coco => export::save_coco(
+                annotations_path,
+                annotated_images,
+                args.pipeline.export_keypoints,
+            ),
+            AnnotationFormat::Yolo => export::save_yolo(
+                annotations_path,
+                annotated_images,
+                args.pipeline.export_keypoints,
+            ),
+            AnnotationFormat::Voc => export::save_voc(annotations_path, annotated_images),
+        }
+        info!(
+            "Exported the faces of {} image(s) to {}",
+            annotated_images.len(),
+            annotations_path.display()
+        );
+    }
+    if let Some(contact_sheet) = pipeline.run_contact_sheet {
+        let sheets = contact_sheet.finish();
+        info!(
+            "Saved {} contact sheet(s) to {}",
+            sheets,
+            args.pipeline.contact_sheet.as_ref().unwrap().display()
+        );
+    }
+    paths.output_storage.finish();
+    info!("Finished processing images{}", console::symbol("🎉"));
+
+    if pipeline.summary.inputs_failed > 0 {
+        return EXIT_INPUTS_FAILED;
+    }
+    // inputs skipped as already processed still have their crops
+    if pipeline.summary.faces == 0 && pipeline.manifest.crops.is_empty() {
+        warn!("Found no faces in any input");
+        return EXIT_NO_FACES;
+    }
+    EXIT_SUCCESS
+}
+
+/// Function to print an error parsing the arguments, or the help or version clap reports as one.
+/// Clap would exit with 2, which is the exit code of a run that failed some of its inputs.
+///
+/// # Arguments
+///
+/// * `error` - The error.
+///
+/// # Returns
+///
+/// * The exit code, which is fatal unless the help or version was asked for.
+fn report_usage(error: clap::Error) -> i32 {
+    let _ = error.print();
+    match error.use_stderr() {
+        true => EXIT_FATAL,
+        false => EXIT_SUCCESS,
+    }
+}
+
+/// Function to find a pipeline option given before a subcommand. Subcommands that run the
+/// pipeline take its options after their name, and the others don't take them at all.
+///
+/// # Arguments
+///
+/// * `command` - The built command the matches were parsed with.
+/// * `matches` - The parsed command line.
+///
+/// # Returns
+///
+/// * The usage error of the first such option, if any.
+fn misplaced_pipeline_arg(
+    command: &mut clap::Command,
+    matches: &ArgMatches,
+) -> Option<clap::Error> {
+    let subcommand = matches.subcommand_name()?;
+    // the options of the command itself that aren't global are those of the pipeline
+    let arg = command.get_arguments().find(|arg| {
+        !arg.is_global_set()
+            && !arg.is_positional()
+            && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    })?;
+    let id = arg.get_id().clone();
+    let long = arg.get_long().unwrap_or_default().to_string();
+    let message = match command
+        .find_subcommand(subcommand)
+        .is_some_and(|subcommand| subcommand.get_arguments().any(|arg| *arg.get_id() == id))
+    {
+        true => format!(
+            "--{} must follow the subcommand, as in `facecrop {} ... --{}`",
+            long, subcommand, long
+        ),
+        false => format!(
+            "--{} is an option of the crop pipeline, which `facecrop {}` doesn't run",
+            long, subcommand
+        ),
+    };
+    Some(command.error(ErrorKind::ArgumentConflict, message))
+}
+
+/// Function to check whether the crops an input has in the manifest are current. If it has crops
+/// that aren't, they are removed from the manifest and the output directory so reprocessing the
+/// input doesn't leave stale crops behind.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest of the output directory.
+/// * `input_path` - Path of the input, as the manifest lists it. Crops of images within an
+///   archive are included.
+/// * `source_hash` - Current content hash of the input, if known.
+/// * `check` - How to decide that the crops are current.
+/// * `output_dir` - The output directory.
+/// * `output_storage` - Where the output directory is stored.
+///
+/// # Returns
+///
+/// * True if the input has crops and they are all current.
+fn has_current_crops(
+    manifest: &mut manifest::Manifest,
+    input_path: &Path,
+    source_hash: Option<&str>,
+    check: SkipExistingCheck,
+    output_dir: &Path,
+    output_storage: &dyn storage::Storage,
+) -> bool {
+    let crops: Vec<_> = manifest
+        .crops
+        .iter()
+        .filter(|crop| crop.source.starts_with(input_path))
+        .collect();
+    if crops.is_empty() {
+        return false;
+    }
+    let current = crops.iter().all(|crop| {
+        output_storage.exists(&output_dir.join(&crop.path))
+            && (check == SkipExistingCheck::Exists
+                || (source_hash.is_some() && crop.source_hash.as_deref() == source_hash))
+    });
+    if current {
+        return true;
+    }
+
+    info!(
+        "Crops of {} are out of date. Reprocessing",
+        input_path.display()
+    );
+    remove_crops(manifest, input_path, output_dir, output_storage);
+    false
+}
+
+/// Function to remove the crops of an input from the manifest and the output directory before it
+/// is reprocessed.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest of the output directory.
+/// * `input_path` - Path of the input, as the manifest lists it.
+/// * `output_dir` - The output directory.
+/// * `output_storage` - Where the output directory is stored.
+fn remove_crops(
+    manifest: &mut manifest::Manifest,
+    input_path: &Path,
+    output_dir: &Path,
+    output_storage: &dyn storage::Storage,
+) {
+    manifest.crops.retain(|crop| {
+        if !crop.source.starts_with(input_path) {
+            return true;
+        }
+        output_storage
+            .remove(&output_dir.join(&crop.path))
+            .unwrap_or_else(|_| panic!("Failed to remove stale crop"));
+        false
+    });
+}
+
+/// Function to describe the parameters of a run that affect its outputs, so the cache can tell
+/// whether an input was processed with the same ones. Paths, logging, concurrency and what to
+/// skip or resume don't change the crops and are left out.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run.
+///
+/// # Returns
+///
+/// * The hash of the parameters.
+fn cache_params_hash(args: &Args) -> String {
+    let mut params = args.clone();
+    let command = match &args.command {
+        Some(Command::CropFrom { boxes, .. }) => {
+            format!("crop-from {:?}", manifest::file_hash(boxes))
+        }
+        Some(Command::Redact { .. }) => "redact".to_string(),
+        _ => "crop".to_string(),
+    };
+    let import_annotations = args
+        .pipeline
+        .import_annotations
+        .as_deref()
+        .map(manifest::file_hash);
+    params.command = None;
+    params.image_path_or_dir = None;
+    params.output_dir = None;
+    params.pipeline.priority_dirs = Vec::new();
+    params.follow_symlinks = false;
+    params.pipeline.newer_than = None;
+    params.pipeline.input_list = None;
+    params.pipeline.null = false;
+    params.pipeline.summary = None;
+    params.pipeline.report = None;
+    params.pipeline.export_pdf = None;
+    params.pipeline.output_zip = None;
+    params.pipeline.stop_after_faces = None;
+    params.pipeline.resume = false;
+    params.pipeline.skip_existing = false;
+    params.pipeline.skip_existing_check = SkipExistingCheck::Exists;
+    params.pipeline.cache = false;
+    params.pipeline.duplicate_report = None;
+    params.inference_timeout = None;
+    params.workers = 1;
+    params.pipeline.decode_threads = None;
+    params.pipeline.encode_threads = None;
+    params.verbose = 0;
+    params.no_color = false;
+    params.ascii = false;
+    cache::params_hash(&format!(
+        "{} {} {:?} {:?}",
+        env!("CARGO_PKG_VERSION"),
+        command,
+        import_annotations,
+        params
+    ))
+}
+
+/// The input after `current`, or `current` itself if it is the last one (resuming then skips
+/// everything before it and only re-checks it).
+fn next_input_path(input_image_paths: &[PathBuf], current: &Path) -> PathBuf {
+    let position = input_image_paths
+        .iter()
+        .position(|path| path == current)
+        .unwrap();
+    input_image_paths
+        .get(position + 1)
+        .unwrap_or(&input_image_paths[position])
+        .clone()
+}
+
+/// Position of an input in the processing queue: inputs in earlier priority directories come
+/// first, then by path.
+fn queue_key(path: &Path, input_root: &Path, priority_dirs: &[PathBuf]) -> (usize, PathBuf) {
+    let relative_path = path.strip_prefix(input_root).unwrap_or(path);
+    let lane = priority_dirs
+        .iter()
+        .position(|priority_dir| relative_path.starts_with(priority_dir))
+        .unwrap_or(priority_dirs.len());
+    (lane, path.to_path_buf())
+}
+
+fn get_paths(args: &Args) -> Paths {
+    let (image_path_or_dir, output_dir) = (
+        args.image_path_or_dir.as_deref().unwrap(),
+        args.output_dir.as_deref(),
+    );
+    let watching = matches!(args.command, Some(Command::Watch { .. }));
+    if watching
+        && [Some(image_path_or_dir), output_dir]
+            .iter()
+            .flatten()
+            .any(|path| storage::scheme(path).is_some())
+    {
+        panic!("Object store directories can't be watched");
+    }
+    if args.pipeline.output_zip.is_some() {
+        if watching {
+            panic!("--output-zip can't be used when watching");
+        }
+        if output_dir.is_some() {
+            panic!("--output-zip replaces the output directory. Give only one of them");
+        }
+    }
+    if args.pipeline.input_list.is_some() {
+        if watching {
+            panic!("--input-list can't be used when watching");
+        }
+        if storage::scheme(image_path_or_dir).is_some() {
+            panic!("--input-list can only list local inputs");
+        }
+    }
+    let output_storage: Box<dyn storage::Storage> = match (&args.pipeline.output_zip, output_dir) {
+        // the report and the PDF read the crops back, and crops replaced by higher ranked ones
+        // are removed again, so those keep the crops staged until the run finishes
+        (Some(zip_path), _) => Box::new(storage::ZipStorage::new(
+            zip_path,
+            args.pipeline.report.is_some()
+                || args.pipeline.export_pdf.is_some()
+                || args.pipeline.max_total_crops.is_some(),
+        )),
+        (None, output_dir) => storage::open(output_dir.unwrap(), "output"),
+    };
+    // what an earlier run left tells this one what it can skip or resume
+    for run_file in run_files(output_storage.local_path()) {
+        output_storage.fetch(&run_file);
+    }
+    let input_storage: Arc<dyn storage::Storage> =
+        Arc::from(storage::open(image_path_or_dir, "input"));
+
+    let input_image_path = input_storage.local_path().to_path_buf();
+    if input_image_path.as_os_str() != STDIO_PATH && !input_image_path.exists() {
+        panic!("Input path does not exist");
+    }
+    if input_image_path.as_os_str() == STDIO_PATH && args.pipeline.virtual_crop {
+        panic!("Virtual crops can't be written to an image read from stdin");
+    }
+    let input_image_paths = match &args.pipeline.input_list {
+        Some(input_list_path) => {
+            if !input_image_path.is_dir() {
+                panic!("--input-list needs the input directory the listed inputs are in");
+            }
+            let mut input_paths =
+                read_input_list(input_list_path, args.pipeline.null, &input_image_path);
+            info!(
+                "Received {} input(s) listed in {}",
+                input_paths.len(),
+                input_list_path.display()
+            );
+            input_paths.sort_by_cached_key(|path| {
+                queue_key(path, &input_image_path, &args.pipeline.priority_dirs)
+            });
+            input_paths.dedup();
+            input_paths
+        }
+        None if input_image_path.as_os_str() == STDIO_PATH || input_image_path.is_file() => {
+            info!("Received file {}", input_image_path.display());
+            vec![input_image_path.clone()]
+        }
+        None => match input_storage.list(&|path| {
+            is_input(path)
+                && (args.pipeline.preserve_structure
+                    || path.parent() == Some(Path::new(""))
+                    || args
+                        .pipeline
+                        .priority_dirs
+                        .iter()
+                        .any(|priority_dir| path.starts_with(priority_dir)))
+        }) {
+            // inputs at a location are checked for facecrop's own outputs once they are fetched
+            Some(mut input_paths) => {
+                input_paths.sort_by_cached_key(|path| {
+                    queue_key(path, &input_image_path, &args.pipeline.priority_dirs)
+                });
+                input_paths
+            }
+            None => list_local_inputs(&input_image_path, args),
+        },
+    };
+    // boxes can only be supplied for still images
+    let boxes_only = matches!(args.command, Some(Command::CropFrom { .. }));
+    let input_image_paths = input_image_paths
+        .into_iter()
+        .filter(|path| !skip_unsupported(path))
+        .filter(|path| {
+            let skip = boxes_only && input_kind(path) != InputKind::Image;
+            if skip {
+                warn!(
+                    "Skipping {} as boxes can only be supplied for images",
+                    path.display()
+                );
+            }
+            !skip
+        })
+        .collect();
+
+    let output_dir = output_storage.local_path().to_path_buf();
+    if output_dir.exists() && !output_dir.is_dir() {
+        panic!("Output directory is not a directory");
+    }
+    std::fs::create_dir_all(&output_dir)
+        .unwrap_or_else(|_| panic!("Failed to create output directory"));
+
+    let input_root = match input_image_path.is_dir() {
+        true => input_image_path,
+        false => input_image_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+
+    Paths {
+        input_image_paths,
+        input_root,
+        output_dir,
+        input_storage,
+        output_storage,
+    }
+}
+
+/// Function to get the files a run keeps in its output directory to tell the next run what it can
+/// skip or resume: the manifest, the run state and the cache.
+fn run_files(output_dir: &Path) -> [PathBuf; 3] {
+    [
+        manifest::manifest_path(output_dir),
+        state::state_path(output_dir),
+        cache::cache_path(output_dir),
+    ]
+}
+
+/// Function to list the inputs of a local input directory, in the order they are processed.
+fn list_local_inputs(input_dir: &Path, args: &Args) -> Vec<PathBuf> {
+    info!("Received directory {}", input_dir.display());
+
+    let mut input_paths = list_input_dir(
+        input_dir,
+        args.pipeline.preserve_structure,
+        args.follow_symlinks,
+    );
+    for priority_dir in &args.pipeline.priority_dirs {
+        let priority_dir = input_dir.join(priority_dir);
+        match priority_dir.is_dir() {
+            true => input_paths.extend(list_dir(
+                &priority_dir,
+                true,
+                args.follow_symlinks,
+                &ignore_file::IgnoreRules::load(input_dir, &priority_dir),
+                &mut HashSet::new(),
+            )),
+            false => warn!(
+                "Priority directory {} does not exist",
+                priority_dir.display()
+            ),
+        }
+    }
+    input_paths.sort_by_cached_key(|path| queue_key(path, input_dir, &args.pipeline.priority_dirs));
+    input_paths.dedup();
+
+    input_paths
+        .into_iter()
+        .filter(|path| {
+            input_kind(path) != InputKind::Image
+                || !skip_own_output(path, args.pipeline.own_outputs, || {
+                    provenance::is_facecrop_output(path)
+                })
+        })
+        .collect()
+}
+
+/// Function to read the inputs of an input list file, ordered as in the file. The caller sorts
+/// them into the processing queue like scanned inputs, which resuming relies on.
+///
+/// # Arguments
+///
+/// * `input_list_path` - Path to the list, or "-" to read it from stdin.
+/// * `null` - Whether the paths are separated by NUL characters rather than newlines.
+/// * `input_dir` - The input directory. Inputs outside it are skipped with a warning, as their
+///   outputs would have nowhere to go.
+///
+/// # Returns
+///
+/// * The listed inputs that exist, as paths in the input directory like those found by scanning
+///   it.
+fn read_input_list(input_list_path: &Path, null: bool, input_dir: &Path) -> Vec<PathBuf> {
+    let contents = match input_list_path.as_os_str() == STDIO_PATH {
+        true => {
+            let mut contents = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut contents)
+                .map(|_| contents)
+        }
+        false => std::fs::read(input_list_path),
+    }
+    .unwrap_or_else(|_| panic!("Failed to read input list"));
+    let canonical_input_dir = std::fs::canonicalize(input_dir)
+        .unwrap_or_else(|_| panic!("Failed to read input directory"));
+
+    contents
+        .split(|byte| *byte == if null { b'\0' } else { b'\n' })
+        .map(|line| {
+            String::from_utf8_lossy(line)
+                .trim_end_matches('\r')
+                .to_string()
+        })
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let path = PathBuf::from(line);
+            let Ok(canonical_path) = std::fs::canonicalize(&path) else {
+                warn!(
+                    "Skipping listed input {} as it does not exist",
+                    path.display()
+                );
+                return None;
+            };
+            if !canonical_path.is_file() || !is_input(&path) {
+                warn!(
+                    "Skipping listed input {} as it is not an image, video or archive",
+                    path.display()
+                );
+                return None;
+            }
+            match canonical_path.strip_prefix(&canonical_input_dir) {
+                Ok(relative_path) => Some(input_dir.join(relative_path)),
+                Err(_) => {
+                    warn!(
+                        "Skipping listed input {} as it is outside the input directory",
+                        path.display()
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Sorted images, videos and archives in a directory, optionally including its subdirectories.
+/// Files and directories matched by `.facecropignore` files are skipped, as are symlinks unless
+/// they are followed.
+fn list_input_dir(input_dir: &Path, recursive: bool, follow_symlinks: bool) -> Vec<PathBuf> {
+    list_dir(
+        input_dir,
+        recursive,
+        follow_symlinks,
+        &ignore_file::IgnoreRules::default().descend(input_dir),
+        &mut HashSet::new(),
+    )
+}
+
+/// Like [`list_input_dir`], with the ignore rules that apply in the directory and the canonical
+/// paths of the directories scanned so far, so a symlink back up the tree isn't followed in
+/// circles.
+fn list_dir(
+    dir: &Path,
+    recursive: bool,
+    follow_symlinks: bool,
+    ignore_rules: &ignore_file::IgnoreRules,
+    scanned_dirs: &mut HashSet<PathBuf>,
+) -> Vec<PathBuf> {
+    let mut input_paths = vec![];
+    if let Ok(canonical_dir) = std::fs::canonicalize(dir) {
+        if !scanned_dirs.insert(canonical_dir) {
+            warn!(
+                "Skipping {} as it links to a directory that was already scanned",
+                dir.display()
+            );
+            return input_paths;
+        }
+    }
+    for entry in std::fs::read_dir(dir).unwrap_or_else(|_| panic!("Failed to read input directory"))
+    {
+        let entry = entry.unwrap();
+        let path = entry.path();
+        let is_symlink = entry
+            .file_type()
+            .is_ok_and(|file_type| file_type.is_symlink());
+        if is_symlink && !follow_symlinks {
+            debug!("Skipping symlink {}", path.display());
+        } else if ignore_rules.is_ignored(&path, path.is_dir()) {
+            debug!("Ignoring {}", path.display());
+        } else if recursive && path.is_dir() {
+            input_paths.extend(list_dir(
+                &path,
+                recursive,
+                follow_symlinks,
+                &ignore_rules.descend(&path),
+                scanned_dirs,
+            ));
+        } else if path.is_file() && is_image(&path) {
+            debug!("Found image {}", path.display());
+            input_paths.push(path);
+        } else if path.is_file() && is_video(&path) {
+            debug!("Found video {}", path.display());
+            input_paths.push(path);
+        } else if path.is_file() && has_extension(&path, &ANIMATION_EXTENSIONS) {
+            debug!("Found animation {}", path.display());
+            input_paths.push(path);
+        } else if path.is_file() && archive::is_archive(&path) {
+            debug!("Found archive {}", path.display());
+            input_paths.push(path);
+        }
+    }
+    input_paths.sort();
+    input_paths
+}
+
+/// Whether a file is an image, video, animation or archive input, judging by its name.
+fn is_input(path: &Path) -> bool {
+    is_image(path)
+        || is_video(path)
+        || has_extension(path, &ANIMATION_EXTENSIONS)
+        || archive::is_archive(path)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_lowercase().as_str()))
+}
+
+fn is_image(path: &Path) -> bool {
+    has_extension(path, &IMAGE_EXTENSIONS)
+        || has_extension(path, &HEIF_EXTENSIONS)
+        || has_extension(path, &AVIF_EXTENSIONS)
+}
+
+fn is_video(path: &Path) -> bool {
+    has_extension(path, &VIDEO_EXTENSIONS)
+}
+
+/// Function to check whether an image has more than 8 bits per channel, e.g. a 16-bit PNG or TIFF.
+fn is_high_bit_depth(color: image::ColorType) -> bool {
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+/// Extensions of the images this build can decode.
+fn supported_image_extensions() -> Vec<&'static str> {
+    let mut extensions = IMAGE_EXTENSIONS.to_vec();
+    if cfg!(feature = "heif") {
+        extensions.extend(HEIF_EXTENSIONS);
+    }
+    if cfg!(feature = "avif") {
+        extensions.extend(AVIF_EXTENSIONS);
+    }
+    extensions
+}
+
+/// Function to check whether an input needs a cargo feature this build was made without, warning
+/// if so.
+///
+/// # Arguments
+///
+/// * `path` - Path to the input.
+///
+/// # Returns
+///
+/// * True if the input should be skipped.
+fn skip_unsupported(path: &Path) -> bool {
+    let missing_feature = match () {
+        _ if is_video(path) && !cfg!(feature = "video") => "video",
+        _ if has_extension(path, &HEIF_EXTENSIONS) && !cfg!(feature = "heif") => "heif",
+        _ if has_extension(path, &AVIF_EXTENSIONS) && !cfg!(feature = "avif") => "avif",
+        _ => return false,
+    };
+    warn!(
+        "Skipping {} as facecrop was built without the {} feature",
+        path.display(),
+        missing_feature
+    );
+    true
+}
+
+fn input_kind(path: &Path) -> InputKind {
+    if is_video(path) {
+        InputKind::Video
+    } else if has_extension(path, &ANIMATION_EXTENSIONS) {
+        InputKind::Animation
+    } else if archive::is_archive(path) {
+        InputKind::Archive
+    } else {
+        InputKind::Image
+    }
+}
+
+fn skip_own_output<F>(path: &Path, policy: OwnOutputsPolicy, is_facecrop_output: F) -> bool
+where
+    F: FnOnce() -> bool,
+{
+    if policy == OwnOutputsPolicy::Process || !is_facecrop_output() {
+        return false;
+    }
+    match policy {
+        OwnOutputsPolicy::Skip => {
+            warn!(
+                "Skipping {} as it was written by facecrop. Use --own-outputs=process to crop it anyway",
+                path.display()
+            );
+            true
+        }
+        _ => {
+            warn!(
+                "{} was written by facecrop. Cropping it anyway",
+                path.display()
+            );
+            false
+        }
+    }
+}
+
+/// Function to set one of the crop arguments that can be swept, or overridden for a class.
+///
+/// # Arguments
+///
+/// * `args` - The arguments to set it in.
+/// * `name` - The name of the argument, checked by `sweep::param_name`.
+/// * `value` - The value of the argument.
+fn set_crop_arg(args: &mut Args, name: &str, value: f32) {
+    match name {
+        "proportion_of_face" => args.proportion_of_face = value,
+        "top_padding" => args.top_padding = value,
+        "padding_bottom" => args.padding_bottom = value,
+        "padding_left" => args.padding_left = value,
+        "padding_right" => args.padding_right = value,
+        "aspect_ratio" => args.aspect_ratio = value,
+        "height" => args.height = value as u32,
+        "width" => args.width = value as u32,
+        _ => unreachable!("Crop arguments are checked when parsed"),
+    }
+}
+
+/// Function to pick the classes of the class model that are cropped and their crop settings,
+/// with the arguments given for each class in place of those of the run.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run.
+///
+/// # Returns
+///
+/// * The classes that are cropped.
+fn get_class_crops(args: &Args) -> ClassCrops {
+    let class_index = |class: &str| {
+        args.pipeline
+            .class_names
+            .iter()
+            .position(|name| name == class)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Unknown class {}. Expected one of {}",
+                    class,
+                    args.pipeline.class_names.join(", ")
+                )
+            })
+    };
+    let cropped: Vec<usize> = match args.pipeline.classes.is_empty() {
+        true => (0..args.pipeline.class_names.len()).collect(),
+        false => args
+            .pipeline
+            .classes
+            .iter()
+            .map(|class| class_index(class))
+            .collect(),
+    };
+    for class_params in &args.pipeline.class_params {
+        if !cropped.contains(&class_index(&class_params.class)) {
+            warn!(
+                "--class-params are given for {}, which isn't cropped",
+                class_params.class
+            );
+        }
+    }
+    let crop_params = cropped
+        .into_iter()
+        .map(|class| {
+            let mut class_args = args.clone();
+            for class_params in &args.pipeline.class_params {
+                if class_params.class == args.pipeline.class_names[class] {
+                    for (name, value) in &class_params.params {
+                        set_crop_arg(&mut class_args, name, *value);
+                    }
+                }
+            }
+            (class, get_crop_params(&class_args))
+        })
+        .collect();
+    ClassCrops {
+        names: args.pipeline.class_names.clone(),
+        crop_params,
+    }
+}
+
+fn get_crop_params(args: &Args) -> cropping::CropParams {
+    if args.top_padding < 0.0 || args.top_padding > 1.0 {
+        panic!("Top padding must be between 0.0 and 1.0");
+    }
+    for (name, padding) in [
+        ("Bottom", args.padding_bottom),
+        ("Left", args.padding_left),
+        ("Right", args.padding_right),
+    ] {
+        if !(0.0..=1.0).contains(&padding) {
+            panic!("{} padding must be between 0.0 and 1.0", name);
+        }
+    }
+    let crop_params_kind = match args.strategy {
+        CropStrategy::Absolute => cropping::CropParamsKind::Absolute(cropping::AbsoluteCrop {
+            height: args.height,
+            width: args.width,
+        }),
+        CropStrategy::Relative => {
+            if args.aspect_ratio <= 0.0 {
+                panic!("Aspect ratio must be greater than 0");
+            }
+            if args.proportion_of_face < 0.0 || args.proportion_of_face > 1.0 {
+                panic!("Proportion of face must be between 0.0 and 1.0");
+            }
+
+            cropping::CropParamsKind::Relative(cropping::RelativeCrop {
+                aspect_ratio: args.aspect_ratio,
+                proportion_of_face: args.proportion_of_face,
+            })
+        }
+    };
+
+    cropping::CropParams {
+        top_padding: args.top_padding,
+        bottom_padding: args.padding_bottom,
+        left_padding: args.padding_left,
+        right_padding: args.padding_right,
+        face_anchor: args.face_anchor.map(|FaceAnchor(x, y)| (x, y)),
+        compose: match args.compose {
+            Compose::Centered => cropping::Compose::Centered,
+            Compose::Thirds => cropping::Compose::Thirds,
+        },
+        dims_multiple_of: args.dims_multiple_of,
+        kind: crop_params_kind,
+    }
+}
+
+fn get_post_process_params(args: &Args) -> post_processing::PostProcessParams {
+    if args.upscale.is_some() && !cfg!(feature = "upscale") {
+        panic!("facecrop was built without the upscale feature");
+    }
+    post_processing::PostProcessParams {
+        resize: args.resize,
+        fit: match args.fit {
+            Fit::Stretch => post_processing::Fit::Stretch,
+            Fit::Pad => post_processing::Fit::Pad,
+            Fit::Cover => post_processing::Fit::Cover,
+        },
+        resize_filter: match args.resize_filter {
+            ResizeFilter::Nearest => image::imageops::FilterType::Nearest,
+            ResizeFilter::Bilinear => image::imageops::FilterType::Triangle,
+            ResizeFilter::Catmullrom => image::imageops::FilterType::CatmullRom,
+            ResizeFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+        },
+        no_upscale: args.no_upscale,
+        filter_by_size: args.filter_by_size,
+        height: args.height,
+        width: args.width,
+        sharpen: args.sharpen,
+        auto_levels: args.auto_levels,
+        clahe: args.clahe,
+        denoise: args.denoise,
+        buckets: match args.pipeline.layout {
+            OutputLayout::Kohya => args.pipeline.training_resolutions.0.clone(),
+            _ => Vec::new(),
+        },
+        color_mode: match args.color_mode {
+            ColorMode::Rgb => post_processing::ColorMode::Rgb,
+            ColorMode::Grayscale => post_processing::ColorMode::Grayscale,
+        },
+        #[cfg(feature = "upscale")]
+        upscaler: args.upscale.zip(args.upscale_model.as_deref()).map(
+            |(Upscale::Realesrgan, model_path)| {
+                upscale::Upscaler::from_file(model_path, get_session_options(args))
+                    .unwrap_or_else(|error| panic!("Failed to load upscaling model: {}", error))
+            },
+        ),
+    }
+}
+
+fn get_redact_params(args: &Args) -> Option<post_processing::RedactParams> {
+    if !args.pipeline.redact {
+        return None;
+    }
+    let style = match args.pipeline.redact_style {
+        RedactStyle::Blur => post_processing::RedactStyle::Blur,
+        RedactStyle::Pixelate => {
+            if args.pipeline.redact_block_size == 0 {
+                panic!("Redact block size must be greater than 0");
+            }
+            post_processing::RedactStyle::Pixelate {
+                block_size: args.pipeline.redact_block_size,
+            }
+        }
+        RedactStyle::Solid => post_processing::RedactStyle::Solid {
+            color: args.pipeline.redact_color,
+        },
+    };
+
+    Some(post_processing::RedactParams { style })
+}
+
+fn get_contact_sheet_params(args: &Args) -> contact_sheet::ContactSheetParams {
+    if args.contact_sheet_tile_size < 32 {
+        panic!("Contact sheet tile size must be at least 32");
+    }
+    if args.contact_sheet_columns == 0 || args.contact_sheet_rows == 0 {
+        panic!("Contact sheet columns and rows must be greater than 0");
+    }
+
+    contact_sheet::ContactSheetParams {
+        tile_size: args.contact_sheet_tile_size,
+        columns: args.contact_sheet_columns,
+        rows: args.contact_sheet_rows,
+    }
+}
+
+#[cfg(feature = "video")]
+fn get_frame_sampling_params(args: &Args) -> video::FrameSamplingParams {
+    let sampling = match args.pipeline.every_nth_frame {
+        Some(0) => panic!("Every nth frame must be greater than 0"),
+        Some(n) => video::FrameSampling::EveryNth(n),
+        None => video::FrameSampling::Interval(args.pipeline.frame_interval),
+    };
+    if args.pipeline.max_frames == Some(0) {
+        panic!("Max frames must be greater than 0");
+    }
+
+    video::FrameSamplingParams {
+        sampling,
+        max_frames: args.pipeline.max_frames,
+    }
+}
+
+fn get_animation_sampling_params(args: &Args) -> animation::FrameSamplingParams {
+    if args.pipeline.every_nth_frame == Some(0) {
+        panic!("Every nth frame must be greater than 0");
+    }
+    if args.pipeline.max_frames == Some(0) {
+        panic!("Max frames must be greater than 0");
+    }
+
+    animation::FrameSamplingParams {
+        every_nth: args.pipeline.every_nth_frame.unwrap_or(1),
+        max_frames: args.pipeline.max_frames,
+    }
+}
+
+fn parse_sharpen(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(amount) if (0.0..=1.0).contains(&amount) => Ok(amount),
+        _ => Err(format!(
+            "Invalid sharpen amount {}. Expected a value from 0.0 to 1.0",
+            value
+        )),
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.trim() {
+        value if value.ends_with("ms") => (&value[..value.len() - 2], 0.001),
+        value if value.ends_with('s') => (&value[..value.len() - 1], 1.0),
+        value => (value, 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => {
+            Ok(Duration::from_secs_f64(number * unit))
+        }
+        _ => Err(format!(
+            "Invalid duration {}. Expected seconds, e.g. 1.0s or 500ms",
+            value
+        )),
+    }
+}
+
+/// Format outputs are written in and whether they keep the alpha channel of the input.
+fn get_output_format(args: &Args, has_alpha: bool) -> (image::ImageFormat, bool) {
+    match args.output_format {
+        OutputFormat::Auto if has_alpha => (image::ImageFormat::Png, true),
+        OutputFormat::Auto | OutputFormat::Jpeg => (image::ImageFormat::Jpeg, false),
+        OutputFormat::Png => (image::ImageFormat::Png, has_alpha),
+        OutputFormat::Webp => (image::ImageFormat::WebP, has_alpha),
+    }
+}
+
+/// Reads a signing key file, ignoring trailing whitespace such as a final newline.
+fn read_signing_key(key_path: &Path) -> Vec<u8> {
+    let mut key =
+        std::fs::read(key_path).unwrap_or_else(|_| panic!("Failed to read signing key file"));
+    while key.last().is_some_and(u8::is_ascii_whitespace) {
+        key.pop();
+    }
+    if key.is_empty() {
+        panic!("Signing key file is empty");
+    }
+    key
+}
+
+fn get_jpeg_options(args: &Args) -> output::JpegOptions {
+    output::JpegOptions {
+        subsampling: match args.jpeg_subsampling {
+            JpegSubsampling::Yuv444 => output::ChromaSubsampling::Yuv444,
+            JpegSubsampling::Yuv422 => output::ChromaSubsampling::Yuv422,
+            JpegSubsampling::Yuv420 => output::ChromaSubsampling::Yuv420,
+        },
+        progressive: args.progressive,
+    }
+}
+
+fn get_conflict_policy(args: &Args) -> output::ConflictPolicy {
+    match args.pipeline.on_conflict {
+        OnConflict::Overwrite => output::ConflictPolicy::Overwrite,
+        OnConflict::Skip => output::ConflictPolicy::Skip,
+        OnConflict::Rename => output::ConflictPolicy::Rename,
+    }
+}
+
+fn get_layout(args: &Args) -> layout::Layout {
+    match args.pipeline.layout {
+        OutputLayout::Flat => layout::Layout::Flat,
+        OutputLayout::PersonDate => layout::Layout::PersonDate,
+        OutputLayout::Kohya => layout::Layout::Kohya {
+            repeats: args.pipeline.kohya_repeats,
+            // the subject defaults to the token the captions identify it by
+            subject: args
+                .pipeline
+                .kohya_subject
+                .clone()
+                .or_else(|| args.pipeline.caption_token.clone())
+                .unwrap_or_else(|| "ohwx".to_string()),
+            class: args.pipeline.kohya_class.clone(),
+        },
+    }
+}
+
+fn get_acceleration(args: &Args) -> acceleration::Acceleration {
+    match args.acceleration {
+        Acceleration::Auto => acceleration::Acceleration::Auto,
+        Acceleration::Cpu => acceleration::Acceleration::Cpu,
+        Acceleration::CoreMl => acceleration::Acceleration::CoreMl,
+        Acceleration::TensorRt => acceleration::Acceleration::TensorRt,
+    }
+}
+
+/// Function to build a face detector, downscaling big images before detection if
+/// `--detect-max-dim` is given.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run.
+///
+/// # Returns
+///
+/// * The detector.
+fn get_face_detector(args: &Args) -> Box<dyn rust_faces::FaceDetector> {
+    let (model, _) = get_detector_params(args);
+    get_model_detector(args, model)
+}
+
+/// Function to build a detector of the given model with the acceleration and inference options
+/// of the run.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run.
+/// * `model` - The detector model.
+///
+/// # Returns
+///
+/// * The detector.
+fn get_model_detector(
+    args: &Args,
+    model: cropping::FaceDetectorModel,
+) -> Box<dyn rust_faces::FaceDetector> {
+    let (_, infer_params) = get_detector_params(args);
+    let precision = match model {
+        // only BlazeFace has quantized weights
+        cropping::FaceDetectorModel::MtCnn => cropping::ModelPrecision::Fp32,
+        _ => get_model_precision(args),
+    };
+    let face_detector = match args.acceleration {
+        Acceleration::TensorRt => {
+            acceleration::tensorrt_detector(model, precision, args.tensorrt_engine_cache.as_deref())
+        }
+        _ => cropping::get_face_detector(model, precision, infer_params),
+    };
+    let face_detector = with_detect_max_dim(args, face_detector);
+    let face_detector: Box<dyn rust_faces::FaceDetector> = match args.multiscale {
+        true => Box::new(multiscale::MultiscaleDetector::new(face_detector)),
+        false => face_detector,
+    };
+    match args.try_rotations {
+        true => Box::new(rotation::RotatingDetector::new(face_detector)),
+        false => face_detector,
+    }
+}
+
+fn with_detect_max_dim(
+    args: &Args,
+    face_detector: Box<dyn rust_faces::FaceDetector>,
+) -> Box<dyn rust_faces::FaceDetector> {
+    match args.detect_max_dim {
+        Some(max_dim) => Box::new(downscale::DownscalingDetector::new(face_detector, max_dim)),
+        None => face_detector,
+    }
+}
+
+fn get_detector_model(model: DetectorModel) -> cropping::FaceDetectorModel {
+    match model {
+        DetectorModel::BlazeFace640 => cropping::FaceDetectorModel::BlazeFace640,
+        DetectorModel::BlazeFace320 => cropping::FaceDetectorModel::BlazeFace320,
+        DetectorModel::MtCnn => cropping::FaceDetectorModel::MtCnn,
+    }
+}
+
+fn get_model_precision(args: &Args) -> cropping::ModelPrecision {
+    match args.model_precision {
+        ModelPrecision::Fp32 => cropping::ModelPrecision::Fp32,
+        ModelPrecision::Fp16 => cropping::ModelPrecision::Fp16,
+        ModelPrecision::Int8 => cropping::ModelPrecision::Int8,
+    }
+}
+
+fn get_session_options(args: &Args) -> acceleration::SessionOptions {
+    let default_threads = args.low_memory.then_some(1);
+    acceleration::SessionOptions {
+        intra_threads: args.intra_threads.map(usize::from).or(default_threads),
+        inter_threads: args.inter_threads.map(usize::from).or(default_threads),
+        graph_optimization: args.graph_optimization.map(|level| match level {
+            GraphOptimization::Disable => acceleration::GraphOptimization::Disable,
+            GraphOptimization::Basic => acceleration::GraphOptimization::Basic,
+            GraphOptimization::Extended => acceleration::GraphOptimization::Extended,
+            GraphOptimization::All => acceleration::GraphOptimization::All,
+        }),
+    }
+}
+
+/// Function to get the number of threads of a pipelined stage, which runs on the main thread
+/// with --low-memory unless the number is given, so inputs and crops aren't held in memory ahead.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run.
+/// * `threads` - The number of threads given for the stage, if any.
+///
+/// # Returns
+///
+/// * The number of threads, 0 to run the stage on the main thread.
+fn get_stage_threads(args: &Args, threads: Option<usize>) -> usize {
+    threads.unwrap_or(match args.low_memory {
+        true => 0,
+        false => DEFAULT_STAGE_THREADS,
+    })
+}
+
+fn get_emotion(emotion: Emotion) -> attributes::Emotion {
+    match emotion {
+        Emotion::Neutral => attributes::Emotion::Neutral,
+        Emotion::Happy => attributes::Emotion::Happy,
+        Emotion::Surprised => attributes::Emotion::Surprised,
+        Emotion::Sad => attributes::Emotion::Sad,
+        Emotion::Angry => attributes::Emotion::Angry,
+        Emotion::Disgusted => attributes::Emotion::Disgusted,
+        Emotion::Fearful => attributes::Emotion::Fearful,
+        Emotion::Contemptuous => attributes::Emotion::Contemptuous,
+    }
+}
+
+fn get_attribute_pipeline(args: &Args) -> Option<attributes::AttributePipeline> {
+    // attributes are estimated to filter by them even when they aren't asked for
+    let age = args.pipeline.attributes.contains(&Attribute::Age) || args.pipeline.min_age.is_some();
+    let gender = args.pipeline.attributes.contains(&Attribute::Gender);
+    let emotion = args.pipeline.attributes.contains(&Attribute::Emotion)
+        || !args.pipeline.only_emotions.is_empty()
+        || args.pipeline.emotion_in_filename;
+    let occlusion =
+        args.pipeline.attributes.contains(&Attribute::Occlusion) || args.pipeline.skip_masked;
+    let mut models: Vec<Box<dyn attributes::AttributeModel>> = Vec::new();
+    if age || gender {
+        let model_path = args.pipeline.age_gender_model.as_ref().unwrap();
+        let session = acceleration::load_session(model_path, get_session_options(args))
+            .unwrap_or_else(|_| panic!("Failed to load gender and age model"));
+        models.push(Box::new(attributes::GenderAgeModel::new(
+            session, age, gender,
+        )));
+    }
+    if emotion {
+        let model_path = args.pipeline.emotion_model.as_ref().unwrap();
+        let session = acceleration::load_session(model_path, get_session_options(args))
+            .unwrap_or_else(|_| panic!("Failed to load emotion model"));
+        models.push(Box::new(attributes::EmotionModel::new(session)));
+    }
+    if occlusion {
+        let model_path = args.pipeline.occlusion_model.as_ref().unwrap();
+        let session = acceleration::load_session(model_path, get_session_options(args))
+            .unwrap_or_else(|_| panic!("Failed to load occlusion model"));
+        models.push(Box::new(attributes::OcclusionModel::new(session)));
+    }
+    (!models.is_empty()).then(|| attributes::AttributePipeline::new(models))
+}
+
+fn get_detector_params(args: &Args) -> (cropping::FaceDetectorModel, rust_faces::InferParams) {
+    let session_options = get_session_options(args);
+    let infer_params = rust_faces::InferParams {
+        provider: acceleration::provider(get_acceleration(args)),
+        intra_threads: session_options.intra_threads,
+        inter_threads: session_options.inter_threads,
+    };
+    match args.low_memory {
+        true => (cropping::FaceDetectorModel::BlazeFace320, infer_params),
+        false => (cropping::FaceDetectorModel::BlazeFace640, infer_params),
+    }
+}
+
+/// Function to set up the skipping of near-duplicate crops, seeded with the images already in the
+/// output directory if they are skipped too.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run.
+/// * `output_dir` - The output directory.
+///
+/// # Returns
+///
+/// * The deduplicator.
+fn get_deduplicator(args: &Args, output_dir: &Path) -> dedupe::Deduplicator {
+    let mut deduplicator = dedupe::Deduplicator::new(args.pipeline.dedupe_distance);
+    if args.pipeline.dedupe_existing {
+        let existing: Vec<_> = list_input_dir(output_dir, true, false)
+            .into_iter()
+            .filter(|path| input_kind(path) == InputKind::Image)
+            .collect();
+        info!(
+            "Hashing {} existing images in the output directory",
+            existing.len()
+        );
+        for path in existing {
+            match decoding::open(&path, args.low_memory) {
+                Ok(image) => {
+                    deduplicator.check(&image);
+                }
+                Err(_) => warn!("Failed to read {}. Skipping", path.display()),
+            }
+        }
+    }
+    deduplicator
+}
+
+/// Function to compute the embedding of the face in a reference image.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run, for the alpha background.
+/// * `reference_path` - Path to the reference image.
+/// * `embedding_model` - The recognition model.
+/// * `face_detector` - The detector to find the reference face with.
+///
+/// # Returns
+///
+/// * The embedding of the most confident face in the image.
+fn get_reference_embedding(
+    args: &Args,
+    reference_path: &Path,
+    embedding_model: &embedding::EmbeddingModel,
+    face_detector: &dyn rust_faces::FaceDetector,
+) -> Vec<f32> {
+    let reference_image = read_image(reference_path, args.low_memory);
+    let detection_image = match reference_image.color().has_alpha() {
+        true => cropping::flatten_alpha(&reference_image.to_rgba8(), args.alpha_background),
+        false => reference_image.to_rgb8(),
+    };
+    let faces = cropping::detect_faces_in_image(&detection_image, face_detector);
+    let face = faces
+        .iter()
+        .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+        .unwrap_or_else(|| panic!("No face found in reference image"));
+    if faces.len() > 1 {
+        warn!(
+            "Found {} faces in reference image. Matching the most confident one",
+            faces.len()
+        );
+    }
+    embedding_model
+        .embed(&detection_image, face)
+        .unwrap_or_else(|_| panic!("Failed to compute reference face embedding"))
+}
+
+/// Function to build the detectors of a run, rebuilding hung detectors if an inference timeout is
+/// given or the run is long-running.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run.
+/// * `long_running` - True when watching or serving.
+///
+/// # Returns
+///
+/// * A pool of `--workers` detectors.
+fn get_detector_pool(args: &Args, long_running: bool) -> DetectorPool {
+    let build_detector = {
+        let detector_args = args.clone();
+        move || get_face_detector(&detector_args)
+    };
+    let inference_timeout = args.inference_timeout.or(match long_running {
+        true => Some(LONG_RUNNING_INFERENCE_TIMEOUT),
+        false => None,
+    });
+    DetectorPool::new(args.workers, || match inference_timeout {
+        Some(timeout) => Box::new(WatchdogDetector::new(
+            Box::new(build_detector.clone()),
+            timeout,
+        )),
+        None => build_detector(),
+    })
+}
+
+/// Function to point the crop pipeline at the input, output and options of the subcommand that
+/// runs it, so the rest of the run treats every such subcommand like `facecrop
+/// <IMAGE_PATH_OR_DIR> <OUTPUT_DIR>`.
+///
+/// # Arguments
+///
+/// * `args` - The parsed arguments.
+///
+/// # Returns
+///
+/// * The arguments with the input, output and pipeline options of the subcommand, if any.
+fn resolve_pipeline_args(args: Args) -> Args {
+    let (image_path_or_dir, output_dir, pipeline) = match &args.command {
+        Some(Command::Crop {
+            image_path_or_dir,
+            output_dir,
+            pipeline,
+        })
+        | Some(Command::CropFrom {
+            image_path_or_dir,
+            output_dir,
+            pipeline,
+            ..
+        })
+        | Some(Command::Redact {
+            image_path_or_dir,
+            output_dir,
+            pipeline,
+        }) => (image_path_or_dir.clone(), output_dir.clone(), pipeline),
+        Some(Command::Video {
+            video_path,
+            output_dir,
+            pipeline,
+        }) => {
+            if !cfg!(feature = "video") {
+                panic!("facecrop was built without the video feature");
+            }
+            if !video_path.is_file() || !has_extension(video_path, &VIDEO_EXTENSIONS) {
+                panic!("Video path is not a video file");
+            }
+            (
+                video_path.to_string_lossy().to_string(),
+                output_dir
+                    .as_ref()
+                    .map(|output_dir| output_dir.to_string_lossy().to_string()),
+                pipeline,
+            )
+        }
+        // watching runs the regular pipeline, just fed with inputs as they appear
+        Some(Command::Watch {
+            input_dir,
+            output_dir,
+            pipeline,
+            ..
+        }) => {
+            if !input_dir.is_dir() {
+                panic!("Watched path is not a directory");
+            }
+            (
+                input_dir.to_string_lossy().to_string(),
+                Some(output_dir.to_string_lossy().to_string()),
+                pipeline,
+            )
+        }
+        _ => return args,
+    };
+    let pipeline = PipelineArgs {
+        redact: pipeline.redact || matches!(args.command, Some(Command::Redact { .. })),
+        ..pipeline.clone()
+    };
+    Args {
+        image_path_or_dir: Some(image_path_or_dir),
+        output_dir,
+        pipeline,
+        ..args
+    }
+}
+
+fn get_size_bucket_params(args: &Args) -> cropping::SizeBucketParams {
+    cropping::SizeBucketParams {
+        thresholds: args.pipeline.size_buckets.0,
+    }
+}
+
+fn parse_size_buckets(value: &str) -> Result<SizeBuckets, String> {
+    let thresholds = value
+        .split(',')
+        .map(|threshold| threshold.trim().parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| {
+            format!(
+                "Invalid size buckets {}. Expected three sizes in pixels",
+                value
+            )
+        })?;
+    match thresholds[..] {
+        [small, medium, large] if small < medium && medium < large => {
+            Ok(SizeBuckets([small, medium, large]))
+        }
+        _ => Err(format!(
+            "Invalid size buckets {}. Expected three increasing sizes, e.g. 32,96,256",
+            value
+        )),
+    }
+}
+
+fn parse_face_anchor(value: &str) -> Result<FaceAnchor, String> {
+    let proportions = value
+        .split(',')
+        .map(|proportion| proportion.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| format!("Invalid face anchor {}. Expected x,y", value))?;
+    match proportions[..] {
+        [x, y] if (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y) => Ok(FaceAnchor(x, y)),
+        _ => Err(format!(
+            "Invalid face anchor {}. Expected x,y from 0.0 to 1.0, e.g. 0.5,0.38",
+            value
+        )),
+    }
+}
+
+fn parse_newer_than(value: &str) -> Result<NewerThan, String> {
+    let value = value.trim();
+    if value == "last-run" {
+        return Ok(NewerThan::LastRun);
+    }
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(NewerThan::Time(UNIX_EPOCH + Duration::from_secs(seconds)));
+    }
+    let error = || {
+        format!(
+            "Invalid time {}. Expected a UTC date and time, e.g. 2024-05-01 or \
+            2024-05-01T18:00:00Z, Unix seconds or last-run",
+            value
+        )
+    };
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, "00:00:00"));
+    let numbers = |text: &str| {
+        text.split(['-', ':'])
+            .map(|number| number.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+    };
+    let (Some(date), Some(time)) = (numbers(date), numbers(time.trim_end_matches('Z'))) else {
+        return Err(error());
+    };
+    let (&[year, month, day], &[hour, minute, second]) = (&date[..], &time[..]) else {
+        return Err(error());
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(error());
+    }
+    u64::try_from(unix_seconds(year, month, day, hour, minute, second))
+        .map(|seconds| NewerThan::Time(UNIX_EPOCH + Duration::from_secs(seconds)))
+        .map_err(|_| error())
+}
+
+/// Function to convert a UTC date and time of the proleptic Gregorian calendar to seconds since
+/// the Unix epoch.
+fn unix_seconds(year: u32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> i64 {
+    // days since the epoch, counting years from March
+    let (year, month) = match month {
+        1 | 2 => (year as i64 - 1, month + 9),
+        _ => (year as i64, month - 3),
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month as i64 + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    days * 86400 + (hour * 3600 + minute * 60 + second) as i64
+}
+
+/// Function to get when an input was taken, for the modification times of its crops.
+///
+/// # Arguments
+///
+/// * `input_path` - Path of the input.
+///
+/// # Returns
+///
+/// * The EXIF capture time of the input, falling back to its modification time, or None if
+///   neither is known.
+fn source_time(input_path: &Path) -> Option<SystemTime> {
+    let captured = layout::capture_date_time(input_path).and_then(|date_time| {
+        let seconds = unix_seconds(
+            date_time.year as u32,
+            date_time.month as u32,
+            date_time.day as u32,
+            date_time.hour as u32,
+            date_time.minute as u32,
+            date_time.second as u32,
+        ) - date_time.offset.unwrap_or(0) as i64 * 60;
+        u64::try_from(seconds)
+            .ok()
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+    });
+    captured.or_else(|| {
+        std::fs::metadata(input_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+}
+
+fn parse_resolutions(value: &str) -> Result<Resolutions, String> {
+    value
+        .split(',')
+        .map(|resolution| {
+            let (width, height) = resolution.trim().split_once('x')?;
+            let (width, height) = (width.parse::<u32>().ok()?, height.parse::<u32>().ok()?);
+            (width > 0 && height > 0).then_some((width, height))
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(Resolutions)
+        .ok_or_else(|| {
+            format!(
+                "Invalid resolutions {}. Expected widthxheight separated by commas, e.g. 512x512,576x448",
+                value
+            )
+        })
+}
+
+fn parse_caption_template(value: &str) -> Result<String, String> {
+    match caption::unknown_placeholder(value) {
+        Some(placeholder) => Err(format!(
+            "Unknown placeholder {{{}}}. Expected one of {}",
+            placeholder,
+            caption::PLACEHOLDERS
+                .map(|placeholder| format!("{{{}}}", placeholder))
+                .join(", ")
+        )),
+        None => Ok(value.to_string()),
+    }
+}
+
+fn parse_splits(value: &str) -> Result<Splits, String> {
+    let invalid = || {
+        format!(
+            "Invalid split {}. Expected names and ratios, e.g. train:0.9,val:0.1",
+            value
+        )
+    };
+    let splits = value
+        .split(',')
+        .map(|split| {
+            let (name, ratio) = split.split_once(':').ok_or_else(invalid)?;
+            let name = name.trim();
+            let ratio = ratio.trim().parse::<f32>().map_err(|_| invalid())?;
+            // the names are folder names
+            match !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && ratio.is_finite()
+                && ratio >= 0.0
+            {
+                true => Ok(split::Split {
+                    name: name.to_string(),
+                    ratio,
+                }),
+                false => Err(invalid()),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match splits.iter().any(|split| split.ratio > 0.0) {
+        true => Ok(Splits(splits)),
+        false => Err(invalid()),
+    }
+}
+
+fn parse_color(value: &str) -> Result<image::Rgb<u8>, String> {
+    let channels = value
+        .split(',')
+        .map(|channel| channel.trim().parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| format!("Invalid color {}. Expected R,G,B with values 0-255", value))?;
+    match channels[..] {
+        [r, g, b] => Ok(image::Rgb([r, g, b])),
+        _ => Err(format!("Invalid color {}. Expected R,G,B", value)),
+    }
+}
+
+fn read_image(input_image_path: &std::path::Path, streaming: bool) -> image::DynamicImage {
+    if input_image_path.as_os_str() == STDIO_PATH {
+        let mut encoded = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut encoded)
+            .unwrap_or_else(|_| panic!("Failed to read image from stdin"));
+        return decoding::decode(&encoded)
+            .unwrap_or_else(|_| panic!("Failed to decode image from stdin"));
+    }
+
+    let input_image = decoding::open(input_image_path, streaming)
+        .unwrap_or_else(|_| panic!("Failed to open image file"));
+
+    input_image
+}
+
+/// Function to write the crop of a single face of a single input image to stdout, for use in
+/// shell pipelines. Fails if the face to write is ambiguous or can't be cropped.
+///
+/// # Arguments
+///
+/// * `args` - The program arguments. The input is a file or "-" for stdin.
+///
+/// # Returns
+///
+/// * The exit code of the program.
+fn write_face_to_stdout(args: &Args) -> i32 {
+    let input_path = PathBuf::from(args.image_path_or_dir.as_ref().unwrap());
+    if input_path.is_dir() {
+        panic!("Writing to stdout requires a single input image");
+    }
+    let crop_params = get_crop_params(args);
+    let post_process_params = get_post_process_params(args);
+
+    let input_image = read_image(&input_path, args.low_memory);
+    let has_alpha = input_image.color().has_alpha();
+    let detection_image = match has_alpha {
+        true => cropping::flatten_alpha(&input_image.to_rgba8(), args.alpha_background),
+        false => input_image.to_rgb8(),
+    };
+    let face_detector = get_face_detector(args);
+    let faces = cropping::detect_faces_in_image(&detection_image, &*face_detector);
+
+    let face = match (args.pipeline.face_index, faces.len()) {
+        (Some(face_index), face_count) => match faces.get(face_index) {
+            Some(face) => face,
+            None => {
+                error!(
+                    "Face index {} is out of range. Found {} faces",
+                    face_index, face_count
+                );
+                return EXIT_FATAL;
+            }
+        },
+        (None, 1) => &faces[0],
+        (None, 0) => {
+            error!("Found no faces");
+            return EXIT_NO_FACES;
+        }
+        (None, face_count) => {
+            error!(
+                "Found {} faces. Use --face-index to choose the face to write",
+                face_count
+            );
+            return EXIT_FATAL;
+        }
+    };
+    let faces = vec![face.clone()];
+
+    let (format, keep_alpha) = get_output_format(args, has_alpha);
+    let encoded = match keep_alpha {
+        true => encode_face_crop(
+            &input_image.into_rgba8(),
+            &faces,
+            &crop_params,
+            &post_process_params,
+            format,
+            args.quality,
+            get_jpeg_options(args),
+        ),
+        false => encode_face_crop(
+            &detection_image,
+            &faces,
+            &crop_params,
+            &post_process_params,
+            format,
+            args.quality,
+            get_jpeg_options(args),
+        ),
+    };
+    let Some(encoded) = encoded else {
+        error!("Cropped image is too small");
+        return EXIT_FATAL;
+    };
+    std::io::stdout()
+        .lock()
+        .write_all(&encoded)
+        .unwrap_or_else(|_| panic!("Failed to write image to stdout"));
+    info!(
+        "Wrote face with confidence {:.3} to stdout",
+        face.confidence
+    );
+    EXIT_SUCCESS
+}
+
+fn encode_face_crop<P>(
+    input_image: &cropping::Image<P>,
+    faces: &Vec<rust_faces::Face>,
+    crop_params: &cropping::CropParams,
     post_process_params: &post_processing::PostProcessParams,
-    output_dir: &Path,
-    image_name: &str,
-) {
-    let crop_outputs = cropping::crop_faces(faces_to_crop, crop_params);
+    format: image::ImageFormat,
+    quality: u8,
+    jpeg_options: output::JpegOptions,
+) -> Option<Vec<u8>>
+where
+    P: image::Pixel<Subpixel = u8> + image::PixelWithColorType + 'static,
+{
+    let crop =
+        cropping::crop_faces(cropping::CropInputs { input_image, faces }, crop_params)?.pop()?;
+    let output_image = post_processing::post_process_image(&crop.image, post_process_params)?;
+    Some(output::encode_crop(
+        &output_image,
+        post_process_params.color_mode,
+        format,
+        quality,
+        jpeg_options,
+    ))
+}
+
+/// Function to score a crop for ranking.
+fn rank_score<P>(
+    rank_by: RankBy,
+    crop: &cropping::CropOutputs<P>,
+    cropped_image: &cropping::Image<P>,
+) -> f32
+where
+    P: image::Pixel<Subpixel = u8> + 'static,
+{
+    match rank_by {
+        RankBy::Confidence => crop.confidence,
+        RankBy::Sharpness => selection::sharpness(cropped_image),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_faces<P>(
+    faces_to_crop: cropping::CropInputs<P>,
+    crop_params: &[&cropping::CropParams],
+    post_process_params: &post_processing::PostProcessParams,
+    size_bucket_params: &cropping::SizeBucketParams,
+    target: &OutputTarget,
+    deep_image: Option<&image::DynamicImage>,
+    contact_sheets: &mut [&mut ContactSheet],
+    selection: &mut CropSelection,
+    budget: &mut CropBudget,
+    summary: &mut summary::RunSummary,
+) -> Vec<manifest::CropRecord>
+where
+    P: image::Pixel<Subpixel = u8> + image::PixelWithColorType + Send + 'static,
+{
+    let crop_outputs = cropping::crop_faces_by(faces_to_crop, |i| crop_params[i]);
     if crop_outputs.is_none() {
-        warn!("No crops for image {}. Skipping", image_name);
-        return;
+        warn!("No crops for image {}. Skipping", target.image_name);
+        return Vec::new();
     }
 
+    // at -vv the faces are summarized in a table instead of a line each
+    let show_table = tracing::enabled!(tracing::Level::TRACE);
+    let mut rows = Vec::new();
+    let mut written = Vec::new();
     for (i, crop) in crop_outputs.unwrap().iter().enumerate() {
+        let mut row = report::FaceRow {
+            index: i,
+            face: crop.face,
+            confidence: crop.confidence,
+            crop: crop.crop,
+            outcome: report::FaceOutcome::Written,
+        };
+        if budget.remaining == Some(written.len()) {
+            row.outcome = report::FaceOutcome::OverLimit;
+            rows.push(row);
+            continue;
+        }
         let output_image = post_processing::post_process_image(&crop.image, post_process_params);
         match output_image {
+            Some(_) if budget.skip > 0 => {
+                if !show_table {
+                    debug!(
+                        "Face {} in image {} was written by the resumed run. Skipping",
+                        i, target.image_name
+                    );
+                }
+                row.outcome = report::FaceOutcome::Resumed;
+                budget.skip -= 1;
+            }
+            Some(cropped_image)
+                if selection.top_crops.as_ref().is_some_and(|top_crops| {
+                    !top_crops.would_admit(rank_score(selection.rank_by, crop, &cropped_image))
+                }) =>
+            {
+                if !show_table {
+                    debug!(
+                        "Face {} in image {} ranks below the kept crops. Skipping",
+                        i, target.image_name
+                    );
+                }
+                row.outcome = report::FaceOutcome::Outranked;
+            }
+            Some(cropped_image)
+                if selection
+                    .deduplicator
+                    .as_mut()
+                    .is_some_and(|deduplicator| deduplicator.check(&cropped_image)) =>
+            {
+                if !show_table {
+                    debug!(
+                        "Face {} in image {} is a near-duplicate of an earlier crop. Skipping",
+                        i, target.image_name
+                    );
+                }
+                row.outcome = report::FaceOutcome::Duplicate;
+            }
             Some(cropped_image) => {
-                let output_path =
-                    output_dir.join(format!("{}-{}-{:.3}.jpg", image_name, i, crop.confidence));
-                cropped_image
-                    .save(&output_path)
-                    .unwrap_or_else(|_| panic!("Failed to save output image"));
+                let size_bucket = cropping::size_bucket(&crop.face, size_bucket_params);
+                let name = match target.stable_names {
+                    true => manifest::box_id(&crop.face),
+                    false => format!("{}-{:.3}", i, crop.confidence),
+                };
+                let name = match target.size_bucket_in_name {
+                    true => format!("{}-{}", name, size_bucket.name()),
+                    false => name,
+                };
+                let emotion = target
+                    .emotions_in_name
+                    .as_ref()
+                    .and_then(|emotions| emotions[i]);
+                let crop_path = match emotion {
+                    Some(emotion) => target.crop_path(i, &format!("{}-{}", name, emotion.name())),
+                    None => target.crop_path(i, &name),
+                };
+                let output_path = match output::resolve_conflict(
+                    &target.output_dir.join(&crop_path),
+                    target.on_conflict,
+                    |path| target.output_storage.exists(path),
+                ) {
+                    Some(output_path) => output_path,
+                    None => {
+                        if !show_table {
+                            debug!(
+                                "Face {} in image {} already exists. Skipping",
+                                i, target.image_name
+                            );
+                        }
+                        row.outcome = report::FaceOutcome::Exists;
+                        rows.push(row);
+                        continue;
+                    }
+                };
+                // the file name changes if the output is renamed
+                let crop_path = crop_path.with_file_name(output_path.file_name().unwrap());
+                std::fs::create_dir_all(output_path.parent().unwrap())
+                    .unwrap_or_else(|_| panic!("Failed to create crop directory"));
+                let description = target.embed_provenance.then(|| {
+                    xmp::provenance_description(&xmp::CropProvenance {
+                        source: &target
+                            .source
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy(),
+                        confidence: crop.confidence,
+                        x: crop.crop.x as u32,
+                        y: crop.crop.y as u32,
+                        width: crop.crop.width as u32,
+                        height: crop.crop.height as u32,
+                    })
+                });
+                for contact_sheet in contact_sheets.iter_mut() {
+                    contact_sheet.add(
+                        &cropped_image,
+                        target.image_name,
+                        format!("#{} {:.3}", i, crop.confidence),
+                    );
+                }
+                let score = selection
+                    .top_crops
+                    .is_some()
+                    .then(|| rank_score(selection.rank_by, crop, &cropped_image));
+                let color_mode = post_process_params.color_mode;
+                let job_path = output_path.clone();
+                match deep_image {
+                    Some(deep_image) => {
+                        let deep_crop = post_processing::post_process_deep_image(
+                            &deep_image.crop_imm(
+                                crop.crop.x as u32,
+                                crop.crop.y as u32,
+                                crop.crop.width as u32,
+                                crop.crop.height as u32,
+                            ),
+                            post_process_params,
+                        );
+                        target.writer.submit(output_path.clone(), move || {
+                            output::save_deep_crop(
+                                &deep_crop,
+                                &job_path,
+                                color_mode,
+                                description.as_deref(),
+                            )
+                        });
+                    }
+                    None => {
+                        let (format, quality, jpeg_options) =
+                            (target.format, target.quality, target.jpeg_options);
+                        target.writer.submit(output_path.clone(), move || {
+                            output::save_crop(
+                                &cropped_image,
+                                &job_path,
+                                color_mode,
+                                format,
+                                quality,
+                                jpeg_options,
+                                description.as_deref(),
+                            )
+                        });
+                    }
+                }
                 info!(
                     "Saved face {} in image {} to {}",
                     i,
-                    image_name,
+                    target.image_name,
                     output_path.display()
                 );
+                if let (Some(top_crops), Some(score)) = (selection.top_crops.as_mut(), score) {
+                    if let selection::Admission::Admitted(Some(replaced)) =
+                        top_crops.admit(score, crop_path.clone())
+                    {
+                        debug!("Removing {} as it ranks lower", replaced.display());
+                        // the replaced crop may still be queued
+                        target.writer.wait();
+                        // crops may have no caption or landmarks sidecar, which storages
+                        // take as already removed
+                        let replaced_path = target.output_dir.join(&replaced);
+                        for path in [
+                            replaced_path.clone(),
+                            caption::caption_path(&replaced_path),
+                            manifest::landmarks_sidecar_path(&replaced_path),
+                        ] {
+                            target.output_storage.remove(&path).unwrap_or_else(|error| {
+                                panic!("Failed to remove replaced crop: {}", error)
+                            });
+                        }
+                        summary.add_filtered(report::FaceOutcome::Outranked.name(), 1);
+                        match written
+                            .iter()
+                            .position(|record: &manifest::CropRecord| record.path == replaced)
+                        {
+                            Some(position) => {
+                                written.remove(position);
+                            }
+                            // crops of earlier inputs are already counted as written
+                            None => {
+                                summary.crops_written -= 1;
+                                selection.replaced.push(replaced)
+                            }
+                        }
+                    }
+                }
+                written.push(manifest::CropRecord {
+                    path: crop_path,
+                    source: target.source.to_path_buf(),
+                    face: i,
+                    confidence: crop.confidence,
+                    face_rect: crop.face.into(),
+                    crop_rect: crop.crop.into(),
+                    size_bucket,
+                    // set once the whole input has been processed
+                    source_hash: None,
+                    // set when the manifest is signed
+                    hash: None,
+                    embedding: None,
+                    // set when the crops are recorded
+                    detector_confidences: None,
+                    class: target.classes.as_ref().map(|classes| classes[i].clone()),
+                    head_pose: None,
+                    attributes: None,
+                    review: None,
+                });
+            }
+            None => {
+                if !show_table {
+                    warn!("Cropped image is too small. Skipping");
+                }
+                row.outcome = report::FaceOutcome::TooSmall;
             }
-            None => warn!("Cropped image is too small. Skipping"),
         }
+        rows.push(row);
+    }
+
+    for row in &rows {
+        match row.outcome {
+            report::FaceOutcome::Written | report::FaceOutcome::Resumed => (),
+            outcome => summary.add_filtered(outcome.name(), 1),
+        }
+    }
+    if show_table {
+        trace!(
+            "Faces in image {}:\n{}",
+            target.image_name,
+            report::face_table(&rows)
+        );
+    }
+    written
+}
+
+fn write_debug_overlay(
+    input_image: &image::RgbImage,
+    faces: &[rust_faces::Face],
+    crop_params: &[&cropping::CropParams],
+    output_dir: &Path,
+    image_name: &str,
+) -> PathBuf {
+    let image_rect = rust_faces::Rect::at(0.0, 0.0)
+        .with_size(input_image.width() as f32, input_image.height() as f32);
+    let crops: Vec<_> = faces
+        .iter()
+        .zip(crop_params)
+        .map(|(face, crop_params)| cropping::calculate_face_crop(face, &image_rect, crop_params))
+        .collect();
+
+    let mut overlay_image = input_image.clone();
+    drawing::draw_overlay(&mut overlay_image, faces, &crops);
+
+    let output_path = output_dir.join(format!("{}-debug.jpg", image_name));
+    output::save_image(&overlay_image, &output_path, image::ImageFormat::Jpeg);
+    debug!(
+        "Saved debug overlay for image {} to {}",
+        image_name,
+        output_path.display()
+    );
+    output_path
+}
+
+fn write_confidence_heatmap(
+    input_image: &image::RgbImage,
+    candidate_detector: &dyn rust_faces::FaceDetector,
+    output_dir: &Path,
+    image_name: &str,
+) -> PathBuf {
+    let candidates = cropping::detect_faces_in_image(input_image, candidate_detector);
+    let heatmap_image = heatmap::render(input_image, &candidates);
+
+    let output_path = output_dir.join(format!("{}-heatmap.jpg", image_name));
+    output::save_image(&heatmap_image, &output_path, image::ImageFormat::Jpeg);
+    debug!(
+        "Saved confidence heatmap of {} candidates for image {} to {}",
+        candidates.len(),
+        image_name,
+        output_path.display()
+    );
+    output_path
+}
+
+fn write_virtual_crop(
+    image_path: &Path,
+    input_image: &image::RgbImage,
+    faces: &[rust_faces::Face],
+    crop_params: &[&cropping::CropParams],
+) {
+    let (face, crop_params) = match faces
+        .iter()
+        .zip(crop_params)
+        .max_by(|(a, _), (b, _)| a.confidence.total_cmp(&b.confidence))
+    {
+        Some(face) => face,
+        None => {
+            warn!("No faces in image {}. Skipping", image_path.display());
+            return;
+        }
+    };
+    if faces.len() > 1 {
+        info!(
+            "Found {} faces in {}. Using the most confident for the virtual crop",
+            faces.len(),
+            image_path.display()
+        );
+    }
+
+    let (width, height) = (input_image.width() as f32, input_image.height() as f32);
+    let image_rect = rust_faces::Rect::at(0.0, 0.0).with_size(width, height);
+    let crop = cropping::calculate_face_crop(face, &image_rect, crop_params);
+
+    let encoded = std::fs::read(image_path).unwrap_or_else(|_| panic!("Failed to read image file"));
+    if xmp::has_crop(&encoded) {
+        warn!(
+            "{} already has crop metadata. Skipping",
+            image_path.display()
+        );
+        return;
+    }
+    let description = xmp::crop_description(&xmp::NormalizedCrop {
+        top: crop.y / height,
+        left: crop.x / width,
+        bottom: crop.bottom() / height,
+        right: crop.right() / width,
+    });
+    let updated = match xmp::embed_description(encoded, &description) {
+        Ok(updated) => updated,
+        Err(error) => {
+            warn!(
+                "Failed to write crop metadata to {}: {}. Skipping",
+                image_path.display(),
+                error
+            );
+            return;
+        }
+    };
+
+    // the original is only replaced once the updated copy is fully written
+    output::write_atomically(image_path, updated)
+        .unwrap_or_else(|_| panic!("Failed to write image metadata"));
+    info!("Wrote virtual crop {} to {}", crop, image_path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Function to create an empty directory for a test, named after it.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("facecrop-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn unix_time(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn newer_than_time(value: &str) -> Option<SystemTime> {
+        match parse_newer_than(value) {
+            Ok(NewerThan::Time(time)) => Some(time),
+            _ => None,
+        }
+    }
+
+    /// A JPEG holding nothing but an EXIF `DateTime` tag, which is all the capture time is read
+    /// from.
+    fn exif_jpeg(date_time: &str) -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        // DateTime, ASCII, 20 bytes at the offset right after the IFD
+        tiff.extend([0x32, 0x01, 0x02, 0x00, 0x14, 0, 0, 0, 0x1a, 0, 0, 0]);
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(date_time.as_bytes());
+        tiff.push(0);
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend(tiff);
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend(((app1.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(app1);
+        jpeg.extend([0xff, 0xd9]);
+        jpeg
+    }
+
+    #[test]
+    fn queue_key_puts_priority_dirs_first() {
+        let input_root = Path::new("/photos");
+        let priority_dirs = [PathBuf::from("best"), PathBuf::from("good")];
+        let mut paths = vec![
+            PathBuf::from("/photos/a.jpg"),
+            PathBuf::from("/photos/good/b.jpg"),
+            PathBuf::from("/photos/best/c.jpg"),
+            PathBuf::from("/photos/good/a.jpg"),
+            PathBuf::from("/photos/other/a.jpg"),
+        ];
+        paths.sort_by_cached_key(|path| queue_key(path, input_root, &priority_dirs));
+        assert_eq!(
+            paths,
+            [
+                "/photos/best/c.jpg",
+                "/photos/good/a.jpg",
+                "/photos/good/b.jpg",
+                "/photos/a.jpg",
+                "/photos/other/a.jpg",
+            ]
+            .map(PathBuf::from)
+        );
+        // without priority directories inputs are ordered by path alone
+        assert!(queue_key(&paths[3], input_root, &[]) < queue_key(&paths[0], input_root, &[]));
+    }
+
+    #[test]
+    fn read_input_list_splits_crlf_and_nul_lists() {
+        let dir = test_dir("input-list");
+        let input_dir = dir.join("inputs");
+        std::fs::create_dir_all(input_dir.join("nested")).unwrap();
+        for name in ["a.jpg", "nested/b.png", "notes.txt"] {
+            std::fs::write(input_dir.join(name), b"").unwrap();
+        }
+        std::fs::write(dir.join("outside.jpg"), b"").unwrap();
+        let listed = |name: &str| dir.join(name).display().to_string();
+        let expected = [input_dir.join("nested/b.png"), input_dir.join("a.jpg")];
+
+        let list_path = dir.join("list.txt");
+        std::fs::write(
+            &list_path,
+            format!(
+                "{}\r\n\r\n{}\r\n{}\r\n{}\r\n{}\r\n",
+                listed("inputs/nested/b.png"),
+                listed("inputs/a.jpg"),
+                listed("inputs/notes.txt"),
+                listed("inputs/missing.jpg"),
+                listed("outside.jpg"),
+            ),
+        )
+        .unwrap();
+        assert_eq!(read_input_list(&list_path, false, &input_dir), expected);
+
+        // a NUL separated list keeps newlines within paths
+        let list_path = dir.join("list.nul");
+        std::fs::write(
+            &list_path,
+            format!(
+                "{}\0{}\0{}\nx\0",
+                listed("inputs/nested/b.png"),
+                listed("inputs/a.jpg"),
+                listed("inputs/a.jpg"),
+            ),
+        )
+        .unwrap();
+        assert_eq!(read_input_list(&list_path, true, &input_dir), expected);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    #[test]
+    fn unix_seconds_of_known_dates() {
+        assert_eq!(unix_seconds(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(unix_seconds(1969, 12, 31, 0, 0, 0), -86400);
+        assert_eq!(unix_seconds(2000, 3, 1, 0, 0, 0), 951868800);
+        assert_eq!(unix_seconds(2024, 2, 29, 12, 30, 15), 1709209815);
+        // 2100 isn't a leap year, so March follows February 28th
+        assert_eq!(unix_seconds(2100, 3, 1, 0, 0, 0), 4107542400);
+        assert_eq!(
+            unix_seconds(2100, 3, 1, 0, 0, 0) - unix_seconds(2100, 2, 28, 0, 0, 0),
+            86400
+        );
+    }
+
+    #[test]
+    fn parse_newer_than_dates_and_times() {
+        assert_eq!(newer_than_time("2024-05-01"), Some(unix_time(1714521600)));
+        assert_eq!(
+            newer_than_time("2024-05-01T18:00:00Z"),
+            Some(unix_time(1714586400))
+        );
+        assert_eq!(
+            newer_than_time(" 2024-05-01 18:00:00 "),
+            Some(unix_time(1714586400))
+        );
+        assert_eq!(newer_than_time("1714521600"), Some(unix_time(1714521600)));
+        assert!(matches!(
+            parse_newer_than("last-run"),
+            Ok(NewerThan::LastRun)
+        ));
+    }
+
+    #[test]
+    fn parse_newer_than_rejects_invalid_times() {
+        for value in [
+            "yesterday",
+            "2024-05",
+            "2024-13-01",
+            "2024-05-32",
+            "2024-05-01T24:00:00",
+            "2024-05-01T18:00",
+            "1969-12-31",
+        ] {
+            assert!(parse_newer_than(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    fn cache_key(args: &[&str]) -> String {
+        cache_params_hash(&Args::try_parse_from(args).unwrap())
+    }
+
+    #[test]
+    fn cache_key_ignores_paths_and_run_options() {
+        let key = cache_key(&["facecrop", "photos", "crops"]);
+        assert!(key.starts_with("sha256:"));
+        assert_eq!(
+            key,
+            cache_key(&[
+                "facecrop",
+                "other-photos",
+                "other-crops",
+                "-vv",
+                "--workers",
+                "4",
+                "--skip-existing",
+                "--cache",
+            ])
+        );
+        assert_eq!(key, cache_key(&["facecrop", "crop", "photos", "crops"]));
+    }
+
+    #[test]
+    fn cache_key_changes_with_the_outputs() {
+        let key = cache_key(&["facecrop", "photos", "crops"]);
+        assert_ne!(
+            key,
+            cache_key(&["facecrop", "photos", "crops", "--top-padding", "0.2"])
+        );
+        assert_ne!(
+            key,
+            cache_key(&["facecrop", "photos", "crops", "--output-format", "png"])
+        );
+        assert_ne!(key, cache_key(&["facecrop", "redact", "photos", "crops"]));
+    }
+
+    #[test]
+    fn source_time_reads_exif_capture_time() {
+        let dir = test_dir("source-time-exif");
+        let image_path = dir.join("photo.jpg");
+        std::fs::write(&image_path, exif_jpeg("2024:05:01 18:00:00")).unwrap();
+        assert_eq!(source_time(&image_path), Some(unix_time(1714586400)));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn source_time_falls_back_to_modification_time() {
+        let dir = test_dir("source-time-modified");
+        let image_path = dir.join("photo.png");
+        std::fs::write(&image_path, b"not an image").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&image_path)
+            .and_then(|file| file.set_modified(unix_time(1714521600)))
+            .unwrap();
+        assert_eq!(source_time(&image_path), Some(unix_time(1714521600)));
+        assert_eq!(source_time(&dir.join("missing.png")), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn class_crops_override_the_crop_arguments_of_their_class() {
+        let args = Args::try_parse_from([
+            "facecrop",
+            "photos",
+            "crops",
+            "--top-padding",
+            "0.1",
+            "--class-model",
+            "classes.onnx",
+            "--class-names",
+            "face,head,person",
+            "--classes",
+            "face,person",
+            "--class-params",
+            "person:top_padding=0.4,padding_bottom=0.8",
+        ])
+        .unwrap();
+        let class_crops = get_class_crops(&args);
+        assert_eq!(class_crops.names, ["face", "head", "person"]);
+        let mut cropped: Vec<_> = class_crops.crop_params.keys().copied().collect();
+        cropped.sort();
+        assert_eq!(cropped, [0, 2]);
+        let (face, person) = (&class_crops.crop_params[&0], &class_crops.crop_params[&2]);
+        assert_eq!((face.top_padding, face.bottom_padding), (0.1, 0.0));
+        assert_eq!((person.top_padding, person.bottom_padding), (0.4, 0.8));
+    }
+
+    #[test]
+    fn pipeline_options_follow_their_subcommand() {
+        let args = resolve_pipeline_args(
+            Args::try_parse_from([
+                "facecrop", "--height", "300", "redact", "photos", "crops", "--report", "r.html",
+            ])
+            .unwrap(),
+        );
+        assert_eq!(args.height, 300);
+        assert_eq!(args.image_path_or_dir.as_deref(), Some("photos"));
+        assert_eq!(args.pipeline.report, Some(PathBuf::from("r.html")));
+        assert!(args.pipeline.redact);
+
+        let misplaced = |argv: &[&str]| {
+            let mut command = Args::command();
+            let matches = command.try_get_matches_from_mut(argv).unwrap();
+            misplaced_pipeline_arg(&mut command, &matches).is_some()
+        };
+        assert!(misplaced(&[
+            "facecrop", "--report", "r.html", "crop", "photos", "crops"
+        ]));
+        assert!(misplaced(&[
+            "facecrop", "--report", "r.html", "detect", "photos"
+        ]));
+        assert!(!misplaced(&[
+            "facecrop", "photos", "crops", "--report", "r.html"
+        ]));
+        assert!(!misplaced(&["facecrop", "-v", "detect", "photos"]));
     }
 }
@@ -0,0 +1,385 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use hmac::{Hmac, Mac};
+use rust_faces::{Face, Rect};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    attributes::FaceAttributes, cropping::SizeBucket, output, pose::HeadPose,
+    review::ReviewDecision,
+};
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Record of every crop written to an output directory, so outputs can be traced back to their
+/// source and filtered without re-running detection.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub crops: Vec<CropRecord>,
+    /// Inputs that were processed without writing a crop, e.g. as they have no faces, so
+    /// skipping existing outputs doesn't process them again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs_without_crops: Vec<InputRecord>,
+    /// HMAC-SHA256 of the serialized crops as `hmac-sha256:<hex>`, if the manifest is signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CropRecord {
+    /// Path of the crop, relative to the output directory.
+    pub path: PathBuf,
+    /// Path of the input the crop was taken from.
+    pub source: PathBuf,
+    /// Index of the face within the input.
+    pub face: usize,
+    pub confidence: f32,
+    /// Bounding box of the face in the input.
+    pub face_rect: RectRecord,
+    /// Region of the input the crop was taken from.
+    pub crop_rect: RectRecord,
+    pub size_bucket: SizeBucket,
+    /// Content hash of the input when the crop was made, used to detect changed inputs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+    /// SHA-256 of the crop file as `sha256:<hex>`, recorded when the manifest is signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// L2-normalized identity embedding of the face, if embeddings are computed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    /// Confidence of each detector of an `--ensemble` that found the face, by model name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detector_confidences: Option<BTreeMap<String, f32>>,
+    /// Class of the box the crop was taken from, if it was detected by a `--class-model`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    /// Yaw, pitch and roll of the face, if head poses are estimated and the face has landmarks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_pose: Option<HeadPose>,
+    /// Attributes of the face, if any are estimated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<FaceAttributes>,
+    /// Decision of `facecrop review` about the crop, if it was reviewed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<ReviewDecision>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputRecord {
+    /// Path of the input.
+    pub source: PathBuf,
+    /// Content hash of the input when it was processed, if it was computed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RectRecord {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl From<Rect> for RectRecord {
+    fn from(rect: Rect) -> Self {
+        Self {
+            x: rect.x,
+            y: rect.y,
+            width: rect.width,
+            height: rect.height,
+        }
+    }
+}
+
+/// A detected face, as reported by detection without cropping.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FaceRecord {
+    pub rect: RectRecord,
+    /// Boxes supplied without a confidence, e.g. manual annotations, are taken as certain.
+    #[serde(default = "full_confidence")]
+    pub confidence: f32,
+    /// Facial landmarks in image coordinates, if the detector finds them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landmarks: Option<Vec<(f32, f32)>>,
+}
+
+impl From<&Face> for FaceRecord {
+    fn from(face: &Face) -> Self {
+        Self {
+            rect: face.rect.into(),
+            confidence: face.confidence,
+            landmarks: face.landmarks.clone(),
+        }
+    }
+}
+
+fn full_confidence() -> f32 {
+    1.0
+}
+
+/// The 68 landmarks of the face of a crop, written next to it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LandmarksSidecar {
+    /// Path of the input the crop was taken from.
+    pub source: PathBuf,
+    /// Index of the face within the input.
+    pub face: usize,
+    /// The landmarks in the 68-point layout, normalized to the crop (0.0 - 1.0) so they still
+    /// apply once it is resized. Points outside the crop fall outside the range.
+    pub points: Vec<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_pose: Option<HeadPose>,
+}
+
+/// Function to get the path of the landmarks sidecar of a crop, e.g. `0-0.998.landmarks.json`
+/// for `0-0.998.jpg`.
+pub fn landmarks_sidecar_path(crop_path: &Path) -> PathBuf {
+    crop_path.with_extension("landmarks.json")
+}
+
+pub fn save_landmarks(crop_path: &Path, sidecar: &LandmarksSidecar) {
+    let contents = serde_json::to_string_pretty(sidecar)
+        .unwrap_or_else(|_| panic!("Failed to serialize landmarks"));
+    std::fs::write(landmarks_sidecar_path(crop_path), contents)
+        .unwrap_or_else(|_| panic!("Failed to write landmarks"));
+}
+
+pub fn manifest_path(output_dir: &Path) -> PathBuf {
+    output_dir.join(MANIFEST_FILE_NAME)
+}
+
+pub fn load(output_dir: &Path) -> Option<Manifest> {
+    let contents = std::fs::read_to_string(manifest_path(output_dir)).ok()?;
+    let manifest =
+        serde_json::from_str(&contents).unwrap_or_else(|_| panic!("Failed to parse manifest"));
+    Some(manifest)
+}
+
+pub fn save(output_dir: &Path, manifest: &Manifest) {
+    let contents = serde_json::to_string_pretty(manifest)
+        .unwrap_or_else(|_| panic!("Failed to serialize manifest"));
+    output::write_atomically(&manifest_path(output_dir), contents)
+        .unwrap_or_else(|_| panic!("Failed to write manifest"));
+}
+
+/// Function to hash the contents of a file in a streaming fashion.
+///
+/// # Arguments
+///
+/// * `path` - Path to the file.
+///
+/// # Returns
+///
+/// * The hash as `crc32:<hex>`, or None if the file can't be read.
+pub fn file_hash(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).ok()?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Some(format!("crc32:{:08x}", hasher.finalize()))
+}
+
+/// Function to sign a manifest, first recording the hash of every crop file that doesn't have
+/// one yet so the signature also covers the crop contents.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest to sign.
+/// * `output_dir` - The output directory the crop paths are relative to.
+/// * `key` - The signing key.
+pub fn sign(manifest: &mut Manifest, output_dir: &Path, key: &[u8]) {
+    hash_crops(&mut manifest.crops, output_dir);
+    manifest.signature = Some(signature(&manifest.crops, key));
+}
+
+/// Function to record the hash of every crop file that doesn't have one yet, e.g. before the crops
+/// are stored somewhere they can't be read back from to sign the manifest.
+///
+/// # Arguments
+///
+/// * `crops` - The crops to hash.
+/// * `output_dir` - The output directory the crop paths are relative to.
+pub fn hash_crops(crops: &mut [CropRecord], output_dir: &Path) {
+    for crop in crops.iter_mut().filter(|crop| crop.hash.is_none()) {
+        crop.hash = Some(
+            sha256_file(&output_dir.join(&crop.path))
+                .unwrap_or_else(|| panic!("Failed to hash crop {}", crop.path.display())),
+        );
+    }
+}
+
+/// Function to check the signature of a manifest and the hashes of the crops it lists.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest to check.
+/// * `output_dir` - The output directory the crop paths are relative to.
+/// * `key` - The key the manifest was signed with.
+///
+/// # Returns
+///
+/// * A description of every problem found, empty if the manifest and crops are intact.
+pub fn verify(manifest: &Manifest, output_dir: &Path, key: &[u8]) -> Vec<String> {
+    let mut problems = Vec::new();
+    match &manifest.signature {
+        Some(signature) if *signature == self::signature(&manifest.crops, key) => (),
+        Some(_) => problems.push("Manifest signature does not match".to_string()),
+        None => problems.push("Manifest is not signed".to_string()),
+    }
+    for crop in &manifest.crops {
+        match (&crop.hash, sha256_file(&output_dir.join(&crop.path))) {
+            (None, _) => problems.push(format!("{} has no hash", crop.path.display())),
+            (Some(_), None) => problems.push(format!("{} is missing", crop.path.display())),
+            (Some(expected), Some(actual)) if *expected != actual => {
+                problems.push(format!("{} has been modified", crop.path.display()))
+            }
+            _ => (),
+        }
+    }
+    problems
+}
+
+fn signature(crops: &[CropRecord], key: &[u8]) -> String {
+    let contents =
+        serde_json::to_vec(crops).unwrap_or_else(|_| panic!("Failed to serialize manifest"));
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key).unwrap_or_else(|_| panic!("Invalid signing key"));
+    mac.update(&contents);
+    format!("hmac-sha256:{}", to_hex(&mac.finalize().into_bytes()))
+}
+
+fn sha256_file(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).ok()?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Some(format!("sha256:{}", to_hex(&hasher.finalize())))
+}
+
+/// Function to derive an identifier of a face box from its coordinates rounded to whole pixels, so
+/// the same face gets the same identifier when an image is processed again.
+///
+/// # Arguments
+///
+/// * `face` - The face box.
+///
+/// # Returns
+///
+/// * The first 8 hex digits of the SHA-256 of the coordinates.
+pub fn box_id(face: &Rect) -> String {
+    let coordinates = format!(
+        "{},{},{},{}",
+        face.x.round(),
+        face.y.round(),
+        face.width.round(),
+        face.height.round()
+    );
+    to_hex(&Sha256::digest(coordinates.as_bytes())[..4])
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Function to create an empty directory for a test, named after it.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("facecrop-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Function to create a manifest of one crop, written to the output directory.
+    fn manifest_of_crop(output_dir: &Path) -> Manifest {
+        std::fs::write(output_dir.join("photo-0.jpg"), b"crop").unwrap();
+        let crop = serde_json::from_value(serde_json::json!({
+            "path": "photo-0.jpg",
+            "source": "input/photo.jpg",
+            "face": 0,
+            "confidence": 0.9,
+            "face_rect": {"x": 10.0, "y": 10.0, "width": 20.0, "height": 20.0},
+            "crop_rect": {"x": 0.0, "y": 0.0, "width": 40.0, "height": 40.0},
+            "size_bucket": "small",
+        }))
+        .unwrap();
+        Manifest {
+            crops: vec![crop],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signed_manifest_verifies() {
+        let dir = test_dir("sign-verify");
+        let mut manifest = manifest_of_crop(&dir);
+        sign(&mut manifest, &dir, b"key");
+        assert!(manifest.crops[0]
+            .hash
+            .as_ref()
+            .unwrap()
+            .starts_with("sha256:"));
+        assert!(manifest
+            .signature
+            .as_ref()
+            .unwrap()
+            .starts_with("hmac-sha256:"));
+        assert!(verify(&manifest, &dir, b"key").is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn verify_finds_tampered_manifests_and_crops() {
+        let dir = test_dir("verify-tampered");
+        let mut manifest = manifest_of_crop(&dir);
+        assert_eq!(
+            verify(&manifest, &dir, b"key"),
+            ["Manifest is not signed", "photo-0.jpg has no hash"]
+        );
+        sign(&mut manifest, &dir, b"key");
+        assert_eq!(
+            verify(&manifest, &dir, b"other key"),
+            ["Manifest signature does not match"]
+        );
+
+        manifest.crops[0].confidence = 1.0;
+        assert_eq!(
+            verify(&manifest, &dir, b"key"),
+            ["Manifest signature does not match"]
+        );
+        manifest.crops[0].confidence = 0.9;
+
+        std::fs::write(dir.join("photo-0.jpg"), b"edited crop").unwrap();
+        assert_eq!(
+            verify(&manifest, &dir, b"key"),
+            ["photo-0.jpg has been modified"]
+        );
+        std::fs::remove_file(dir.join("photo-0.jpg")).unwrap();
+        assert_eq!(verify(&manifest, &dir, b"key"), ["photo-0.jpg is missing"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::Path;
+
+use image::imageops::FilterType;
+use ndarray::{Array4, CowArray};
+use ort::{tensor::OrtOwnedTensor, Environment, OrtResult, Session, Value};
+
+use crate::acceleration::{self, SessionOptions};
+
+/// Side of the square image the matting model takes as input.
+const MATTE_SIZE: u32 = 512;
+
+/// MODNet-style portrait matting model, estimating how much of each pixel belongs to the person.
+pub struct MattingModel {
+    session: Session,
+}
+
+impl MattingModel {
+    /// Loads a matting model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_path` - Path to the ONNX model, taking a 1x3x512x512 RGB input normalized to
+    ///   -1..1 and returning a 1x1x512x512 matte in 0..1.
+    /// * `session_options` - Threading and graph optimization of the session.
+    pub fn from_file(model_path: &Path, session_options: SessionOptions) -> OrtResult<Self> {
+        let environment = Environment::builder()
+            .with_name("facecrop")
+            .build()?
+            .into_arc();
+        let session = acceleration::session_builder(&environment, session_options)?
+            .with_model_from_file(model_path)?;
+        Ok(Self { session })
+    }
+
+    /// Function to estimate the alpha matte of an image.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image.
+    ///
+    /// # Returns
+    ///
+    /// * The matte at the size of the image, 255 for the person and 0 for the background.
+    pub fn matte(&self, image: &image::RgbImage) -> OrtResult<image::GrayImage> {
+        let resized = image::imageops::resize(image, MATTE_SIZE, MATTE_SIZE, FilterType::Triangle);
+        let input = Array4::from_shape_fn(
+            (1, 3, MATTE_SIZE as usize, MATTE_SIZE as usize),
+            |(_, c, y, x)| (resized.get_pixel(x as u32, y as u32)[c] as f32 - 127.5) / 127.5,
+        );
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let output: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let output = output.view();
+        let matte = image::GrayImage::from_fn(MATTE_SIZE, MATTE_SIZE, |x, y| {
+            image::Luma([(output[[0, 0, y as usize, x as usize]] * 255.0)
+                .round()
+                .clamp(0.0, 255.0) as u8])
+        });
+        Ok(image::imageops::resize(
+            &matte,
+            image.width(),
+            image.height(),
+            FilterType::Triangle,
+        ))
+    }
+
+    /// Function to make the background of an image transparent.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image, with the background of transparent images flattened.
+    /// * `alpha_image` - The original image if it was transparent, whose alpha is kept.
+    ///
+    /// # Returns
+    ///
+    /// * The image with the matte as its alpha channel.
+    pub fn remove_background(
+        &self,
+        image: &image::RgbImage,
+        alpha_image: Option<&image::RgbaImage>,
+    ) -> OrtResult<image::RgbaImage> {
+        let matte = self.matte(image)?;
+        Ok(image::RgbaImage::from_fn(
+            image.width(),
+            image.height(),
+            |x, y| {
+                let (pixel, alpha) = match alpha_image {
+                    Some(alpha_image) => {
+                        let pixel = alpha_image.get_pixel(x, y);
+                        ([pixel[0], pixel[1], pixel[2]], pixel[3] as u16)
+                    }
+                    None => (image.get_pixel(x, y).0, 255),
+                };
+                let alpha = (alpha * matte.get_pixel(x, y)[0] as u16 / 255) as u8;
+                image::Rgba([pixel[0], pixel[1], pixel[2], alpha])
+            },
+        ))
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const STAGES: [Stage; 5] = [
+    Stage::Decode,
+    Stage::Detect,
+    Stage::Annotate,
+    Stage::Crop,
+    Stage::Write,
+];
+
+/// A stage of processing an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Decode,
+    Detect,
+    /// Matching against a reference face and estimating landmarks and attributes.
+    Annotate,
+    Crop,
+    /// Waiting for the encoder threads to write the crops.
+    Write,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Detect => "detect",
+            Stage::Annotate => "annotate",
+            Stage::Crop => "crop",
+            Stage::Write => "write",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Number of observations at most the bound of each bucket.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+/// Counters and latency histograms of a long-running server or watcher, served to Prometheus.
+#[derive(Default)]
+pub struct Metrics {
+    images: AtomicU64,
+    faces: AtomicU64,
+    errors: AtomicU64,
+    rejected: AtomicU64,
+    stages: [Histogram; STAGES.len()],
+}
+
+impl Metrics {
+    /// Counts an image and the faces detected in it.
+    pub fn add_image(&self, faces: usize) {
+        self.images.fetch_add(1, Ordering::Relaxed);
+        self.faces.fetch_add(faces as u64, Ordering::Relaxed);
+    }
+
+    /// Counts an input or request that failed to process.
+    pub fn add_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request turned away because the server was saturated.
+    pub fn add_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the time spent in a stage of processing an image.
+    pub fn observe(&self, stage: Stage, elapsed: Duration) {
+        let histogram = &self.stages[STAGES.iter().position(|s| *s == stage).unwrap()];
+        let seconds = elapsed.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        histogram.count.fetch_add(1, Ordering::Relaxed);
+        histogram
+            .sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Function to render the metrics in the Prometheus text exposition format.
+    ///
+    /// # Returns
+    ///
+    /// * The metrics, to serve at `/metrics`.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for (name, help, counter) in [
+            ("images_processed", "Images processed", &self.images),
+            ("faces_detected", "Faces detected", &self.faces),
+            (
+                "errors",
+                "Inputs or requests that failed to process",
+                &self.errors,
+            ),
+            (
+                "requests_rejected",
+                "Requests turned away because the server was saturated",
+                &self.rejected,
+            ),
+        ] {
+            let _ = writeln!(text, "# HELP facecrop_{}_total {}.", name, help);
+            let _ = writeln!(text, "# TYPE facecrop_{}_total counter", name);
+            let _ = writeln!(
+                text,
+                "facecrop_{}_total {}",
+                name,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+        text.push_str(
+            "# HELP facecrop_stage_duration_seconds Time spent in each stage of processing an \
+            image.\n# TYPE facecrop_stage_duration_seconds histogram\n",
+        );
+        for (stage, histogram) in STAGES.iter().zip(&self.stages) {
+            let count = histogram.count.load(Ordering::Relaxed);
+            for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                let _ = writeln!(
+                    text,
+                    "facecrop_stage_duration_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                    stage.name(),
+                    bound,
+                    bucket.load(Ordering::Relaxed)
+                );
+            }
+            let _ = writeln!(
+                text,
+                "facecrop_stage_duration_seconds_bucket{{stage=\"{}\",le=\"+Inf\"}} {}",
+                stage.name(),
+                count
+            );
+            let _ = writeln!(
+                text,
+                "facecrop_stage_duration_seconds_sum{{stage=\"{}\"}} {}",
+                stage.name(),
+                histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+            );
+            let _ = writeln!(
+                text,
+                "facecrop_stage_duration_seconds_count{{stage=\"{}\"}} {}",
+                stage.name(),
+                count
+            );
+        }
+        text
+    }
+}
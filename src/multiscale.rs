@@ -0,0 +1,90 @@
+use rust_faces::{Face, FaceDetector, Rect, RustFacesResult, ToArray3};
+
+use crate::cropping;
+
+/// Fraction of each side of the image a region covers, so neighbouring regions overlap and
+/// faces on a region boundary are whole in one of them.
+const REGION_SIZE: f32 = 0.6;
+/// Factor the regions are upsampled by before detection.
+const UPSAMPLE: u32 = 2;
+/// Overlap, relative to the smaller box, above which detections in the full image and the
+/// regions are taken to be the same face.
+const SCALE_MERGE_OVERLAP: f32 = 0.5;
+
+/// Face detector that, besides the image itself, detects in upsampled copies of its four corner
+/// regions and its centre. Detectors resize their input to a fixed size, which shrinks the faces
+/// of wide group photos below what they can find, and the upsampled regions give those faces
+/// twice the pixels.
+pub struct MultiscaleDetector {
+    detector: Box<dyn FaceDetector>,
+}
+
+impl MultiscaleDetector {
+    /// Wraps a detector.
+    ///
+    /// # Arguments
+    ///
+    /// * `detector` - The detector to pass the image and its upsampled regions to.
+    pub fn new(detector: Box<dyn FaceDetector>) -> Self {
+        Self { detector }
+    }
+}
+
+impl FaceDetector for MultiscaleDetector {
+    fn detect(&self, image: ndarray::ArrayViewD<u8>) -> RustFacesResult<Vec<Face>> {
+        let mut faces = self.detector.detect(image.view())?;
+        let full_image = cropping::array_to_image(&image);
+        let (width, height) = full_image.dimensions();
+        let region_width = ((width as f32 * REGION_SIZE).ceil() as u32).min(width);
+        let region_height = ((height as f32 * REGION_SIZE).ceil() as u32).min(height);
+        let (right, bottom) = (width - region_width, height - region_height);
+        let regions = [
+            (0, 0),
+            (right, 0),
+            (0, bottom),
+            (right, bottom),
+            (right / 2, bottom / 2),
+        ];
+        for (x, y) in regions {
+            let region = image::imageops::crop_imm(&full_image, x, y, region_width, region_height)
+                .to_image();
+            let upsampled = image::imageops::resize(
+                &region,
+                region_width * UPSAMPLE,
+                region_height * UPSAMPLE,
+                image::imageops::FilterType::CatmullRom,
+            );
+            let region_faces = self
+                .detector
+                .detect(upsampled.into_array3().view().into_dyn())?;
+            faces.extend(
+                region_faces
+                    .into_iter()
+                    .map(|face| to_image_coordinates(face, x as f32, y as f32)),
+            );
+        }
+
+        // detections of a face include partial detections of faces cut by a region boundary
+        Ok(cropping::merge_overlapping(faces, SCALE_MERGE_OVERLAP))
+    }
+}
+
+/// Function to map a face detected in an upsampled region back to the image.
+fn to_image_coordinates(face: Face, x: f32, y: f32) -> Face {
+    let scale = UPSAMPLE as f32;
+    Face {
+        rect: Rect {
+            x: face.rect.x / scale + x,
+            y: face.rect.y / scale + y,
+            width: face.rect.width / scale,
+            height: face.rect.height / scale,
+        },
+        confidence: face.confidence,
+        landmarks: face.landmarks.map(|landmarks| {
+            landmarks
+                .into_iter()
+                .map(|(landmark_x, landmark_y)| (landmark_x / scale + x, landmark_y / scale + y))
+                .collect()
+        }),
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use image::{ColorType, EncodableLayout, Pixel, PixelWithColorType};
+use tracing::warn;
+
+use crate::{cropping::Image, post_processing::ColorMode, provenance, xmp};
+
+/// Quality of lossy formats unless one is given.
+pub const DEFAULT_QUALITY: u8 = 75;
+/// Suffix of the temporary files outputs are written to before they are renamed into place.
+const TEMP_SUFFIX: &str = ".facecrop-tmp";
+
+/// Chroma subsampling of JPEG outputs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// Colour at full resolution.
+    Yuv444,
+    /// Colour at half the horizontal resolution.
+    Yuv422,
+    /// Colour at half the horizontal and vertical resolution.
+    Yuv420,
+}
+
+/// How JPEG crops are encoded.
+#[derive(Copy, Clone, Debug)]
+pub struct JpegOptions {
+    pub subsampling: ChromaSubsampling,
+    /// True to write progressive JPEGs, which show a coarse image while loading.
+    pub progressive: bool,
+}
+
+/// What to do when an output file already exists.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Overwrite,
+    /// Keep the existing file and don't write the output.
+    Skip,
+    /// Write the output next to the existing file with a numeric suffix, e.g. `a-1.jpg`.
+    Rename,
+}
+
+/// Function to get the path to write an output to, given what to do if the file already exists.
+///
+/// # Arguments
+///
+/// * `output_path` - Path the output would be written to.
+/// * `policy` - What to do if the file already exists.
+/// * `exists` - Whether a file exists where the outputs are stored.
+///
+/// # Returns
+///
+/// * The path to write to, or None if the output shouldn't be written.
+pub fn resolve_conflict(
+    output_path: &Path,
+    policy: ConflictPolicy,
+    exists: impl Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    if !exists(output_path) {
+        return Some(output_path.to_path_buf());
+    }
+    match policy {
+        ConflictPolicy::Overwrite => Some(output_path.to_path_buf()),
+        ConflictPolicy::Skip => None,
+        ConflictPolicy::Rename => {
+            let stem = output_path.file_stem().unwrap().to_string_lossy();
+            let extension = output_path
+                .extension()
+                .map(|extension| format!(".{}", extension.to_string_lossy()))
+                .unwrap_or_default();
+            (1..)
+                .map(|n| output_path.with_file_name(format!("{}-{}{}", stem, n, extension)))
+                .find(|path| !exists(path))
+        }
+    }
+}
+
+/// Function to write a file so that it only appears once it is complete. The contents are
+/// written to a temporary file next to it, which then replaces it, so an interrupted run never
+/// leaves a truncated output behind.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file.
+/// * `contents` - The contents to write.
+///
+/// # Returns
+///
+/// * Whether the file was written.
+pub fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let temp_path = temp_path(path);
+    let written =
+        std::fs::write(&temp_path, contents).and_then(|_| std::fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Path of the temporary file an output is written to before it is renamed into place.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(TEMP_SUFFIX);
+    path.with_file_name(temp_name)
+}
+
+pub fn save_image<P>(output_image: &Image<P>, output_path: &Path, output_format: image::ImageFormat)
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    save_image_with_quality(output_image, output_path, output_format, DEFAULT_QUALITY);
+}
+
+pub fn save_image_with_quality<P>(
+    output_image: &Image<P>,
+    output_path: &Path,
+    output_format: image::ImageFormat,
+    quality: u8,
+) where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    write_atomically(
+        output_path,
+        encode_image(output_image, output_format, quality),
+    )
+    .unwrap_or_else(|_| panic!("Failed to save output image"));
+}
+
+/// Function to write a crop in the colour mode crops are written in.
+///
+/// # Arguments
+///
+/// * `crop` - The post-processed crop.
+/// * `output_path` - Path to write the crop to.
+/// * `color_mode` - The colour mode to convert the crop to.
+/// * `output_format` - The format to encode the crop with.
+/// * `quality` - Quality of lossy formats.
+/// * `jpeg_options` - How the crop is encoded if it is written as a JPEG.
+/// * `description` - XMP `rdf:Description` to embed into the crop, if any.
+pub fn save_crop<P>(
+    crop: &Image<P>,
+    output_path: &Path,
+    color_mode: ColorMode,
+    output_format: image::ImageFormat,
+    quality: u8,
+    jpeg_options: JpegOptions,
+    description: Option<&str>,
+) where
+    P: Pixel<Subpixel = u8> + PixelWithColorType + 'static,
+{
+    let encoded = encode_crop(crop, color_mode, output_format, quality, jpeg_options);
+    write_atomically(output_path, with_description(encoded, description))
+        .unwrap_or_else(|_| panic!("Failed to save output image"));
+}
+
+/// Function to embed an XMP description into an encoded crop, warning if the format doesn't
+/// support it.
+fn with_description(encoded: Vec<u8>, description: Option<&str>) -> Vec<u8> {
+    let description = match description {
+        Some(description) => description,
+        None => return encoded,
+    };
+    match xmp::embed_description(encoded.clone(), description) {
+        Ok(updated) => updated,
+        Err(error) => {
+            warn!("Failed to embed metadata into crop: {}", error);
+            encoded
+        }
+    }
+}
+
+/// Function to encode a crop in the colour mode crops are written in.
+///
+/// # Arguments
+///
+/// * `crop` - The post-processed crop.
+/// * `color_mode` - The colour mode to convert the crop to.
+/// * `output_format` - The format to encode the crop with.
+/// * `quality` - Quality of lossy formats.
+/// * `jpeg_options` - How the crop is encoded if it is written as a JPEG.
+///
+/// # Returns
+///
+/// * The encoded crop.
+pub fn encode_crop<P>(
+    crop: &Image<P>,
+    color_mode: ColorMode,
+    output_format: image::ImageFormat,
+    quality: u8,
+    jpeg_options: JpegOptions,
+) -> Vec<u8>
+where
+    P: Pixel<Subpixel = u8> + PixelWithColorType + 'static,
+{
+    match color_mode {
+        ColorMode::Rgb => encode_crop_image(crop, output_format, quality, jpeg_options),
+        ColorMode::Grayscale if P::COLOR_TYPE.has_alpha() => encode_crop_image(
+            &image::imageops::grayscale_alpha(crop),
+            output_format,
+            quality,
+            jpeg_options,
+        ),
+        ColorMode::Grayscale => encode_crop_image(
+            &image::imageops::grayscale(crop),
+            output_format,
+            quality,
+            jpeg_options,
+        ),
+    }
+}
+
+fn encode_crop_image<P>(
+    crop: &Image<P>,
+    output_format: image::ImageFormat,
+    quality: u8,
+    jpeg_options: JpegOptions,
+) -> Vec<u8>
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    if output_format == image::ImageFormat::Jpeg {
+        if let Some(encoded) = encode_jpeg(crop, quality, jpeg_options) {
+            return encoded;
+        }
+    }
+    encode_image(crop, output_format, quality)
+}
+
+/// The image crate has no control over chroma subsampling or progressive encoding, so JPEG crops
+/// go through jpeg-encoder. Returns None for color types it doesn't take, which are then encoded
+/// by the image crate.
+fn encode_jpeg<P>(
+    output_image: &Image<P>,
+    quality: u8,
+    jpeg_options: JpegOptions,
+) -> Option<Vec<u8>>
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    let color_type = match P::COLOR_TYPE {
+        ColorType::L8 => jpeg_encoder::ColorType::Luma,
+        ColorType::Rgb8 => jpeg_encoder::ColorType::Rgb,
+        ColorType::Rgba8 => jpeg_encoder::ColorType::Rgba,
+        _ => return None,
+    };
+    let (width, height) = output_image.dimensions();
+    let (width, height) = (u16::try_from(width).ok()?, u16::try_from(height).ok()?);
+
+    let mut encoded = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut encoded, quality);
+    encoder.set_sampling_factor(match jpeg_options.subsampling {
+        ChromaSubsampling::Yuv444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+        ChromaSubsampling::Yuv422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+        ChromaSubsampling::Yuv420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+    encoder.set_progressive(jpeg_options.progressive);
+    encoder
+        .encode(output_image.as_raw().as_bytes(), width, height, color_type)
+        .unwrap_or_else(|_| panic!("Failed to encode output image"));
+    Some(provenance::embed_marker(
+        encoded,
+        &image::ImageOutputFormat::Jpeg(quality),
+    ))
+}
+
+/// Function to write a crop of a high bit depth image as a 16-bit PNG.
+///
+/// # Arguments
+///
+/// * `crop` - The post-processed crop.
+/// * `output_path` - Path to write the crop to.
+/// * `color_mode` - The colour mode to convert the crop to.
+/// * `description` - XMP `rdf:Description` to embed into the crop, if any.
+pub fn save_deep_crop(
+    crop: &image::DynamicImage,
+    output_path: &Path,
+    color_mode: ColorMode,
+    description: Option<&str>,
+) {
+    let crop = match color_mode {
+        ColorMode::Rgb => crop.clone(),
+        // keeps the alpha channel and the bit depth
+        ColorMode::Grayscale => crop.grayscale(),
+    };
+    let format = image::ImageOutputFormat::Png;
+    let mut encoded = Vec::new();
+    crop.write_to(&mut Cursor::new(&mut encoded), format.clone())
+        .unwrap_or_else(|_| panic!("Failed to encode output image"));
+    let encoded = provenance::embed_marker(encoded, &format);
+    write_atomically(output_path, with_description(encoded, description))
+        .unwrap_or_else(|_| panic!("Failed to save output image"));
+}
+
+pub fn encode_image<P>(
+    output_image: &Image<P>,
+    output_format: image::ImageFormat,
+    quality: u8,
+) -> Vec<u8>
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    let format = match output_format {
+        image::ImageFormat::Jpeg => image::ImageOutputFormat::Jpeg(quality),
+        image::ImageFormat::WebP => {
+            if let Some(encoded) = encode_lossy_webp(output_image, quality) {
+                return encoded;
+            }
+            image::ImageOutputFormat::WebP
+        }
+        _ => image::ImageOutputFormat::from(output_format),
+    };
+    let mut encoded = Vec::new();
+    output_image
+        .write_to(&mut Cursor::new(&mut encoded), format.clone())
+        .unwrap_or_else(|_| panic!("Failed to encode output image"));
+
+    provenance::embed_marker(encoded, &format)
+}
+
+/// The image crate only writes lossless WebP, so lossy WebP goes through libwebp. Returns None
+/// for color types libwebp doesn't take, which are then written losslessly.
+fn encode_lossy_webp<P>(output_image: &Image<P>, quality: u8) -> Option<Vec<u8>>
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    let bytes = output_image.as_raw().as_bytes();
+    let (width, height) = output_image.dimensions();
+    let encoder = match P::COLOR_TYPE {
+        ColorType::Rgb8 => webp::Encoder::from_rgb(bytes, width, height),
+        ColorType::Rgba8 => webp::Encoder::from_rgba(bytes, width, height),
+        _ => return None,
+    };
+    Some(encoder.encode(quality as f32).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn files(paths: &[&str]) -> HashSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn resolve_conflict_keeps_free_paths() {
+        let existing = files(&["out/a-1.jpg"]);
+        for policy in [
+            ConflictPolicy::Overwrite,
+            ConflictPolicy::Skip,
+            ConflictPolicy::Rename,
+        ] {
+            assert_eq!(
+                resolve_conflict(Path::new("out/a.jpg"), policy, |path| existing
+                    .contains(path)),
+                Some(PathBuf::from("out/a.jpg"))
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_conflict_overwrites_or_skips_existing_paths() {
+        let existing = files(&["out/a.jpg"]);
+        let exists = |path: &Path| existing.contains(path);
+        assert_eq!(
+            resolve_conflict(Path::new("out/a.jpg"), ConflictPolicy::Overwrite, exists),
+            Some(PathBuf::from("out/a.jpg"))
+        );
+        assert_eq!(
+            resolve_conflict(Path::new("out/a.jpg"), ConflictPolicy::Skip, exists),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve_conflict_renames_to_the_first_free_number() {
+        let existing = files(&["out/a.jpg", "out/a-1.jpg", "out/a-2.jpg", "out/a-4.jpg"]);
+        let exists = |path: &Path| existing.contains(path);
+        assert_eq!(
+            resolve_conflict(Path::new("out/a.jpg"), ConflictPolicy::Rename, exists),
+            Some(PathBuf::from("out/a-3.jpg"))
+        );
+        // the number goes before the last extension only
+        let existing = files(&["out/a.b.png", "out/README"]);
+        let exists = |path: &Path| existing.contains(path);
+        assert_eq!(
+            resolve_conflict(Path::new("out/a.b.png"), ConflictPolicy::Rename, exists),
+            Some(PathBuf::from("out/a.b-1.png"))
+        );
+        assert_eq!(
+            resolve_conflict(Path::new("out/README"), ConflictPolicy::Rename, exists),
+            Some(PathBuf::from("out/README-1"))
+        );
+    }
+}
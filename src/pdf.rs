@@ -0,0 +1,274 @@
+use std::{fmt::Write, io::Cursor, path::Path};
+
+use tracing::warn;
+
+use crate::manifest::CropRecord;
+
+/// Width and height of an A4 page, in points.
+const PAGE_SIZE: (f32, f32) = (595.0, 842.0);
+const MARGIN: f32 = 36.0;
+/// Height of the title at the top of each page.
+const HEADER_HEIGHT: f32 = 24.0;
+const COLUMNS: usize = 4;
+const ROWS: usize = 5;
+/// Height of the area a thumbnail is fitted into within its cell, in points.
+const THUMBNAIL_HEIGHT: f32 = 110.0;
+/// Space between the thumbnails, in points.
+const GUTTER: f32 = 10.0;
+const CAPTION_FONT_SIZE: f32 = 7.0;
+const CAPTION_LINE_HEIGHT: f32 = 9.0;
+/// Characters that fit on a caption line, given the average width of Helvetica.
+const CAPTION_CHARACTERS: usize = 34;
+/// Long side of the thumbnails, in pixels, about twice their printed size in points.
+const THUMBNAIL_SIZE: u32 = 240;
+const THUMBNAIL_QUALITY: u8 = 85;
+/// Object numbers of the document catalog, page tree and caption font.
+const CATALOG_ID: usize = 1;
+const PAGES_ID: usize = 2;
+const FONT_ID: usize = 3;
+
+/// Function to write a paginated PDF of the crops of a run, laid out in a grid with captions for
+/// the input each was taken from, its confidence and the size of the face, for reviewing crops
+/// offline or sharing them with people who won't open the output directory.
+///
+/// # Arguments
+///
+/// * `pdf_path` - Path of the PDF file to write.
+/// * `output_dir` - The output directory the crop paths are relative to.
+/// * `crops` - The crops of the run.
+pub fn save(pdf_path: &Path, output_dir: &Path, crops: &[CropRecord]) {
+    let per_page = COLUMNS * ROWS;
+    let pages: Vec<&[CropRecord]> = match crops.is_empty() {
+        true => vec![&[]],
+        false => crops.chunks(per_page).collect(),
+    };
+    let cell_width = (PAGE_SIZE.0 - 2.0 * MARGIN) / COLUMNS as f32;
+    let cell_height = (PAGE_SIZE.1 - 2.0 * MARGIN - HEADER_HEIGHT) / ROWS as f32;
+
+    // the catalog and page tree are filled in once the pages are known
+    let mut objects: Vec<Vec<u8>> = vec![
+        Vec::new(),
+        Vec::new(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    ];
+    let mut page_ids = Vec::new();
+    for (page_index, page_crops) in pages.iter().enumerate() {
+        let mut content = String::new();
+        let title = format!(
+            "facecrop review - {} crop(s) - page {} of {}",
+            crops.len(),
+            page_index + 1,
+            pages.len()
+        );
+        text(
+            &mut content,
+            MARGIN,
+            PAGE_SIZE.1 - MARGIN - 12.0,
+            12.0,
+            &title,
+        );
+        if crops.is_empty() {
+            text(
+                &mut content,
+                MARGIN,
+                PAGE_SIZE.1 - MARGIN - HEADER_HEIGHT - 12.0,
+                10.0,
+                "No crops",
+            );
+        }
+
+        let mut images = Vec::new();
+        for (index, crop) in page_crops.iter().enumerate() {
+            let (column, row) = (index % COLUMNS, index / COLUMNS);
+            let cell_x = MARGIN + column as f32 * cell_width;
+            let cell_top = PAGE_SIZE.1 - MARGIN - HEADER_HEIGHT - row as f32 * cell_height;
+            let box_width = cell_width - GUTTER;
+            let thumbnail_bottom = cell_top - THUMBNAIL_HEIGHT;
+
+            let crop_path = output_dir.join(&crop.path);
+            match thumbnail(&crop_path) {
+                Some((jpeg, width, height)) => {
+                    let scale = (box_width / width as f32).min(THUMBNAIL_HEIGHT / height as f32);
+                    let (drawn_width, drawn_height) = (width as f32 * scale, height as f32 * scale);
+                    let name = format!("Im{}", images.len() + 1);
+                    let _ = writeln!(
+                        content,
+                        "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /{} Do Q",
+                        drawn_width,
+                        drawn_height,
+                        cell_x + (box_width - drawn_width) / 2.0,
+                        thumbnail_bottom + (THUMBNAIL_HEIGHT - drawn_height) / 2.0,
+                        name
+                    );
+                    let mut image = format!(
+                        "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace \
+                        /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode /Length {} >>\nstream\n",
+                        width,
+                        height,
+                        jpeg.len()
+                    )
+                    .into_bytes();
+                    image.extend_from_slice(&jpeg);
+                    image.extend_from_slice(b"\nendstream");
+                    objects.push(image);
+                    images.push((name, objects.len()));
+                }
+                None => {
+                    warn!("Failed to read crop {} for the PDF", crop_path.display());
+                    text(
+                        &mut content,
+                        cell_x,
+                        cell_top - THUMBNAIL_HEIGHT / 2.0,
+                        CAPTION_FONT_SIZE,
+                        "missing",
+                    );
+                }
+            }
+
+            let source = crop
+                .source
+                .file_name()
+                .unwrap_or(crop.source.as_os_str())
+                .to_string_lossy();
+            let captions = [
+                source.to_string(),
+                format!("Confidence {:.2}", crop.confidence),
+                format!(
+                    "Face {:.0}x{:.0} ({})",
+                    crop.face_rect.width,
+                    crop.face_rect.height,
+                    crop.size_bucket.name()
+                ),
+            ];
+            for (line, caption) in captions.iter().enumerate() {
+                text(
+                    &mut content,
+                    cell_x,
+                    thumbnail_bottom - (line + 1) as f32 * CAPTION_LINE_HEIGHT,
+                    CAPTION_FONT_SIZE,
+                    &truncate(caption),
+                );
+            }
+        }
+
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(content.as_bytes());
+        stream.extend_from_slice(b"endstream");
+        objects.push(stream);
+        let content_id = objects.len();
+        let image_resources: String = images
+            .iter()
+            .map(|(name, id)| format!(" /{} {} 0 R", name, id))
+            .collect();
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /Font << \
+                /F1 {} 0 R >> /XObject <<{} >> >> /Contents {} 0 R >>",
+                PAGES_ID, PAGE_SIZE.0, PAGE_SIZE.1, FONT_ID, image_resources, content_id
+            )
+            .into_bytes(),
+        );
+        page_ids.push(objects.len());
+    }
+    objects[CATALOG_ID - 1] = format!("<< /Type /Catalog /Pages {} 0 R >>", PAGES_ID).into_bytes();
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    objects[PAGES_ID - 1] = format!(
+        "<< /Type /Pages /Kids [{}] /Count {} >>",
+        kids.join(" "),
+        page_ids.len()
+    )
+    .into_bytes();
+
+    std::fs::write(pdf_path, serialize(&objects)).unwrap_or_else(|_| panic!("Failed to write PDF"));
+}
+
+/// Function to serialize the objects of a PDF along with the cross-reference table locating
+/// them. Objects are numbered from 1 in order.
+fn serialize(objects: &[Vec<u8>]) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", index + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref_offset = pdf.len();
+    let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(xref, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        xref,
+        "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        CATALOG_ID,
+        xref_offset
+    );
+    pdf.extend_from_slice(xref.as_bytes());
+    pdf
+}
+
+/// Function to encode a JPEG of a crop small enough to embed in the PDF.
+///
+/// # Arguments
+///
+/// * `crop_path` - Path to the crop.
+///
+/// # Returns
+///
+/// * The JPEG and its width and height, or None if the crop can't be read.
+fn thumbnail(crop_path: &Path) -> Option<(Vec<u8>, u32, u32)> {
+    let crop = image::open(crop_path).ok()?;
+    let thumbnail = crop.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).into_rgb8();
+    let (width, height) = thumbnail.dimensions();
+    let mut encoded = Vec::new();
+    image::DynamicImage::ImageRgb8(thumbnail)
+        .write_to(
+            &mut Cursor::new(&mut encoded),
+            image::ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY),
+        )
+        .ok()?;
+    Some((encoded, width, height))
+}
+
+/// Function to add a line of Helvetica text to the content of a page.
+fn text(content: &mut String, x: f32, y: f32, size: f32, value: &str) {
+    let _ = writeln!(
+        content,
+        "BT /F1 {} Tf {:.2} {:.2} Td ({}) Tj ET",
+        size,
+        x,
+        y,
+        escape(value)
+    );
+}
+
+/// Function to shorten a caption to fit its cell, keeping the end, which tells inputs apart
+/// better than the start.
+fn truncate(caption: &str) -> String {
+    let characters: Vec<char> = caption.chars().collect();
+    match characters.len() > CAPTION_CHARACTERS {
+        true => {
+            let end: String = characters[characters.len() - (CAPTION_CHARACTERS - 3)..]
+                .iter()
+                .collect();
+            format!("...{}", end)
+        }
+        false => caption.to_string(),
+    }
+}
+
+/// Function to escape a PDF string literal. Captions use a standard font without embedding it,
+/// so characters outside ASCII are replaced.
+fn escape(value: &str) -> String {
+    value
+        .chars()
+        .map(|character| match character {
+            '(' | ')' | '\\' => format!("\\{}", character),
+            ' '..='~' => character.to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,64 @@
+use std::{
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use rust_faces::{Face, FaceDetector, RustFacesResult};
+
+/// An image to detect faces in and where to send the detections.
+type Job = (ndarray::ArrayD<u8>, Sender<RustFacesResult<Vec<Face>>>);
+
+/// Face detectors that are built once and kept loaded on worker threads, so the model load is
+/// paid once per run rather than per use. Detections are fed to whichever worker is free, so
+/// concurrent callers are served in parallel.
+pub struct DetectorPool {
+    jobs: Mutex<Sender<Job>>,
+}
+
+impl DetectorPool {
+    /// Builds the detectors and starts their workers.
+    ///
+    /// # Arguments
+    ///
+    /// * `workers` - The number of detectors to build.
+    /// * `build` - Builds a detector.
+    pub fn new<F>(workers: usize, build: F) -> Self
+    where
+        F: Fn() -> Box<dyn FaceDetector>,
+    {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let detector = build();
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                // the lock is released before detecting so other workers can take the next job
+                let job = receiver.lock().unwrap().recv();
+                let Ok((image, reply)) = job else {
+                    break;
+                };
+                let _ = reply.send(detector.detect(image.view()));
+            });
+        }
+        Self {
+            jobs: Mutex::new(sender),
+        }
+    }
+}
+
+impl FaceDetector for DetectorPool {
+    fn detect(&self, image: ndarray::ArrayViewD<u8>) -> RustFacesResult<Vec<Face>> {
+        let (reply, detections) = channel();
+        self.jobs
+            .lock()
+            .unwrap()
+            .send((image.to_owned(), reply))
+            .unwrap_or_else(|_| panic!("Face detection workers stopped"));
+        detections
+            .recv()
+            .unwrap_or_else(|_| panic!("Face detection worker stopped"))
+    }
+}
@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+/// Eyes, nose tip and mouth corners of a mean adult face in millimetres, in the order detectors
+/// return them: x to the right of the image, y down and z away from the camera, with the nose tip
+/// at the origin.
+const MEAN_FACE: [[f32; 3]; 5] = [
+    [-33.0, -34.0, 27.0],
+    [33.0, -34.0, 27.0],
+    [0.0, 0.0, 0.0],
+    [-30.0, 30.0, 25.0],
+    [30.0, 30.0, 25.0],
+];
+
+/// Orientation of a head relative to the camera, in degrees. All zero for a face looking straight
+/// into the camera.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeadPose {
+    /// Turn about the vertical axis, positive towards the left of the image.
+    pub yaw: f32,
+    /// Tilt about the horizontal axis, positive looking down.
+    pub pitch: f32,
+    /// Rotation in the image plane, positive clockwise.
+    pub roll: f32,
+}
+
+/// Function to estimate the pose of a head from its landmarks.
+///
+/// The landmarks are fitted to `MEAN_FACE` under a scaled orthographic camera, as in the POS step
+/// of POSIT, which is accurate enough for faces that fill a small part of the field of view.
+///
+/// # Arguments
+///
+/// * `landmarks` - The eyes, nose tip and mouth corners of the face, in image coordinates.
+///
+/// # Returns
+///
+/// * The head pose, or None if there aren't five landmarks or they are degenerate.
+pub fn estimate(landmarks: &[(f32, f32)]) -> Option<HeadPose> {
+    if landmarks.len() != MEAN_FACE.len() {
+        return None;
+    }
+    let count = MEAN_FACE.len() as f32;
+    let model_mean: [f32; 3] =
+        std::array::from_fn(|axis| MEAN_FACE.iter().map(|point| point[axis]).sum::<f32>() / count);
+    let image_mean = (
+        landmarks.iter().map(|(x, _)| x).sum::<f32>() / count,
+        landmarks.iter().map(|(_, y)| y).sum::<f32>() / count,
+    );
+
+    // least-squares fit of the 2x3 projection of the centred model onto the centred landmarks
+    let mut model_moments = [[0.0; 3]; 3];
+    let mut image_moments = [[0.0; 3]; 2];
+    for (point, (x, y)) in MEAN_FACE.iter().zip(landmarks) {
+        let centred: [f32; 3] = std::array::from_fn(|axis| point[axis] - model_mean[axis]);
+        let projected = [x - image_mean.0, y - image_mean.1];
+        for row in 0..3 {
+            for column in 0..3 {
+                model_moments[row][column] += centred[row] * centred[column];
+            }
+        }
+        for row in 0..2 {
+            for column in 0..3 {
+                image_moments[row][column] += projected[row] * centred[column];
+            }
+        }
+    }
+    let inverse = invert(&model_moments)?;
+    let projection: [[f32; 3]; 2] = std::array::from_fn(|row| {
+        std::array::from_fn(|column| {
+            (0..3)
+                .map(|k| image_moments[row][k] * inverse[k][column])
+                .sum()
+        })
+    });
+
+    // the rows of the projection are the first two rows of the rotation, scaled
+    let first = normalize(projection[0])?;
+    let second = projection[1];
+    let along = dot(first, second);
+    let second = normalize(std::array::from_fn(|axis| {
+        second[axis] - along * first[axis]
+    }))?;
+    let third = cross(first, second);
+
+    Some(HeadPose {
+        yaw: (-third[0]).clamp(-1.0, 1.0).asin().to_degrees(),
+        pitch: third[1].atan2(third[2]).to_degrees(),
+        roll: second[0].atan2(first[0]).to_degrees(),
+    })
+}
+
+/// Function to reduce 68 landmarks to the eyes, nose tip and mouth corners `estimate` takes.
+///
+/// # Arguments
+///
+/// * `landmarks` - The landmarks in the 68-point layout.
+///
+/// # Returns
+///
+/// * The five landmarks, or None if there aren't 68.
+pub fn five_point_landmarks(landmarks: &[(f32, f32)]) -> Option<Vec<(f32, f32)>> {
+    if landmarks.len() != 68 {
+        return None;
+    }
+    let centre = |points: &[(f32, f32)]| {
+        let count = points.len() as f32;
+        (
+            points.iter().map(|(x, _)| x).sum::<f32>() / count,
+            points.iter().map(|(_, y)| y).sum::<f32>() / count,
+        )
+    };
+    Some(vec![
+        centre(&landmarks[36..42]),
+        centre(&landmarks[42..48]),
+        landmarks[30],
+        landmarks[48],
+        landmarks[54],
+    ])
+}
+
+fn invert(matrix: &[[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+    let cofactor = |row: usize, column: usize| {
+        let (r1, r2) = ((row + 1) % 3, (row + 2) % 3);
+        let (c1, c2) = ((column + 1) % 3, (column + 2) % 3);
+        matrix[r1][c1] * matrix[r2][c2] - matrix[r1][c2] * matrix[r2][c1]
+    };
+    let determinant: f32 = (0..3)
+        .map(|column| matrix[0][column] * cofactor(0, column))
+        .sum();
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    Some(std::array::from_fn(|row| {
+        std::array::from_fn(|column| cofactor(column, row) / determinant)
+    }))
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(vector: [f32; 3]) -> Option<[f32; 3]> {
+    let norm = dot(vector, vector).sqrt();
+    (norm > f32::EPSILON).then(|| vector.map(|value| value / norm))
+}
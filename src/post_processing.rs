@@ -1,34 +1,732 @@
-use image;
+use image::{
+    imageops::FilterType, DynamicImage, GenericImage, GenericImageView, ImageBuffer, Pixel,
+};
+use rust_faces::Rect;
+
+/// Blur radius of the unsharp mask, small enough to only bring back the detail softened by
+/// resizing.
+const SHARPEN_SIGMA: f32 = 1.0;
+/// Share of the darkest and of the brightest pixels clipped by auto levels, so a few specular
+/// highlights or black borders don't stop the rest of the crop from being stretched.
+const AUTO_LEVELS_CLIP: f32 = 0.005;
+/// Tiles per side of the crop that CLAHE equalizes separately.
+const CLAHE_TILES: u32 = 8;
+/// Largest histogram bin relative to the average bin, limiting how much CLAHE amplifies noise.
+const CLAHE_CLIP_LIMIT: f32 = 2.0;
 
 #[derive(Debug)]
 pub struct PostProcessParams {
     pub resize: bool,
+    /// How crops are resized to the output size when they are resized.
+    pub fit: Fit,
+    /// Filter crops are resized with.
+    pub resize_filter: FilterType,
+    /// True to keep crops smaller than the size they would be resized to at their original size,
+    /// only resizing them down.
+    pub no_upscale: bool,
     pub filter_by_size: bool,
     pub height: u32,
     pub width: u32,
+    /// Strength of the unsharp mask applied after resizing, from 0.0 (off) to 1.0.
+    pub sharpen: f32,
+    /// True to stretch the brightness of each crop to the full range after resizing.
+    pub auto_levels: bool,
+    /// True to equalize the local contrast of each crop with CLAHE after resizing.
+    pub clahe: bool,
+    /// True to remove noise with a median filter after resizing.
+    pub denoise: bool,
+    /// Training resolutions, as (width, height). If there are any, each crop is resized and
+    /// center cropped to the one closest to its aspect ratio instead of to the output size.
+    pub buckets: Vec<(u32, u32)>,
+    pub color_mode: ColorMode,
+    /// Super-resolution model run on crops smaller than the output size before they are resized.
+    #[cfg(feature = "upscale")]
+    pub upscaler: Option<crate::upscale::Upscaler>,
+}
+
+/// How a crop is resized to an exact size whose aspect ratio may differ from its own, e.g. where
+/// the crop was clamped to the edge of the image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fit {
+    /// Resize to fit within the size and fill the rest with black, or transparency for crops
+    /// with alpha.
+    Pad,
+    /// Resize to cover the size and center crop what's outside it.
+    Cover,
+    /// Resize to the size, distorting the crop.
+    Stretch,
+}
+
+/// Colour the crops are written in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    Rgb,
+    /// 8-bit grayscale, keeping the alpha channel of transparent crops.
+    Grayscale,
+}
+
+#[derive(Debug)]
+pub struct RedactParams {
+    pub style: RedactStyle,
+}
+
+#[derive(Debug)]
+pub enum RedactStyle {
+    Blur,
+    Pixelate { block_size: u32 },
+    Solid { color: image::Rgb<u8> },
 }
 
-pub fn post_process_image(
-    input_image: &image::RgbImage,
+pub fn post_process_image<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
     post_process_params: &PostProcessParams,
-) -> Option<image::RgbImage> {
-    if post_process_params.filter_by_size {
-        if input_image.width() < post_process_params.width
-            || input_image.height() < post_process_params.height
+) -> Option<ImageBuffer<P, Vec<u8>>> {
+    let is_small = input_image.width() < post_process_params.width
+        || input_image.height() < post_process_params.height;
+    if post_process_params.filter_by_size && is_small {
+        return None;
+    }
+
+    let keeps_size = keeps_size(input_image.dimensions(), post_process_params);
+    let upscaled_image = match post_process_params.resize && is_small && !keeps_size {
+        true => upscale(input_image, post_process_params),
+        false => None,
+    };
+    let input_image = upscaled_image.as_ref().unwrap_or(input_image);
+
+    let filter = post_process_params.resize_filter;
+    let resized_image = match (
+        closest_bucket(input_image.dimensions(), &post_process_params.buckets),
+        post_process_params.resize,
+    ) {
+        _ if keeps_size => input_image.clone(),
+        (Some(bucket), _) => resize_to_cover(input_image, bucket, filter),
+        (None, true) => {
+            let size = (post_process_params.width, post_process_params.height);
+            match post_process_params.fit {
+                Fit::Pad => pad(&resize_to_fit(input_image, size, filter), size),
+                Fit::Cover => resize_to_cover(input_image, size, filter),
+                Fit::Stretch => image::imageops::resize(input_image, size.0, size.1, filter),
+            }
+        }
+        (None, false) => input_image.clone(),
+    };
+
+    let leveled_image = match post_process_params.auto_levels {
+        true => auto_levels(&resized_image),
+        false => resized_image,
+    };
+    let equalized_image = match post_process_params.clahe {
+        true => clahe(&leveled_image),
+        false => leveled_image,
+    };
+    // denoised first so the noise isn't sharpened with the detail
+    let denoised_image = match post_process_params.denoise {
+        true => imageproc::filter::median_filter(&equalized_image, 1, 1),
+        false => equalized_image,
+    };
+    let sharpened_image = match post_process_params.sharpen > 0.0 {
+        true => sharpen(&denoised_image, post_process_params.sharpen),
+        false => denoised_image,
+    };
+
+    Some(sharpened_image)
+}
+
+/// Function to check whether crops can be post-processed without converting them to 8 bits, so
+/// high bit depth inputs keep their bit depth. Upscaling, contrast normalization, denoising and
+/// sharpening are only done in 8 bits.
+///
+/// # Arguments
+///
+/// * `post_process_params` - The post-processing parameters.
+///
+/// # Returns
+///
+/// * True if only the resize is done.
+pub fn keeps_bit_depth(post_process_params: &PostProcessParams) -> bool {
+    #[cfg(feature = "upscale")]
+    if post_process_params.upscaler.is_some() {
+        return false;
+    }
+    !post_process_params.auto_levels
+        && !post_process_params.clahe
+        && !post_process_params.denoise
+        && post_process_params.sharpen == 0.0
+}
+
+/// Function to post-process a crop of a high bit depth image, in its bit depth. Whether the crop
+/// is written at all is decided on its 8-bit copy by `post_process_image`.
+///
+/// # Arguments
+///
+/// * `input_image` - The crop.
+/// * `post_process_params` - The post-processing parameters, for which `keeps_bit_depth` holds.
+///
+/// # Returns
+///
+/// * The post-processed crop.
+pub fn post_process_deep_image(
+    input_image: &DynamicImage,
+    post_process_params: &PostProcessParams,
+) -> DynamicImage {
+    if keeps_size(input_image.dimensions(), post_process_params) {
+        return input_image.clone();
+    }
+    let filter = post_process_params.resize_filter;
+    if let Some((width, height)) =
+        closest_bucket(input_image.dimensions(), &post_process_params.buckets)
+    {
+        return input_image.resize_to_fill(width, height, filter);
+    }
+    if !post_process_params.resize {
+        return input_image.clone();
+    }
+    let (width, height) = (post_process_params.width, post_process_params.height);
+    match post_process_params.fit {
+        Fit::Pad => match input_image.resize(width, height, filter) {
+            DynamicImage::ImageLuma16(image) => {
+                DynamicImage::ImageLuma16(pad(&image, (width, height)))
+            }
+            DynamicImage::ImageLumaA16(image) => {
+                DynamicImage::ImageLumaA16(pad(&image, (width, height)))
+            }
+            DynamicImage::ImageRgb16(image) => {
+                DynamicImage::ImageRgb16(pad(&image, (width, height)))
+            }
+            DynamicImage::ImageRgb32F(image) => {
+                DynamicImage::ImageRgb32F(pad(&image, (width, height)))
+            }
+            DynamicImage::ImageRgba32F(image) => {
+                DynamicImage::ImageRgba32F(pad(&image, (width, height)))
+            }
+            image => DynamicImage::ImageRgba16(pad(&image.into_rgba16(), (width, height))),
+        },
+        Fit::Cover => input_image.resize_to_fill(width, height, filter),
+        Fit::Stretch => input_image.resize_exact(width, height, filter),
+    }
+}
+
+/// Function to check whether a crop is kept at its original size because it is smaller than the
+/// size it would be resized to and only resizing down is allowed.
+///
+/// # Arguments
+///
+/// * `dimensions` - Width and height of the crop.
+/// * `post_process_params` - The post-processing parameters.
+///
+/// # Returns
+///
+/// * True if the crop isn't resized.
+fn keeps_size(dimensions: (u32, u32), post_process_params: &PostProcessParams) -> bool {
+    if !post_process_params.no_upscale {
+        return false;
+    }
+    let target = closest_bucket(dimensions, &post_process_params.buckets).or(post_process_params
+        .resize
+        .then_some((post_process_params.width, post_process_params.height)));
+    target.is_some_and(|(width, height)| dimensions.0 < width || dimensions.1 < height)
+}
+
+/// Function to pick the training resolution whose aspect ratio is closest to that of a crop.
+///
+/// # Arguments
+///
+/// * `dimensions` - Width and height of the crop.
+/// * `buckets` - The training resolutions, as (width, height).
+///
+/// # Returns
+///
+/// * The closest resolution, or None if there are none.
+fn closest_bucket(dimensions: (u32, u32), buckets: &[(u32, u32)]) -> Option<(u32, u32)> {
+    // aspect ratios are compared on a log scale so 1:2 and 2:1 are as far from 1:1
+    let log_ratio = |(width, height): (u32, u32)| (width.max(1) as f32 / height.max(1) as f32).ln();
+    buckets.iter().copied().min_by(|a, b| {
+        let distance = |bucket| (log_ratio(bucket) - log_ratio(dimensions)).abs();
+        distance(*a).total_cmp(&distance(*b))
+    })
+}
+
+/// Function to resize a crop to fit within a size, keeping its aspect ratio.
+fn resize_to_fit<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
+    (width, height): (u32, u32),
+    filter: FilterType,
+) -> ImageBuffer<P, Vec<u8>> {
+    let scale = (width as f32 / input_image.width().max(1) as f32)
+        .min(height as f32 / input_image.height().max(1) as f32);
+    image::imageops::resize(
+        input_image,
+        ((input_image.width() as f32 * scale).round() as u32).clamp(1, width),
+        ((input_image.height() as f32 * scale).round() as u32).clamp(1, height),
+        filter,
+    )
+}
+
+/// Function to center an image on a canvas of a size at least as large, leaving the rest of the
+/// canvas zero, i.e. black or transparent.
+fn pad<P: Pixel + 'static>(
+    input_image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    (width, height): (u32, u32),
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let mut padded_image = ImageBuffer::new(width, height);
+    image::imageops::replace(
+        &mut padded_image,
+        input_image,
+        (width.saturating_sub(input_image.width()) / 2) as i64,
+        (height.saturating_sub(input_image.height()) / 2) as i64,
+    );
+    padded_image
+}
+
+/// Function to resize a crop to cover a size, e.g. a training resolution, and center crop it to
+/// exactly that size.
+fn resize_to_cover<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
+    (width, height): (u32, u32),
+    filter: FilterType,
+) -> ImageBuffer<P, Vec<u8>> {
+    let scale = (width as f32 / input_image.width().max(1) as f32)
+        .max(height as f32 / input_image.height().max(1) as f32);
+    let resized_image = image::imageops::resize(
+        input_image,
+        ((input_image.width() as f32 * scale).round() as u32).max(width),
+        ((input_image.height() as f32 * scale).round() as u32).max(height),
+        filter,
+    );
+    image::imageops::crop_imm(
+        &resized_image,
+        (resized_image.width() - width) / 2,
+        (resized_image.height() - height) / 2,
+        width,
+        height,
+    )
+    .to_image()
+}
+
+/// Function to sharpen an image with an unsharp mask, leaving any alpha channel as it is.
+///
+/// # Arguments
+///
+/// * `input_image` - The image to sharpen.
+/// * `amount` - How much of the difference to the blurred image is added back.
+///
+/// # Returns
+///
+/// * The sharpened image.
+fn sharpen<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
+    amount: f32,
+) -> ImageBuffer<P, Vec<u8>> {
+    let blurred_image = image::imageops::blur(input_image, SHARPEN_SIGMA);
+    let color_channels = color_channels::<P>();
+    let mut output_image = input_image.clone();
+    for (pixel, blurred) in output_image.pixels_mut().zip(blurred_image.pixels()) {
+        for (channel, blurred) in pixel
+            .channels_mut()
+            .iter_mut()
+            .zip(blurred.channels())
+            .take(color_channels)
         {
-            return None;
+            let value = *channel as f32 + amount * (*channel as f32 - *blurred as f32);
+            *channel = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    output_image
+}
+
+/// Function to stretch the brightness of an image so its darkest pixels are black and its
+/// brightest white. The colour channels are stretched together so the hue is kept.
+///
+/// # Arguments
+///
+/// * `input_image` - The image to stretch.
+///
+/// # Returns
+///
+/// * The stretched image, or a copy of the image if it is a single brightness.
+fn auto_levels<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
+) -> ImageBuffer<P, Vec<u8>> {
+    let color_channels = color_channels::<P>();
+    let mut histogram = [0usize; 256];
+    for pixel in input_image.pixels() {
+        histogram[luma(pixel.channels(), color_channels) as usize] += 1;
+    }
+    let clipped = (input_image.pixels().len() as f32 * AUTO_LEVELS_CLIP) as usize;
+    let mut count = 0;
+    let low = (0..256)
+        .find(|&value| {
+            count += histogram[value];
+            count > clipped
+        })
+        .unwrap_or(0);
+    let mut count = 0;
+    let high = (0..256)
+        .rev()
+        .find(|&value| {
+            count += histogram[value];
+            count > clipped
+        })
+        .unwrap_or(255);
+    if high <= low {
+        return input_image.clone();
+    }
+
+    let scale = 255.0 / (high - low) as f32;
+    let mut output_image = input_image.clone();
+    for pixel in output_image.pixels_mut() {
+        for channel in pixel.channels_mut().iter_mut().take(color_channels) {
+            let value = (*channel as f32 - low as f32) * scale;
+            *channel = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    output_image
+}
+
+/// Function to equalize the local contrast of an image with contrast limited adaptive histogram
+/// equalization. The brightness of each tile is equalized with a clipped histogram, blending
+/// between the neighbouring tiles, and the change in brightness added to every colour channel.
+///
+/// # Arguments
+///
+/// * `input_image` - The image to equalize.
+///
+/// # Returns
+///
+/// * The equalized image.
+fn clahe<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
+) -> ImageBuffer<P, Vec<u8>> {
+    let (width, height) = input_image.dimensions();
+    let color_channels = color_channels::<P>();
+    let lumas: Vec<u8> = input_image
+        .pixels()
+        .map(|pixel| luma(pixel.channels(), color_channels))
+        .collect();
+    let tiles_x = CLAHE_TILES.min(width).max(1);
+    let tiles_y = CLAHE_TILES.min(height).max(1);
+
+    // the equalized brightness of every brightness, for each tile
+    let mut mappings = Vec::with_capacity((tiles_x * tiles_y) as usize);
+    for tile_y in 0..tiles_y {
+        for tile_x in 0..tiles_x {
+            let mut histogram = [0u32; 256];
+            for y in tile_y * height / tiles_y..(tile_y + 1) * height / tiles_y {
+                for x in tile_x * width / tiles_x..(tile_x + 1) * width / tiles_x {
+                    histogram[lumas[(y * width + x) as usize] as usize] += 1;
+                }
+            }
+            let count = histogram.iter().sum::<u32>().max(1);
+            let limit = ((CLAHE_CLIP_LIMIT * count as f32 / 256.0) as u32).max(1);
+            // the counts clipped off are spread evenly over all bins
+            let excess = histogram
+                .iter()
+                .map(|bin| bin.saturating_sub(limit))
+                .sum::<u32>() as f32
+                / 256.0;
+            let mut cumulative = 0.0;
+            let mut mapping = [0.0f32; 256];
+            for (value, bin) in histogram.iter().enumerate() {
+                cumulative += (*bin).min(limit) as f32 + excess;
+                mapping[value] = cumulative * 255.0 / count as f32;
+            }
+            mappings.push(mapping);
         }
     }
 
-    let resized_image = match post_process_params.resize {
-        true => image::imageops::resize(
-            input_image,
-            post_process_params.width,
-            post_process_params.height,
-            image::imageops::FilterType::Lanczos3,
-        ),
-        false => input_image.clone(),
+    // the position of a pixel between the centres of the tiles next to it
+    let neighbours = |position: u32, size: u32, tiles: u32| {
+        let tile = ((position as f32 + 0.5) * tiles as f32 / size as f32 - 0.5).max(0.0);
+        let first = (tile as u32).min(tiles - 1);
+        let weight = (tile - first as f32).min(1.0);
+        (first, (first + 1).min(tiles - 1), weight)
     };
+    let mut output_image = input_image.clone();
+    for (x, y, pixel) in output_image.enumerate_pixels_mut() {
+        let value = lumas[(y * width + x) as usize] as usize;
+        let (left, right, weight_x) = neighbours(x, width, tiles_x);
+        let (top, bottom, weight_y) = neighbours(y, height, tiles_y);
+        let mapped =
+            |tile_x: u32, tile_y: u32| mappings[(tile_y * tiles_x + tile_x) as usize][value];
+        let equalized = (mapped(left, top) * (1.0 - weight_x) + mapped(right, top) * weight_x)
+            * (1.0 - weight_y)
+            + (mapped(left, bottom) * (1.0 - weight_x) + mapped(right, bottom) * weight_x)
+                * weight_y;
+        let change = equalized - value as f32;
+        for channel in pixel.channels_mut().iter_mut().take(color_channels) {
+            *channel = (*channel as f32 + change).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    output_image
+}
+
+/// Function to get the number of colour channels of a pixel type, i.e. excluding alpha.
+fn color_channels<P: Pixel>() -> usize {
+    match P::CHANNEL_COUNT {
+        // luma with alpha and RGBA
+        2 | 4 => P::CHANNEL_COUNT as usize - 1,
+        count => count as usize,
+    }
+}
+
+/// Function to get the brightness of a pixel from its colour channels.
+fn luma(channels: &[u8], color_channels: usize) -> u8 {
+    match color_channels {
+        3 => (0.299 * channels[0] as f32 + 0.587 * channels[1] as f32 + 0.114 * channels[2] as f32)
+            .round() as u8,
+        _ => channels[0],
+    }
+}
+
+/// Function to upscale a crop with the super-resolution model, if one was given.
+///
+/// # Arguments
+///
+/// * `input_image` - The crop.
+/// * `post_process_params` - The parameters holding the model.
+///
+/// # Returns
+///
+/// * The upscaled crop, or None if there is no model or it failed.
+#[cfg(feature = "upscale")]
+fn upscale<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
+    post_process_params: &PostProcessParams,
+) -> Option<ImageBuffer<P, Vec<u8>>> {
+    let upscaler = post_process_params.upscaler.as_ref()?;
+    upscaler
+        .upscale(input_image)
+        .map_err(|error| {
+            tracing::warn!(
+                "Failed to upscale crop, resizing it directly instead: {}",
+                error
+            )
+        })
+        .ok()
+}
+
+#[cfg(not(feature = "upscale"))]
+fn upscale<P: Pixel<Subpixel = u8> + 'static>(
+    _input_image: &ImageBuffer<P, Vec<u8>>,
+    _post_process_params: &PostProcessParams,
+) -> Option<ImageBuffer<P, Vec<u8>>> {
+    None
+}
+
+/// Function to obscure the given regions of an image.
+///
+/// # Arguments
+///
+/// * `input_image` - The full image to redact.
+/// * `regions` - The regions to obscure, in pixel coordinates of the image.
+/// * `redact_params` - How the regions should be obscured.
+///
+/// # Returns
+///
+/// * A copy of the image with every region obscured.
+pub fn redact_regions<P: Pixel<Subpixel = u8> + 'static>(
+    input_image: &ImageBuffer<P, Vec<u8>>,
+    regions: &[Rect],
+    redact_params: &RedactParams,
+) -> ImageBuffer<P, Vec<u8>> {
+    let mut output_image = input_image.clone();
+    for region in regions {
+        let region = region.clamp(input_image.width() as f32, input_image.height() as f32);
+        let (x, y) = (region.x as u32, region.y as u32);
+        let (width, height) = (region.width as u32, region.height as u32);
+        if width == 0 || height == 0 {
+            continue;
+        }
+
+        let mut view = output_image.sub_image(x, y, width, height);
+        match redact_params.style {
+            RedactStyle::Blur => blur(&mut *view),
+            RedactStyle::Pixelate { block_size } => pixelate(&mut *view, block_size),
+            RedactStyle::Solid { color } => fill(&mut *view, color),
+        }
+    }
+
+    output_image
+}
+
+fn blur<I>(region: &mut I)
+where
+    I: GenericImage,
+    I::Pixel: 'static,
+{
+    // strong enough that no facial features survive regardless of the face size
+    let sigma = region.width().max(region.height()) as f32 / 6.0;
+    let blurred = image::imageops::blur(region, sigma);
+    region
+        .copy_from(&blurred, 0, 0)
+        .unwrap_or_else(|_| panic!("Failed to blur region"));
+}
+
+fn pixelate<I>(region: &mut I, block_size: u32)
+where
+    I: GenericImage,
+    I::Pixel: Pixel<Subpixel = u8>,
+{
+    let block_size = block_size.max(1);
+    for block_y in (0..region.height()).step_by(block_size as usize) {
+        for block_x in (0..region.width()).step_by(block_size as usize) {
+            let block_width = block_size.min(region.width() - block_x);
+            let block_height = block_size.min(region.height() - block_y);
+
+            let mut sums = [0u32; 4];
+            for y in block_y..block_y + block_height {
+                for x in block_x..block_x + block_width {
+                    for (sum, channel) in sums.iter_mut().zip(region.get_pixel(x, y).channels()) {
+                        *sum += *channel as u32;
+                    }
+                }
+            }
 
-    Some(resized_image)
+            let count = block_width * block_height;
+            let mut average = region.get_pixel(block_x, block_y);
+            for (channel, sum) in average.channels_mut().iter_mut().zip(sums) {
+                *channel = (sum / count) as u8;
+            }
+            for y in block_y..block_y + block_height {
+                for x in block_x..block_x + block_width {
+                    region.put_pixel(x, y, average);
+                }
+            }
+        }
+    }
+}
+
+fn fill<I>(region: &mut I, color: image::Rgb<u8>)
+where
+    I: GenericImage,
+    I::Pixel: Pixel<Subpixel = u8>,
+{
+    for y in 0..region.height() {
+        for x in 0..region.width() {
+            // only the color channels are replaced so alpha is preserved
+            let mut pixel = region.get_pixel(x, y);
+            for (channel, value) in pixel.channels_mut().iter_mut().zip(color.0) {
+                *channel = value;
+            }
+            region.put_pixel(x, y, pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    /// Function to create the parameters of resizing crops to 100x100 and nothing else.
+    fn resize_params(fit: Fit) -> PostProcessParams {
+        PostProcessParams {
+            resize: true,
+            fit,
+            resize_filter: FilterType::Lanczos3,
+            no_upscale: false,
+            filter_by_size: false,
+            height: 100,
+            width: 100,
+            sharpen: 0.0,
+            auto_levels: false,
+            clahe: false,
+            denoise: false,
+            buckets: Vec::new(),
+            color_mode: ColorMode::Rgb,
+            #[cfg(feature = "upscale")]
+            upscaler: None,
+        }
+    }
+
+    /// A 200x100 crop whose red channel is its x coordinate.
+    fn wide_crop() -> RgbImage {
+        RgbImage::from_fn(200, 100, |x, _| Rgb([x as u8, 255, 255]))
+    }
+
+    #[test]
+    fn pad_letterboxes_the_crop() {
+        let resized = post_process_image(&wide_crop(), &resize_params(Fit::Pad)).unwrap();
+        assert_eq!(resized.dimensions(), (100, 100));
+        // resized to 100x50 and centred, with black above and below
+        assert_eq!(resized.get_pixel(50, 10), &Rgb([0, 0, 0]));
+        assert_eq!(resized.get_pixel(50, 89), &Rgb([0, 0, 0]));
+        assert_eq!(resized.get_pixel(50, 50)[1], 255);
+        assert_eq!(resized.get_pixel(50, 25)[1], 255);
+    }
+
+    #[test]
+    fn cover_center_crops_the_crop() {
+        let resized = post_process_image(&wide_crop(), &resize_params(Fit::Cover)).unwrap();
+        assert_eq!(resized.dimensions(), (100, 100));
+        // only the middle half of the width is left
+        assert!(resized.get_pixel(0, 50)[0].abs_diff(50) <= 2);
+        assert!(resized.get_pixel(99, 50)[0].abs_diff(149) <= 2);
+    }
+
+    #[test]
+    fn stretch_keeps_the_whole_crop() {
+        let resized = post_process_image(&wide_crop(), &resize_params(Fit::Stretch)).unwrap();
+        assert_eq!(resized.dimensions(), (100, 100));
+        assert!(resized.get_pixel(0, 50)[0] <= 2);
+        assert!(resized.get_pixel(99, 50)[0] >= 196);
+    }
+
+    #[test]
+    fn resize_to_cover_gives_the_exact_size() {
+        for (width, height) in [(300, 100), (100, 300), (64, 64), (7, 5)] {
+            let crop = RgbImage::new(width, height);
+            for size in [(100, 100), (64, 48), (48, 64)] {
+                assert_eq!(
+                    resize_to_cover(&crop, size, FilterType::Lanczos3).dimensions(),
+                    size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn no_upscale_keeps_small_crops_at_their_size() {
+        let params = PostProcessParams {
+            no_upscale: true,
+            ..resize_params(Fit::Stretch)
+        };
+        let small = RgbImage::new(80, 120);
+        assert_eq!(
+            post_process_image(&small, &params).unwrap().dimensions(),
+            (80, 120)
+        );
+        let large = RgbImage::new(300, 200);
+        assert_eq!(
+            post_process_image(&large, &params).unwrap().dimensions(),
+            (100, 100)
+        );
+        let buckets = PostProcessParams {
+            buckets: vec![(64, 64)],
+            ..params
+        };
+        assert_eq!(
+            post_process_image(&small, &buckets).unwrap().dimensions(),
+            (64, 64)
+        );
+    }
+
+    #[test]
+    fn nearest_filter_keeps_hard_edges() {
+        let params = PostProcessParams {
+            resize_filter: FilterType::Nearest,
+            ..resize_params(Fit::Stretch)
+        };
+        let stripes = RgbImage::from_fn(200, 100, |x, _| match x / 3 % 2 {
+            0 => Rgb([0, 0, 0]),
+            _ => Rgb([255, 255, 255]),
+        });
+        let resized = post_process_image(&stripes, &params).unwrap();
+        // every pixel is one of the original ones, where Lanczos would blend the stripes
+        assert!(resized
+            .pixels()
+            .all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+    }
 }
@@ -0,0 +1,151 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+use crate::{decoding, storage::Storage};
+
+/// The result of decoding an input, or None if decoding panicked and the input should be decoded
+/// again by the caller to surface the error.
+type Decoded = Option<image::ImageResult<image::DynamicImage>>;
+
+/// How far the inputs have been taken, shared with the decoding threads so they only decode a
+/// bounded number of inputs ahead.
+struct Window {
+    /// Index of the next input to be taken.
+    next: Mutex<usize>,
+    moved: Condvar,
+}
+
+/// Image inputs decoded on background threads ahead of the input being processed, so reading and
+/// decoding overlaps detection. Inputs are taken in the order they were given, and inputs that
+/// are passed over are dropped.
+pub struct Prefetcher {
+    indices: HashMap<PathBuf, usize>,
+    window: Arc<Window>,
+    decoded: Receiver<(usize, Decoded)>,
+    /// Inputs decoded before the input waited for.
+    buffered: BTreeMap<usize, Decoded>,
+}
+
+impl Prefetcher {
+    /// Starts decoding the inputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The image inputs, in the order they are taken.
+    /// * `threads` - The number of decoding threads, which is also how many inputs are decoded
+    ///   ahead.
+    /// * `storage` - Where the inputs are fetched from before they are decoded.
+    /// * `streaming` - True to decode the inputs as they are read.
+    pub fn new(
+        paths: Vec<PathBuf>,
+        threads: usize,
+        storage: Arc<dyn Storage>,
+        streaming: bool,
+    ) -> Self {
+        let depth = threads.max(1);
+        let indices = paths
+            .iter()
+            .enumerate()
+            .map(|(index, path)| (path.clone(), index))
+            .collect();
+        let paths = Arc::new(paths);
+        let window = Arc::new(Window {
+            next: Mutex::new(0),
+            moved: Condvar::new(),
+        });
+        let claimed = Arc::new(AtomicUsize::new(0));
+        let (sender, decoded) = sync_channel(depth);
+        for _ in 0..depth {
+            let (paths, window, claimed, sender, storage) = (
+                paths.clone(),
+                window.clone(),
+                claimed.clone(),
+                sender.clone(),
+                storage.clone(),
+            );
+            thread::spawn(move || loop {
+                let index = claimed.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(index) else {
+                    break;
+                };
+                let next = window
+                    .moved
+                    .wait_while(window.next.lock().unwrap(), |next| {
+                        index >= next.saturating_add(depth)
+                    })
+                    .unwrap();
+                // inputs that were passed over, e.g. when resuming, are not decoded
+                if index < *next {
+                    continue;
+                }
+                drop(next);
+                let decoded = catch_unwind(AssertUnwindSafe(|| {
+                    storage.fetch(path);
+                    decoding::open(path, streaming)
+                }))
+                .ok();
+                if sender.send((index, decoded)).is_err() {
+                    break;
+                }
+            });
+        }
+        Self {
+            indices,
+            window,
+            decoded,
+            buffered: BTreeMap::new(),
+        }
+    }
+
+    /// Function to take the decoded image of an input, waiting for it if it is still being
+    /// decoded.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the input.
+    ///
+    /// # Returns
+    ///
+    /// * The decoded image, or None if the input is not prefetched and should be decoded by the
+    ///   caller.
+    pub fn take(&mut self, path: &Path) -> Option<image::ImageResult<image::DynamicImage>> {
+        let index = *self.indices.get(path)?;
+        self.move_window(index);
+        self.buffered = self.buffered.split_off(&index);
+        let decoded = loop {
+            if let Some(decoded) = self.buffered.remove(&index) {
+                break decoded;
+            }
+            match self.decoded.recv() {
+                Ok((decoded_index, decoded)) if decoded_index >= index => {
+                    self.buffered.insert(decoded_index, decoded);
+                }
+                Ok(_) => (),
+                Err(_) => break None,
+            }
+        };
+        self.move_window(index + 1);
+        decoded
+    }
+
+    fn move_window(&self, next: usize) {
+        *self.window.next.lock().unwrap() = next;
+        self.window.moved.notify_all();
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        // every input left is passed over so the decoding threads run out
+        self.move_window(usize::MAX);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{fs::File, io::Read, path::Path};
+
+use image::ImageOutputFormat;
+
+/// Prefix of the marker facecrop embeds into every image it writes.
+const MARKER_PREFIX: &str = "facecrop";
+
+/// Number of bytes read from the start of a file when looking for the marker. The marker is
+/// always written directly after the image header so this only needs to cover that region.
+const MARKER_SEARCH_LIMIT: u64 = 64 * 1024;
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_COM: u8 = 0xFE;
+const JPEG_SOS: u8 = 0xDA;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const PNG_TEXT_KEYWORD: &[u8] = b"Software";
+
+pub fn marker() -> String {
+    format!("{} {}", MARKER_PREFIX, env!("CARGO_PKG_VERSION"))
+}
+
+/// Function to embed the facecrop marker into an encoded image.
+///
+/// JPEGs get a COM segment directly after the SOI marker and PNGs get a `Software` tEXt chunk
+/// directly after the IHDR chunk. Other formats are returned unchanged.
+///
+/// # Arguments
+///
+/// * `encoded` - The encoded image bytes.
+/// * `format` - The format the image was encoded with.
+///
+/// # Returns
+///
+/// * The encoded image bytes with the marker embedded.
+pub fn embed_marker(encoded: Vec<u8>, format: &ImageOutputFormat) -> Vec<u8> {
+    match format {
+        ImageOutputFormat::Jpeg(_) => embed_jpeg_comment(encoded, marker().as_bytes()),
+        ImageOutputFormat::Png => embed_png_text(encoded, marker().as_bytes()),
+        _ => encoded,
+    }
+}
+
+/// Function to check whether the given image file was written by facecrop.
+///
+/// # Arguments
+///
+/// * `path` - Path to the image file.
+///
+/// # Returns
+///
+/// * True if the file carries the facecrop marker.
+pub fn is_facecrop_output(path: &Path) -> bool {
+    let mut header = Vec::new();
+    let read =
+        File::open(path).and_then(|file| file.take(MARKER_SEARCH_LIMIT).read_to_end(&mut header));
+    if read.is_err() {
+        return false;
+    }
+
+    has_marker(&header)
+}
+
+/// Function to check whether encoded image bytes carry the facecrop marker.
+pub fn has_marker(encoded: &[u8]) -> bool {
+    let header = &encoded[..encoded.len().min(MARKER_SEARCH_LIMIT as usize)];
+    let found = if header.starts_with(&JPEG_SOI) {
+        find_jpeg_comment(header)
+    } else if header.starts_with(&PNG_SIGNATURE) {
+        find_png_text(header)
+    } else {
+        None
+    };
+    found.is_some_and(|text| text.starts_with(MARKER_PREFIX.as_bytes()))
+}
+
+fn embed_jpeg_comment(encoded: Vec<u8>, comment: &[u8]) -> Vec<u8> {
+    if !encoded.starts_with(&JPEG_SOI) {
+        return encoded;
+    }
+    let segment_length = (comment.len() + 2) as u16;
+
+    let mut output = Vec::with_capacity(encoded.len() + comment.len() + 4);
+    output.extend_from_slice(&JPEG_SOI);
+    output.extend_from_slice(&[0xFF, JPEG_COM]);
+    output.extend_from_slice(&segment_length.to_be_bytes());
+    output.extend_from_slice(comment);
+    output.extend_from_slice(&encoded[JPEG_SOI.len()..]);
+    output
+}
+
+fn find_jpeg_comment(bytes: &[u8]) -> Option<&[u8]> {
+    let mut offset = JPEG_SOI.len();
+    while offset + 4 <= bytes.len() {
+        if bytes[offset] != 0xFF {
+            return None;
+        }
+        let marker = bytes[offset + 1];
+        if marker == JPEG_SOS {
+            return None;
+        }
+        let length = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        let data_start = offset + 4;
+        let data_end = (offset + 2 + length).min(bytes.len());
+        if marker == JPEG_COM && length >= 2 {
+            return Some(&bytes[data_start..data_end]);
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+fn embed_png_text(encoded: Vec<u8>, text: &[u8]) -> Vec<u8> {
+    // IHDR always directly follows the signature and is 13 bytes of data plus the 12 byte
+    // length/type/crc envelope
+    let ihdr_end = PNG_SIGNATURE.len() + 12 + 13;
+    if !encoded.starts_with(&PNG_SIGNATURE) || encoded.len() < ihdr_end {
+        return encoded;
+    }
+
+    let mut data = Vec::with_capacity(PNG_TEXT_KEYWORD.len() + 1 + text.len());
+    data.extend_from_slice(PNG_TEXT_KEYWORD);
+    data.push(0);
+    data.extend_from_slice(text);
+
+    let mut output = Vec::with_capacity(encoded.len() + data.len() + 12);
+    output.extend_from_slice(&encoded[..ihdr_end]);
+    output.extend_from_slice(&png_chunk(b"tEXt", &data));
+    output.extend_from_slice(&encoded[ihdr_end..]);
+    output
+}
+
+pub fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+    chunk
+}
+
+fn find_png_text(bytes: &[u8]) -> Option<&[u8]> {
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        let chunk_type = &bytes[offset + 4..offset + 8];
+        let data_start = offset + 8;
+        let data_end = (data_start + length).min(bytes.len());
+        if chunk_type == b"IDAT" {
+            return None;
+        }
+        if chunk_type == b"tEXt" {
+            let data = &bytes[data_start..data_end];
+            if let Some(separator) = data.iter().position(|&b| b == 0) {
+                if &data[..separator] == PNG_TEXT_KEYWORD {
+                    return Some(&data[separator + 1..]);
+                }
+            }
+        }
+        offset = data_end + 4;
+    }
+    None
+}
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use tracing::{info, warn};
+
+use crate::{cropping, output, post_processing, OutputTarget};
+
+/// Function to write a copy of an image with every face obscured, in place of its crops.
+///
+/// # Arguments
+///
+/// * `faces_to_redact` - The image and the faces to obscure in it.
+/// * `redact_params` - How faces are obscured.
+/// * `target` - Where and how the copy is written.
+/// * `outputs` - The outputs of the input, which the copy is added to.
+///
+/// # Returns
+///
+/// * The number of faces redacted, or 0 if the copy wasn't written.
+pub fn redact_faces<P>(
+    faces_to_redact: cropping::CropInputs<P>,
+    redact_params: &post_processing::RedactParams,
+    target: &OutputTarget,
+    outputs: &mut Vec<PathBuf>,
+) -> usize
+where
+    P: image::Pixel<Subpixel = u8> + image::PixelWithColorType + 'static,
+{
+    if faces_to_redact.faces.is_empty() {
+        warn!(
+            "No faces to redact in image {}. Skipping",
+            target.image_name
+        );
+        return 0;
+    }
+
+    let regions: Vec<_> = faces_to_redact.faces.iter().map(|face| face.rect).collect();
+    let output_image =
+        post_processing::redact_regions(faces_to_redact.input_image, &regions, redact_params);
+    let output_path =
+        match output::resolve_conflict(&target.path("redacted"), target.on_conflict, |path| {
+            target.output_storage.exists(path)
+        }) {
+            Some(output_path) => output_path,
+            None => {
+                info!(
+                    "Redacted copy of image {} already exists. Skipping",
+                    target.image_name
+                );
+                return 0;
+            }
+        };
+    output::save_image_with_quality(&output_image, &output_path, target.format, target.quality);
+    info!(
+        "Saved {} redacted faces in image {} to {}",
+        regions.len(),
+        target.image_name,
+        output_path.display()
+    );
+    outputs.push(output_path);
+    regions.len()
+}
@@ -0,0 +1,104 @@
+use rust_faces::Rect;
+
+/// What happened to a detected face.
+#[derive(Copy, Clone, Debug)]
+pub enum FaceOutcome {
+    Written,
+    /// Already written by the run being resumed.
+    Resumed,
+    /// Filtered out for being smaller than the requested size.
+    TooSmall,
+    /// Not written as the crop limit was reached.
+    OverLimit,
+    /// Not written as the output file already exists.
+    Exists,
+    /// Not written as it is a near-duplicate of an earlier crop.
+    Duplicate,
+    /// Not written as it ranks below every crop kept by the run.
+    Outranked,
+}
+
+impl FaceOutcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FaceOutcome::Written => "written",
+            FaceOutcome::Resumed => "resumed",
+            FaceOutcome::TooSmall => "too small",
+            FaceOutcome::OverLimit => "over limit",
+            FaceOutcome::Exists => "exists",
+            FaceOutcome::Duplicate => "duplicate",
+            FaceOutcome::Outranked => "outranked",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FaceRow {
+    pub index: usize,
+    pub face: Rect,
+    pub confidence: f32,
+    pub crop: Rect,
+    pub outcome: FaceOutcome,
+}
+
+/// Function to render the faces of an image as an aligned ASCII table, one row per face.
+///
+/// # Arguments
+///
+/// * `rows` - The faces in detection order.
+///
+/// # Returns
+///
+/// * The table, starting with a header line.
+pub fn face_table(rows: &[FaceRow]) -> String {
+    let header = [
+        "face".to_string(),
+        "bbox".to_string(),
+        "conf".to_string(),
+        "crop".to_string(),
+        "outcome".to_string(),
+    ];
+    let cells: Vec<[String; 5]> = std::iter::once(header)
+        .chain(rows.iter().map(|row| {
+            [
+                row.index.to_string(),
+                format_rect(&row.face),
+                format!("{:.3}", row.confidence),
+                format_rect(&row.crop),
+                row.outcome.name().to_string(),
+            ]
+        }))
+        .collect();
+
+    let mut widths = [0; 5];
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    cells
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Formats a rect as `x,y wxh` in whole pixels.
+fn format_rect(rect: &Rect) -> String {
+    format!(
+        "{},{} {}x{}",
+        rect.x.round(),
+        rect.y.round(),
+        rect.width.round(),
+        rect.height.round()
+    )
+}
@@ -0,0 +1,305 @@
+use std::{io::IsTerminal, path::Path};
+
+use image::{imageops::FilterType, RgbImage};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    attributes::Gender,
+    caption,
+    manifest::{self, CropRecord, Manifest},
+};
+
+/// Keybindings shown below the crop.
+const HELP: &str = "←/→ previous/next  k keep  r reject  m move  u undo  q quit";
+
+/// What the reviewer decided about a crop.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewDecision {
+    Kept,
+    Rejected,
+    /// Moved to the move directory, e.g. to sort it out by hand.
+    Moved,
+}
+
+/// Counts of the decisions in a reviewed manifest.
+#[derive(Debug, Default)]
+pub struct ReviewCounts {
+    pub kept: usize,
+    pub rejected: usize,
+    pub moved: usize,
+    pub undecided: usize,
+}
+
+struct Review<'a> {
+    output_dir: &'a Path,
+    move_dir: &'a Path,
+    manifest: Manifest,
+    /// Index of the crop shown.
+    index: usize,
+    /// Outcome of the last action, shown below the keybindings.
+    status: String,
+}
+
+/// Function to review the crops of an output directory one at a time in the terminal, recording
+/// the decisions in its manifest as they are made.
+///
+/// # Arguments
+///
+/// * `output_dir` - The output directory of the run to review.
+/// * `move_dir` - Directory moved crops are put in, relative to the output directory.
+///
+/// # Returns
+///
+/// * The decisions in the manifest once the review is quit.
+pub fn review(output_dir: &Path, move_dir: &Path) -> ReviewCounts {
+    if !std::io::stdout().is_terminal() {
+        panic!("Reviewing crops needs an interactive terminal");
+    }
+    let mut manifest = manifest::load(output_dir)
+        .unwrap_or_else(|| panic!("Failed to read manifest of {}", output_dir.display()));
+    if manifest.signature.take().is_some() {
+        warn!(
+            "The manifest is signed. Reviewing it removes the signature, which no longer matches"
+        );
+    }
+    let index = manifest
+        .crops
+        .iter()
+        .position(|crop| crop.review.is_none())
+        .unwrap_or(0);
+    let mut review = Review {
+        output_dir,
+        move_dir,
+        manifest,
+        index,
+        status: String::new(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = review.run(&mut terminal);
+    ratatui::restore();
+    result.unwrap_or_else(|error| panic!("Failed to run review: {}", error));
+
+    let mut counts = ReviewCounts::default();
+    for crop in &review.manifest.crops {
+        match crop.review {
+            Some(ReviewDecision::Kept) => counts.kept += 1,
+            Some(ReviewDecision::Rejected) => counts.rejected += 1,
+            Some(ReviewDecision::Moved) => counts.moved += 1,
+            None => counts.undecided += 1,
+        }
+    }
+    counts
+}
+
+impl Review<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let count = self.manifest.crops.len();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Left | KeyCode::Up => self.index = self.index.saturating_sub(1),
+                KeyCode::Right | KeyCode::Down => {
+                    self.index = (self.index + 1).min(count.saturating_sub(1))
+                }
+                KeyCode::Char('k') if count > 0 => self.decide(Some(ReviewDecision::Kept)),
+                KeyCode::Char('r') if count > 0 => self.decide(Some(ReviewDecision::Rejected)),
+                KeyCode::Char('m') if count > 0 => self.move_crop(),
+                KeyCode::Char('u') if count > 0 => self.decide(None),
+                _ => (),
+            }
+        }
+    }
+
+    /// Records a decision about the crop shown and moves on to the next one. Moved crops stay
+    /// moved.
+    fn decide(&mut self, decision: Option<ReviewDecision>) {
+        let crop = &mut self.manifest.crops[self.index];
+        if crop.review == Some(ReviewDecision::Moved) {
+            self.status = format!("{} was already moved", crop.path.display());
+            return;
+        }
+        crop.review = decision;
+        self.status = match decision {
+            Some(ReviewDecision::Kept) => format!("Kept {}", crop.path.display()),
+            Some(ReviewDecision::Rejected) => format!("Rejected {}", crop.path.display()),
+            _ => format!("Cleared the decision on {}", crop.path.display()),
+        };
+        manifest::save(self.output_dir, &self.manifest);
+        if decision.is_some() {
+            self.index = (self.index + 1).min(self.manifest.crops.len() - 1);
+        }
+    }
+
+    /// Moves the crop shown and its sidecars to the move directory and moves on to the next one.
+    fn move_crop(&mut self) {
+        let crop = &mut self.manifest.crops[self.index];
+        if crop.review == Some(ReviewDecision::Moved) {
+            self.status = format!("{} was already moved", crop.path.display());
+            return;
+        }
+        let moved_path = self.move_dir.join(&crop.path);
+        let (from, to) = (
+            self.output_dir.join(&crop.path),
+            self.output_dir.join(&moved_path),
+        );
+        let moved = to
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::rename(&from, &to));
+        if let Err(error) = moved {
+            self.status = format!("Failed to move {}: {}", crop.path.display(), error);
+            return;
+        }
+        for sidecar_path in [manifest::landmarks_sidecar_path, caption::caption_path] {
+            let _ = std::fs::rename(sidecar_path(&from), sidecar_path(&to));
+        }
+        self.status = format!("Moved {} to {}", crop.path.display(), moved_path.display());
+        crop.path = moved_path;
+        crop.review = Some(ReviewDecision::Moved);
+        manifest::save(self.output_dir, &self.manifest);
+        self.index = (self.index + 1).min(self.manifest.crops.len() - 1);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(2)]).areas(frame.area());
+        let [preview_area, details_area] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main);
+
+        let Some(crop) = self.manifest.crops.get(self.index) else {
+            frame.render_widget(Paragraph::new("The manifest has no crops"), main);
+            frame.render_widget(Paragraph::new(HELP), footer);
+            return;
+        };
+        let preview_block = Block::default().borders(Borders::ALL).title(format!(
+            " {} of {} ",
+            self.index + 1,
+            self.manifest.crops.len()
+        ));
+        let inner = preview_block.inner(preview_area);
+        let preview = match image::open(self.output_dir.join(&crop.path)) {
+            Ok(crop_image) => preview_lines(&crop_image.into_rgb8(), inner.width, inner.height),
+            Err(_) => vec![Line::from("The crop can't be read")],
+        };
+        frame.render_widget(Paragraph::new(preview).block(preview_block), preview_area);
+        frame.render_widget(
+            Paragraph::new(detail_lines(crop))
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title(" Crop ")),
+            details_area,
+        );
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from(HELP),
+                Line::from(Span::styled(
+                    self.status.as_str(),
+                    Style::default().add_modifier(Modifier::DIM),
+                )),
+            ]),
+            footer,
+        );
+    }
+}
+
+/// Function to render an image with half blocks, each cell showing two pixels stacked as the
+/// colors of its upper and lower half, so terminals without an image protocol can preview it.
+///
+/// # Arguments
+///
+/// * `image` - The image.
+/// * `width` - Width of the area to fit the preview in, in cells.
+/// * `height` - Height of the area to fit the preview in, in cells.
+///
+/// # Returns
+///
+/// * A line per row of cells.
+fn preview_lines(image: &RgbImage, width: u16, height: u16) -> Vec<Line<'static>> {
+    let (max_width, max_height) = (width as f32, height as f32 * 2.0);
+    let scale = (max_width / image.width() as f32).min(max_height / image.height() as f32);
+    let (preview_width, preview_height) = (
+        ((image.width() as f32 * scale) as u32).max(1),
+        ((image.height() as f32 * scale) as u32).max(1),
+    );
+    let preview =
+        image::imageops::resize(image, preview_width, preview_height, FilterType::Triangle);
+    let color = |x: u32, y: u32| {
+        let [r, g, b] = preview.get_pixel(x, y.min(preview_height - 1)).0;
+        Color::Rgb(r, g, b)
+    };
+    (0..preview_height)
+        .step_by(2)
+        .map(|y| {
+            Line::from(
+                (0..preview_width)
+                    .map(|x| {
+                        Span::styled("▀", Style::default().fg(color(x, y)).bg(color(x, y + 1)))
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect()
+}
+
+fn detail_lines(crop: &CropRecord) -> Vec<Line<'static>> {
+    let decision = match crop.review {
+        Some(ReviewDecision::Kept) => "kept",
+        Some(ReviewDecision::Rejected) => "rejected",
+        Some(ReviewDecision::Moved) => "moved",
+        None => "undecided",
+    };
+    let mut lines = vec![
+        format!("Decision: {}", decision),
+        format!("Path: {}", crop.path.display()),
+        format!("Source: {}", crop.source.display()),
+        format!("Face: {}", crop.face),
+        format!("Confidence: {:.3}", crop.confidence),
+        format!(
+            "Face box: {:.0}x{:.0} at {:.0}, {:.0}",
+            crop.face_rect.width, crop.face_rect.height, crop.face_rect.x, crop.face_rect.y
+        ),
+        format!("Size: {}", crop.size_bucket.name()),
+    ];
+    if let Some(attributes) = &crop.attributes {
+        if let Some(age) = attributes.age {
+            lines.push(format!("Age: {:.0}", age));
+        }
+        if let Some(gender) = attributes.gender {
+            lines.push(match gender {
+                Gender::Female => "Gender: female".to_string(),
+                Gender::Male => "Gender: male".to_string(),
+            });
+        }
+        if let Some(emotion) = attributes.emotion {
+            lines.push(format!("Emotion: {}", emotion.name()));
+        }
+        if let Some(occluded) = attributes.occluded {
+            lines.push(format!("Occluded: {:.2}", occluded));
+        }
+    }
+    if let Some(head_pose) = &crop.head_pose {
+        lines.push(format!(
+            "Head pose: yaw {:.0}°, pitch {:.0}°, roll {:.0}°",
+            head_pose.yaw, head_pose.pitch, head_pose.roll
+        ));
+    }
+    lines.into_iter().map(Line::from).collect()
+}
@@ -0,0 +1,150 @@
+use rust_faces::{Face, FaceDetector, Rect, RustFacesResult, ToArray3};
+
+use crate::cropping;
+
+/// Overlap, relative to the smaller box, above which detections in differently rotated copies
+/// are taken to be the same face.
+const ROTATION_MERGE_OVERLAP: f32 = 0.5;
+
+/// Clockwise rotations of the image the faces are detected in.
+const ROTATIONS: [u32; 4] = [0, 90, 180, 270];
+
+/// Face detector that also detects in copies of the image rotated by 90, 180 and 270 degrees, so
+/// faces lying sideways or upside down, as in scanned prints, are found. The detections are
+/// rotated back to the original orientation and the duplicates of a face found in several copies
+/// are merged, keeping the most confident.
+pub struct RotatingDetector {
+    detector: Box<dyn FaceDetector>,
+}
+
+impl RotatingDetector {
+    pub fn new(detector: Box<dyn FaceDetector>) -> Self {
+        Self { detector }
+    }
+}
+
+impl FaceDetector for RotatingDetector {
+    fn detect(&self, image: ndarray::ArrayViewD<u8>) -> RustFacesResult<Vec<Face>> {
+        let original = cropping::array_to_image(&image);
+        let (width, height) = (original.width() as f32, original.height() as f32);
+        let mut faces = Vec::new();
+        for rotation in ROTATIONS {
+            let rotated = match rotation {
+                0 => original.clone(),
+                90 => image::imageops::rotate90(&original),
+                180 => image::imageops::rotate180(&original),
+                _ => image::imageops::rotate270(&original),
+            };
+            let rotated_faces = self
+                .detector
+                .detect(rotated.into_array3().view().into_dyn())?;
+            faces.extend(
+                rotated_faces
+                    .into_iter()
+                    .map(|face| rotate_back(face, rotation, width, height)),
+            );
+        }
+
+        Ok(cropping::merge_overlapping(faces, ROTATION_MERGE_OVERLAP))
+    }
+}
+
+/// Function to map a face detected in a rotated copy of an image back to the image.
+///
+/// # Arguments
+///
+/// * `face` - The face in the rotated copy.
+/// * `rotation` - Clockwise rotation of the copy in degrees.
+/// * `width` - Width of the original image.
+/// * `height` - Height of the original image.
+///
+/// # Returns
+///
+/// * The face in the original image.
+fn rotate_back(face: Face, rotation: u32, width: f32, height: f32) -> Face {
+    let point = |x: f32, y: f32| match rotation {
+        90 => (y, height - x),
+        180 => (width - x, height - y),
+        270 => (width - y, x),
+        _ => (x, y),
+    };
+    let Rect {
+        x,
+        y,
+        width: rect_width,
+        height: rect_height,
+    } = face.rect;
+    // the corners swap places under rotation, so the box spans the rotated opposite corners
+    let (x1, y1) = point(x, y);
+    let (x2, y2) = point(x + rect_width, y + rect_height);
+    Face {
+        rect: Rect {
+            x: x1.min(x2),
+            y: y1.min(y2),
+            width: (x2 - x1).abs(),
+            height: (y2 - y1).abs(),
+        },
+        confidence: face.confidence,
+        landmarks: face.landmarks.map(|landmarks| {
+            landmarks
+                .into_iter()
+                .map(|(landmark_x, landmark_y)| point(landmark_x, landmark_y))
+                .collect()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Function to map a point of a 200x100 image to where it is in a copy rotated clockwise.
+    fn rotate(x: f32, y: f32, rotation: u32) -> (f32, f32) {
+        match rotation {
+            90 => (100.0 - y, x),
+            180 => (200.0 - x, 100.0 - y),
+            270 => (y, 200.0 - x),
+            _ => (x, y),
+        }
+    }
+
+    fn rect_of(face: &Face) -> (f32, f32, f32, f32) {
+        (face.rect.x, face.rect.y, face.rect.width, face.rect.height)
+    }
+
+    #[test]
+    fn rotate_back_maps_a_sideways_face_to_the_image() {
+        // a face at (10, 20) sized 30x40 lies at (40, 10) sized 40x30 once the image is rotated
+        // clockwise
+        let face = Face {
+            rect: Rect::at(40.0, 10.0).with_size(40.0, 30.0),
+            confidence: 0.9,
+            landmarks: Some(vec![(75.0, 15.0)]),
+        };
+        let face = rotate_back(face, 90, 200.0, 100.0);
+        assert_eq!(rect_of(&face), (10.0, 20.0, 30.0, 40.0));
+        assert_eq!(face.landmarks, Some(vec![(15.0, 25.0)]));
+        assert_eq!(face.confidence, 0.9);
+    }
+
+    #[test]
+    fn rotate_back_undoes_every_rotation() {
+        for rotation in ROTATIONS {
+            let (x1, y1) = rotate(10.0, 20.0, rotation);
+            let (x2, y2) = rotate(40.0, 60.0, rotation);
+            let face = Face {
+                rect: Rect::at(x1.min(x2), y1.min(y2)).with_size((x2 - x1).abs(), (y2 - y1).abs()),
+                confidence: 0.9,
+                landmarks: Some(vec![rotate(15.0, 25.0, rotation)]),
+            };
+            let face = rotate_back(face, rotation, 200.0, 100.0);
+            assert_eq!(
+                rect_of(&face),
+                (10.0, 20.0, 30.0, 40.0),
+                "rotation {}",
+                rotation
+            );
+            assert_eq!(face.landmarks, Some(vec![(15.0, 25.0)]));
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{cmp::Ordering, cmp::Reverse, collections::BinaryHeap, path::PathBuf};
+
+use image::{GenericImageView, Pixel};
+
+/// A written crop and the score it is ranked by.
+struct Ranked {
+    score: f32,
+    path: PathBuf,
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score)
+    }
+}
+
+/// The highest scoring crops of a run, so a run can keep a fixed number of crops without
+/// knowing the scores of the crops still to come.
+pub struct TopCrops {
+    capacity: usize,
+    // lowest score first, so it is the one replaced
+    crops: BinaryHeap<Reverse<Ranked>>,
+}
+
+/// Whether a crop made it into the top crops.
+pub enum Admission {
+    /// The crop is in, replacing the crop at the given path if there were enough already.
+    Admitted(Option<PathBuf>),
+    /// The crop scores lower than every crop kept.
+    Rejected,
+}
+
+impl TopCrops {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            crops: BinaryHeap::new(),
+        }
+    }
+
+    /// Function to check whether a crop would be kept, without admitting it.
+    pub fn would_admit(&self, score: f32) -> bool {
+        self.crops.len() < self.capacity
+            || self
+                .crops
+                .peek()
+                .is_some_and(|Reverse(lowest)| score > lowest.score)
+    }
+
+    /// Function to admit a crop once it has been written.
+    ///
+    /// # Arguments
+    ///
+    /// * `score` - The score of the crop.
+    /// * `path` - The path of the crop, relative to the output directory.
+    ///
+    /// # Returns
+    ///
+    /// * Whether the crop was admitted and the crop it replaced, whose file should be removed.
+    pub fn admit(&mut self, score: f32, path: PathBuf) -> Admission {
+        if !self.would_admit(score) {
+            return Admission::Rejected;
+        }
+        let replaced = match self.crops.len() < self.capacity {
+            true => None,
+            false => self.crops.pop().map(|Reverse(lowest)| lowest.path),
+        };
+        self.crops.push(Reverse(Ranked { score, path }));
+        Admission::Admitted(replaced)
+    }
+}
+
+/// Function to measure the sharpness of an image as the variance of its Laplacian. Blurry images
+/// have few edges and so a low variance.
+///
+/// # Arguments
+///
+/// * `image` - The image to measure.
+///
+/// # Returns
+///
+/// * The variance, higher for sharper images.
+pub fn sharpness<I>(image: &I) -> f32
+where
+    I: GenericImageView,
+    I::Pixel: Pixel<Subpixel = u8> + 'static,
+{
+    let gray = image::imageops::grayscale(image);
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let value = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f32;
+    let laplacians: Vec<f32> = (1..height - 1)
+        .flat_map(|y| (1..width - 1).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            value(x - 1, y) + value(x + 1, y) + value(x, y - 1) + value(x, y + 1)
+                - 4.0 * value(x, y)
+        })
+        .collect();
+    let mean = laplacians.iter().sum::<f32>() / laplacians.len() as f32;
+    laplacians
+        .iter()
+        .map(|laplacian| (laplacian - mean).powi(2))
+        .sum::<f32>()
+        / laplacians.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replaced(admission: Admission) -> Option<PathBuf> {
+        match admission {
+            Admission::Admitted(replaced) => replaced,
+            Admission::Rejected => panic!("Crop was rejected"),
+        }
+    }
+
+    #[test]
+    fn top_crops_admit_until_full() {
+        let mut top_crops = TopCrops::new(2);
+        assert!(top_crops.would_admit(0.1));
+        assert_eq!(replaced(top_crops.admit(0.5, PathBuf::from("a.jpg"))), None);
+        assert_eq!(replaced(top_crops.admit(0.1, PathBuf::from("b.jpg"))), None);
+        assert!(!top_crops.would_admit(0.1));
+        assert!(matches!(
+            top_crops.admit(0.05, PathBuf::from("c.jpg")),
+            Admission::Rejected
+        ));
+    }
+
+    #[test]
+    fn top_crops_replace_the_lowest_scoring_crop() {
+        let mut top_crops = TopCrops::new(2);
+        top_crops.admit(0.5, PathBuf::from("a.jpg"));
+        top_crops.admit(0.1, PathBuf::from("b.jpg"));
+        assert_eq!(
+            replaced(top_crops.admit(0.9, PathBuf::from("c.jpg"))),
+            Some(PathBuf::from("b.jpg"))
+        );
+        assert_eq!(
+            replaced(top_crops.admit(0.7, PathBuf::from("d.jpg"))),
+            Some(PathBuf::from("a.jpg"))
+        );
+        // a tie with the lowest kept crop doesn't replace it
+        assert!(!top_crops.would_admit(0.7));
+        assert!(top_crops.would_admit(0.8));
+    }
+
+    #[test]
+    fn top_crops_of_no_capacity_admit_nothing() {
+        let mut top_crops = TopCrops::new(0);
+        assert!(!top_crops.would_admit(1.0));
+        assert!(matches!(
+            top_crops.admit(1.0, PathBuf::from("a.jpg")),
+            Admission::Rejected
+        ));
+    }
+}
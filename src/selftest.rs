@@ -0,0 +1,196 @@
+use std::time::Instant;
+
+use rust_faces::{Face, InferParams, Rect};
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::cropping;
+
+/// Sample images bundled into the binary so the self test works from any install location.
+const SAMPLES: [(&str, &[u8]); 2] = [
+    ("example.png", include_bytes!("../assets/example.png")),
+    ("banner.png", include_bytes!("../assets/banner.png")),
+];
+const EXPECTATIONS: &str = include_str!("../assets/selftest/expectations.json");
+
+/// Minimum IoU between a detection and a reference face for the reference to count as found.
+/// The reference boxes are approximate so this is deliberately loose.
+const MIN_REFERENCE_IOU: f32 = 0.3;
+const ASPECT_RATIO_TOLERANCE: f32 = 0.02;
+
+#[derive(Debug, Deserialize)]
+struct Expectations {
+    crop: CropExpectations,
+    samples: Vec<SampleExpectations>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CropExpectations {
+    aspect_ratio: f32,
+    proportion_of_face: f32,
+    top_padding: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct SampleExpectations {
+    name: String,
+    min_faces: usize,
+    max_faces: usize,
+    reference_faces: Vec<ReferenceFace>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReferenceFace {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+/// Function to run the bundled samples through every face detector and check the detections
+/// and crop geometry against the stored expectations.
+///
+/// # Returns
+///
+/// * True if every detector passed.
+pub fn run() -> bool {
+    let expectations: Expectations = serde_json::from_str(EXPECTATIONS)
+        .unwrap_or_else(|_| panic!("Failed to parse self test expectations"));
+    let crop_params = cropping::CropParams {
+        top_padding: expectations.crop.top_padding,
+        bottom_padding: 0.0,
+        left_padding: 0.0,
+        right_padding: 0.0,
+        face_anchor: None,
+        compose: cropping::Compose::Centered,
+        dims_multiple_of: None,
+        kind: cropping::CropParamsKind::Relative(cropping::RelativeCrop {
+            aspect_ratio: expectations.crop.aspect_ratio,
+            proportion_of_face: expectations.crop.proportion_of_face,
+        }),
+    };
+    let samples: Vec<_> = SAMPLES
+        .iter()
+        .map(|(name, bytes)| {
+            let image = image::load_from_memory(bytes)
+                .unwrap_or_else(|_| panic!("Failed to decode bundled sample {}", name))
+                .into_rgb8();
+            (*name, image)
+        })
+        .collect();
+
+    let mut passed = true;
+    for model in cropping::FACE_DETECTOR_MODELS {
+        let build_start = Instant::now();
+        let face_detector = match cropping::build_face_detector(model, InferParams::default()) {
+            Ok(face_detector) => face_detector,
+            Err(error) => {
+                error!("FAIL {:?}: failed to build detector: {}", model, error);
+                passed = false;
+                continue;
+            }
+        };
+        let build_time = build_start.elapsed();
+
+        let mut failures = Vec::new();
+        let detect_start = Instant::now();
+        for (name, image) in &samples {
+            let expected = expectations
+                .samples
+                .iter()
+                .find(|sample| sample.name == *name)
+                .unwrap_or_else(|| panic!("Missing self test expectations for {}", name));
+            let faces = cropping::detect_faces_in_image(image, &*face_detector);
+            failures.extend(
+                check_sample(image, &faces, expected, &crop_params)
+                    .into_iter()
+                    .map(|failure| format!("{}: {}", name, failure)),
+            );
+        }
+        let detect_time = detect_start.elapsed();
+        let throughput = samples.len() as f64 / detect_time.as_secs_f64();
+
+        match failures.is_empty() {
+            true => info!(
+                "PASS {:?}: built in {:.2?}, {:.2} images/s",
+                model, build_time, throughput
+            ),
+            false => {
+                passed = false;
+                error!("FAIL {:?}: {:.2} images/s", model, throughput);
+                for failure in failures {
+                    warn!("  {}", failure);
+                }
+            }
+        }
+    }
+
+    passed
+}
+
+fn check_sample(
+    image: &image::RgbImage,
+    faces: &[Face],
+    expected: &SampleExpectations,
+    crop_params: &cropping::CropParams,
+) -> Vec<String> {
+    let mut failures = Vec::new();
+    if faces.len() < expected.min_faces || faces.len() > expected.max_faces {
+        failures.push(format!(
+            "expected {}-{} faces but detected {}",
+            expected.min_faces,
+            expected.max_faces,
+            faces.len()
+        ));
+    }
+
+    for reference in &expected.reference_faces {
+        let reference =
+            Rect::at(reference.x, reference.y).with_size(reference.width, reference.height);
+        let best_iou = faces
+            .iter()
+            .map(|face| face.rect.iou(&reference))
+            .fold(0.0, f32::max);
+        if best_iou < MIN_REFERENCE_IOU {
+            failures.push(format!(
+                "reference face {} not found (best IoU {:.2})",
+                reference, best_iou
+            ));
+        }
+    }
+
+    let image_rect = Rect::at(0.0, 0.0).with_size(image.width() as f32, image.height() as f32);
+    let expected_aspect_ratio = match &crop_params.kind {
+        cropping::CropParamsKind::Relative(relative) => relative.aspect_ratio,
+        cropping::CropParamsKind::Absolute(absolute) => {
+            absolute.width as f32 / absolute.height as f32
+        }
+    };
+    for face in faces {
+        let crop = cropping::calculate_face_crop(face, &image_rect, crop_params);
+        if crop.x < 0.0
+            || crop.y < 0.0
+            || crop.right() > image_rect.right()
+            || crop.bottom() > image_rect.bottom()
+        {
+            failures.push(format!("crop {} extends outside the image", crop));
+        }
+        let face_in_image = face.rect.intersection(&image_rect);
+        if face_in_image.intersection(&crop).width < face_in_image.width.min(crop.width) * 0.99 {
+            failures.push(format!("crop {} does not contain face {}", crop, face.rect));
+        }
+        let touches_edge = crop.x <= 0.0
+            || crop.y <= 0.0
+            || crop.right() >= image_rect.right()
+            || crop.bottom() >= image_rect.bottom();
+        let aspect_ratio = crop.width / crop.height;
+        if !touches_edge && (aspect_ratio - expected_aspect_ratio).abs() > ASPECT_RATIO_TOLERANCE {
+            failures.push(format!(
+                "crop {} has aspect ratio {:.3} instead of {:.3}",
+                crop, aspect_ratio, expected_aspect_ratio
+            ));
+        }
+    }
+
+    failures
+}
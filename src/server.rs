@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use tiny_http::{Header, Method, Server};
+use tracing::{error, info};
+
+use crate::{
+    archive, console, cropping, decoding, encode_face_crop, get_crop_params, get_detector_pool,
+    get_jpeg_options, get_output_format, get_post_process_params, manifest,
+    metrics::{self, Metrics},
+    post_processing, Args,
+};
+
+/// Content type of the Prometheus text exposition format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+/// Seconds a client is asked to wait before retrying a request rejected because the queue is full.
+const RETRY_AFTER_SECONDS: &str = "1";
+
+/// A request, with its body read into memory.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: format!("{}\n", message).into_bytes(),
+        }
+    }
+}
+
+/// Function to serve HTTP requests until the program is interrupted.
+///
+/// # Arguments
+///
+/// * `address` - The address to listen on, e.g. `127.0.0.1:8080`.
+/// * `max_concurrent` - The number of requests handled at once.
+/// * `queue_depth` - The number of requests waiting for a free handler. Their bodies aren't read
+///   until a handler takes them, and requests beyond the queue are answered with a 429.
+/// * `metrics` - Metrics served at `GET /metrics`, which is answered even while the handlers are
+///   busy. Requests answered with a server error are counted as errors.
+/// * `handle` - Called with each other request. A panic while handling a request is answered with
+///   a 500 rather than stopping the server.
+pub fn serve<F>(
+    address: &str,
+    max_concurrent: usize,
+    queue_depth: usize,
+    metrics: &Metrics,
+    handle: F,
+) where
+    F: Fn(Request) -> Response + Sync,
+{
+    let server = Server::http(address)
+        .unwrap_or_else(|error| panic!("Failed to listen on {}: {}", address, error));
+    info!("Listening on http://{}", address);
+    let max_concurrent = max_concurrent.max(1);
+    // Requests queued or being handled.
+    let in_flight = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<tiny_http::Request>();
+    let receiver = Mutex::new(receiver);
+    thread::scope(|scope| {
+        for _ in 0..max_concurrent {
+            scope.spawn(|| loop {
+                let Ok(mut request) = receiver.lock().unwrap().recv() else {
+                    return;
+                };
+                let mut body = Vec::new();
+                let response = match request.as_reader().read_to_end(&mut body) {
+                    Ok(_) => {
+                        let (path, query) = parse_url(request.url());
+                        let request = Request {
+                            method: request.method().to_string(),
+                            path,
+                            query,
+                            body,
+                        };
+                        catch_unwind(AssertUnwindSafe(|| handle(request))).unwrap_or_else(|_| {
+                            Response::error(500, "Failed to process the request")
+                        })
+                    }
+                    Err(_) => Response::error(400, "Failed to read the request body"),
+                };
+                respond(request, response, metrics);
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        while let Ok(request) = server.recv() {
+            if request.method() == &Method::Get && request.url() == "/metrics" {
+                let response = Response::ok(METRICS_CONTENT_TYPE, metrics.render().into_bytes());
+                respond(request, response, metrics);
+            } else if in_flight.load(Ordering::SeqCst) >= max_concurrent + queue_depth {
+                metrics.add_rejected();
+                let response = Response::error(429, "Too many requests, try again later");
+                respond(request, response, metrics);
+            } else {
+                in_flight.fetch_add(1, Ordering::SeqCst);
+                let _ = sender.send(request);
+            }
+        }
+        drop(sender);
+    });
+}
+
+fn respond(request: tiny_http::Request, response: Response, metrics: &Metrics) {
+    if response.status >= 500 {
+        metrics.add_error();
+    }
+    info!(
+        "{} {} -> {}",
+        request.method(),
+        request.url(),
+        response.status
+    );
+    let mut reply = tiny_http::Response::from_data(response.body)
+        .with_status_code(response.status)
+        .with_header(Header::from_bytes("Content-Type", response.content_type).unwrap());
+    if response.status == 429 {
+        reply.add_header(Header::from_bytes("Retry-After", RETRY_AFTER_SECONDS).unwrap());
+    }
+    if let Err(error) = request.respond(reply) {
+        error!("Failed to send response: {}", error);
+    }
+}
+
+/// Splits a request URL into its path and query parameters. Parameters aren't percent-decoded as
+/// none of the endpoints take free text.
+fn parse_url(url: &str) -> (String, HashMap<String, String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect();
+    (path.to_string(), query)
+}
+
+/// Function to serve detection and cropping over HTTP until the program is interrupted.
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the run, for the detector and crop settings.
+/// * `address` - The address to listen on.
+pub fn run(args: &Args, address: &str, max_concurrent: usize, queue_depth: usize) {
+    let crop_params = get_crop_params(args);
+    let post_process_params = get_post_process_params(args);
+    info!("Instantiating face detector{}", console::symbol("🤖"));
+    let face_detector = get_detector_pool(args, true);
+    let metrics = Metrics::default();
+    serve(address, max_concurrent, queue_depth, &metrics, |request| {
+        handle_request(
+            args,
+            &face_detector,
+            &crop_params,
+            &post_process_params,
+            &metrics,
+            request,
+        )
+    });
+}
+
+fn handle_request(
+    args: &Args,
+    face_detector: &dyn rust_faces::FaceDetector,
+    crop_params: &cropping::CropParams,
+    post_process_params: &post_processing::PostProcessParams,
+    metrics: &Metrics,
+    request: Request,
+) -> Response {
+    if request.method != "POST" || !["/detect", "/crop"].contains(&request.path.as_str()) {
+        return Response::error(404, "Not found");
+    }
+    let started = Instant::now();
+    let input_image = match decoding::decode(&request.body) {
+        Ok(input_image) => input_image,
+        Err(_) => return Response::error(400, "Failed to decode image"),
+    };
+    let has_alpha = input_image.color().has_alpha();
+    let detection_image = match has_alpha {
+        true => cropping::flatten_alpha(&input_image.to_rgba8(), args.alpha_background),
+        false => input_image.to_rgb8(),
+    };
+    metrics.observe(metrics::Stage::Decode, started.elapsed());
+    let started = Instant::now();
+    let faces = cropping::detect_faces_in_image(&detection_image, face_detector);
+    metrics.observe(metrics::Stage::Detect, started.elapsed());
+    metrics.add_image(faces.len());
+
+    if request.path == "/detect" {
+        let faces: Vec<_> = faces.iter().map(manifest::FaceRecord::from).collect();
+        let body = serde_json::to_vec(&faces)
+            .unwrap_or_else(|_| panic!("Failed to serialize detected faces"));
+        return Response::ok("application/json", body);
+    }
+
+    let (format, keep_alpha) = get_output_format(args, has_alpha);
+    if request.query.get("face").map(String::as_str) == Some("all") {
+        let started = Instant::now();
+        let mut entries = Vec::new();
+        let mut cropped = Vec::new();
+        for (index, face) in faces.iter().enumerate() {
+            let faces = vec![face.clone()];
+            let encoded = match keep_alpha {
+                true => encode_face_crop(
+                    &input_image.to_rgba8(),
+                    &faces,
+                    crop_params,
+                    post_process_params,
+                    format,
+                    args.quality,
+                    get_jpeg_options(args),
+                ),
+                false => encode_face_crop(
+                    &detection_image,
+                    &faces,
+                    crop_params,
+                    post_process_params,
+                    format,
+                    args.quality,
+                    get_jpeg_options(args),
+                ),
+            };
+            if let Some(encoded) = encoded {
+                entries.push((
+                    format!("face-{}.{}", index, format.extensions_str()[0]),
+                    encoded,
+                ));
+                cropped.push(manifest::FaceRecord::from(face));
+            }
+        }
+        let faces_json = serde_json::to_vec(&cropped)
+            .unwrap_or_else(|_| panic!("Failed to serialize detected faces"));
+        entries.push(("faces.json".to_string(), faces_json));
+        metrics.observe(metrics::Stage::Crop, started.elapsed());
+        return Response::ok("application/zip", archive::zip_entries(&entries));
+    }
+
+    let face_index = match request.query.get("face").map(|face| face.parse::<usize>()) {
+        Some(Ok(face_index)) => Some(face_index),
+        Some(Err(_)) => return Response::error(400, "Invalid face index"),
+        None => None,
+    };
+    let face = match (face_index, faces.len()) {
+        (Some(face_index), face_count) => match faces.get(face_index) {
+            Some(face) => face,
+            None => {
+                return Response::error(
+                    422,
+                    &format!(
+                        "Face index {} is out of range. Found {} faces",
+                        face_index, face_count
+                    ),
+                )
+            }
+        },
+        (None, 1) => &faces[0],
+        (None, face_count) => {
+            return Response::error(
+                422,
+                &format!(
+                    "Found {} faces. Use ?face=<index> to choose the face to crop",
+                    face_count
+                ),
+            )
+        }
+    };
+    let faces = vec![face.clone()];
+
+    let started = Instant::now();
+    let encoded = match keep_alpha {
+        true => encode_face_crop(
+            &input_image.into_rgba8(),
+            &faces,
+            crop_params,
+            post_process_params,
+            format,
+            args.quality,
+            get_jpeg_options(args),
+        ),
+        false => encode_face_crop(
+            &detection_image,
+            &faces,
+            crop_params,
+            post_process_params,
+            format,
+            args.quality,
+            get_jpeg_options(args),
+        ),
+    };
+    metrics.observe(metrics::Stage::Crop, started.elapsed());
+    match encoded {
+        Some(encoded) => Response::ok(format.to_mime_type(), encoded),
+        None => Response::error(422, "Cropped image is too small"),
+    }
+}
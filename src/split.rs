@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// A subset of the dataset the crops are divided into, e.g. `train`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Split {
+    /// Name of the split, which is also the folder its crops are written to.
+    pub name: String,
+    /// Share of the inputs routed to the split, relative to the other splits.
+    pub ratio: f32,
+}
+
+/// Function to pick the split the crops of an input are written to. Every crop of an input goes
+/// to the same split so faces from one photo don't end up on both sides, and the pick only
+/// depends on the seed and the path of the input so reruns route inputs the same way.
+///
+/// # Arguments
+///
+/// * `splits` - The splits, with at least one of a positive ratio.
+/// * `seed` - Seed of the assignment.
+/// * `source` - Path of the input, relative to the input directory.
+///
+/// # Returns
+///
+/// * The name of the split.
+pub fn assign<'a>(splits: &'a [Split], seed: u64, source: &Path) -> &'a str {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.to_le_bytes());
+    // separators are normalized so the assignment is the same on every platform
+    hasher.update(source.to_string_lossy().replace('\\', "/").as_bytes());
+    let digest = hasher.finalize();
+    let value = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    let position = (value >> 11) as f64 / (1u64 << 53) as f64;
+
+    let total: f64 = splits.iter().map(|split| split.ratio as f64).sum();
+    let mut cumulative = 0.0;
+    for split in splits {
+        cumulative += split.ratio as f64 / total;
+        if position < cumulative {
+            return &split.name;
+        }
+    }
+    &splits.last().unwrap().name
+}
@@ -0,0 +1,89 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::output;
+
+const STATE_FILE_NAME: &str = ".facecrop-state.json";
+
+/// Checkpoint of a run, written to the output directory so an interrupted or quota-limited run
+/// can be resumed. Inputs are processed in sorted order so everything before `next_input` is
+/// complete.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunState {
+    /// The first input that hasn't been fully processed, relative to the input root (the input
+    /// directory, or the directory of a single input file). Nothing is stored relative to the
+    /// working directory or as an absolute path, so a run can be resumed after the input and
+    /// output volumes are mounted elsewhere. None once the run is complete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_input: Option<PathBuf>,
+    /// Number of crops of `next_input` that were already written.
+    #[serde(default)]
+    pub crops_written: usize,
+    /// When the last complete run given `--newer-than` started, in seconds after the Unix epoch.
+    /// Kept while later runs are in progress, so an interrupted run doesn't move it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<u64>,
+}
+
+impl RunState {
+    pub fn new(
+        next_input: &Path,
+        input_root: &Path,
+        crops_written: usize,
+        last_run: Option<u64>,
+    ) -> Self {
+        Self {
+            next_input: Some(
+                next_input
+                    .strip_prefix(input_root)
+                    .unwrap_or(next_input)
+                    .to_path_buf(),
+            ),
+            crops_written,
+            last_run,
+        }
+    }
+
+    /// State of a complete run, recording when it started.
+    pub fn finished(started: SystemTime) -> Self {
+        Self {
+            next_input: None,
+            crops_written: 0,
+            last_run: Some(
+                started
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            ),
+        }
+    }
+
+    /// Path of the next input under the given input root.
+    pub fn next_input_path(&self, input_root: &Path) -> Option<PathBuf> {
+        self.next_input
+            .as_ref()
+            .map(|next_input| input_root.join(next_input))
+    }
+}
+
+pub fn state_path(output_dir: &Path) -> PathBuf {
+    output_dir.join(STATE_FILE_NAME)
+}
+
+pub fn load(output_dir: &Path) -> Option<RunState> {
+    let contents = std::fs::read_to_string(state_path(output_dir)).ok()?;
+    let state = serde_json::from_str(&contents)
+        .unwrap_or_else(|_| panic!("Failed to parse run state file"));
+    Some(state)
+}
+
+pub fn save(output_dir: &Path, state: &RunState) {
+    let contents = serde_json::to_string_pretty(state)
+        .unwrap_or_else(|_| panic!("Failed to serialize run state"));
+    output::write_atomically(&state_path(output_dir), contents)
+        .unwrap_or_else(|_| panic!("Failed to write run state file"));
+}
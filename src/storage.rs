@@ -0,0 +1,757 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use tracing::{info, warn};
+
+use crate::archive;
+
+/// Where the inputs of a run are read from or its outputs written to.
+pub trait Storage: fmt::Display + Send + Sync {
+    /// The local path the run reads from or writes to, a staging directory for object stores.
+    fn local_path(&self) -> &Path;
+
+    /// Function to list the inputs at the location without fetching them.
+    ///
+    /// # Arguments
+    ///
+    /// * `keep` - Whether a file is listed, given its path relative to the location.
+    ///
+    /// # Returns
+    ///
+    /// * The paths below the local path the inputs are fetched to, or None if the location is
+    ///   the local path itself, which is scanned instead.
+    fn list(&self, keep: &dyn Fn(&Path) -> bool) -> Option<Vec<PathBuf>>;
+
+    /// Function to fetch a file at the location to the local path if it isn't there yet, e.g. an
+    /// input about to be read or the manifest an earlier run left.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - Path of the file below the local path.
+    fn fetch(&self, local_path: &Path);
+
+    /// Function to drop the local copy of a fetched input once it has been processed.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - Path of the file below the local path.
+    fn release(&self, local_path: &Path);
+
+    /// Function to get when a file was last modified at the location, without fetching it.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - Path of the file below the local path.
+    fn modified(&self, local_path: &Path) -> Option<SystemTime>;
+
+    /// Function to check whether a file exists, either below the local path or at the location.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - Path of the file below the local path.
+    fn exists(&self, local_path: &Path) -> bool;
+
+    /// Function to remove a file from below the local path and from the location.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - Path of the file below the local path.
+    fn remove(&self, local_path: &Path) -> io::Result<()>;
+
+    /// Function to store outputs at the location as soon as they are written, e.g. the crops of
+    /// an input.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_paths` - Paths of the outputs below the local path.
+    fn store(&self, local_paths: &[PathBuf]);
+
+    /// Function to store the files that are rewritten as the run goes, e.g. its manifest, at the
+    /// location so an interrupted run can be resumed from there.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_paths` - Paths of the files below the local path.
+    fn checkpoint(&self, local_paths: &[PathBuf]);
+
+    /// Function to store whatever else was written to the local path at the location once the
+    /// run finishes.
+    fn finish(&self);
+
+    /// Function to get where a file staged at the local path lives.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_path` - Path of the file below the local path.
+    ///
+    /// # Returns
+    ///
+    /// * The URL of the file at the location, or None if the location is the local path itself.
+    fn url(&self, local_path: &Path) -> Option<String>;
+}
+
+impl fmt::Debug for dyn Storage + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Function to get when a local file was last modified.
+fn local_modified(local_path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(local_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Function to remove a local file, if it exists.
+fn remove_local(local_path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(local_path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        removed => removed,
+    }
+}
+
+/// A file or directory on the local file system, read and written in place.
+pub struct LocalStorage {
+    path: PathBuf,
+}
+
+impl Storage for LocalStorage {
+    fn local_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn list(&self, _keep: &dyn Fn(&Path) -> bool) -> Option<Vec<PathBuf>> {
+        None
+    }
+
+    fn fetch(&self, _local_path: &Path) {}
+
+    fn release(&self, _local_path: &Path) {}
+
+    fn modified(&self, local_path: &Path) -> Option<SystemTime> {
+        local_modified(local_path)
+    }
+
+    fn exists(&self, local_path: &Path) -> bool {
+        local_path.exists()
+    }
+
+    fn remove(&self, local_path: &Path) -> io::Result<()> {
+        remove_local(local_path)
+    }
+
+    fn store(&self, _local_paths: &[PathBuf]) {}
+
+    fn checkpoint(&self, _local_paths: &[PathBuf]) {}
+
+    fn finish(&self) {}
+
+    fn url(&self, _local_path: &Path) -> Option<String> {
+        None
+    }
+}
+
+impl fmt::Display for LocalStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())
+    }
+}
+
+/// A zip file the outputs are added to as they are stored, staged in a local directory until
+/// then.
+pub struct ZipStorage {
+    zip_path: PathBuf,
+    staging_dir: PathBuf,
+    /// The zip, None once it is finished.
+    archive: Mutex<Option<archive::ZipOutput>>,
+    /// Whether outputs stay staged until the run finishes, as they are read back or replaced
+    /// by then.
+    defer: bool,
+}
+
+impl ZipStorage {
+    /// Function to start the zip file of a run.
+    ///
+    /// # Arguments
+    ///
+    /// * `zip_path` - Path of the zip file.
+    /// * `defer` - Whether to keep outputs staged until the run finishes rather than adding them
+    ///   as they are stored.
+    ///
+    /// # Returns
+    ///
+    /// * The storage, with an empty zip file.
+    pub fn new(zip_path: &Path, defer: bool) -> Self {
+        let staging_dir = staging_root().join("output");
+        let archive = archive::ZipOutput::create(zip_path, &staging_dir)
+            .unwrap_or_else(|error| panic!("Failed to create {}: {}", zip_path.display(), error));
+        Self {
+            zip_path: zip_path.to_path_buf(),
+            staging_dir,
+            archive: Mutex::new(Some(archive)),
+            defer,
+        }
+    }
+
+    /// Function to run a function on the zip, unless it is finished.
+    fn with_archive<T>(&self, f: impl FnOnce(&mut archive::ZipOutput) -> T) -> Option<T> {
+        self.archive.lock().unwrap().as_mut().map(f)
+    }
+}
+
+impl Storage for ZipStorage {
+    fn local_path(&self) -> &Path {
+        &self.staging_dir
+    }
+
+    fn list(&self, _keep: &dyn Fn(&Path) -> bool) -> Option<Vec<PathBuf>> {
+        None
+    }
+
+    fn fetch(&self, _local_path: &Path) {}
+
+    fn release(&self, _local_path: &Path) {}
+
+    fn modified(&self, local_path: &Path) -> Option<SystemTime> {
+        local_modified(local_path)
+    }
+
+    fn exists(&self, local_path: &Path) -> bool {
+        local_path.exists()
+            || self
+                .with_archive(|archive| archive.contains(local_path))
+                .unwrap_or(false)
+    }
+
+    fn remove(&self, local_path: &Path) -> io::Result<()> {
+        if self
+            .with_archive(|archive| archive.contains(local_path))
+            .unwrap_or(false)
+        {
+            return Err(io::Error::other(format!(
+                "{} is already in {}",
+                local_path.display(),
+                self
+            )));
+        }
+        remove_local(local_path)
+    }
+
+    fn store(&self, local_paths: &[PathBuf]) {
+        if self.defer {
+            return;
+        }
+        for local_path in local_paths {
+            let added = self
+                .with_archive(|archive| archive.add(local_path))
+                .unwrap_or(Ok(false))
+                .unwrap_or_else(|error| panic!("Failed to write {}: {}", self, error));
+            match added {
+                // the zip holds the only copy so the outputs don't take up the disk twice
+                true => remove_local(local_path)
+                    .unwrap_or_else(|_| panic!("Failed to remove {}", local_path.display())),
+                false => warn!(
+                    "{} is already in {}. Keeping the first one",
+                    local_path.display(),
+                    self
+                ),
+            }
+        }
+    }
+
+    // the files that are rewritten as the run goes are added once, when it finishes
+    fn checkpoint(&self, _local_paths: &[PathBuf]) {}
+
+    fn finish(&self) {
+        let Some(archive) = self.archive.lock().unwrap().take() else {
+            return;
+        };
+        let files = archive
+            .finish()
+            .unwrap_or_else(|error| panic!("Failed to write {}: {}", self, error));
+        info!("Wrote {} file(s) to {}", files, self);
+    }
+
+    fn url(&self, _local_path: &Path) -> Option<String> {
+        None
+    }
+}
+
+impl fmt::Display for ZipStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.zip_path.display())
+    }
+}
+
+/// Function to get the scheme of a URL given for an input or output.
+///
+/// # Returns
+///
+/// * The scheme, e.g. "s3", or None for a local path.
+pub fn scheme(path: &str) -> Option<&str> {
+    path.split_once("://")
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| scheme.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Function to open the storage of an input or output, selected by the scheme of its URL:
+/// `s3://`, `gs://` or `az://` for a bucket or container prefix (with the s3, gcs and azure
+/// features), otherwise a local path.
+///
+/// # Arguments
+///
+/// * `path` - The URL or local path.
+/// * `name` - What the storage holds, to keep the staging directories of a run apart.
+///
+/// # Returns
+///
+/// * The storage.
+pub fn open(path: &str, name: &str) -> Box<dyn Storage> {
+    match scheme(path) {
+        None => Box::new(LocalStorage {
+            path: PathBuf::from(path),
+        }),
+        #[cfg(feature = "object-store")]
+        Some(_) => Box::new(object::ObjectStorage::new(path, name)),
+        #[cfg(not(feature = "object-store"))]
+        Some(scheme) => {
+            let _ = name;
+            panic!(
+                "{}:// paths need facecrop built with the {} feature",
+                scheme,
+                feature_name(scheme)
+            )
+        }
+    }
+}
+
+/// Function to get the cargo feature that reads and writes URLs with a scheme.
+fn feature_name(scheme: &str) -> &'static str {
+    match scheme {
+        "gs" => "gcs",
+        "az" | "abfs" | "abfss" => "azure",
+        _ => "s3",
+    }
+}
+
+/// Removes the staging directories of the run once its inputs and outputs are no longer needed.
+pub fn remove_staging_dirs() {
+    let _ = std::fs::remove_dir_all(staging_root());
+}
+
+/// Directory the staging directories of this process are created in.
+fn staging_root() -> PathBuf {
+    std::env::temp_dir().join(format!("facecrop-staging-{}", std::process::id()))
+}
+
+#[cfg(feature = "object-store")]
+mod object {
+    use std::{
+        collections::HashMap,
+        fmt,
+        fs::File,
+        io::{self, Write},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex, PoisonError,
+        },
+        time::SystemTime,
+    };
+
+    use futures::TryStreamExt;
+    use object_store::{path::Path as ObjectPath, ObjectStore, ObjectStoreExt, PutPayload};
+    use tokio::runtime::Runtime;
+    use tracing::{debug, info};
+
+    /// A prefix of an object store bucket or container, staged in a local directory. Inputs are
+    /// downloaded one at a time as they are read, and outputs uploaded as they are written.
+    pub struct ObjectStorage {
+        store: Box<dyn ObjectStore>,
+        /// URL of the prefix, ending with a slash.
+        url: String,
+        /// The prefix, empty to stage the whole bucket.
+        prefix: ObjectPath,
+        staging_dir: PathBuf,
+        runtime: Runtime,
+        /// Paths relative to the prefix of the objects at it and when they were last modified,
+        /// listed the first time they are needed.
+        objects: Mutex<Option<HashMap<PathBuf, SystemTime>>>,
+        /// Size and modification time of the staged files as they were when they were last
+        /// downloaded or uploaded, so unchanged files aren't uploaded again.
+        stored: Mutex<HashMap<PathBuf, (u64, SystemTime)>>,
+        /// Number of files uploaded so far.
+        uploaded: AtomicUsize,
+        /// Held while an input is fetched, as the decoding threads and the run can ask for the
+        /// same input at once.
+        fetching: Mutex<()>,
+    }
+
+    impl ObjectStorage {
+        /// Function to open a bucket prefix with the credentials of the environment, e.g.
+        /// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` or the instance metadata of the
+        /// cloud.
+        ///
+        /// # Arguments
+        ///
+        /// * `url` - The URL of the prefix, e.g. `s3://bucket/prefix/`.
+        /// * `name` - What the storage holds, to name its staging directory.
+        ///
+        /// # Returns
+        ///
+        /// * The storage, with an empty staging directory.
+        pub fn new(url: &str, name: &str) -> Self {
+            let (scheme, location) = url.split_once("://").unwrap();
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            if bucket.is_empty() {
+                panic!("Invalid URL {}. Expected {}://bucket/prefix/", url, scheme);
+            }
+            let bucket_url = format!("{}://{}", scheme, bucket);
+            let store: Box<dyn ObjectStore> = match scheme {
+                #[cfg(feature = "s3")]
+                "s3" => Box::new(
+                    object_store::aws::AmazonS3Builder::from_env()
+                        .with_url(&bucket_url)
+                        .build()
+                        .unwrap_or_else(|error| panic!("Failed to open {}: {}", url, error)),
+                ),
+                #[cfg(feature = "gcs")]
+                "gs" => Box::new(
+                    object_store::gcp::GoogleCloudStorageBuilder::from_env()
+                        .with_url(&bucket_url)
+                        .build()
+                        .unwrap_or_else(|error| panic!("Failed to open {}: {}", url, error)),
+                ),
+                #[cfg(feature = "azure")]
+                "az" | "abfs" | "abfss" => Box::new(
+                    object_store::azure::MicrosoftAzureBuilder::from_env()
+                        .with_url(&bucket_url)
+                        .build()
+                        .unwrap_or_else(|error| panic!("Failed to open {}: {}", url, error)),
+                ),
+                _ => panic!(
+                    "{}:// paths need facecrop built with the {} feature",
+                    scheme,
+                    super::feature_name(scheme)
+                ),
+            };
+
+            let staging_dir = super::staging_root().join(name);
+            let _ = std::fs::remove_dir_all(&staging_dir);
+            std::fs::create_dir_all(&staging_dir)
+                .unwrap_or_else(|_| panic!("Failed to create staging directory"));
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap_or_else(|_| panic!("Failed to start object store runtime"));
+            Self {
+                store,
+                url: match prefix.trim_matches('/') {
+                    "" => format!("{}/", bucket_url),
+                    prefix => format!("{}/{}/", bucket_url, prefix),
+                },
+                prefix: ObjectPath::from(prefix),
+                staging_dir,
+                runtime,
+                objects: Mutex::new(None),
+                stored: Mutex::new(HashMap::new()),
+                uploaded: AtomicUsize::new(0),
+                fetching: Mutex::new(()),
+            }
+        }
+
+        /// Location of an object given its path relative to the prefix.
+        fn location(&self, relative_path: &Path) -> ObjectPath {
+            ObjectPath::from(format!(
+                "{}/{}",
+                self.prefix,
+                relative_path.to_string_lossy().replace('\\', "/")
+            ))
+        }
+
+        /// Function to run a function on the objects at the prefix, listing them if they haven't
+        /// been yet.
+        fn with_objects<T>(&self, f: impl FnOnce(&mut HashMap<PathBuf, SystemTime>) -> T) -> T {
+            let mut objects = self.objects.lock().unwrap();
+            let objects = objects.get_or_insert_with(|| {
+                self.runtime.block_on(async {
+                    let objects: Vec<_> = self
+                        .store
+                        .list(Some(&self.prefix))
+                        .try_collect()
+                        .await
+                        .unwrap_or_else(|error| panic!("Failed to list {}: {}", self, error));
+                    objects
+                        .iter()
+                        .map(|object| {
+                            (
+                                PathBuf::from(self.relative_key(&object.location)),
+                                object.last_modified.into(),
+                            )
+                        })
+                        .collect()
+                })
+            });
+            f(objects)
+        }
+
+        /// Function to download an object to a local path, writing it next to it first so a
+        /// partial download is never taken for the object.
+        async fn download(&self, location: &ObjectPath, local_path: &Path) {
+            if let Some(parent) = local_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .unwrap_or_else(|_| panic!("Failed to create staging directory"));
+            }
+            let temp_path = crate::output::temp_path(local_path);
+            let mut file = File::create(&temp_path)
+                .unwrap_or_else(|_| panic!("Failed to create {}", local_path.display()));
+            let object = self
+                .store
+                .get(location)
+                .await
+                .unwrap_or_else(|error| panic!("Failed to download {}: {}", location, error));
+            // the local copy is as old as the object so --newer-than can tell them apart
+            let modified = object.meta.last_modified;
+            let mut chunks = object.into_stream();
+            while let Some(chunk) = chunks
+                .try_next()
+                .await
+                .unwrap_or_else(|error| panic!("Failed to download {}: {}", location, error))
+            {
+                file.write_all(&chunk)
+                    .unwrap_or_else(|_| panic!("Failed to write {}", local_path.display()));
+            }
+            let _ = file.set_modified(modified.into());
+            std::fs::rename(&temp_path, local_path)
+                .unwrap_or_else(|_| panic!("Failed to write {}", local_path.display()));
+            self.mark_stored(local_path);
+            debug!("Downloaded {}", location);
+        }
+
+        /// Function to upload the staged files that changed since they were last stored.
+        fn upload(&self, local_paths: &[PathBuf]) {
+            for local_path in local_paths {
+                let Ok(relative_path) = local_path.strip_prefix(&self.staging_dir) else {
+                    continue;
+                };
+                let stamp = stamp(local_path);
+                if stamp.is_none() || self.stored.lock().unwrap().get(local_path) == stamp.as_ref()
+                {
+                    continue;
+                }
+                let location = self.location(relative_path);
+                let contents = std::fs::read(local_path)
+                    .unwrap_or_else(|_| panic!("Failed to read {}", local_path.display()));
+                self.runtime
+                    .block_on(self.store.put(&location, PutPayload::from(contents)))
+                    .unwrap_or_else(|error| panic!("Failed to upload {}: {}", location, error));
+                self.mark_stored(local_path);
+                self.with_objects(|objects| {
+                    objects.insert(relative_path.to_path_buf(), SystemTime::now())
+                });
+                self.uploaded.fetch_add(1, Ordering::Relaxed);
+                debug!("Uploaded {}", location);
+            }
+        }
+
+        /// Function to record that a staged file is the same as its object.
+        fn mark_stored(&self, local_path: &Path) {
+            if let Some(stamp) = stamp(local_path) {
+                self.stored
+                    .lock()
+                    .unwrap()
+                    .insert(local_path.to_path_buf(), stamp);
+            }
+        }
+
+        /// Key of an object relative to the prefix.
+        fn relative_key<'a>(&self, location: &'a ObjectPath) -> &'a str {
+            let key = location.as_ref();
+            match self.prefix.as_ref() {
+                "" => key,
+                prefix => key[prefix.len()..].trim_start_matches('/'),
+            }
+        }
+    }
+
+    /// Function to get the size and modification time of a local file, which tell whether it
+    /// changed since it was stored.
+    fn stamp(local_path: &Path) -> Option<(u64, SystemTime)> {
+        let metadata = std::fs::metadata(local_path).ok()?;
+        Some((metadata.len(), metadata.modified().ok()?))
+    }
+
+    impl super::Storage for ObjectStorage {
+        fn local_path(&self) -> &Path {
+            &self.staging_dir
+        }
+
+        fn list(&self, keep: &dyn Fn(&Path) -> bool) -> Option<Vec<PathBuf>> {
+            let inputs: Vec<_> = self.with_objects(|objects| {
+                objects
+                    .keys()
+                    .filter(|relative_path| keep(relative_path))
+                    .map(|relative_path| self.staging_dir.join(relative_path))
+                    .collect()
+            });
+            info!("Listed {} input(s) at {}", inputs.len(), self);
+            Some(inputs)
+        }
+
+        fn fetch(&self, local_path: &Path) {
+            let Ok(relative_path) = local_path.strip_prefix(&self.staging_dir) else {
+                return;
+            };
+            let _fetching = self.fetching.lock().unwrap_or_else(PoisonError::into_inner);
+            if !local_path.exists()
+                && self.with_objects(|objects| objects.contains_key(relative_path))
+            {
+                self.runtime
+                    .block_on(self.download(&self.location(relative_path), local_path));
+            }
+        }
+
+        fn release(&self, local_path: &Path) {
+            if local_path.starts_with(&self.staging_dir) {
+                let _ = super::remove_local(local_path);
+                self.stored.lock().unwrap().remove(local_path);
+            }
+        }
+
+        fn modified(&self, local_path: &Path) -> Option<SystemTime> {
+            let relative_path = local_path.strip_prefix(&self.staging_dir).ok()?;
+            self.with_objects(|objects| objects.get(relative_path).copied())
+        }
+
+        fn exists(&self, local_path: &Path) -> bool {
+            local_path.exists()
+                || local_path
+                    .strip_prefix(&self.staging_dir)
+                    .is_ok_and(|relative_path| {
+                        self.with_objects(|objects| objects.contains_key(relative_path))
+                    })
+        }
+
+        fn remove(&self, local_path: &Path) -> io::Result<()> {
+            super::remove_local(local_path)?;
+            self.stored.lock().unwrap().remove(local_path);
+            let Ok(relative_path) = local_path.strip_prefix(&self.staging_dir) else {
+                return Ok(());
+            };
+            if self.with_objects(|objects| objects.remove(relative_path).is_some()) {
+                let location = self.location(relative_path);
+                self.runtime
+                    .block_on(self.store.delete(&location))
+                    .map_err(io::Error::other)?;
+                debug!("Deleted {}", location);
+            }
+            Ok(())
+        }
+
+        fn store(&self, local_paths: &[PathBuf]) {
+            self.upload(local_paths);
+        }
+
+        fn checkpoint(&self, local_paths: &[PathBuf]) {
+            self.upload(local_paths);
+        }
+
+        fn finish(&self) {
+            let mut local_paths = Vec::new();
+            let mut dirs = vec![self.staging_dir.clone()];
+            while let Some(dir) = dirs.pop() {
+                for entry in std::fs::read_dir(&dir)
+                    .unwrap_or_else(|_| panic!("Failed to read staging directory"))
+                {
+                    let path = entry.unwrap().path();
+                    match path.is_dir() {
+                        true => dirs.push(path),
+                        false => local_paths.push(path),
+                    }
+                }
+            }
+            self.upload(&local_paths);
+            info!(
+                "Uploaded {} file(s) to {}",
+                self.uploaded.load(Ordering::Relaxed),
+                self
+            );
+        }
+
+        fn url(&self, local_path: &Path) -> Option<String> {
+            let relative_path = local_path.strip_prefix(&self.staging_dir).ok()?;
+            Some(format!(
+                "{}{}",
+                self.url,
+                relative_path.to_string_lossy().replace('\\', "/")
+            ))
+        }
+    }
+
+    impl fmt::Display for ObjectStorage {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.url)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest;
+
+    /// Function to create an empty directory for a test, named after it.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("facecrop-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn crops_stored_in_a_zip_can_be_signed() {
+        let dir = test_dir("zip-sign");
+        let zip_path = dir.join("crops.zip");
+        let storage = ZipStorage::new(&zip_path, false);
+        let staging_dir = storage.local_path().to_path_buf();
+        std::fs::create_dir_all(&staging_dir).unwrap();
+        std::fs::write(staging_dir.join("photo-0.jpg"), b"crop").unwrap();
+        let crop = serde_json::from_value(serde_json::json!({
+            "path": "photo-0.jpg",
+            "source": "input/photo.jpg",
+            "face": 0,
+            "confidence": 0.9,
+            "face_rect": {"x": 10.0, "y": 10.0, "width": 20.0, "height": 20.0},
+            "crop_rect": {"x": 0.0, "y": 0.0, "width": 40.0, "height": 40.0},
+            "size_bucket": "small",
+        }))
+        .unwrap();
+        let mut manifest = manifest::Manifest {
+            crops: vec![crop],
+            ..Default::default()
+        };
+
+        manifest::hash_crops(&mut manifest.crops, &staging_dir);
+        storage.store(&[staging_dir.join("photo-0.jpg")]);
+        assert!(!staging_dir.join("photo-0.jpg").exists());
+        manifest::sign(&mut manifest, &staging_dir, b"key");
+        storage.finish();
+
+        let extracted_dir = dir.join("extracted");
+        std::fs::create_dir_all(&extracted_dir).unwrap();
+        archive::for_each_entry(&zip_path, &["jpg"], |entry| {
+            std::fs::write(extracted_dir.join(entry.path), entry.bytes).unwrap();
+            true
+        })
+        .unwrap();
+        assert!(manifest::verify(&manifest, &extracted_dir, b"key").is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+        let _ = std::fs::remove_dir_all(staging_dir);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, path::Path};
+
+use rust_faces::Face;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::metrics::Stage;
+
+/// Seconds spent in each stage of the pipeline, summed over the images of a run.
+#[derive(Debug, Default, Serialize)]
+pub struct StageSeconds {
+    pub decode: f64,
+    pub detect: f64,
+    pub annotate: f64,
+    pub crop: f64,
+    pub write: f64,
+}
+
+impl StageSeconds {
+    pub fn add(&mut self, stage: Stage, seconds: f64) {
+        match stage {
+            Stage::Decode => self.decode += seconds,
+            Stage::Detect => self.detect += seconds,
+            Stage::Annotate => self.annotate += seconds,
+            Stage::Crop => self.crop += seconds,
+            Stage::Write => self.write += seconds,
+        }
+    }
+}
+
+/// Statistics of a run, logged when it finishes.
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    /// Images processed, counting each sampled frame of a video or animation.
+    pub images: usize,
+    pub images_without_faces: usize,
+    /// Faces detected or supplied, before any are filtered out.
+    pub faces: usize,
+    pub crops_written: usize,
+    /// Inputs that failed to process and were skipped.
+    pub inputs_failed: usize,
+    /// Faces that weren't cropped, by the filter that left them out.
+    pub filtered: BTreeMap<&'static str, usize>,
+    pub stage_seconds: StageSeconds,
+    /// Average confidence of the faces, if there are any.
+    pub average_confidence: Option<f32>,
+    #[serde(skip)]
+    confidence_sum: f64,
+}
+
+impl RunSummary {
+    /// Counts an image and the faces detected in it.
+    pub fn add_image(&mut self, faces: &[Face]) {
+        self.images += 1;
+        if faces.is_empty() {
+            self.images_without_faces += 1;
+        }
+        self.faces += faces.len();
+        self.confidence_sum += faces.iter().map(|face| face.confidence as f64).sum::<f64>();
+        self.average_confidence =
+            (self.faces > 0).then(|| (self.confidence_sum / self.faces as f64) as f32);
+    }
+
+    /// Counts faces left out by a filter.
+    pub fn add_filtered(&mut self, filter: &'static str, count: usize) {
+        if count > 0 {
+            *self.filtered.entry(filter).or_default() += count;
+        }
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Processed {} image(s), {} without faces. Found {} face(s){} and wrote {} crop(s)",
+            self.images,
+            self.images_without_faces,
+            self.faces,
+            self.average_confidence
+                .map(|confidence| format!(" with an average confidence of {:.3}", confidence))
+                .unwrap_or_default(),
+            self.crops_written
+        );
+        if self.inputs_failed > 0 {
+            warn!("Failed to process {} input(s)", self.inputs_failed);
+        }
+        if !self.filtered.is_empty() {
+            let filtered: Vec<_> = self
+                .filtered
+                .iter()
+                .map(|(filter, count)| format!("{} {}", count, filter))
+                .collect();
+            info!("Filtered out {}", filtered.join(", "));
+        }
+        let stages = &self.stage_seconds;
+        info!(
+            "Spent {:.2}s decoding, {:.2}s detecting, {:.2}s annotating, {:.2}s cropping and \
+            {:.2}s writing",
+            stages.decode, stages.detect, stages.annotate, stages.crop, stages.write
+        );
+    }
+}
+
+pub fn save(summary_path: &Path, summary: &RunSummary) {
+    let contents = serde_json::to_string_pretty(summary)
+        .unwrap_or_else(|_| panic!("Failed to serialize run summary"));
+    std::fs::write(summary_path, contents)
+        .unwrap_or_else(|_| panic!("Failed to write run summary"));
+}
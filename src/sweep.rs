@@ -0,0 +1,229 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info};
+
+use crate::{
+    console,
+    contact_sheet::{self, ContactSheet},
+    cropping, get_contact_sheet_params, get_crop_params, get_face_detector,
+    get_post_process_params, input_kind, list_input_dir, post_processing, read_image, set_crop_arg,
+    skip_unsupported, Args, InputKind,
+};
+
+/// Arguments that can be swept, with the short names used to label their values.
+const SWEEP_PARAMS: [(&str, &str); 8] = [
+    ("proportion_of_face", "pof"),
+    ("top_padding", "tp"),
+    ("padding_bottom", "pb"),
+    ("padding_left", "pl"),
+    ("padding_right", "pr"),
+    ("aspect_ratio", "ar"),
+    ("height", "h"),
+    ("width", "w"),
+];
+
+/// An argument and the values to try it with, parsed from `--param name=v1,v2`.
+#[derive(Clone, Debug)]
+pub struct SweepParam {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+/// One value of every swept argument.
+pub type Combination = Vec<(String, f32)>;
+
+pub fn parse_param(value: &str) -> Result<SweepParam, String> {
+    let (name, values) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid sweep parameter {}. Expected name=v1,v2", value))?;
+    let name = param_name(name)?;
+    let values = values
+        .split(',')
+        .map(|value| value.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid values for sweep parameter {}", name))?;
+
+    Ok(SweepParam { name, values })
+}
+
+/// Function to check that an argument can be swept, which also makes it one that can be
+/// overridden for a class with `--class-params`.
+///
+/// # Arguments
+///
+/// * `name` - The name of the argument, with dashes or underscores.
+///
+/// # Returns
+///
+/// * The name with underscores, or an error if the argument can't be swept.
+pub fn param_name(name: &str) -> Result<String, String> {
+    let name = name.trim().replace('-', "_");
+    if !SWEEP_PARAMS.iter().any(|(param, _)| *param == name) {
+        return Err(format!(
+            "Unknown parameter {}. Expected one of {}",
+            name,
+            SWEEP_PARAMS
+                .iter()
+                .map(|(param, _)| *param)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    Ok(name)
+}
+
+/// Function to get every combination of the values of the swept arguments.
+///
+/// # Arguments
+///
+/// * `params` - The swept arguments.
+///
+/// # Returns
+///
+/// * The combinations, varying the last argument fastest.
+pub fn combinations(params: &[SweepParam]) -> Vec<Combination> {
+    params.iter().fold(vec![vec![]], |combinations, param| {
+        combinations
+            .iter()
+            .flat_map(|combination| {
+                param.values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((param.name.clone(), *value));
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
+/// Short label of a combination for contact sheet captions, e.g. `pof=0.2 tp=0.1`.
+pub fn label(combination: &Combination) -> String {
+    combination
+        .iter()
+        .map(|(name, value)| {
+            let short_name = SWEEP_PARAMS
+                .iter()
+                .find(|(param, _)| param == name)
+                .map_or(name.as_str(), |(_, short_name)| short_name);
+            format!("{}={}", short_name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Picks up to `size` inputs spread evenly over the sorted inputs, so the sample isn't made of
+/// a single folder or shoot.
+pub fn sample(paths: Vec<PathBuf>, size: usize) -> Vec<PathBuf> {
+    if paths.len() <= size {
+        return paths;
+    }
+    (0..size)
+        .map(|i| paths[i * paths.len() / size].clone())
+        .collect()
+}
+
+/// Function to crop a sample of the inputs with every combination of the swept parameters and
+/// write a contact sheet per input, one row per face and one column per combination.
+///
+/// # Arguments
+///
+/// * `args` - The program arguments the swept parameters are applied to.
+/// * `input_path` - Path to the image file or directory to sample.
+/// * `output_dir` - Path to write the contact sheets to.
+/// * `params` - The swept parameters.
+/// * `sample_size` - Number of inputs to sample.
+pub fn run(
+    args: &Args,
+    input_path: &Path,
+    output_dir: &Path,
+    params: &[SweepParam],
+    sample_size: usize,
+) {
+    if !input_path.exists() {
+        panic!("Input path does not exist");
+    }
+    let image_paths = match input_path.is_file() {
+        true => vec![input_path.to_path_buf()],
+        false => list_input_dir(input_path, false, args.follow_symlinks)
+            .into_iter()
+            .filter(|path| input_kind(path) == InputKind::Image)
+            .filter(|path| !skip_unsupported(path))
+            .collect(),
+    };
+    let image_paths = sample(image_paths, sample_size);
+    std::fs::create_dir_all(output_dir)
+        .unwrap_or_else(|_| panic!("Failed to create output directory"));
+
+    let combinations = combinations(params);
+    let combination_params: Vec<_> = combinations
+        .iter()
+        .map(|combination| {
+            let mut combination_args = args.clone();
+            for (name, value) in combination {
+                set_crop_arg(&mut combination_args, name, *value);
+            }
+            (
+                get_crop_params(&combination_args),
+                get_post_process_params(&combination_args),
+            )
+        })
+        .collect();
+    let contact_sheet_params = contact_sheet::ContactSheetParams {
+        columns: combinations.len() as u32,
+        ..get_contact_sheet_params(args)
+    };
+    info!(
+        "Sweeping {} combinations over {} images{}",
+        combinations.len(),
+        image_paths.len(),
+        console::symbol("🧪")
+    );
+
+    let face_detector = get_face_detector(args);
+    for image_path in &image_paths {
+        let image_name = image_path.file_stem().unwrap().to_str().unwrap();
+        let input_image = read_image(image_path, args.low_memory);
+        let detection_image = match input_image.color().has_alpha() {
+            true => cropping::flatten_alpha(&input_image.into_rgba8(), args.alpha_background),
+            false => input_image.into_rgb8(),
+        };
+        let faces = cropping::detect_faces_in_image(&detection_image, &*face_detector);
+        if faces.is_empty() {
+            debug!("No faces found in {}", image_name);
+            continue;
+        }
+
+        let mut contact_sheet = ContactSheet::new(
+            &contact_sheet_params,
+            output_dir.join(format!("{}-sweep.jpg", image_name)),
+        );
+        for (i, face) in faces.iter().enumerate() {
+            let face = vec![face.clone()];
+            for (combination, (crop_params, post_process_params)) in
+                combinations.iter().zip(&combination_params)
+            {
+                let crop = cropping::crop_faces(
+                    cropping::CropInputs {
+                        input_image: &detection_image,
+                        faces: &face,
+                    },
+                    crop_params,
+                )
+                .and_then(|crops| crops.into_iter().next())
+                .and_then(|crop| {
+                    post_processing::post_process_image(&crop.image, post_process_params)
+                });
+                // a filtered out crop keeps its cell so the columns stay aligned
+                let crop = crop.unwrap_or_else(|| image::RgbImage::new(1, 1));
+                contact_sheet.add(
+                    &crop,
+                    &format!("{} face {}", image_name, i),
+                    label(combination),
+                );
+            }
+        }
+        contact_sheet.finish();
+    }
+
+    info!("Sweep complete{}", console::symbol("🎉"));
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use image::{ImageBuffer, Pixel};
+use ndarray::{Array4, CowArray};
+use ort::{tensor::OrtOwnedTensor, Environment, OrtResult, Session, Value};
+
+use crate::acceleration::{self, SessionOptions};
+
+/// Real-ESRGAN-style super-resolution model, upscaling an image by the fixed factor it was
+/// trained for.
+#[derive(Debug)]
+pub struct Upscaler {
+    session: Session,
+}
+
+impl Upscaler {
+    /// Loads a super-resolution model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_path` - Path to the ONNX model, taking a 1x3xHxW RGB input in 0..1.
+    /// * `session_options` - Threading and graph optimization of the session.
+    pub fn from_file(model_path: &Path, session_options: SessionOptions) -> OrtResult<Self> {
+        let environment = Environment::builder()
+            .with_name("facecrop")
+            .build()?
+            .into_arc();
+        let session = acceleration::session_builder(&environment, session_options)?
+            .with_model_from_file(model_path)?;
+        Ok(Self { session })
+    }
+
+    /// Function to upscale an image. Only the colour is run through the model, any alpha channel
+    /// is resized with Lanczos to match.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_image` - The image to upscale.
+    ///
+    /// # Returns
+    ///
+    /// * The upscaled image, the model's scale factor times the size of the input.
+    pub fn upscale<P>(
+        &self,
+        input_image: &ImageBuffer<P, Vec<u8>>,
+    ) -> OrtResult<ImageBuffer<P, Vec<u8>>>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+    {
+        let (width, height) = input_image.dimensions();
+        let input =
+            Array4::from_shape_fn((1, 3, height as usize, width as usize), |(_, c, y, x)| {
+                input_image.get_pixel(x as u32, y as u32).to_rgb()[c] as f32 / 255.0
+            });
+        let outputs = self.session.run(vec![Value::from_array(
+            self.session.allocator(),
+            &CowArray::from(input).into_dyn(),
+        )?])?;
+        let output: OrtOwnedTensor<f32, _> = outputs[0].try_extract()?;
+        let output = output.view();
+        let (output_height, output_width) = (output.shape()[2] as u32, output.shape()[3] as u32);
+
+        let mut output_image = image::imageops::resize(
+            input_image,
+            output_width,
+            output_height,
+            image::imageops::FilterType::Lanczos3,
+        );
+        for (x, y, pixel) in output_image.enumerate_pixels_mut() {
+            let value = |c: usize| {
+                (output[[0, c, y as usize, x as usize]] * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            };
+            let rgb = image::Rgb([value(0), value(1), value(2)]);
+            match P::CHANNEL_COUNT {
+                // gray, with or without alpha
+                1 | 2 => pixel.channels_mut()[0] = rgb.to_luma()[0],
+                _ => pixel.channels_mut()[..3].copy_from_slice(&rgb.0),
+            }
+        }
+        Ok(output_image)
+    }
+}
@@ -0,0 +1,43 @@
+use std::{path::PathBuf, time::Instant};
+
+use tracing::{debug, error, info};
+
+use crate::decoding;
+
+/// Function to fully decode every image, without running detection, and report the ones that
+/// fail to decode.
+///
+/// # Arguments
+///
+/// * `image_paths` - Paths of the images to verify.
+///
+/// # Returns
+///
+/// * True if every image decoded successfully.
+pub fn run(image_paths: &[PathBuf]) -> bool {
+    let start = Instant::now();
+    let mut corrupt = 0;
+    for image_path in image_paths {
+        // the format is guessed from the contents so misnamed files are not reported as corrupt
+        match decoding::open(image_path, false) {
+            Ok(image) => debug!(
+                "OK {} ({}x{})",
+                image_path.display(),
+                image.width(),
+                image.height()
+            ),
+            Err(error) => {
+                error!("CORRUPT {}: {}", image_path.display(), error);
+                corrupt += 1;
+            }
+        }
+    }
+
+    info!(
+        "Verified {} images in {:.2?}, {} corrupt",
+        image_paths.len(),
+        start.elapsed(),
+        corrupt
+    );
+    corrupt == 0
+}
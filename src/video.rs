@@ -0,0 +1,208 @@
+use std::{path::Path, time::Duration};
+
+use ffmpeg::{
+    format::Pixel,
+    media::Type,
+    software::scaling::{context::Context, flag::Flags},
+    util::frame::video::Video,
+};
+use ffmpeg_next as ffmpeg;
+use tracing::{debug, warn};
+
+use crate::{contact_sheet::ContactSheet, CropBudget, Pipeline};
+
+/// Which frames of a video are sampled for detection.
+#[derive(Clone, Copy, Debug)]
+pub enum FrameSampling {
+    /// Sample a frame, then the first frame at least this long after it.
+    Interval(Duration),
+    /// Sample every nth decoded frame, starting with the first.
+    EveryNth(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameSamplingParams {
+    pub sampling: FrameSampling,
+    /// Stop decoding once this many frames have been sampled.
+    pub max_frames: Option<usize>,
+}
+
+/// A decoded video frame.
+pub struct VideoFrame {
+    pub image: image::RgbImage,
+    /// Presentation time of the frame from the start of the video.
+    pub timestamp: Duration,
+}
+
+/// Function to decode a video and pass a sampled subset of its frames to a callback.
+///
+/// # Arguments
+///
+/// * `video_path` - Path to the video file.
+/// * `params` - Which frames to sample.
+/// * `on_frame` - Called with each sampled frame in order. Returning false stops decoding.
+///
+/// # Returns
+///
+/// * The number of frames passed to the callback, or an error if the video can't be decoded.
+pub fn for_each_frame<F>(
+    video_path: &Path,
+    params: &FrameSamplingParams,
+    mut on_frame: F,
+) -> Result<usize, ffmpeg::Error>
+where
+    F: FnMut(VideoFrame) -> bool,
+{
+    ffmpeg::init()?;
+
+    let mut input_context = ffmpeg::format::input(&video_path)?;
+    let stream = input_context
+        .streams()
+        .best(Type::Video)
+        .ok_or(ffmpeg::Error::StreamNotFound)?;
+    let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base());
+
+    let decoder_context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+    let mut decoder = decoder_context.decoder().video()?;
+    let mut scaler = Context::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGB24,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )?;
+
+    let mut decoded_frames = 0;
+    let mut sampled = 0;
+    let mut next_sample = Duration::ZERO;
+    let mut stopped = false;
+    let mut receive_frames = |decoder: &mut ffmpeg::decoder::Video| -> Result<bool, ffmpeg::Error> {
+        let mut decoded = Video::empty();
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let frame_index = decoded_frames;
+            decoded_frames += 1;
+            let timestamp = decoded
+                .timestamp()
+                .map(|timestamp| Duration::from_secs_f64((timestamp as f64 * time_base).max(0.0)))
+                .unwrap_or(next_sample);
+            match params.sampling {
+                FrameSampling::Interval(interval) => {
+                    if timestamp < next_sample {
+                        continue;
+                    }
+                    next_sample = timestamp + interval;
+                }
+                FrameSampling::EveryNth(n) => {
+                    if frame_index % n != 0 {
+                        continue;
+                    }
+                }
+            }
+
+            let mut rgb_frame = Video::empty();
+            scaler.run(&decoded, &mut rgb_frame)?;
+            sampled += 1;
+            if !on_frame(VideoFrame {
+                image: to_rgb_image(&rgb_frame),
+                timestamp,
+            }) || params.max_frames == Some(sampled)
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
+
+    for (stream, packet) in input_context.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet)?;
+        if !receive_frames(&mut decoder)? {
+            stopped = true;
+            break;
+        }
+    }
+    if !stopped {
+        decoder.send_eof()?;
+        receive_frames(&mut decoder)?;
+    }
+
+    Ok(sampled)
+}
+
+/// Name used for the outputs of a frame, e.g. `clip-0012.500s`.
+pub fn frame_name(video_name: &str, timestamp: Duration) -> String {
+    format!("{}-{:08.3}s", video_name, timestamp.as_secs_f64())
+}
+
+fn to_rgb_image(frame: &Video) -> image::RgbImage {
+    // rows of the frame buffer are padded to the stride
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let stride = frame.stride(0);
+    let data = frame.data(0);
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for row in 0..height {
+        pixels.extend_from_slice(&data[row * stride..row * stride + width * 3]);
+    }
+    image::RgbImage::from_raw(width as u32, height as u32, pixels)
+        .unwrap_or_else(|| panic!("Failed to convert video frame"))
+}
+
+/// Function to detect and crop the faces in sampled frames of a video. Outputs are named after
+/// the timestamp of the frame they were taken from.
+///
+/// # Arguments
+///
+/// * `pipeline` - The pipeline to run each frame through.
+/// * `video_path` - Path to the video file.
+/// * `video_name` - Name the frame names are derived from.
+/// * `video_contact_sheet` - Contact sheet of the video, if any.
+/// * `budget` - Crops to skip and write at most. Decoding stops once it runs out.
+///
+/// # Returns
+///
+/// * The number of crops written.
+pub fn process<'a>(
+    pipeline: &mut Pipeline<'a>,
+    video_path: &Path,
+    video_name: &str,
+    mut video_contact_sheet: Option<&mut ContactSheet<'a>>,
+    budget: &mut CropBudget,
+) -> usize {
+    if pipeline.args.pipeline.virtual_crop {
+        warn!(
+            "Virtual crops are not supported for video {}. Skipping",
+            video_path.display()
+        );
+        return 0;
+    }
+
+    let mut crops_written = 0;
+    // copied out as the pipeline is borrowed mutably by the callback
+    let frame_sampling = pipeline.frame_sampling;
+    let frames = for_each_frame(video_path, &frame_sampling, |frame| {
+        let frame_name = frame_name(video_name, frame.timestamp);
+        crops_written += pipeline.process_image(
+            image::DynamicImage::ImageRgb8(frame.image),
+            &frame_name,
+            video_path,
+            video_contact_sheet.as_deref_mut(),
+            budget,
+        );
+        budget.remaining != Some(0)
+    });
+    match frames {
+        Ok(frames) => debug!("Sampled {} frames from {}", frames, video_path.display()),
+        Err(error) => warn!(
+            "Failed to decode video {}: {}. Skipping the rest of it",
+            video_path.display(),
+            error
+        ),
+    }
+
+    crops_written
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{error, info, warn};
+
+use crate::{
+    archive, console, has_extension, ignore_file, input_kind, is_image, is_video, provenance,
+    queue_key, skip_own_output, skip_unsupported, CropBudget, InputKind, Paths, Pipeline,
+    ANIMATION_EXTENSIONS,
+};
+
+/// Time without events after which a file is checked for having been fully written.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+/// How often pending files are checked while no events arrive.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A file that changed and hasn't been handed out yet.
+struct Pending {
+    last_change: Instant,
+    /// Size when the file was last checked, to tell when it stops growing.
+    size: Option<u64>,
+}
+
+/// Directory watched for new files, handing them out once they have been fully written.
+pub struct HotFolder {
+    // kept alive for as long as events are received
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    pending: HashMap<PathBuf, Pending>,
+}
+
+impl HotFolder {
+    /// Starts watching a directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory to watch.
+    /// * `recursive` - True to also watch its subdirectories.
+    pub fn new(dir: &Path, recursive: bool) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        let mode = match recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+        watcher.watch(dir, mode)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Function to wait until at least one new or changed file has been fully written.
+    ///
+    /// A file counts as fully written once no events arrived for it for a debounce period and
+    /// its size didn't change over another one, so files that are still being copied in aren't
+    /// read half-written.
+    ///
+    /// # Returns
+    ///
+    /// * The fully written files, or None if the watch stopped.
+    pub fn wait_for_files(&mut self) -> Option<Vec<PathBuf>> {
+        loop {
+            match self.events.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) => self.record(event),
+                Ok(Err(error)) => warn!("Failed to watch for changes: {}", error),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+
+            let ready = self.take_ready();
+            if !ready.is_empty() {
+                return Some(ready);
+            }
+        }
+    }
+
+    fn record(&mut self, event: Event) {
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return;
+        }
+        for path in event.paths {
+            let pending = self.pending.entry(path).or_insert(Pending {
+                last_change: Instant::now(),
+                size: None,
+            });
+            pending.last_change = Instant::now();
+        }
+    }
+
+    fn take_ready(&mut self) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+        self.pending.retain(|path, pending| {
+            if pending.last_change.elapsed() < DEBOUNCE {
+                return true;
+            }
+            let size = match std::fs::metadata(path) {
+                Ok(metadata) if metadata.is_file() => metadata.len(),
+                // removed again, or a directory
+                _ => return false,
+            };
+            if pending.size == Some(size) {
+                ready.push(path.clone());
+                return false;
+            }
+            // still growing, or not checked yet
+            pending.size = Some(size);
+            pending.last_change = Instant::now();
+            true
+        });
+        ready
+    }
+}
+
+/// Function to process the inputs that appear in the input directory until the program is
+/// interrupted. Inputs in priority directories are processed first when several are ready.
+///
+/// # Arguments
+///
+/// * `pipeline` - The pipeline to run each input through.
+/// * `paths` - The input and output directories.
+pub fn run(pipeline: &mut Pipeline, paths: &Paths) {
+    let args = pipeline.args;
+    if args.pipeline.contact_sheet.is_some()
+        || args.pipeline.stop_after_faces.is_some()
+        || args.pipeline.resume
+    {
+        warn!("--contact-sheet, --stop-after-faces and --resume are ignored when watching");
+    }
+    let recursive = args.pipeline.preserve_structure || !args.pipeline.priority_dirs.is_empty();
+    let mut hot_folder = HotFolder::new(&paths.input_root, recursive)
+        .unwrap_or_else(|_| panic!("Failed to watch input directory"));
+    info!(
+        "Watching {} for new inputs{}",
+        paths.input_root.display(),
+        console::symbol("👀")
+    );
+
+    while let Some(ready_paths) = hot_folder.wait_for_files() {
+        let mut input_paths: Vec<_> = ready_paths
+            .into_iter()
+            .filter(|path| {
+                // the same inputs a batch run over the directory would pick up
+                let relative_dir = path
+                    .parent()
+                    .and_then(|parent| parent.strip_prefix(&paths.input_root).ok())
+                    .unwrap_or(Path::new(""));
+                let listed = relative_dir.as_os_str().is_empty()
+                    || args.pipeline.preserve_structure
+                    || args
+                        .pipeline
+                        .priority_dirs
+                        .iter()
+                        .any(|priority_dir| relative_dir.starts_with(priority_dir));
+                let supported = is_image(path)
+                    || is_video(path)
+                    || has_extension(path, &ANIMATION_EXTENSIONS)
+                    || archive::is_archive(path);
+                listed
+                    && supported
+                    && !path.starts_with(&paths.output_dir)
+                    && (args.follow_symlinks || !path.is_symlink())
+                    && !ignore_file::IgnoreRules::load(&paths.input_root, path.parent().unwrap())
+                        .is_ignored(path, false)
+                    && !skip_unsupported(path)
+                    && (input_kind(path) != InputKind::Image
+                        || !skip_own_output(path, args.pipeline.own_outputs, || {
+                            provenance::is_facecrop_output(path)
+                        }))
+            })
+            .collect();
+        input_paths.sort_by_cached_key(|path| {
+            queue_key(path, &paths.input_root, &args.pipeline.priority_dirs)
+        });
+
+        pipeline.prefetch(&input_paths);
+        for input_path in &input_paths {
+            info!("Processing {}", input_path.display());
+            let mut budget = CropBudget {
+                skip: 0,
+                remaining: None,
+            };
+            // a failed input is logged and counted rather than stopping the watcher
+            let processed = catch_unwind(AssertUnwindSafe(|| {
+                pipeline.process_input(input_path, &mut budget)
+            }));
+            if processed.is_err() {
+                error!("Failed to process {}", input_path.display());
+                if let Some(metrics) = pipeline.metrics {
+                    metrics.add_error();
+                }
+            }
+        }
+        pipeline.save_run_files();
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    sync::{mpsc::channel, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use rust_faces::{Face, FaceDetector, RustFacesResult};
+use tracing::{error, warn};
+
+type DetectorBuilder = Box<dyn Fn() -> Box<dyn FaceDetector> + Send + Sync>;
+
+/// Face detector that rebuilds the wrapped detector when an inference hangs, e.g. on a driver
+/// stall, so long-running modes keep going without an operator restarting them.
+///
+/// Each inference runs on its own thread. A hung thread can't be stopped, so it is abandoned
+/// along with its detector session and a fresh session is built for the next inference.
+pub struct WatchdogDetector {
+    build: DetectorBuilder,
+    timeout: Duration,
+    detector: Mutex<Arc<dyn FaceDetector>>,
+}
+
+impl WatchdogDetector {
+    /// Builds the detector and starts watching its inferences.
+    ///
+    /// # Arguments
+    ///
+    /// * `build` - Builds a new detector session, called again whenever an inference hangs.
+    /// * `timeout` - Time after which an inference is considered hung.
+    pub fn new(build: DetectorBuilder, timeout: Duration) -> Self {
+        let detector = Arc::from(build());
+        Self {
+            build,
+            timeout,
+            detector: Mutex::new(detector),
+        }
+    }
+
+    fn detect_with_timeout(
+        &self,
+        image: &ndarray::ArrayViewD<u8>,
+    ) -> Option<RustFacesResult<Vec<Face>>> {
+        let detector = self.detector.lock().unwrap().clone();
+        let image = image.to_owned();
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            // the receiver is gone if the inference took too long
+            let _ = sender.send(detector.detect(image.view()));
+        });
+        receiver.recv_timeout(self.timeout).ok()
+    }
+
+    fn rebuild(&self) {
+        let detector = Arc::from((self.build)());
+        *self.detector.lock().unwrap() = detector;
+    }
+}
+
+impl FaceDetector for WatchdogDetector {
+    fn detect(&self, image: ndarray::ArrayViewD<u8>) -> RustFacesResult<Vec<Face>> {
+        if let Some(result) = self.detect_with_timeout(&image) {
+            return result;
+        }
+        warn!(
+            "Inference hung for longer than {:?}, rebuilding the face detector",
+            self.timeout
+        );
+        self.rebuild();
+        if let Some(result) = self.detect_with_timeout(&image) {
+            return result;
+        }
+        // the input itself may be what stalls the detector, so skip it rather than retry forever
+        error!(
+            "Inference hung again after rebuilding the face detector, skipping the image{}",
+            crate::console::symbol("⏱️")
+        );
+        self.rebuild();
+        Ok(Vec::new())
+    }
+}
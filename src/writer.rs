@@ -0,0 +1,179 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        mpsc::{sync_channel, SyncSender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
+/// Encodes and writes a crop, along with the path it is written to.
+type Job = (PathBuf, Box<dyn FnOnce() + Send>);
+
+/// Jobs submitted but not yet done, so callers can wait for their crops to be on disk.
+#[derive(Debug, Default)]
+struct Pending {
+    count: Mutex<usize>,
+    done: Condvar,
+    /// Paths of the crops that failed to write since the last flush.
+    failed: Mutex<Vec<PathBuf>>,
+}
+
+impl Pending {
+    /// Runs a job, recording its crop as failed if it panics.
+    fn run(&self, (path, job): Job) {
+        if catch_unwind(AssertUnwindSafe(job)).is_err() {
+            self.failed.lock().unwrap().push(path);
+        }
+    }
+}
+
+/// Threads that encode and write crops, so encoding runs alongside the cropping of the next faces
+/// instead of holding up detection. Submitting blocks once the queue is full, bounding the crops
+/// held in memory.
+#[derive(Debug)]
+pub struct Writer {
+    /// Queue of the threads, or None to write on the calling thread.
+    jobs: Option<Mutex<SyncSender<Job>>>,
+    pending: Arc<Pending>,
+}
+
+impl Writer {
+    /// Starts the writing threads.
+    ///
+    /// # Arguments
+    ///
+    /// * `threads` - The number of threads, or 0 to write each crop as it is submitted.
+    pub fn new(threads: usize) -> Self {
+        let pending = Arc::new(Pending::default());
+        if threads == 0 {
+            return Self {
+                jobs: None,
+                pending,
+            };
+        }
+        let (sender, receiver) = sync_channel::<Job>(threads * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..threads {
+            let (receiver, pending) = (receiver.clone(), pending.clone());
+            thread::spawn(move || loop {
+                // the lock is released before writing so other threads can take the next job
+                let job = receiver.lock().unwrap().recv();
+                let Ok(job) = job else {
+                    break;
+                };
+                pending.run(job);
+                *pending.count.lock().unwrap() -= 1;
+                pending.done.notify_all();
+            });
+        }
+        Self {
+            jobs: Some(Mutex::new(sender)),
+            pending,
+        }
+    }
+
+    /// Function to queue a crop to be encoded and written.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path the crop is written to, reported by `flush` if the job panics.
+    /// * `job` - The function encoding and writing the crop.
+    pub fn submit<F>(&self, path: PathBuf, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let jobs = match &self.jobs {
+            Some(jobs) => jobs,
+            None => return self.pending.run((path, Box::new(job))),
+        };
+        *self.pending.count.lock().unwrap() += 1;
+        jobs.lock()
+            .unwrap()
+            .send((path, Box::new(job)))
+            .unwrap_or_else(|_| panic!("Crop writing threads stopped"));
+    }
+
+    /// Function to wait until every crop submitted so far has been written or failed to, keeping
+    /// the failures for the next flush.
+    pub fn wait(&self) {
+        let _count = self
+            .pending
+            .done
+            .wait_while(self.pending.count.lock().unwrap(), |count| *count > 0)
+            .unwrap();
+    }
+
+    /// Function to wait until every crop submitted so far has been written or failed to.
+    ///
+    /// # Returns
+    ///
+    /// * The paths of the crops that failed to write since the last flush, which are forgotten
+    ///   so they don't fail later flushes.
+    pub fn flush(&self) -> Vec<PathBuf> {
+        self.wait();
+        std::mem::take(&mut *self.pending.failed.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Function to submit jobs that count themselves, failing those at the given paths.
+    fn submit_jobs(writer: &Writer, paths: &[&str], failing: &[&str], done: &Arc<AtomicUsize>) {
+        for path in paths {
+            let (fails, done) = (failing.contains(path), done.clone());
+            writer.submit(PathBuf::from(path), move || {
+                if fails {
+                    panic!("Failed to write crop");
+                }
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    #[test]
+    fn flush_waits_for_every_job() {
+        for threads in [0, 1, 4] {
+            let writer = Writer::new(threads);
+            let done = Arc::new(AtomicUsize::new(0));
+            let paths: Vec<_> = (0..20).map(|i| format!("{}.jpg", i)).collect();
+            let paths: Vec<_> = paths.iter().map(String::as_str).collect();
+            submit_jobs(&writer, &paths, &[], &done);
+            assert!(writer.flush().is_empty());
+            assert_eq!(done.load(Ordering::SeqCst), 20, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn flush_reports_failures_once() {
+        for threads in [0, 2] {
+            let writer = Writer::new(threads);
+            let done = Arc::new(AtomicUsize::new(0));
+            submit_jobs(&writer, &["a.jpg", "b.jpg", "c.jpg"], &["b.jpg"], &done);
+            assert_eq!(writer.flush(), [PathBuf::from("b.jpg")]);
+            assert_eq!(done.load(Ordering::SeqCst), 2);
+
+            // the failure belongs to the earlier flush, not to the crops written since
+            submit_jobs(&writer, &["d.jpg"], &[], &done);
+            assert!(writer.flush().is_empty());
+        }
+    }
+
+    #[test]
+    fn wait_keeps_failures_for_the_next_flush() {
+        let writer = Writer::new(2);
+        let done = Arc::new(AtomicUsize::new(0));
+        submit_jobs(&writer, &["a.jpg", "b.jpg"], &["a.jpg"], &done);
+        writer.wait();
+        assert_eq!(done.load(Ordering::SeqCst), 1);
+        submit_jobs(&writer, &["c.jpg"], &["c.jpg"], &done);
+        let mut failed = writer.flush();
+        failed.sort();
+        assert_eq!(failed, [PathBuf::from("a.jpg"), PathBuf::from("c.jpg")]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::ops::Range;
+
+use crate::provenance;
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_APP0: u8 = 0xE0;
+const JPEG_APP1: u8 = 0xE1;
+const JPEG_SOS: u8 = 0xDA;
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const JPEG_MAX_SEGMENT_LENGTH: usize = 0xFFFF;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const PNG_IHDR_END: usize = 8 + 12 + 13;
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+const RDF_CLOSE: &str = "</rdf:RDF>";
+
+/// Crop rectangle in coordinates normalized to the image size (0.0 - 1.0).
+#[derive(Debug)]
+pub struct NormalizedCrop {
+    pub top: f32,
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+}
+
+/// Function to build an `rdf:Description` carrying Camera Raw crop settings, which
+/// non-destructive editors (Lightroom, Camera Raw, darktable) apply as a virtual crop.
+///
+/// # Arguments
+///
+/// * `crop` - The crop rectangle, normalized to the image size.
+///
+/// # Returns
+///
+/// * The `rdf:Description` element.
+pub fn crop_description(crop: &NormalizedCrop) -> String {
+    format!(
+        "<rdf:Description rdf:about=\"\" \
+        xmlns:crs=\"http://ns.adobe.com/camera-raw-settings/1.0/\" \
+        crs:HasCrop=\"True\" \
+        crs:CropTop=\"{:.6}\" \
+        crs:CropLeft=\"{:.6}\" \
+        crs:CropBottom=\"{:.6}\" \
+        crs:CropRight=\"{:.6}\" \
+        crs:CropAngle=\"0\" \
+        crs:AlreadyApplied=\"False\"/>",
+        crop.top, crop.left, crop.bottom, crop.right
+    )
+}
+
+/// Where a crop was taken from, recorded in the crop so it stays traceable without the manifest.
+#[derive(Debug)]
+pub struct CropProvenance<'a> {
+    /// File name of the input the crop was taken from.
+    pub source: &'a str,
+    pub confidence: f32,
+    /// Region of the input the crop was taken from, in pixels.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Function to build an `rdf:Description` recording where a crop was taken from, in the
+/// `facecrop` namespace, along with the facecrop version as the XMP `CreatorTool`.
+///
+/// # Arguments
+///
+/// * `crop` - Where the crop was taken from.
+///
+/// # Returns
+///
+/// * The `rdf:Description` element.
+pub fn provenance_description(crop: &CropProvenance) -> String {
+    format!(
+        "<rdf:Description rdf:about=\"\" \
+        xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" \
+        xmlns:facecrop=\"https://github.com/ryanlyn/facecrop.rs/ns/1.0/\" \
+        xmp:CreatorTool=\"{}\" \
+        facecrop:Source=\"{}\" \
+        facecrop:Confidence=\"{:.6}\" \
+        facecrop:CropX=\"{}\" \
+        facecrop:CropY=\"{}\" \
+        facecrop:CropWidth=\"{}\" \
+        facecrop:CropHeight=\"{}\"/>",
+        provenance::marker(),
+        escape_attribute(crop.source),
+        crop.confidence,
+        crop.x,
+        crop.y,
+        crop.width,
+        crop.height
+    )
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Function to check whether an encoded image already carries Camera Raw crop settings.
+pub fn has_crop(encoded: &[u8]) -> bool {
+    existing_packet(encoded).is_some_and(|(_, packet)| packet.contains("crs:HasCrop"))
+}
+
+/// Function to add an `rdf:Description` to the XMP packet of an encoded JPEG or PNG.
+///
+/// If the image already has an XMP packet the description is appended to it, otherwise a new
+/// packet is created. The pixel data is left untouched.
+///
+/// # Arguments
+///
+/// * `encoded` - The encoded image bytes.
+/// * `description` - The `rdf:Description` element to add.
+///
+/// # Returns
+///
+/// * The encoded image bytes with the updated XMP packet, or an error if the image format is
+///   not supported or the existing packet can't be extended.
+pub fn embed_description(encoded: Vec<u8>, description: &str) -> Result<Vec<u8>, String> {
+    let existing = existing_packet(&encoded);
+    let packet = match &existing {
+        Some((_, packet)) => {
+            let close = packet
+                .rfind(RDF_CLOSE)
+                .ok_or_else(|| "Existing XMP packet has no rdf:RDF element".to_string())?;
+            format!("{}{}{}", &packet[..close], description, &packet[close..])
+        }
+        None => new_packet(description),
+    };
+    let existing_range = existing.map(|(range, _)| range);
+
+    if encoded.starts_with(&JPEG_SOI) {
+        let length = 2 + JPEG_XMP_HEADER.len() + packet.len();
+        if length > JPEG_MAX_SEGMENT_LENGTH {
+            return Err("XMP packet is too large for a single JPEG segment".to_string());
+        }
+        let mut segment = vec![0xFF, JPEG_APP1];
+        segment.extend_from_slice(&(length as u16).to_be_bytes());
+        segment.extend_from_slice(JPEG_XMP_HEADER);
+        segment.extend_from_slice(packet.as_bytes());
+
+        let range = existing_range.unwrap_or_else(|| {
+            let offset = jpeg_insert_offset(&encoded);
+            offset..offset
+        });
+        Ok(splice(encoded, range, &segment))
+    } else if encoded.starts_with(&PNG_SIGNATURE) && encoded.len() >= PNG_IHDR_END {
+        let mut data = Vec::with_capacity(PNG_XMP_KEYWORD.len() + 5 + packet.len());
+        data.extend_from_slice(PNG_XMP_KEYWORD);
+        // null separator, uncompressed, no compression method, empty language and translation
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(packet.as_bytes());
+        let chunk = provenance::png_chunk(b"iTXt", &data);
+
+        let range = existing_range.unwrap_or(PNG_IHDR_END..PNG_IHDR_END);
+        Ok(splice(encoded, range, &chunk))
+    } else {
+        Err("Only JPEG and PNG images support XMP metadata".to_string())
+    }
+}
+
+fn new_packet(description: &str) -> String {
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+        <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+        <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+        {}{}\
+        </x:xmpmeta>\
+        <?xpacket end=\"w\"?>",
+        description, RDF_CLOSE
+    )
+}
+
+fn splice(encoded: Vec<u8>, range: Range<usize>, replacement: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(encoded.len() + replacement.len());
+    output.extend_from_slice(&encoded[..range.start]);
+    output.extend_from_slice(replacement);
+    output.extend_from_slice(&encoded[range.end..]);
+    output
+}
+
+/// Returns the byte range of the segment/chunk holding the XMP packet and the packet itself.
+fn existing_packet(encoded: &[u8]) -> Option<(Range<usize>, String)> {
+    if encoded.starts_with(&JPEG_SOI) {
+        jpeg_segments(encoded).find_map(|(marker, range)| {
+            let data = &encoded[range.start + 4..range.end];
+            match marker == JPEG_APP1 && data.starts_with(JPEG_XMP_HEADER) {
+                true => Some((
+                    range.clone(),
+                    String::from_utf8_lossy(&data[JPEG_XMP_HEADER.len()..]).into_owned(),
+                )),
+                false => None,
+            }
+        })
+    } else if encoded.starts_with(&PNG_SIGNATURE) {
+        png_chunks(encoded).find_map(|(chunk_type, range)| {
+            let data = &encoded[range.start + 8..range.end - 4];
+            if &chunk_type != b"iTXt" || !data.starts_with(PNG_XMP_KEYWORD) {
+                return None;
+            }
+            // keyword, null, compression flag, compression method, language, null, translated
+            // keyword, null. Compressed packets aren't supported
+            let header = &data[PNG_XMP_KEYWORD.len()..];
+            if header.len() < 3 || header[1] != 0 {
+                return None;
+            }
+            let rest = &header[3..];
+            let language_end = rest.iter().position(|&b| b == 0)?;
+            let rest = &rest[language_end + 1..];
+            let translated_end = rest.iter().position(|&b| b == 0)?;
+            Some((
+                range,
+                String::from_utf8_lossy(&rest[translated_end + 1..]).into_owned(),
+            ))
+        })
+    } else {
+        None
+    }
+}
+
+/// XMP goes after a leading JFIF APP0 segment, which must stay first.
+fn jpeg_insert_offset(encoded: &[u8]) -> usize {
+    match jpeg_segments(encoded).next() {
+        Some((JPEG_APP0, range)) => range.end,
+        _ => JPEG_SOI.len(),
+    }
+}
+
+/// Iterates the (marker, byte range) of every JPEG header segment before the scan data.
+fn jpeg_segments(encoded: &[u8]) -> impl Iterator<Item = (u8, Range<usize>)> + '_ {
+    let mut offset = JPEG_SOI.len();
+    std::iter::from_fn(move || {
+        if offset + 4 > encoded.len() || encoded[offset] != 0xFF || encoded[offset + 1] == JPEG_SOS
+        {
+            return None;
+        }
+        let marker = encoded[offset + 1];
+        let length = u16::from_be_bytes([encoded[offset + 2], encoded[offset + 3]]) as usize;
+        let end = offset + 2 + length;
+        if length < 2 || end > encoded.len() {
+            return None;
+        }
+        let range = offset..end;
+        offset = end;
+        Some((marker, range))
+    })
+}
+
+/// Iterates the (type, byte range) of every PNG chunk before the image data.
+fn png_chunks(encoded: &[u8]) -> impl Iterator<Item = ([u8; 4], Range<usize>)> + '_ {
+    let mut offset = PNG_SIGNATURE.len();
+    std::iter::from_fn(move || {
+        if offset + 12 > encoded.len() {
+            return None;
+        }
+        let length = u32::from_be_bytes(encoded[offset..offset + 4].try_into().unwrap()) as usize;
+        let chunk_type: [u8; 4] = encoded[offset + 4..offset + 8].try_into().unwrap();
+        let end = offset + 12 + length;
+        if &chunk_type == b"IDAT" || end > encoded.len() {
+            return None;
+        }
+        let range = offset..end;
+        offset = end;
+        Some((chunk_type, range))
+    })
+}